@@ -0,0 +1,504 @@
+//! Resolution of the [features](https://protobuf.dev/editions/features/)
+//! that decide how each field is encoded.
+//!
+//! Proto2 and proto3 files have fixed behavior, expressed here as fixed sets of features.
+//! Files using Editions syntax start from the defaults of their edition,
+//! which may be overridden by the file, then by each enclosing message, then by the field itself.
+//!
+//! `prost-types` predates Editions, so it silently drops each file's `edition`
+//! and the `features` of every set of options.
+//! To recover them, the request is decoded a second time
+//! into a minimal [mirror](FeaturesRequest) of the descriptors that keeps only those parts.
+//! Both decodings see the same repeated fields in the same order,
+//! so the mirror lines up with the `prost-types` descriptors index-for-index.
+
+use anyhow::{bail, Result};
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::FieldDescriptorProto;
+
+use crate::metadata::{EXPANDED_OFFSET, EXPLICIT_OFFSET, PACKED_OFFSET};
+
+/// `google.protobuf.Edition.EDITION_2023`.
+pub(crate) const EDITION_2023: i32 = 1000;
+
+/// Whether a field tracks presence
+/// (`google.protobuf.FeatureSet.FieldPresence`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum FieldPresence {
+    Explicit = 1,
+    Implicit = 2,
+    LegacyRequired = 3,
+}
+
+/// How repeated scalar fields are laid out on the wire
+/// (`google.protobuf.FeatureSet.RepeatedFieldEncoding`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum RepeatedFieldEncoding {
+    Packed = 1,
+    Expanded = 2,
+}
+
+/// Whether an enum accepts values it does not declare
+/// (`google.protobuf.FeatureSet.EnumType`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EnumType {
+    Open = 1,
+    Closed = 2,
+}
+
+/// The fully-resolved features that apply to a single field.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct Features {
+    pub(crate) field_presence: FieldPresence,
+    pub(crate) repeated_field_encoding: RepeatedFieldEncoding,
+    /// Only meaningful for the features of an enum itself, rather than a field.
+    pub(crate) enum_type: EnumType,
+}
+
+/// Mirror of `google.protobuf.compiler.CodeGeneratorRequest`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FeaturesRequest {
+    #[prost(message, repeated, tag = "15")]
+    pub(crate) proto_file: Vec<FileFeatures>,
+}
+
+/// Mirror of `google.protobuf.FileDescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FileFeatures {
+    #[prost(message, repeated, tag = "4")]
+    pub(crate) message_type: Vec<MessageFeatures>,
+    #[prost(message, repeated, tag = "5")]
+    pub(crate) enum_type: Vec<EnumFeatures>,
+    #[prost(message, repeated, tag = "6")]
+    pub(crate) service: Vec<ServiceFeatures>,
+    #[prost(message, optional, tag = "8")]
+    options: Option<OptionsFeatures>,
+    #[prost(int32, optional, tag = "14")]
+    edition: Option<i32>,
+}
+
+/// Mirror of `google.protobuf.DescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MessageFeatures {
+    #[prost(message, repeated, tag = "2")]
+    field: Vec<FieldFeatures>,
+    #[prost(message, repeated, tag = "3")]
+    pub(crate) nested_type: Vec<MessageFeatures>,
+    #[prost(message, repeated, tag = "4")]
+    pub(crate) enum_type: Vec<EnumFeatures>,
+    #[prost(message, optional, tag = "7")]
+    options: Option<OptionsFeatures>,
+}
+
+/// Mirror of `google.protobuf.EnumDescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct EnumFeatures {
+    #[prost(message, optional, tag = "3")]
+    options: Option<OptionsFeatures>,
+}
+
+/// Mirror of `google.protobuf.FieldDescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct FieldFeatures {
+    #[prost(message, optional, tag = "8")]
+    options: Option<OptionsFeatures>,
+}
+
+/// Mirror of `google.protobuf.ServiceDescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct ServiceFeatures {
+    #[prost(message, repeated, tag = "2")]
+    pub(crate) method: Vec<MethodFeatures>,
+}
+
+/// Mirror of `google.protobuf.MethodDescriptorProto`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodFeatures {
+    #[prost(message, optional, tag = "4")]
+    options: Option<MethodOptionsFeatures>,
+}
+
+/// Mirror of the custom options in `compiler/options.proto`
+/// that apply to `google.protobuf.MethodOptions`.
+#[derive(Clone, PartialEq, Message)]
+struct MethodOptionsFeatures {
+    /// `(vimana.error)`.
+    #[prost(message, repeated, tag = "50233")]
+    error: Vec<MethodError>,
+    /// `(vimana.example)`.
+    #[prost(message, repeated, tag = "50234")]
+    example: Vec<MethodExample>,
+}
+
+/// Mirror of `vimana.Error`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodError {
+    #[prost(string, optional, tag = "1")]
+    pub(crate) case: Option<String>,
+    #[prost(int32, optional, tag = "2")]
+    pub(crate) code: Option<i32>,
+}
+
+/// Mirror of `vimana.Example`.
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct MethodExample {
+    #[prost(string, optional, tag = "1")]
+    pub(crate) name: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub(crate) request: Option<Vec<u8>>,
+    #[prost(bytes = "vec", optional, tag = "3")]
+    pub(crate) response: Option<Vec<u8>>,
+}
+
+/// Mirror of the `features` common to
+/// `google.protobuf.FileOptions`, `MessageOptions`, `FieldOptions`, and `EnumOptions`,
+/// along with the custom options in `compiler/options.proto`.
+#[derive(Clone, PartialEq, Message)]
+struct OptionsFeatures {
+    #[prost(message, optional, tag = "50")]
+    features: Option<FeatureSet>,
+    /// `(vimana.resource)`, which only applies to messages.
+    #[prost(bool, optional, tag = "50231")]
+    resource: Option<bool>,
+}
+
+/// Mirror of `google.protobuf.FeatureSet`, limited to the features that affect encoding.
+#[derive(Clone, PartialEq, Message)]
+struct FeatureSet {
+    #[prost(int32, optional, tag = "1")]
+    field_presence: Option<i32>,
+    #[prost(int32, optional, tag = "2")]
+    enum_type: Option<i32>,
+    #[prost(int32, optional, tag = "3")]
+    repeated_field_encoding: Option<i32>,
+}
+
+impl MessageFeatures {
+    /// Whether the message is marked with the `(vimana.resource)` option.
+    pub(crate) fn is_resource(&self) -> bool {
+        self.options
+            .as_ref()
+            .and_then(|options| options.resource)
+            .unwrap_or(false)
+    }
+}
+
+impl MethodFeatures {
+    /// The errors declared with the `(vimana.error)` option.
+    pub(crate) fn errors(&self) -> &[MethodError] {
+        self.options
+            .as_ref()
+            .map(|options| options.error.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// The examples declared with the `(vimana.example)` option.
+    pub(crate) fn examples(&self) -> &[MethodExample] {
+        self.options
+            .as_ref()
+            .map(|options| options.example.as_slice())
+            .unwrap_or_default()
+    }
+}
+
+impl Features {
+    /// Proto2 fields track presence, and repeated fields are expanded.
+    pub(crate) const PROTO2: Self = Self {
+        field_presence: FieldPresence::Explicit,
+        repeated_field_encoding: RepeatedFieldEncoding::Expanded,
+        enum_type: EnumType::Closed,
+    };
+
+    /// Proto3 fields only track presence if marked `optional`,
+    /// and repeated scalar fields are packed.
+    pub(crate) const PROTO3: Self = Self {
+        field_presence: FieldPresence::Implicit,
+        repeated_field_encoding: RepeatedFieldEncoding::Packed,
+        enum_type: EnumType::Open,
+    };
+
+    /// Defaults for every field in a file using edition 2023.
+    const EDITION_2023: Self = Self {
+        field_presence: FieldPresence::Explicit,
+        repeated_field_encoding: RepeatedFieldEncoding::Packed,
+        enum_type: EnumType::Open,
+    };
+
+    /// Features of a field in a proto3 file.
+    pub(crate) fn proto3(field: &FieldDescriptorProto) -> Self {
+        if field.proto3_optional() {
+            Self {
+                field_presence: FieldPresence::Explicit,
+                ..Self::PROTO3
+            }
+        } else {
+            Self::PROTO3
+        }
+    }
+
+    /// Features of a file using Editions syntax,
+    /// which every message and field in the file inherits unless overridden.
+    pub(crate) fn file(file: &FileFeatures, file_name: &str) -> Result<Self> {
+        let defaults = match file.edition {
+            Some(EDITION_2023) => Self::EDITION_2023,
+            Some(edition) => bail!(
+                "Unsupported edition {edition} in '{file_name}' (only edition 2023 is supported)"
+            ),
+            None => bail!("File '{file_name}' uses Editions syntax but lacks an edition"),
+        };
+        defaults.merge(file.options.as_ref())
+    }
+
+    /// Features of a message, inherited from the enclosing message or file.
+    pub(crate) fn message(self, message: Option<&MessageFeatures>) -> Result<Self> {
+        self.merge(message.and_then(|message| message.options.as_ref()))
+    }
+
+    /// Features of an enum, inherited from the enclosing message or file.
+    pub(crate) fn enumeration(self, enumeration: Option<&EnumFeatures>) -> Result<Self> {
+        self.merge(enumeration.and_then(|enumeration| enumeration.options.as_ref()))
+    }
+
+    /// Features of the field at `index` in a message, inherited from the message.
+    pub(crate) fn field(self, message: Option<&MessageFeatures>, index: usize) -> Result<Self> {
+        self.merge(
+            message
+                .and_then(|message| message.field.get(index))
+                .and_then(|field| field.options.as_ref()),
+        )
+    }
+
+    /// Override these features with any that are explicitly set in `options`.
+    fn merge(self, options: Option<&OptionsFeatures>) -> Result<Self> {
+        let Some(features) = options.and_then(|options| options.features.as_ref()) else {
+            return Ok(self);
+        };
+        Ok(Self {
+            field_presence: match features.field_presence {
+                None => self.field_presence,
+                Some(1) => FieldPresence::Explicit,
+                Some(2) => FieldPresence::Implicit,
+                Some(3) => FieldPresence::LegacyRequired,
+                Some(value) => bail!("Unknown field presence feature value {value}"),
+            },
+            repeated_field_encoding: match features.repeated_field_encoding {
+                None => self.repeated_field_encoding,
+                Some(1) => RepeatedFieldEncoding::Packed,
+                Some(2) => RepeatedFieldEncoding::Expanded,
+                Some(value) => bail!("Unknown repeated field encoding feature value {value}"),
+            },
+            enum_type: match features.enum_type {
+                None => self.enum_type,
+                Some(1) => EnumType::Open,
+                Some(2) => EnumType::Closed,
+                Some(value) => bail!("Unknown enum type feature value {value}"),
+            },
+        })
+    }
+
+    /// Amount to add to the base (implicit) coding of a non-message field
+    /// to get the coding that reflects these features.
+    ///
+    /// Messages always track presence, so singular message fields use no offset.
+    /// Only scalars with a fixed-size or varint encoding can be packed,
+    /// so repeated messages, strings, and bytes are always expanded.
+    pub(crate) fn coding_offset(&self, field: &FieldDescriptorProto) -> i32 {
+        match (field.label(), field.r#type()) {
+            (Label::Repeated, Type::Message | Type::String | Type::Bytes) => EXPANDED_OFFSET,
+            (Label::Repeated, _) => match self.repeated_field_encoding {
+                RepeatedFieldEncoding::Packed => PACKED_OFFSET,
+                RepeatedFieldEncoding::Expanded => EXPANDED_OFFSET,
+            },
+            (_, Type::Message) => 0,
+            (_, _) => match self.field_presence {
+                FieldPresence::Explicit | FieldPresence::LegacyRequired => EXPLICIT_OFFSET,
+                FieldPresence::Implicit => 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{DescriptorProto, FieldOptions, FileDescriptorProto};
+
+    use super::*;
+
+    fn options(
+        field_presence: Option<i32>,
+        repeated_field_encoding: Option<i32>,
+    ) -> Option<OptionsFeatures> {
+        Some(OptionsFeatures {
+            features: Some(FeatureSet {
+                field_presence,
+                enum_type: None,
+                repeated_field_encoding,
+            }),
+            resource: None,
+        })
+    }
+
+    fn field(label: Label, r#type: Type) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto::default();
+        field.set_label(label);
+        field.set_type(r#type);
+        field
+    }
+
+    #[test]
+    fn test_edition_2023_defaults() {
+        let file = FileFeatures {
+            edition: Some(EDITION_2023),
+            ..Default::default()
+        };
+        let features = Features::file(&file, "foo.proto").unwrap();
+        assert_eq!(features.field_presence, FieldPresence::Explicit);
+        assert_eq!(
+            features.repeated_field_encoding,
+            RepeatedFieldEncoding::Packed
+        );
+        assert_eq!(features.enum_type, EnumType::Open);
+    }
+
+    #[test]
+    fn test_unknown_edition() {
+        let file = FileFeatures {
+            // `EDITION_2024`.
+            edition: Some(1001),
+            ..Default::default()
+        };
+        assert_eq!(
+            Features::file(&file, "foo.proto").unwrap_err().to_string(),
+            "Unsupported edition 1001 in 'foo.proto' (only edition 2023 is supported)",
+        );
+    }
+
+    #[test]
+    fn test_inheritance() {
+        let file = FileFeatures {
+            edition: Some(EDITION_2023),
+            options: options(Some(FieldPresence::Implicit as i32), None),
+            ..Default::default()
+        };
+        let message = MessageFeatures {
+            field: vec![
+                FieldFeatures::default(),
+                FieldFeatures {
+                    options: options(Some(FieldPresence::Explicit as i32), None),
+                },
+                FieldFeatures {
+                    options: options(None, Some(RepeatedFieldEncoding::Expanded as i32)),
+                },
+            ],
+            ..Default::default()
+        };
+        let message_features = Features::file(&file, "foo.proto")
+            .unwrap()
+            .message(Some(&message))
+            .unwrap();
+
+        // The first field inherits the file's features.
+        let first = message_features.field(Some(&message), 0).unwrap();
+        assert_eq!(first.field_presence, FieldPresence::Implicit);
+        assert_eq!(first.repeated_field_encoding, RepeatedFieldEncoding::Packed);
+        // The others override one feature each.
+        let second = message_features.field(Some(&message), 1).unwrap();
+        assert_eq!(second.field_presence, FieldPresence::Explicit);
+        let third = message_features.field(Some(&message), 2).unwrap();
+        assert_eq!(third.field_presence, FieldPresence::Implicit);
+        assert_eq!(
+            third.repeated_field_encoding,
+            RepeatedFieldEncoding::Expanded
+        );
+    }
+
+    #[test]
+    fn test_enum_type_inheritance() {
+        let enum_options = |enum_type: EnumType| {
+            Some(OptionsFeatures {
+                features: Some(FeatureSet {
+                    enum_type: Some(enum_type as i32),
+                    ..Default::default()
+                }),
+                resource: None,
+            })
+        };
+        let file = FileFeatures {
+            edition: Some(EDITION_2023),
+            options: enum_options(EnumType::Closed),
+            ..Default::default()
+        };
+        let file_features = Features::file(&file, "foo.proto").unwrap();
+
+        // Enums inherit the file's features unless they override them.
+        let inherited = file_features.enumeration(None).unwrap();
+        assert_eq!(inherited.enum_type, EnumType::Closed);
+        let overridden = EnumFeatures {
+            options: enum_options(EnumType::Open),
+        };
+        let overridden = file_features.enumeration(Some(&overridden)).unwrap();
+        assert_eq!(overridden.enum_type, EnumType::Open);
+    }
+
+    #[test]
+    fn test_coding_offset_matches_legacy_syntax() {
+        let singular = field(Label::Optional, Type::Int32);
+        let repeated = field(Label::Repeated, Type::Int32);
+        let message = field(Label::Optional, Type::Message);
+        let repeated_message = field(Label::Repeated, Type::Message);
+        let repeated_string = field(Label::Repeated, Type::String);
+
+        let edition_2023 = Features::EDITION_2023;
+        let explicit_proto3 = Features::proto3(&FieldDescriptorProto {
+            proto3_optional: Some(true),
+            ..singular.clone()
+        });
+        assert_eq!(edition_2023.coding_offset(&singular), EXPLICIT_OFFSET);
+        assert_eq!(explicit_proto3.coding_offset(&singular), EXPLICIT_OFFSET);
+        assert_eq!(Features::PROTO2.coding_offset(&singular), EXPLICIT_OFFSET);
+        assert_eq!(Features::PROTO3.coding_offset(&singular), 0);
+
+        assert_eq!(edition_2023.coding_offset(&repeated), PACKED_OFFSET);
+        assert_eq!(Features::PROTO3.coding_offset(&repeated), PACKED_OFFSET);
+        assert_eq!(Features::PROTO2.coding_offset(&repeated), EXPANDED_OFFSET);
+
+        for features in [edition_2023, Features::PROTO2, Features::PROTO3] {
+            assert_eq!(features.coding_offset(&message), 0);
+            assert_eq!(features.coding_offset(&repeated_message), EXPANDED_OFFSET);
+            assert_eq!(features.coding_offset(&repeated_string), EXPANDED_OFFSET);
+        }
+    }
+
+    #[test]
+    fn test_mirror_lines_up_with_descriptors() {
+        let descriptor = FileDescriptorProto {
+            name: Some(String::from("foo.proto")),
+            syntax: Some(String::from("editions")),
+            message_type: vec![DescriptorProto {
+                name: Some(String::from("Foo")),
+                field: vec![
+                    FieldDescriptorProto::default(),
+                    FieldDescriptorProto {
+                        // `prost-types` keeps options it knows about alongside the features.
+                        options: Some(FieldOptions {
+                            deprecated: Some(true),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let mut encoded = descriptor.encode_to_vec();
+        // `FileDescriptorProto.edition`, which `prost-types` cannot encode itself.
+        prost::encoding::int32::encode(14, &EDITION_2023, &mut encoded);
+
+        let mirror = FileFeatures::decode(encoded.as_slice()).unwrap();
+        assert_eq!(mirror.edition, Some(EDITION_2023));
+        assert_eq!(mirror.message_type.len(), 1);
+        assert_eq!(mirror.message_type[0].field.len(), 2);
+    }
+}
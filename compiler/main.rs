@@ -1,3 +1,4 @@
+mod features;
 mod metadata;
 mod wit;
 
@@ -6,21 +7,44 @@ use std::fmt::{Display, Formatter, Result as FmtResult};
 use std::io::{stdin, stdout, Read, Write};
 
 use anyhow::{anyhow, bail, Result};
+use prost::encoding::{int32, message};
 use prost::Message;
 use prost_types::compiler::code_generator_response::{Feature, File};
 use prost_types::compiler::{CodeGeneratorRequest, CodeGeneratorResponse};
 use prost_types::{DescriptorProto, EnumDescriptorProto, FileDescriptorProto};
 
-use metadata::MetadataFile;
+use features::{
+    EnumFeatures, EnumType, Features, FeaturesRequest, FileFeatures, MessageFeatures, MethodError,
+    MethodExample, MethodFeatures, EDITION_2023,
+};
+use metadata::{BinaryFile, MetadataFile};
 use wit::WitFile;
 
 /// Version of the Vimana API to import.
 pub(crate) const VIMANA_API_VERSION: &str = "0.0.0";
 /// Version of the WASI API to import.
 pub(crate) const WASI_API_VERSION: &str = "0.2.0";
+/// `FEATURE_SUPPORTS_EDITIONS`, which `prost-types` does not define.
+const FEATURE_SUPPORTS_EDITIONS: u64 = 2;
 /// Bitwise union of supported features.
 /// https://github.com/protocolbuffers/protobuf/blob/v31.1/src/google/protobuf/compiler/code_generator.h#L96
-const SUPPORTED_FEATURES: u64 = Feature::Proto3Optional as u64;
+const SUPPORTED_FEATURES: u64 = Feature::Proto3Optional as u64 | FEATURE_SUPPORTS_EDITIONS;
+/// Field numbers of `CodeGeneratorResponse.minimum_edition` and `maximum_edition`,
+/// which `prost-types` does not define.
+const MINIMUM_EDITION_TAG: u32 = 3;
+const MAXIMUM_EDITION_TAG: u32 = 4;
+/// Field number of `CodeGeneratorResponse.file`,
+/// for generated files with binary content that `prost-types` cannot represent.
+const RESPONSE_FILE_TAG: u32 = 15;
+/// Plugin option (`--vimana_opt=examples`) to record method examples in the metadata.
+const EXAMPLES_OPTION: &str = "examples";
+/// Field numbers of `FileDescriptorProto.message_type`, `DescriptorProto.field`,
+/// `DescriptorProto.nested_type`, and `DescriptorProto.oneof_decl`,
+/// which make up the paths to declarations within a file (as in `SourceCodeInfo`).
+const FILE_MESSAGE_TYPE_TAG: i32 = 4;
+pub(crate) const MESSAGE_FIELD_TAG: i32 = 2;
+const MESSAGE_NESTED_TYPE_TAG: i32 = 3;
+pub(crate) const MESSAGE_ONEOF_DECL_TAG: i32 = 8;
 
 #[derive(Copy, Clone, PartialEq, Eq)]
 pub(crate) enum ProtoSyntax {
@@ -47,10 +71,18 @@ pub(crate) struct DescriptorMap<'a> {
     /// Mapping from filenames to file descriptors.
     files: HashMap<String, (&'a FileDescriptorProto, ProtoSyntax)>,
     /// Mapping from fully-qualified message type names to message descriptors
-    /// and the Protobuf syntax that applies to each descriptor.
-    messages: HashMap<QualifiedTypeName<'a>, (&'a DescriptorProto, ProtoSyntax)>,
-    /// Mapping from fully-qualified enum type names to enum descriptors.
-    enums: HashMap<QualifiedTypeName<'a>, &'a EnumDescriptorProto>,
+    /// and the resolved features of each field in the message.
+    messages: HashMap<QualifiedTypeName<'a>, (&'a DescriptorProto, Vec<Features>)>,
+    /// Mapping from fully-qualified enum type names to enum descriptors
+    /// and whether each enum is open or closed.
+    enums: HashMap<QualifiedTypeName<'a>, (&'a EnumDescriptorProto, EnumType)>,
+    /// Mapping from fully-qualified service names to the mirror of each method in the service.
+    services: HashMap<QualifiedTypeName<'a>, Vec<MethodFeatures>>,
+    /// Fully-qualified names of messages marked with the `(vimana.resource)` option.
+    resources: HashSet<QualifiedTypeName<'a>>,
+    /// Mapping from fully-qualified message type names to the file declaring each message
+    /// and the path to its declaration within that file.
+    sources: HashMap<QualifiedTypeName<'a>, (&'a str, Vec<i32>)>,
 }
 
 fn main() -> Result<()> {
@@ -59,6 +91,7 @@ fn main() -> Result<()> {
     let mut buf: Vec<u8> = Vec::new();
     stdin().read_to_end(&mut buf)?;
     let request: CodeGeneratorRequest = CodeGeneratorRequest::decode(buf.as_slice())?;
+    let features: FeaturesRequest = FeaturesRequest::decode(buf.as_slice())?;
 
     // Generate a response.
     // If an error occurs after this point,
@@ -68,68 +101,122 @@ fn main() -> Result<()> {
         error: None,
         supported_features: Some(SUPPORTED_FEATURES),
     };
-    match compile(request) {
-        Ok(files) => response.file.extend(files),
+    let mut binary_files: Vec<BinaryFile> = Vec::new();
+    match compile(request, features) {
+        Ok((wit_file, metadata_file)) => {
+            response.file.push(wit_file);
+            binary_files.push(metadata_file);
+        }
         Err(error) => response.error = Some(error.to_string()),
     }
 
-    // Write the response to stdout.
-    return Ok(stdout().write_all(response.encode_to_vec().as_slice())?);
+    // Write the response to stdout,
+    // followed by the binary files and the range of supported editions
+    // that `prost-types` cannot encode.
+    let mut encoded = response.encode_to_vec();
+    for file in &binary_files {
+        message::encode(RESPONSE_FILE_TAG, file, &mut encoded);
+    }
+    int32::encode(MINIMUM_EDITION_TAG, &EDITION_2023, &mut encoded);
+    int32::encode(MAXIMUM_EDITION_TAG, &EDITION_2023, &mut encoded);
+    Ok(stdout().write_all(encoded.as_slice())?)
 }
 
-fn compile(request: CodeGeneratorRequest) -> Result<Vec<File>> {
-    let descriptors = DescriptorMap::build(&request.proto_file)?;
+fn compile(request: CodeGeneratorRequest, features: FeaturesRequest) -> Result<(File, BinaryFile)> {
+    let descriptors = DescriptorMap::build(&request.proto_file, &features.proto_file)?;
 
     let mut wit_file: WitFile = WitFile::default();
     let mut metadata_file: MetadataFile = MetadataFile::default();
+    for option in request
+        .parameter()
+        .split(',')
+        .filter(|option| !option.is_empty())
+    {
+        match option {
+            EXAMPLES_OPTION => metadata_file.include_examples(),
+            option => bail!("Unknown option '{option}'"),
+        }
+    }
 
     for file_to_generate in &request.file_to_generate {
-        let (file_descriptor, syntax) = descriptors.get_file(file_to_generate)?;
+        let (file_descriptor, _) = descriptors.get_file(file_to_generate)?;
 
         // `set_or_check_server_package` *must* be invoked
         // before `compile_service` or `compile_message`.
         let package = wit_file.set_or_check_server_package(file_descriptor.package())?;
 
         for service_descriptor in &file_descriptor.service {
-            wit_file.compile_service(service_descriptor)?;
+            wit_file.compile_service(service_descriptor, &descriptors)?;
+            metadata_file.compile_service(service_descriptor, &package, &descriptors)?;
         }
 
         let qualifier = TypeNameQualifier::top_level(package);
         for message_descriptor in &file_descriptor.message_type {
-            wit_file.compile_message(
-                message_descriptor,
-                &qualifier,
-                syntax.clone(),
-                &descriptors,
-            )?;
+            wit_file.compile_message(message_descriptor, &qualifier, &descriptors)?;
         }
     }
 
-    Ok(vec![wit_file.generate()?, metadata_file.generate()?])
+    Ok((wit_file.generate()?, metadata_file.generate()))
 }
 
 impl<'a> DescriptorMap<'a> {
-    fn build(file_descriptors: &'a Vec<FileDescriptorProto>) -> Result<Self> {
+    /// Build the map from the file descriptors of a request,
+    /// along with the [mirror](FeaturesRequest) of the same descriptors.
+    fn build(
+        file_descriptors: &'a [FileDescriptorProto],
+        file_features: &[FileFeatures],
+    ) -> Result<Self> {
         let mut descriptors = Self::default();
 
-        for file_descriptor in file_descriptors {
+        for (index, file_descriptor) in file_descriptors.iter().enumerate() {
             let file_name = file_descriptor.name();
-
-            let syntax = match file_descriptor.syntax.as_ref().map(String::as_str) {
-                None | Some("proto2") => ProtoSyntax::Proto2,
-                Some("proto3") => ProtoSyntax::Proto3,
-                Some("editions") => bail!("Editions syntax is not yet supported"),
+            let file_features = file_features.get(index);
+
+            let (syntax, features) = match file_descriptor.syntax.as_deref() {
+                None | Some("proto2") => (ProtoSyntax::Proto2, Features::PROTO2),
+                Some("proto3") => (ProtoSyntax::Proto3, Features::PROTO3),
+                Some("editions") => {
+                    let file_features = file_features.ok_or_else(|| {
+                        anyhow!("Malformed request lacks features for '{file_name}'")
+                    })?;
+                    (
+                        ProtoSyntax::Editions,
+                        Features::file(file_features, file_name)?,
+                    )
+                }
                 Some(syntax) => bail!("Unknown syntax '{syntax}' in '{file_name}'"),
             };
 
             let qualifier =
                 TypeNameQualifier::top_level(file_descriptor.package().split('.').collect());
 
-            for message_type in &file_descriptor.message_type {
-                descriptors.insert_message(message_type, qualifier.clone(), syntax);
+            for (index, message_type) in file_descriptor.message_type.iter().enumerate() {
+                descriptors.insert_message(
+                    message_type,
+                    file_features.and_then(|file| file.message_type.get(index)),
+                    qualifier.clone(),
+                    syntax,
+                    features,
+                    (file_name, vec![FILE_MESSAGE_TYPE_TAG, index as i32]),
+                )?;
             }
-            for enum_type in &file_descriptor.enum_type {
-                descriptors.insert_enum(enum_type, qualifier.clone());
+            for (index, enum_type) in file_descriptor.enum_type.iter().enumerate() {
+                descriptors.insert_enum(
+                    enum_type,
+                    file_features.and_then(|file| file.enum_type.get(index)),
+                    qualifier.clone(),
+                    syntax,
+                    features,
+                )?;
+            }
+            for (index, service) in file_descriptor.service.iter().enumerate() {
+                let methods = file_features
+                    .and_then(|file| file.service.get(index))
+                    .map(|service| service.method.clone())
+                    .unwrap_or_default();
+                descriptors
+                    .services
+                    .insert(qualifier.r#type(service.name()), methods);
             }
 
             descriptors
@@ -140,34 +227,93 @@ impl<'a> DescriptorMap<'a> {
         Ok(descriptors)
     }
 
+    /// Add a message, and everything nested in it, to the map.
+    /// `features` are those inherited from the enclosing message or file.
+    /// `source` is the file declaring the message and the path to the declaration.
     fn insert_message(
         &mut self,
         descriptor: &'a DescriptorProto,
+        mirror: Option<&MessageFeatures>,
         qualifier: TypeNameQualifier<'a>,
         syntax: ProtoSyntax,
-    ) {
+        features: Features,
+        source: (&'a str, Vec<i32>),
+    ) -> Result<()> {
         let name = descriptor.name();
+        let features = match syntax {
+            ProtoSyntax::Proto2 | ProtoSyntax::Proto3 => features,
+            ProtoSyntax::Editions => features.message(mirror)?,
+        };
 
         // Recursively add all nested messages and enums.
         let nested_qualifier = qualifier.nested(name);
-        for nested_message in &descriptor.nested_type {
-            self.insert_message(nested_message, nested_qualifier.clone(), syntax);
+        for (index, nested_message) in descriptor.nested_type.iter().enumerate() {
+            self.insert_message(
+                nested_message,
+                mirror.and_then(|mirror| mirror.nested_type.get(index)),
+                nested_qualifier.clone(),
+                syntax,
+                features,
+                (
+                    source.0,
+                    [
+                        source.1.as_slice(),
+                        &[MESSAGE_NESTED_TYPE_TAG, index as i32],
+                    ]
+                    .concat(),
+                ),
+            )?;
         }
-        for nested_enum in &descriptor.enum_type {
-            self.insert_enum(nested_enum, nested_qualifier.clone());
+        for (index, nested_enum) in descriptor.enum_type.iter().enumerate() {
+            self.insert_enum(
+                nested_enum,
+                mirror.and_then(|mirror| mirror.enum_type.get(index)),
+                nested_qualifier.clone(),
+                syntax,
+                features,
+            )?;
         }
 
+        let field_features = descriptor
+            .field
+            .iter()
+            .enumerate()
+            .map(|(index, field)| match syntax {
+                ProtoSyntax::Proto2 => Ok(Features::PROTO2),
+                ProtoSyntax::Proto3 => Ok(Features::proto3(field)),
+                ProtoSyntax::Editions => features.field(mirror, index),
+            })
+            .collect::<Result<Vec<Features>>>()?;
+
+        let type_name = qualifier.into_type(name);
+        if mirror.is_some_and(MessageFeatures::is_resource) {
+            self.resources.insert(type_name.clone());
+        }
+        self.sources.insert(type_name.clone(), source);
         self.messages
-            .insert(qualifier.into_type(name), (descriptor, syntax));
+            .insert(type_name, (descriptor, field_features));
+        Ok(())
     }
 
+    /// Add an enum to the map.
+    /// `features` are those inherited from the enclosing message or file.
     fn insert_enum(
         &mut self,
         enum_descriptor: &'a EnumDescriptorProto,
+        mirror: Option<&EnumFeatures>,
         qualifier: TypeNameQualifier<'a>,
-    ) {
-        self.enums
-            .insert(qualifier.into_type(enum_descriptor.name()), enum_descriptor);
+        syntax: ProtoSyntax,
+        features: Features,
+    ) -> Result<()> {
+        let features = match syntax {
+            ProtoSyntax::Proto2 | ProtoSyntax::Proto3 => features,
+            ProtoSyntax::Editions => features.enumeration(mirror)?,
+        };
+        self.enums.insert(
+            qualifier.into_type(enum_descriptor.name()),
+            (enum_descriptor, features.enum_type),
+        );
+        Ok(())
     }
 
     fn get_file(&self, filename: &String) -> Result<(&'a FileDescriptorProto, ProtoSyntax)> {
@@ -180,17 +326,61 @@ impl<'a> DescriptorMap<'a> {
     pub(crate) fn get_message(
         &self,
         name: &QualifiedTypeName<'a>,
-    ) -> Option<(&'a DescriptorProto, ProtoSyntax)> {
-        self.messages.get(name).map(|value| value.clone())
+    ) -> Option<(&'a DescriptorProto, &[Features])> {
+        self.messages
+            .get(name)
+            .map(|(descriptor, features)| (*descriptor, features.as_slice()))
+    }
+
+    pub(crate) fn get_enum(
+        &self,
+        name: &QualifiedTypeName<'a>,
+    ) -> Option<(&'a EnumDescriptorProto, EnumType)> {
+        self.enums.get(name).copied()
+    }
+
+    /// Return the errors declared by the method at `index` in the named service.
+    pub(crate) fn get_method_errors(
+        &self,
+        service: &QualifiedTypeName<'a>,
+        index: usize,
+    ) -> &[MethodError] {
+        self.services
+            .get(service)
+            .and_then(|methods| methods.get(index))
+            .map(MethodFeatures::errors)
+            .unwrap_or_default()
+    }
+
+    /// Return the examples declared by the method at `index` in the named service.
+    pub(crate) fn get_method_examples(
+        &self,
+        service: &QualifiedTypeName<'a>,
+        index: usize,
+    ) -> &[MethodExample] {
+        self.services
+            .get(service)
+            .and_then(|methods| methods.get(index))
+            .map(MethodFeatures::examples)
+            .unwrap_or_default()
+    }
+
+    /// Whether the named message is an opaque handle to a resource managed by the runtime,
+    /// rather than a record of its fields.
+    pub(crate) fn is_resource(&self, name: &QualifiedTypeName<'a>) -> bool {
+        self.resources.contains(name)
     }
 
-    pub(crate) fn get_enum(&self, name: &QualifiedTypeName<'a>) -> Option<&'a EnumDescriptorProto> {
-        self.enums.get(name).map(|value| value.clone())
+    /// Return the file declaring the named message, and the path to the declaration in that file.
+    pub(crate) fn get_source(&self, name: &QualifiedTypeName<'a>) -> Option<(&'a str, &[i32])> {
+        self.sources
+            .get(name)
+            .map(|(file, path)| (*file, path.as_slice()))
     }
 }
 
 impl<'a> QualifiedTypeName<'a> {
-    pub(crate) fn from_path(type_path: &'a str, default_package: &Vec<&'a str>) -> Self {
+    pub(crate) fn from_path(type_path: &'a str, default_package: &[&'a str]) -> Self {
         let mut parts = type_path.split('.');
 
         // The final part is the short name
@@ -219,7 +409,7 @@ impl<'a> QualifiedTypeName<'a> {
             }
             package
         } else {
-            default_package.clone()
+            default_package.to_vec()
         };
 
         // Any remaining parts must be outer nesting messages.
@@ -1,28 +1,393 @@
 //! The compilation step involves consolidating TODO
 
-use anyhow::Result;
-use prost_types::compiler::code_generator_response::File;
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use heck::ToKebabCase;
+use prost::Message;
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{FieldDescriptorProto, MethodDescriptorProto, ServiceDescriptorProto};
+
+use crate::features::{EnumType, Features, FieldPresence, MethodError, MethodExample};
+use crate::{DescriptorMap, QualifiedTypeName, TypeNameQualifier};
+use metadata_proto::work::runtime::field::{Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::{
+    ErrorCode, Field, GrpcArity, GrpcMethod, GrpcService, Metadata, MethodExample as Example,
+};
 
 /// Name of the generated metadata file in the output directory.
 const FILENAME: &str = "metadata.binpb";
 
-const PACKED_OFFSET: i32 = 1;
-const EXPLICIT_OFFSET: i32 = 2;
-const EXPANDED_OFFSET: i32 = 3;
+/// Offsets from the base (implicit) coding of a field type
+/// to its packed, explicit, and expanded variants.
+pub(crate) const PACKED_OFFSET: i32 = 1;
+pub(crate) const EXPLICIT_OFFSET: i32 = 2;
+pub(crate) const EXPANDED_OFFSET: i32 = 3;
+
+/// Largest canonical gRPC status code (`UNAUTHENTICATED`).
+const MAX_STATUS_CODE: i32 = 16;
+
+/// Number and name of the sole subfield of a resource,
+/// holding the handle number by which clients refer to it.
+const HANDLE_FIELD_NUMBER: u32 = 1;
+const HANDLE_FIELD_NAME: &str = "handle";
+
+/// Mirror of `google.protobuf.compiler.CodeGeneratorResponse.File`
+/// with binary content, which `prost-types` cannot represent
+/// (it only allows valid UTF-8).
+#[derive(Clone, PartialEq, Message)]
+pub(crate) struct BinaryFile {
+    #[prost(string, optional, tag = "1")]
+    name: Option<String>,
+    #[prost(bytes = "vec", optional, tag = "15")]
+    content: Option<Vec<u8>>,
+}
 
 #[derive(Default)]
-pub(crate) struct MetadataFile {}
+pub(crate) struct MetadataFile {
+    /// The gRPC services compiled so far.
+    services: Vec<GrpcService>,
+
+    /// Deepest message nesting below any request type compiled so far.
+    max_depth: u32,
+
+    /// Whether to record the examples declared for each method.
+    /// Off by default, to keep metadata small.
+    examples: bool,
+}
 
 impl MetadataFile {
-    pub(crate) fn generate(self) -> Result<File> {
-        Ok(File {
+    /// Record the examples declared for each method compiled from now on.
+    pub(crate) fn include_examples(&mut self) {
+        self.examples = true;
+    }
+
+    /// Compile the methods of a service,
+    /// describing how to decode each request and encode each response.
+    pub(crate) fn compile_service<'a>(
+        &mut self,
+        service: &'a ServiceDescriptorProto,
+        package: &Vec<&'a str>,
+        descriptors: &DescriptorMap<'a>,
+    ) -> Result<()> {
+        let service_name = TypeNameQualifier::top_level(package.clone()).into_type(service.name());
+        let mut methods = HashMap::with_capacity(service.method.len());
+        for (index, method) in service.method.iter().enumerate() {
+            let request = QualifiedTypeName::from_path(method.input_type(), package);
+            let request = message_field(&request, package, descriptors)?;
+            self.max_depth = self.max_depth.max(message_depth(&request));
+            let response = QualifiedTypeName::from_path(method.output_type(), package);
+            let errors = descriptors.get_method_errors(&service_name, index);
+            let examples = if self.examples {
+                examples(
+                    method,
+                    descriptors.get_method_examples(&service_name, index),
+                )?
+            } else {
+                Vec::new()
+            };
+            methods.insert(
+                String::from(method.name()),
+                GrpcMethod {
+                    function: method.name().to_kebab_case(),
+                    arity: arity(method) as i32,
+                    request: Some(request),
+                    response: Some(message_field(&response, package, descriptors)?),
+                    caching: None,
+                    error_codes: error_codes(method, errors)?,
+                    examples,
+                },
+            );
+        }
+        self.services.push(GrpcService {
+            name: format!("{}.{}", package.join("."), service.name()),
+            methods,
+        });
+        Ok(())
+    }
+
+    pub(crate) fn generate(self) -> BinaryFile {
+        let metadata = Metadata {
+            service: self.services,
+            max_depth: self.max_depth,
+            ..Default::default()
+        };
+        BinaryFile {
             name: Some(String::from(FILENAME)),
-            insertion_point: None,
-            content: Some(String::default()),
-            // TODO: Add generated code info to help with debugging.
-            generated_code_info: None,
+            content: Some(metadata.encode_to_vec()),
+        }
+    }
+}
+
+/// Streaming / unarity classification of a method.
+fn arity(method: &MethodDescriptorProto) -> GrpcArity {
+    match (method.client_streaming(), method.server_streaming()) {
+        (false, false) => GrpcArity::Unary,
+        (false, true) => GrpcArity::ServerStreaming,
+        (true, false) => GrpcArity::ClientStreaming,
+        (true, true) => GrpcArity::BidiStreaming,
+    }
+}
+
+/// Map each error declared by a method to its status code,
+/// naming each case the same way as the method's WIT error enum.
+fn error_codes(method: &MethodDescriptorProto, errors: &[MethodError]) -> Result<Vec<ErrorCode>> {
+    let mut error_codes: Vec<ErrorCode> = Vec::with_capacity(errors.len());
+    for error in errors {
+        let case = error.case().to_kebab_case();
+        if case.is_empty() {
+            bail!("Error in method '{}' lacks a case", method.name());
+        }
+        if error_codes.iter().any(|existing| existing.case == case) {
+            bail!(
+                "Duplicate error case '{case}' in method '{}'",
+                method.name()
+            );
+        }
+        let code = error.code();
+        if !(1..=MAX_STATUS_CODE).contains(&code) {
+            bail!(
+                "Error case '{case}' in method '{}' has invalid status code {code}",
+                method.name(),
+            );
+        }
+        error_codes.push(ErrorCode {
+            case,
+            code: code as u32,
+        });
+    }
+    Ok(error_codes)
+}
+
+/// Copy the examples declared by a method, checking that their names are unique.
+fn examples(method: &MethodDescriptorProto, examples: &[MethodExample]) -> Result<Vec<Example>> {
+    let mut compiled: Vec<Example> = Vec::with_capacity(examples.len());
+    for example in examples {
+        let name = example.name();
+        if name.is_empty() {
+            bail!("Example in method '{}' lacks a name", method.name());
+        }
+        if compiled.iter().any(|existing| existing.name == name) {
+            bail!("Duplicate example '{name}' in method '{}'", method.name());
+        }
+        compiled.push(Example {
+            name: String::from(name),
+            request: example.request().to_vec(),
+            response: example.response().to_vec(),
+        });
+    }
+    Ok(compiled)
+}
+
+/// The field describing a request or response message,
+/// of which only the subfields are meaningful.
+fn message_field<'a>(
+    name: &QualifiedTypeName<'a>,
+    package: &Vec<&'a str>,
+    descriptors: &DescriptorMap<'a>,
+) -> Result<Field> {
+    Ok(Field {
+        subfields: message_subfields(name, package, descriptors, &mut Vec::new())?,
+        ..Default::default()
+    })
+}
+
+/// The subfields of a message: one per ordinary field in declaration order,
+/// followed by one per oneof, matching the fields of the generated WIT record.
+///
+/// `visiting` holds the types enclosing this one, to detect recursion,
+/// which neither WIT nor the metadata can represent.
+fn message_subfields<'a>(
+    name: &QualifiedTypeName<'a>,
+    package: &Vec<&'a str>,
+    descriptors: &DescriptorMap<'a>,
+    visiting: &mut Vec<QualifiedTypeName<'a>>,
+) -> Result<Vec<Field>> {
+    if visiting.contains(name) {
+        bail!("Recursive message type '{name}' is not supported");
+    }
+    let (descriptor, field_features) = descriptors
+        .get_message(name)
+        .ok_or_else(|| anyhow!("Unknown message type '{name}'"))?;
+    visiting.push(name.clone());
+
+    let mut oneofs: Vec<Field> = descriptor
+        .oneof_decl
+        .iter()
+        .map(|oneof| Field {
+            name: oneof.name().to_kebab_case(),
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
+            ..Default::default()
         })
+        .collect();
+    let mut subfields = Vec::with_capacity(descriptor.field.len());
+    for (field, features) in descriptor.field.iter().zip(field_features) {
+        let subfield = compile_field(field, *features, package, descriptors, visiting)?;
+        // Proto3 `optional` fields each belong to a synthetic oneof,
+        // but they're just ordinary fields with explicit presence.
+        match (field.oneof_index, field.proto3_optional()) {
+            (Some(index), false) => oneofs
+                .get_mut(index as usize)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Field '{}' in '{name}' has an unknown oneof index {index}",
+                        field.name(),
+                    )
+                })?
+                .subfields
+                .push(subfield),
+            _ => subfields.push(subfield),
+        }
     }
+    // Synthetic oneofs end up without any variants.
+    subfields.extend(
+        oneofs
+            .into_iter()
+            .filter(|oneof| !oneof.subfields.is_empty()),
+    );
+
+    visiting.pop();
+    Ok(subfields)
+}
+
+/// The metadata of a single field, including the subfields of message and enum types.
+fn compile_field<'a>(
+    field: &'a FieldDescriptorProto,
+    features: Features,
+    package: &Vec<&'a str>,
+    descriptors: &DescriptorMap<'a>,
+    visiting: &mut Vec<QualifiedTypeName<'a>>,
+) -> Result<Field> {
+    let mut resource = false;
+    let mut open_enum = false;
+    let mut default_variant = String::new();
+    let subfields = match field.r#type() {
+        Type::Message => {
+            let name = QualifiedTypeName::from_path(field.type_name(), package);
+            if descriptors.is_resource(&name) {
+                // Only the handle number of a resource ever reaches the client.
+                resource = true;
+                vec![Field {
+                    number: HANDLE_FIELD_NUMBER,
+                    name: String::from(HANDLE_FIELD_NAME),
+                    coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+                    ..Default::default()
+                }]
+            } else {
+                message_subfields(&name, package, descriptors, visiting)?
+            }
+        }
+        Type::Enum => {
+            let name = QualifiedTypeName::from_path(field.type_name(), package);
+            let (descriptor, enum_type) = descriptors
+                .get_enum(&name)
+                .ok_or_else(|| anyhow!("Unknown enum type '{name}'"))?;
+            open_enum = enum_type == EnumType::Open;
+            if !open_enum {
+                // Closed enums default to the first variant declared,
+                // unless the field designates another.
+                default_variant = match field.default_value.as_deref() {
+                    Some(name) => name.to_kebab_case(),
+                    None => descriptor
+                        .value
+                        .first()
+                        .map(|value| value.name().to_kebab_case())
+                        .unwrap_or_default(),
+                };
+            }
+            descriptor
+                .value
+                .iter()
+                .map(|value| Field {
+                    // Negative variant numbers are stored in two's complement.
+                    number: value.number() as u32,
+                    name: value.name().to_kebab_case(),
+                    ..Default::default()
+                })
+                .collect()
+        }
+        _ => Vec::new(),
+    };
+    Ok(Field {
+        number: field.number() as u32,
+        name: field.name().to_kebab_case(),
+        subfields,
+        coding: Some(field_coding(field, features)?),
+        resource,
+        open_enum,
+        default_variant,
+        ..Default::default()
+    })
+}
+
+/// The coding of a single field with the given resolved features.
+///
+/// Fields that belong to a (non-synthetic) oneof always track presence,
+/// whatever their features say,
+/// so they get the explicit coding that the decoder requires of every oneof variant.
+fn field_coding(field: &FieldDescriptorProto, features: Features) -> Result<Coding> {
+    let features = if field.oneof_index.is_some() && !field.proto3_optional() {
+        Features {
+            field_presence: FieldPresence::Explicit,
+            ..features
+        }
+    } else {
+        features
+    };
+    let offset = features.coding_offset(field);
+    let scalar = |base: ScalarCoding| Ok(Coding::ScalarCoding(base as i32 + offset));
+    match field.r#type() {
+        Type::Double => scalar(ScalarCoding::DoubleImplicit),
+        Type::Float => scalar(ScalarCoding::FloatImplicit),
+        Type::Int64 => scalar(ScalarCoding::Int64Implicit),
+        Type::Uint64 => scalar(ScalarCoding::Uint64Implicit),
+        Type::Int32 => scalar(ScalarCoding::Int32Implicit),
+        Type::Fixed64 => scalar(ScalarCoding::Fixed64Implicit),
+        Type::Fixed32 => scalar(ScalarCoding::Fixed32Implicit),
+        Type::Bool => scalar(ScalarCoding::BoolImplicit),
+        Type::String => scalar(ScalarCoding::StringUtf8Implicit),
+        Type::Bytes => scalar(ScalarCoding::BytesImplicit),
+        Type::Uint32 => scalar(ScalarCoding::Uint32Implicit),
+        Type::Sfixed32 => scalar(ScalarCoding::Sfixed32Implicit),
+        Type::Sfixed64 => scalar(ScalarCoding::Sfixed64Implicit),
+        Type::Sint32 => scalar(ScalarCoding::Sint32Implicit),
+        Type::Sint64 => scalar(ScalarCoding::Sint64Implicit),
+        Type::Enum => Ok(Coding::CompoundCoding(
+            CompoundCoding::EnumImplicit as i32 + offset,
+        )),
+        // Messages don't follow the cycle of four codings.
+        Type::Message => Ok(Coding::CompoundCoding(match field.label() {
+            Label::Repeated => CompoundCoding::MessageExpanded,
+            _ => CompoundCoding::Message,
+        } as i32)),
+        Type::Group => bail!("Field '{}' is a group (which is unsupported)", field.name()),
+    }
+}
+
+/// Number of message levels nested below a message,
+/// counted the same way as the decoder's depth limit:
+/// oneofs and map entries are transparent, but any messages within them count.
+fn message_depth(message: &Field) -> u32 {
+    message
+        .subfields
+        .iter()
+        .map(|subfield| match subfield.coding {
+            Some(Coding::CompoundCoding(coding))
+                if coding == CompoundCoding::Message as i32
+                    || coding == CompoundCoding::MessageExpanded as i32 =>
+            {
+                message_depth(subfield) + 1
+            }
+            Some(Coding::CompoundCoding(coding))
+                if coding == CompoundCoding::Oneof as i32
+                    || coding == CompoundCoding::Map as i32 =>
+            {
+                message_depth(subfield)
+            }
+            _ => 0,
+        })
+        .max()
+        .unwrap_or(0)
 }
 
 // fn compile_message(
@@ -183,7 +548,7 @@ impl MetadataFile {
 //                 }
 //             }
 //         }
-//         ProtoSyntax::Editions => todo!(),
+//         ProtoSyntax::Editions => coding += features.coding_offset(field),
 //     };
 //
 //     Ok(Field {
@@ -232,3 +597,200 @@ impl MetadataFile {
 //         }),
 //     })
 // }
+
+#[cfg(test)]
+mod tests {
+    use prost_types::{DescriptorProto, FileDescriptorProto, MessageOptions, OneofDescriptorProto};
+
+    use super::*;
+
+    fn field(r#type: Type, oneof_index: Option<i32>) -> FieldDescriptorProto {
+        let mut field = FieldDescriptorProto {
+            oneof_index,
+            ..Default::default()
+        };
+        field.set_label(Label::Optional);
+        field.set_type(r#type);
+        field
+    }
+
+    #[test]
+    fn test_oneof_variants_are_explicit() {
+        // Proto3 fields are implicit, except in a oneof.
+        assert_eq!(
+            field_coding(&field(Type::Int32, None), Features::PROTO3).unwrap(),
+            Coding::ScalarCoding(ScalarCoding::Int32Implicit as i32),
+        );
+        assert_eq!(
+            field_coding(&field(Type::Int32, Some(0)), Features::PROTO3).unwrap(),
+            Coding::ScalarCoding(ScalarCoding::Int32Explicit as i32),
+        );
+        assert_eq!(
+            field_coding(&field(Type::String, Some(1)), Features::PROTO3).unwrap(),
+            Coding::ScalarCoding(ScalarCoding::StringUtf8Explicit as i32),
+        );
+        assert_eq!(
+            field_coding(&field(Type::Enum, Some(0)), Features::PROTO3).unwrap(),
+            Coding::CompoundCoding(CompoundCoding::EnumExplicit as i32),
+        );
+        assert_eq!(
+            field_coding(&field(Type::Message, Some(0)), Features::PROTO3).unwrap(),
+            Coding::CompoundCoding(CompoundCoding::Message as i32),
+        );
+    }
+
+    #[test]
+    fn test_repeated_codings() {
+        let mut repeated = field(Type::Sint64, None);
+        repeated.set_label(Label::Repeated);
+        assert_eq!(
+            field_coding(&repeated, Features::PROTO3).unwrap(),
+            Coding::ScalarCoding(ScalarCoding::Sint64Packed as i32),
+        );
+        assert_eq!(
+            field_coding(&repeated, Features::PROTO2).unwrap(),
+            Coding::ScalarCoding(ScalarCoding::Sint64Expanded as i32),
+        );
+        repeated.set_type(Type::Message);
+        assert_eq!(
+            field_coding(&repeated, Features::PROTO3).unwrap(),
+            Coding::CompoundCoding(CompoundCoding::MessageExpanded as i32),
+        );
+    }
+
+    fn error(case: &str, code: i32) -> MethodError {
+        MethodError {
+            case: Some(String::from(case)),
+            code: Some(code),
+        }
+    }
+
+    #[test]
+    fn test_error_codes() {
+        let method = MethodDescriptorProto {
+            name: Some(String::from("GetUser")),
+            ..Default::default()
+        };
+        assert_eq!(
+            error_codes(&method, &[error("NOT_FOUND", 5), error("denied", 7)]).unwrap(),
+            vec![
+                ErrorCode {
+                    case: String::from("not-found"),
+                    code: 5,
+                },
+                ErrorCode {
+                    case: String::from("denied"),
+                    code: 7,
+                },
+            ],
+        );
+        assert_eq!(
+            error_codes(&method, &[error("NOT_FOUND", 5), error("not-found", 5)])
+                .unwrap_err()
+                .to_string(),
+            "Duplicate error case 'not-found' in method 'GetUser'",
+        );
+        assert_eq!(
+            error_codes(&method, &[error("OK", 0)])
+                .unwrap_err()
+                .to_string(),
+            "Error case 'ok' in method 'GetUser' has invalid status code 0",
+        );
+        assert!(error_codes(&method, &[error("", 5)]).is_err());
+    }
+
+    fn message_field(name: &str, number: i32, type_name: &str) -> FieldDescriptorProto {
+        let mut field = field(Type::Message, None);
+        field.name = Some(String::from(name));
+        field.number = Some(number);
+        field.type_name = Some(String::from(type_name));
+        field
+    }
+
+    fn message(name: &str, field: Vec<FieldDescriptorProto>) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(String::from(name)),
+            field,
+            ..Default::default()
+        }
+    }
+
+    /// The depth declared in the metadata of a service
+    /// with a method for each of the given request types.
+    fn max_depth(requests: &[&str], message_type: Vec<DescriptorProto>) -> Result<u32> {
+        let files = vec![FileDescriptorProto {
+            name: Some(String::from("test.proto")),
+            package: Some(String::from("foo.bar")),
+            syntax: Some(String::from("proto3")),
+            message_type,
+            ..Default::default()
+        }];
+        let descriptors = DescriptorMap::build(&files, &Vec::new()).unwrap();
+        let service = ServiceDescriptorProto {
+            name: Some(String::from("Service")),
+            method: requests
+                .iter()
+                .map(|request| MethodDescriptorProto {
+                    name: Some(String::from("Method")),
+                    input_type: Some(String::from(*request)),
+                    output_type: Some(String::from(".foo.bar.Leaf")),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let mut metadata = MetadataFile::default();
+        metadata.compile_service(&service, &vec!["foo", "bar"], &descriptors)?;
+        let content = metadata.generate().content.unwrap();
+        Ok(Metadata::decode(content.as_slice()).unwrap().max_depth)
+    }
+
+    #[test]
+    fn test_max_depth() {
+        let leaf = message("Leaf", vec![field(Type::Int32, None)]);
+        let inner = message("Inner", vec![message_field("leaf", 1, ".foo.bar.Leaf")]);
+        let mut entry = message(
+            "ByNameEntry",
+            vec![
+                field(Type::String, None),
+                message_field("value", 2, ".foo.bar.Inner"),
+            ],
+        );
+        entry.options = Some(MessageOptions {
+            map_entry: Some(true),
+            ..Default::default()
+        });
+        let mut by_name = message_field("by_name", 1, ".foo.bar.Outer.ByNameEntry");
+        by_name.set_label(Label::Repeated);
+        let mut choice = message_field("choice", 2, ".foo.bar.Inner");
+        choice.oneof_index = Some(0);
+        let mut outer = message("Outer", vec![by_name, choice]);
+        outer.nested_type = vec![entry];
+        outer.oneof_decl = vec![OneofDescriptorProto {
+            name: Some(String::from("either")),
+            ..Default::default()
+        }];
+        let messages = vec![leaf, inner, outer];
+
+        assert_eq!(max_depth(&[".foo.bar.Leaf"], messages.clone()).unwrap(), 0);
+        assert_eq!(max_depth(&[".foo.bar.Inner"], messages.clone()).unwrap(), 1);
+        // Map entries are repeated messages, like the generated WIT records,
+        // but oneofs are transparent.
+        assert_eq!(max_depth(&[".foo.bar.Outer"], messages.clone()).unwrap(), 3);
+        assert_eq!(
+            max_depth(&[".foo.bar.Leaf", ".foo.bar.Outer"], messages.clone()).unwrap(),
+            3,
+        );
+
+        // Recursive types have no finite representation.
+        let mut recursive = messages;
+        recursive.push(message(
+            "Node",
+            vec![message_field("next", 1, ".foo.bar.Node")],
+        ));
+        assert!(max_depth(&[".foo.bar.Node"], recursive)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Recursive message type"));
+    }
+}
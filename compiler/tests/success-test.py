@@ -4,7 +4,10 @@ from os.path import join as joinPath
 from typing import Callable
 from unittest import TestCase, main
 
+from google.protobuf.text_format import Parse
+
 from compiler.tests.util import protoc
+from runtime.metadata_pb2 import Metadata
 
 DATA_PATH = joinPath('compiler', 'tests', 'data')
 
@@ -12,7 +15,10 @@ DATA_PATH = joinPath('compiler', 'tests', 'data')
 # The test class is populated dynamically
 # based on the content of the test data directory.
 class ProtocPluginTest(TestCase):
-    pass
+    def test_examples_require_option(self):
+        result = protoc(joinPath(DATA_PATH, 'example-types.proto'))
+        for method in Metadata.FromString(result.metadata).service[0].methods.values():
+            self.assertEqual(len(method.examples), 0)
 
 
 def generateTestCase(rootName: str) -> Callable[[TestCase], None]:
@@ -24,13 +30,24 @@ def generateTestCase(rootName: str) -> Callable[[TestCase], None]:
         protoFile = joinPath(DATA_PATH, f'{rootName}.proto')
         self.assertTrue(exists(protoFile), f"File '{protoFile}' is missing")
 
-        result = protoc(protoFile)
+        # Examples are only recorded on request.
+        result = protoc(protoFile, options=['examples'])
 
         # Display unmatching outputs in their entirety; not just the lines that differ.
         self.maxDiff = None
         with open(witFile, 'r') as expectedWit:
             self.assertEqual(result.wit, expectedWit.read())
 
+        # Expected metadata is optional, in text format.
+        # Compare parsed messages, since map entries may be encoded in any order.
+        metadataFile = joinPath(DATA_PATH, f'{rootName}.txtpb')
+        if exists(metadataFile):
+            with open(metadataFile, 'r') as expectedMetadata:
+                self.assertEqual(
+                    Metadata.FromString(result.metadata),
+                    Parse(expectedMetadata.read(), Metadata()),
+                )
+
     return testCase
 
 
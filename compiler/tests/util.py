@@ -6,14 +6,18 @@ from tempfile import TemporaryDirectory
 
 PROTOC_PATH = joinPath('..', 'protobuf+', 'protoc')
 PLUGIN_PATH = joinPath('compiler', 'protoc-gen-vimana')
+# Imports resolve against the workspace root (e.g. `compiler/options.proto`)
+# and the well-known types (e.g. `google/protobuf/descriptor.proto`).
+DEFAULT_INCLUDE = ['.', joinPath('..', 'protobuf+', 'src')]
 
 
 @dataclass(kw_only=True)
 class ProtocOutput:
     wit: str
+    metadata: bytes
 
 
-def protoc(*files, include=None) -> ProtocOutput:
+def protoc(*files, include=None, options=None) -> ProtocOutput:
     """
     Helper method to invoke `protoc` with the Vimana plugin.
     """
@@ -24,7 +28,8 @@ def protoc(*files, include=None) -> ProtocOutput:
                 f'--plugin={abspath(PLUGIN_PATH)}',
                 f'--vimana_out={output}',
             ]
-            + [f'--proto_path={path}' for path in (include or [])]
+            + [f'--vimana_opt={option}' for option in options or []]
+            + [f'--proto_path={path}' for path in DEFAULT_INCLUDE + (include or [])]
             + list(files)
         )
         if (status := Popen(args).wait()) != 0:
@@ -32,4 +37,6 @@ def protoc(*files, include=None) -> ProtocOutput:
 
         with open(joinPath(output, 'server.wit'), 'r') as witFile:
             wit = witFile.read()
-        return ProtocOutput(wit=wit)
+        with open(joinPath(output, 'metadata.binpb'), 'rb') as metadataFile:
+            metadata = metadataFile.read()
+        return ProtocOutput(wit=wit, metadata=metadata)
@@ -1,21 +1,27 @@
 use std::cell::OnceCell;
 use std::collections::{HashMap, HashSet};
 use std::default::Default;
+use std::ops::RangeInclusive;
 
-use anyhow::{bail, Result};
+use anyhow::{anyhow, bail, Result};
 use heck::ToKebabCase;
 use prost_types::compiler::code_generator_response::File;
 use prost_types::field_descriptor_proto::{Label, Type as ProtoType};
-use prost_types::{DescriptorProto, EnumDescriptorProto, ServiceDescriptorProto};
+use prost_types::generated_code_info::Annotation;
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, FieldDescriptorProto, GeneratedCodeInfo,
+    ServiceDescriptorProto,
+};
 use wit_encoder::{
     Enum, Field, Ident, Include, Interface, NestedPackage, Package, PackageName, Record,
-    StandaloneFunc, Type as WitType, TypeDef as WitTypeDef, TypeDefKind as WitTypeDefKind, World,
-    WorldItem,
+    ResourceFunc, StandaloneFunc, Type as WitType, TypeDef as WitTypeDef,
+    TypeDefKind as WitTypeDefKind, VariantCase, World, WorldItem,
 };
 
+use crate::features::{EnumType, Features, FieldPresence};
 use crate::{
-    sorted_map_entries, sorted_set_values, DescriptorMap, ProtoSyntax, QualifiedTypeName,
-    TypeNameQualifier, VIMANA_API_VERSION, WASI_API_VERSION,
+    sorted_map_entries, sorted_set_values, DescriptorMap, QualifiedTypeName, TypeNameQualifier,
+    MESSAGE_FIELD_TAG, MESSAGE_ONEOF_DECL_TAG, VIMANA_API_VERSION, WASI_API_VERSION,
 };
 
 /// Name of the generated WIT file in the output directory.
@@ -33,6 +39,44 @@ const WORLD_NAME: &str = "server";
 const TYPES_INTERFACE_NAME: &str = "types";
 
 const REQUEST_PARAMETER_NAME: &str = "request";
+/// Suffix of the name of the error enum of a method that declares errors
+/// (e.g. `get-user-error` for the method `GetUser`).
+const ERROR_TYPE_SUFFIX: &str = "error";
+/// Case of an open enum's variant that carries any variant number the enum does not declare.
+const UNRECOGNIZED_CASE: &str = "unrecognized";
+
+/// Largest valid Protobuf field number (2^29 - 1).
+const MAX_FIELD_NUMBER: i32 = (1 << 29) - 1;
+/// Field numbers reserved for the Protobuf implementation itself.
+const IMPLEMENTATION_RESERVED_FIELD_NUMBERS: RangeInclusive<i32> = 19000..=19999;
+
+/// A WIT variant generated from a Protobuf oneof.
+///
+/// The variant is scoped to the message that declares the oneof,
+/// the same way nested messages are,
+/// so it belongs to the interface qualified by that message's name.
+struct OneofDefinition<'a> {
+    /// Fully-qualified name of the variant.
+    name: QualifiedTypeName<'a>,
+    /// One case per field in the oneof.
+    cases: Vec<VariantCase>,
+    /// Types referenced by the cases.
+    types_used: Vec<QualifiedTypeName<'a>>,
+}
+
+/// Where a WIT record was generated from,
+/// to annotate the generated code with the Protobuf declarations behind it.
+struct RecordSource<'a> {
+    /// Protobuf name of the message.
+    name: &'a str,
+    /// Name of the file declaring the message.
+    file: &'a str,
+    /// Path to the message declaration within the file.
+    path: Vec<i32>,
+    /// Name of each field of the record,
+    /// with the path to the Protobuf field or oneof that it was generated from.
+    fields: Vec<(String, Vec<i32>)>,
+}
 
 /// An incrementally-built model of a Vimana server WIT file,
 /// generated from Protobuf service and type definitions.
@@ -45,6 +89,9 @@ pub(crate) struct WitFile<'a> {
     /// Set to keep track of all types compiled so far,
     /// so we don't compile the same type twice.
     types_compiled: HashSet<QualifiedTypeName<'a>>,
+    /// Sources of the records compiled so far,
+    /// organized by the interface in which each record is defined.
+    record_sources: HashMap<TypeNameQualifier<'a>, Vec<RecordSource<'a>>>,
 }
 
 /// The compiler always generates a single 'server' world for the component to implement.
@@ -125,10 +172,14 @@ impl<'a> WitFile<'a> {
     pub(crate) fn compile_service(
         &mut self,
         service_descriptor: &'a ServiceDescriptorProto,
+        descriptors: &DescriptorMap<'a>,
     ) -> Result<()> {
         let mut service = Interface::new(service_descriptor.name().to_kebab_case());
+        let service_name = self
+            .server_package_qualifier()
+            .into_type(service_descriptor.name());
 
-        for method_descriptor in &service_descriptor.method {
+        for (index, method_descriptor) in service_descriptor.method.iter().enumerate() {
             match method_descriptor.options.as_ref() {
                 Some(options) => {
                     for option in &options.uninterpreted_option {
@@ -154,15 +205,36 @@ impl<'a> WitFile<'a> {
                 method_descriptor.output_type(),
                 self.server_package(),
             );
+            let response = WitType::Named(Ident::from(response_type.name.to_kebab_case()));
 
             let mut function = StandaloneFunc::new(method_descriptor.name().to_kebab_case(), false);
             function.set_params((
                 REQUEST_PARAMETER_NAME,
                 WitType::Named(Ident::from(request_type.name.to_kebab_case())),
             ));
-            function.set_result(Some(WitType::Named(Ident::from(
-                response_type.name.to_kebab_case(),
-            ))));
+            let errors = descriptors.get_method_errors(&service_name, index);
+            if errors.is_empty() {
+                function.set_result(Some(response));
+            } else {
+                // Methods that declare errors return a result,
+                // with an error enum defined alongside the function.
+                let error_name = format!(
+                    "{}-{ERROR_TYPE_SUFFIX}",
+                    method_descriptor.name().to_kebab_case()
+                );
+                let mut error_enum = Enum::empty();
+                for error in errors {
+                    error_enum.case(error.case().to_kebab_case());
+                }
+                service.type_def(WitTypeDef::new(
+                    error_name.clone(),
+                    WitTypeDefKind::Enum(error_enum),
+                ));
+                function.set_result(Some(WitType::result_both(
+                    response,
+                    WitType::named(error_name),
+                )));
+            }
             service.function(function);
 
             self.server_world.types_used.insert(request_type);
@@ -177,35 +249,69 @@ impl<'a> WitFile<'a> {
         &mut self,
         message_descriptor: &'a DescriptorProto,
         qualifier: &TypeNameQualifier<'a>,
-        syntax: ProtoSyntax,
         descriptors: &DescriptorMap<'a>,
     ) -> Result<()> {
         let type_name = qualifier.r#type(message_descriptor.name());
         if !self.types_compiled.contains(&type_name) {
             self.types_compiled.insert(type_name.clone());
 
-            let (type_definition, types_used) =
-                self.message_type_definition(message_descriptor, type_name.name, syntax)?;
+            let (_, field_features) = descriptors
+                .get_message(&type_name)
+                .ok_or_else(|| anyhow!("Type not found: {type_name}"))?;
+
+            if descriptors.is_resource(&type_name) {
+                // The runtime manages the resource on the component's behalf,
+                // so its fields never reach the component (nor its dependencies).
+                let definition = WitTypeDef::resource(
+                    message_descriptor.name().to_kebab_case(),
+                    [ResourceFunc::constructor()],
+                );
+                self.upsert_type_definition(type_name.qualifier, definition, Vec::new());
+                return Ok(());
+            }
+
+            for warning in check_field_numbers(message_descriptor)? {
+                // Plugins have no way to return warnings to `protoc`,
+                // but anything written to standard error is shown to the user.
+                eprintln!("Warning: {warning}");
+            }
+
+            let (type_definition, mut types_used, oneofs) = self.message_type_definition(
+                message_descriptor,
+                &type_name,
+                field_features,
+                descriptors,
+            )?;
 
-            for type_used in &types_used {
+            let oneof_types_used = oneofs.iter().flat_map(|oneof| &oneof.types_used);
+            for type_used in types_used.iter().chain(oneof_types_used) {
                 // Check if it's a message type first
-                if let Some((depended_descriptor, depended_syntax)) =
-                    descriptors.get_message(type_used)
-                {
+                if let Some((depended_descriptor, _)) = descriptors.get_message(type_used) {
                     // Recursively compile message dependencies
-                    self.compile_message(
-                        depended_descriptor,
-                        &type_used.qualifier,
-                        depended_syntax,
-                        descriptors,
-                    )?;
-                } else if let Some(enum_descriptor) = descriptors.get_enum(type_used) {
-                    self.compile_enum(enum_descriptor, &type_used.qualifier);
+                    self.compile_message(depended_descriptor, &type_used.qualifier, descriptors)?;
+                } else if let Some((enum_descriptor, enum_type)) = descriptors.get_enum(type_used) {
+                    self.compile_enum(enum_descriptor, enum_type, &type_used.qualifier)?;
                 } else {
                     bail!("Type not found: {type_used}");
                 }
             }
 
+            for oneof in oneofs {
+                let definition = WitTypeDef::variant(oneof.name.name.to_kebab_case(), oneof.cases);
+                types_used.push(oneof.name.clone());
+                self.upsert_type_definition(oneof.name.qualifier, definition, oneof.types_used);
+            }
+            if let Some((file, path)) = descriptors.get_source(&type_name) {
+                self.record_sources
+                    .entry(type_name.qualifier.clone())
+                    .or_default()
+                    .push(RecordSource {
+                        name: type_name.name,
+                        file,
+                        path: path.to_vec(),
+                        fields: record_field_paths(message_descriptor, path),
+                    });
+            }
             self.upsert_type_definition(type_name.qualifier, type_definition, types_used);
         }
 
@@ -215,15 +321,18 @@ impl<'a> WitFile<'a> {
     fn compile_enum(
         &mut self,
         enum_descriptor: &'a EnumDescriptorProto,
+        enum_type: EnumType,
         qualifier: &TypeNameQualifier<'a>,
-    ) {
+    ) -> Result<()> {
         let type_name = qualifier.r#type(enum_descriptor.name());
         if !self.types_compiled.contains(&type_name) {
             self.types_compiled.insert(type_name.clone());
 
-            let type_definition = self.enum_type_definition(enum_descriptor, type_name.name);
+            let type_definition =
+                self.enum_type_definition(enum_descriptor, enum_type, type_name.name)?;
             self.upsert_type_definition(type_name.qualifier, type_definition, Vec::new());
         }
+        Ok(())
     }
 
     fn upsert_type_definition(
@@ -251,6 +360,7 @@ impl<'a> WitFile<'a> {
 
     pub(crate) fn generate(mut self) -> Result<File> {
         let mut wit_contents = String::new();
+        let mut annotations = Vec::new();
 
         let mut server_package = Package::new(self.server_package_name());
         let server_package_qualifier = self.server_package_qualifier();
@@ -260,100 +370,213 @@ impl<'a> WitFile<'a> {
         {
             server_package.interface(server_package_types_interface.into_interface());
         }
-        wit_contents.push_str(server_package.to_string().as_str());
+        let server_package = server_package.to_string();
+        if let Some(sources) = self.record_sources.remove(&server_package_qualifier) {
+            annotate_records(&server_package, 0, sources, &mut annotations);
+        }
+        wit_contents.push_str(server_package.as_str());
 
         for (name_qualifier, types_interface) in sorted_map_entries(self.types_interfaces) {
             wit_contents.push('\n');
-            wit_contents.push_str(
-                types_interface
-                    .into_nested_package(name_qualifier)
-                    .to_string()
-                    .as_str(),
-            );
+            let sources = self.record_sources.remove(&name_qualifier);
+            let nested_package = types_interface
+                .into_nested_package(name_qualifier)
+                .to_string();
+            if let Some(sources) = sources {
+                annotate_records(
+                    &nested_package,
+                    wit_contents.len(),
+                    sources,
+                    &mut annotations,
+                );
+            }
+            wit_contents.push_str(nested_package.as_str());
         }
 
+        annotations.sort_by_key(|annotation| annotation.begin);
         Ok(File {
             name: Some(String::from(FILENAME)),
             insertion_point: None,
             content: Some(wit_contents),
-            // TODO: Add generated code info to help with debugging.
-            generated_code_info: None,
+            generated_code_info: Some(GeneratedCodeInfo {
+                annotation: annotations,
+            }),
         })
     }
 
     fn message_type_definition(
         &self,
         descriptor: &'a DescriptorProto,
-        name: &'a str,
-        syntax: ProtoSyntax,
-    ) -> Result<(WitTypeDef, Vec<QualifiedTypeName<'a>>)> {
+        type_name: &QualifiedTypeName<'a>,
+        field_features: &[Features],
+        descriptors: &DescriptorMap<'a>,
+    ) -> Result<(
+        WitTypeDef,
+        Vec<QualifiedTypeName<'a>>,
+        Vec<OneofDefinition<'a>>,
+    )> {
         let mut wit_fields: Vec<Field> = Vec::with_capacity(descriptor.field.len());
         let mut types_used: Vec<QualifiedTypeName> = Vec::new();
-        for proto_field in &descriptor.field {
-            let mut wit_type = match proto_field.r#type() {
-                ProtoType::Double => WitType::F64,
-                ProtoType::Float => WitType::F32,
-                ProtoType::Int64 => WitType::S64,
-                ProtoType::Uint64 => WitType::U64,
-                ProtoType::Int32 => WitType::S32,
-                ProtoType::Fixed64 => WitType::U64,
-                ProtoType::Fixed32 => WitType::U32,
-                ProtoType::Bool => WitType::Bool,
-                ProtoType::String => WitType::String,
-                ProtoType::Message | ProtoType::Enum => {
-                    let type_name = QualifiedTypeName::from_path(
-                        proto_field.type_name(),
-                        self.server_package(),
+
+        let nested_qualifier = type_name.qualifier.nested(type_name.name);
+        let mut oneofs: Vec<OneofDefinition> = descriptor
+            .oneof_decl
+            .iter()
+            .map(|oneof_descriptor| OneofDefinition {
+                name: nested_qualifier.r#type(oneof_descriptor.name()),
+                cases: Vec::new(),
+                types_used: Vec::new(),
+            })
+            .collect();
+
+        for (proto_field, features) in descriptor.field.iter().zip(field_features) {
+            // Proto3 `optional` fields each belong to a synthetic oneof,
+            // but they're just ordinary fields with explicit presence.
+            if let (Some(index), false) = (proto_field.oneof_index, proto_field.proto3_optional()) {
+                let Some(oneof) = oneofs.get_mut(index as usize) else {
+                    bail!(
+                        "Field '{}' in '{}' has an unknown oneof index {index}",
+                        proto_field.name(),
+                        type_name.name,
                     );
-                    let wit_short_name = type_name.name.to_kebab_case();
-                    types_used.push(type_name);
-                    WitType::named(wit_short_name)
-                }
-                ProtoType::Bytes => WitType::list(WitType::U8),
-                ProtoType::Uint32 => WitType::U32,
-                ProtoType::Sfixed32 => WitType::S32,
-                ProtoType::Sfixed64 => WitType::S64,
-                ProtoType::Sint32 => WitType::S32,
-                ProtoType::Sint64 => WitType::S64,
-                ProtoType::Group => {
-                    bail!("Protobuf groups are not supported; use nested messages instead")
-                }
-            };
-            wit_type = match proto_field.label() {
-                Label::Optional => {
-                    if syntax == ProtoSyntax::Proto2 || proto_field.proto3_optional() {
-                        WitType::option(wit_type)
+                };
+                let case_name = proto_field.name().to_kebab_case();
+                oneof
+                    .cases
+                    .push(if self.is_empty_message(proto_field, descriptors) {
+                        // WIT records cannot be empty,
+                        // so an empty message (like `google.protobuf.Empty`) is a case without a payload.
+                        VariantCase::empty(case_name)
                     } else {
-                        wit_type
-                    }
-                }
-                Label::Required => {
-                    // YAGNI (this is proto2-only syntax that's highly discouraged).
+                        VariantCase::value(
+                            case_name,
+                            self.field_type(proto_field, &mut oneof.types_used)?,
+                        )
+                    });
+                continue;
+            }
+
+            let mut wit_type = self.field_type(proto_field, &mut types_used)?;
+            wit_type = match (proto_field.label(), features.field_presence) {
+                (Label::Required, _) | (_, FieldPresence::LegacyRequired) => {
+                    // YAGNI (this is legacy syntax that's highly discouraged).
                     bail!("Required fields are not supported");
                 }
-                Label::Repeated => WitType::list(wit_type),
+                (Label::Optional, FieldPresence::Explicit) => WitType::option(wit_type),
+                (Label::Optional, FieldPresence::Implicit) => wit_type,
+                (Label::Repeated, _) => WitType::list(wit_type),
             };
             wit_fields.push(Field::new(proto_field.name().to_kebab_case(), wit_type));
         }
+
+        // Synthetic oneofs end up without any cases.
+        oneofs.retain(|oneof| !oneof.cases.is_empty());
+        // Each oneof follows the ordinary fields as a single field, absent if no case is set.
+        for oneof in &oneofs {
+            let name = oneof.name.name.to_kebab_case();
+            wit_fields.push(Field::new(
+                name.clone(),
+                WitType::option(WitType::named(name)),
+            ));
+        }
+
         Ok((
             WitTypeDef::new(
-                name.to_kebab_case(),
+                type_name.name.to_kebab_case(),
                 WitTypeDefKind::Record(Record::new(wit_fields)),
             ),
             types_used,
+            oneofs,
         ))
     }
 
+    /// The WIT type of a single value of a field,
+    /// recording any named type that it references in `types_used`.
+    fn field_type(
+        &self,
+        proto_field: &'a FieldDescriptorProto,
+        types_used: &mut Vec<QualifiedTypeName<'a>>,
+    ) -> Result<WitType> {
+        Ok(match proto_field.r#type() {
+            ProtoType::Double => WitType::F64,
+            ProtoType::Float => WitType::F32,
+            ProtoType::Int64 => WitType::S64,
+            ProtoType::Uint64 => WitType::U64,
+            ProtoType::Int32 => WitType::S32,
+            ProtoType::Fixed64 => WitType::U64,
+            ProtoType::Fixed32 => WitType::U32,
+            ProtoType::Bool => WitType::Bool,
+            ProtoType::String => WitType::String,
+            ProtoType::Message | ProtoType::Enum => {
+                let type_name =
+                    QualifiedTypeName::from_path(proto_field.type_name(), self.server_package());
+                let wit_short_name = type_name.name.to_kebab_case();
+                types_used.push(type_name);
+                WitType::named(wit_short_name)
+            }
+            ProtoType::Bytes => WitType::list(WitType::U8),
+            ProtoType::Uint32 => WitType::U32,
+            ProtoType::Sfixed32 => WitType::S32,
+            ProtoType::Sfixed64 => WitType::S64,
+            ProtoType::Sint32 => WitType::S32,
+            ProtoType::Sint64 => WitType::S64,
+            ProtoType::Group => {
+                bail!("Protobuf groups are not supported; use nested messages instead")
+            }
+        })
+    }
+
+    /// Whether a field's type is a message with no fields.
+    fn is_empty_message(
+        &self,
+        proto_field: &'a FieldDescriptorProto,
+        descriptors: &DescriptorMap<'a>,
+    ) -> bool {
+        proto_field.r#type() == ProtoType::Message
+            && descriptors
+                .get_message(&QualifiedTypeName::from_path(
+                    proto_field.type_name(),
+                    self.server_package(),
+                ))
+                .is_some_and(|(descriptor, _)| descriptor.field.is_empty())
+    }
+
+    /// Closed enums become WIT enums.
+    /// Open enums must also represent variant numbers they do not declare,
+    /// so they become WIT variants with an extra case carrying the number.
     fn enum_type_definition(
         &self,
         enum_descriptor: &'a EnumDescriptorProto,
+        enum_type: EnumType,
         name: &'a str,
-    ) -> WitTypeDef {
-        let mut wit_enum = Enum::empty();
-        for variant in &enum_descriptor.value {
-            wit_enum.case(variant.name().to_kebab_case());
-        }
-        WitTypeDef::new(name.to_kebab_case(), WitTypeDefKind::Enum(wit_enum))
+    ) -> Result<WitTypeDef> {
+        let case_names = enum_descriptor
+            .value
+            .iter()
+            .map(|variant| variant.name().to_kebab_case());
+        Ok(match enum_type {
+            EnumType::Closed => {
+                let mut wit_enum = Enum::empty();
+                for case_name in case_names {
+                    wit_enum.case(case_name);
+                }
+                WitTypeDef::new(name.to_kebab_case(), WitTypeDefKind::Enum(wit_enum))
+            }
+            EnumType::Open => {
+                let mut cases = Vec::with_capacity(enum_descriptor.value.len() + 1);
+                for case_name in case_names {
+                    if case_name == UNRECOGNIZED_CASE {
+                        bail!(
+                            "Open enum '{}' cannot declare a variant named '{UNRECOGNIZED_CASE}'",
+                            enum_descriptor.name(),
+                        );
+                    }
+                    cases.push(VariantCase::empty(case_name));
+                }
+                cases.push(VariantCase::value(UNRECOGNIZED_CASE, WitType::S32));
+                WitTypeDef::variant(name.to_kebab_case(), cases)
+            }
+        })
     }
 }
 
@@ -428,3 +651,306 @@ impl<'a> TypeNameQualifier<'a> {
         package_name
     }
 }
+
+/// Name each field of the record generated from a message
+/// (see [`WitFile::message_type_definition`]),
+/// along with the path to the Protobuf field or oneof that it was generated from.
+/// `path` is the path to the message itself.
+fn record_field_paths(descriptor: &DescriptorProto, path: &[i32]) -> Vec<(String, Vec<i32>)> {
+    let child_path = |tag: i32, index: usize| [path, &[tag, index as i32]].concat();
+    let mut fields: Vec<(String, Vec<i32>)> = descriptor
+        .field
+        .iter()
+        .enumerate()
+        .filter(|(_, field)| field.oneof_index.is_none() || field.proto3_optional())
+        .map(|(index, field)| {
+            (
+                field.name().to_kebab_case(),
+                child_path(MESSAGE_FIELD_TAG, index),
+            )
+        })
+        .collect();
+    // Synthetic oneofs don't generate a field.
+    for (index, oneof) in descriptor.oneof_decl.iter().enumerate() {
+        if descriptor
+            .field
+            .iter()
+            .any(|field| field.oneof_index == Some(index as i32) && !field.proto3_optional())
+        {
+            fields.push((
+                oneof.name().to_kebab_case(),
+                child_path(MESSAGE_ONEOF_DECL_TAG, index),
+            ));
+        }
+    }
+    fields
+}
+
+/// Annotate each record in a rendered WIT package with the message it was generated from,
+/// and each field of the record with the Protobuf field or oneof it was generated from.
+/// `offset` is the position of the package within the generated file.
+fn annotate_records(
+    package: &str,
+    offset: usize,
+    sources: Vec<RecordSource>,
+    annotations: &mut Vec<Annotation>,
+) {
+    let annotation = |path: Vec<i32>, file: &str, begin: usize, end: usize| Annotation {
+        path,
+        source_file: Some(String::from(file)),
+        begin: Some((offset + begin) as i32),
+        end: Some((offset + end) as i32),
+    };
+    for source in sources {
+        // Records are rendered with one field per line, up to the closing brace,
+        // and no field type contains a brace.
+        let header = format!("record {} {{", Ident::from(source.name.to_kebab_case()));
+        let Some(begin) = package.find(header.as_str()) else {
+            continue;
+        };
+        let Some(length) = package[begin..].find('}') else {
+            continue;
+        };
+        let end = begin + length + 1;
+        let fields: HashMap<String, Vec<i32>> = source
+            .fields
+            .into_iter()
+            .map(|(name, path)| (Ident::from(name).to_string(), path))
+            .collect();
+
+        let mut line_begin = begin + header.len();
+        for line in package[line_begin..end].split_inclusive('\n') {
+            let field = line.trim();
+            let field_begin = line_begin + line.find(field).unwrap_or_default();
+            line_begin += line.len();
+            let Some(path) = field
+                .split_once(':')
+                .and_then(|(name, _)| fields.get(name.trim()))
+            else {
+                continue;
+            };
+            let field = field.trim_end_matches(',');
+            annotations.push(annotation(
+                path.clone(),
+                source.file,
+                field_begin,
+                field_begin + field.len(),
+            ));
+        }
+        annotations.push(annotation(source.path, source.file, begin, end));
+    }
+}
+
+/// Validate the field numbers in a message.
+///
+/// Field numbers outside the valid range, or in the range reserved for the Protobuf implementation,
+/// are rejected with an error.
+/// Field numbers that the message itself declares `reserved` are allowed,
+/// but each one is returned as a warning.
+fn check_field_numbers(descriptor: &DescriptorProto) -> Result<Vec<String>> {
+    let message_name = descriptor.name();
+    let mut warnings = Vec::new();
+    for field in &descriptor.field {
+        let field_name = field.name();
+        let number = field.number();
+        if !(1..=MAX_FIELD_NUMBER).contains(&number) {
+            bail!(
+                "Field '{field_name}' in '{message_name}' has number {number}, \
+                outside the valid range 1 to {MAX_FIELD_NUMBER}"
+            );
+        }
+        if IMPLEMENTATION_RESERVED_FIELD_NUMBERS.contains(&number) {
+            bail!(
+                "Field '{field_name}' in '{message_name}' has number {number}, \
+                which is reserved for the Protobuf implementation ({}-{})",
+                IMPLEMENTATION_RESERVED_FIELD_NUMBERS.start(),
+                IMPLEMENTATION_RESERVED_FIELD_NUMBERS.end(),
+            );
+        }
+        // Reserved ranges are half-open: `end` is exclusive.
+        if descriptor
+            .reserved_range
+            .iter()
+            .any(|range| range.start() <= number && number < range.end())
+        {
+            warnings.push(format!(
+                "Field '{field_name}' in '{message_name}' reuses reserved number {number}"
+            ));
+        }
+    }
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use prost_types::descriptor_proto::ReservedRange;
+    use prost_types::{
+        EnumValueDescriptorProto, FieldDescriptorProto, FileDescriptorProto, OneofDescriptorProto,
+    };
+
+    use super::*;
+
+    fn message(numbers: &[i32], reserved: &[(i32, i32)]) -> DescriptorProto {
+        DescriptorProto {
+            name: Some(String::from("SomeMessage")),
+            field: numbers
+                .iter()
+                .map(|number| FieldDescriptorProto {
+                    name: Some(format!("field_{number}")),
+                    number: Some(*number),
+                    ..Default::default()
+                })
+                .collect(),
+            reserved_range: reserved
+                .iter()
+                .map(|(start, end)| ReservedRange {
+                    start: Some(*start),
+                    end: Some(*end),
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_field_numbers_valid() {
+        let warnings = check_field_numbers(&message(&[1, 18999, 20000, MAX_FIELD_NUMBER], &[]));
+        assert!(warnings.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_field_number_implementation_reserved() {
+        let error = check_field_numbers(&message(&[1, 19500], &[])).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Field 'field_19500' in 'SomeMessage' has number 19500, \
+            which is reserved for the Protobuf implementation (19000-19999)",
+        );
+    }
+
+    #[test]
+    fn test_field_number_too_large() {
+        assert!(check_field_numbers(&message(&[MAX_FIELD_NUMBER + 1], &[])).is_err());
+        assert!(check_field_numbers(&message(&[0], &[])).is_err());
+    }
+
+    #[test]
+    fn test_field_number_declared_reserved() {
+        // Reserves 5, 6, and 7.
+        let warnings = check_field_numbers(&message(&[4, 7, 8], &[(5, 8)])).unwrap();
+        assert_eq!(
+            warnings,
+            vec![String::from(
+                "Field 'field_7' in 'SomeMessage' reuses reserved number 7"
+            )],
+        );
+    }
+
+    #[test]
+    fn test_open_enum_unrecognized_variant() {
+        let enum_descriptor = EnumDescriptorProto {
+            name: Some(String::from("Status")),
+            value: ["UNKNOWN", "UNRECOGNIZED"]
+                .iter()
+                .map(|name| EnumValueDescriptorProto {
+                    name: Some(String::from(*name)),
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        };
+        let wit_file = WitFile::default();
+
+        // Closed enums never need the extra case.
+        assert!(wit_file
+            .enum_type_definition(&enum_descriptor, EnumType::Closed, "Status")
+            .is_ok());
+        assert_eq!(
+            wit_file
+                .enum_type_definition(&enum_descriptor, EnumType::Open, "Status")
+                .unwrap_err()
+                .to_string(),
+            "Open enum 'Status' cannot declare a variant named 'unrecognized'",
+        );
+    }
+
+    #[test]
+    fn test_generated_code_info() {
+        let field = |name: &str, r#type: ProtoType, oneof_index: Option<i32>| {
+            let mut field = FieldDescriptorProto {
+                name: Some(String::from(name)),
+                number: Some(1),
+                oneof_index,
+                ..Default::default()
+            };
+            field.set_label(Label::Optional);
+            field.set_type(r#type);
+            field
+        };
+        let mut inner = field("inner", ProtoType::Message, None);
+        inner.type_name = Some(String::from(".foo.bar.Outer.Inner"));
+        let files = vec![FileDescriptorProto {
+            name: Some(String::from("test.proto")),
+            package: Some(String::from("foo.bar")),
+            syntax: Some(String::from("proto3")),
+            message_type: vec![DescriptorProto {
+                name: Some(String::from("Outer")),
+                field: vec![
+                    field("some_name", ProtoType::String, None),
+                    field("choice_a", ProtoType::Int32, Some(0)),
+                    inner,
+                ],
+                nested_type: vec![DescriptorProto {
+                    name: Some(String::from("Inner")),
+                    field: vec![field("type", ProtoType::Bool, None)],
+                    ..Default::default()
+                }],
+                oneof_decl: vec![OneofDescriptorProto {
+                    name: Some(String::from("choice")),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        }];
+        let descriptors = DescriptorMap::build(&files, &Vec::new()).unwrap();
+
+        let mut wit_file = WitFile::default();
+        let package = wit_file.set_or_check_server_package("foo.bar").unwrap();
+        wit_file
+            .compile_message(
+                &files[0].message_type[0],
+                &TypeNameQualifier::top_level(package),
+                &descriptors,
+            )
+            .unwrap();
+        let file = wit_file.generate().unwrap();
+
+        let content = file.content.unwrap();
+        let annotations: Vec<(Vec<i32>, &str)> = file
+            .generated_code_info
+            .unwrap()
+            .annotation
+            .into_iter()
+            .map(|annotation| {
+                assert_eq!(annotation.source_file(), "test.proto");
+                let range = annotation.begin() as usize..annotation.end() as usize;
+                (annotation.path, &content[range])
+            })
+            .collect();
+        assert_eq!(
+            annotations,
+            vec![
+                (
+                    vec![4, 0],
+                    "record outer {\n    some-name: string,\n    inner: inner,\n    choice: option<choice>,\n  }",
+                ),
+                (vec![4, 0, 2, 0], "some-name: string"),
+                (vec![4, 0, 2, 2], "inner: inner"),
+                (vec![4, 0, 8, 0], "choice: option<choice>"),
+                (vec![4, 0, 3, 0], "record inner {\n      %type: bool,\n    }"),
+                (vec![4, 0, 3, 0, 2, 0], "%type: bool"),
+            ],
+        );
+    }
+}
@@ -0,0 +1,430 @@
+//! Limits on concurrent requests to each pod, admitted in order of priority.
+//!
+//! Every node may cap the number of requests each pod handles at once,
+//! and a component may lower or raise that cap for its own pods in its metadata.
+//! Requests beyond the cap wait in a queue until an earlier request finishes.
+//! A pod may mark some of its methods as more urgent than others
+//! by annotating the pod sandbox with comma-separated `<service>/<method>=<priority>` pairs:
+//!
+//!     vimana.host/request-priorities: shop.Checkout/Pay=9,shop.Reports/Export=0
+//!
+//! A saturated pod admits its most urgent waiting requests first,
+//! and requests of equal priority in the order they arrived.
+//!
+//! Running requests are preempted at every [epoch tick](EPOCH_TICK):
+//! while a more urgent request is waiting, a less urgent running request
+//! hands over its slot and is suspended until it gets a slot back.
+
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::sync::oneshot::{channel, Receiver, Sender};
+use tokio::time::interval;
+use wasmtime::Engine as WasmEngine;
+
+/// Pod annotation assigning [priorities](MethodPriorities) to the pod's methods.
+pub(crate) const PRIORITIES_ANNOTATION: &str = "vimana.host/request-priorities";
+
+/// How often the Wasm engine's epoch advances.
+/// Running instances reach a preemption point (or at least yield to other tasks) once per tick.
+pub(crate) const EPOCH_TICK: Duration = Duration::from_millis(10);
+
+/// Priorities of a pod's methods, from 0 (the default) to 255,
+/// keyed by `<service>/<method>`.
+/// Higher numbers are more urgent.
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct MethodPriorities(HashMap<String, u8>);
+
+impl MethodPriorities {
+    /// Return the priority of a method, or the default if it has none.
+    pub(crate) fn get(&self, service: &str, method: &str) -> u8 {
+        self.0
+            .get(&format!("{service}/{method}"))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+/// Parse the pod's [method priorities](MethodPriorities) from its annotations.
+pub(crate) fn method_priorities(
+    annotations: &HashMap<String, String>,
+) -> Result<Arc<MethodPriorities>> {
+    let Some(pairs) = annotations.get(PRIORITIES_ANNOTATION) else {
+        return Ok(Arc::default());
+    };
+    pairs
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let (method, priority) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("Missing priority for {:?}", pair.trim()))?;
+            let priority = priority
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid priority for {:?}", method.trim()))?;
+            Ok((String::from(method.trim()), priority))
+        })
+        .collect::<Result<HashMap<_, _>>>()
+        .map(|priorities| Arc::new(MethodPriorities(priorities)))
+        .with_context(|| format!("Invalid {PRIORITIES_ANNOTATION:?} annotation"))
+}
+
+/// Return the request limit for a pod:
+/// the component's own limit from its metadata (if non-zero),
+/// otherwise the node's default limit (if any).
+pub(crate) fn max_concurrent_requests(component: u32, node: Option<usize>) -> Option<usize> {
+    match component {
+        0 => node,
+        limit => Some(limit as usize),
+    }
+}
+
+/// Advance the epoch of the Wasm engine every [`EPOCH_TICK`], forever.
+pub(crate) async fn tick_epochs(wasmtime: WasmEngine) {
+    let mut ticks = interval(EPOCH_TICK);
+    loop {
+        ticks.tick().await;
+        wasmtime.increment_epoch();
+    }
+}
+
+/// Admits a limited number of requests to a pod at once, most urgent first.
+/// Shared by every method of the pod.
+#[derive(Clone)]
+pub(crate) struct RequestQueue(Arc<Mutex<QueueState>>);
+
+/// See [`RequestQueue`].
+struct QueueState {
+    /// Number of requests that could be admitted right away.
+    available: usize,
+
+    /// Requests waiting for a slot, most urgent on top.
+    waiting: BinaryHeap<Waiter>,
+
+    /// Incremented for every request, to break ties between equal priorities by arrival.
+    arrivals: u64,
+}
+
+/// A request waiting in a [`RequestQueue`].
+struct Waiter {
+    priority: u8,
+    arrival: u64,
+
+    /// Hands the slot to the waiting request.
+    admit: Sender<()>,
+}
+
+/// Holds one of its pod's request slots until it is dropped.
+pub(crate) struct Admission {
+    queue: RequestQueue,
+    priority: u8,
+    arrival: u64,
+}
+
+/// Removes a request from the queue if it stops waiting before it is admitted,
+/// e.g. because the client cancelled it.
+struct Waiting {
+    queue: RequestQueue,
+    arrival: u64,
+
+    /// Unset once the request has been admitted.
+    admitted: Option<Receiver<()>>,
+}
+
+/// A running request's [admission](Admission),
+/// which it gives up for as long as it is [preempted](Self::preempt).
+#[derive(Clone)]
+pub(crate) struct Slot(Arc<Mutex<Option<Admission>>>);
+
+impl RequestQueue {
+    pub(crate) fn new(max_concurrent_requests: usize) -> Self {
+        Self(Arc::new(Mutex::new(QueueState {
+            available: max_concurrent_requests,
+            waiting: BinaryHeap::new(),
+            arrivals: 0,
+        })))
+    }
+
+    /// Wait until the request can be admitted.
+    pub(crate) async fn admit(&self, priority: u8) -> Admission {
+        let arrival = {
+            let mut state = self.0.lock().unwrap();
+            state.arrivals += 1;
+            state.arrivals
+        };
+        self.admit_arrival(priority, arrival).await
+    }
+
+    /// Wait until a request that arrived at `arrival` can be admitted (again).
+    async fn admit_arrival(&self, priority: u8, arrival: u64) -> Admission {
+        let admitted = {
+            let mut state = self.0.lock().unwrap();
+            if state.available > 0 {
+                state.available -= 1;
+                return Admission {
+                    queue: self.clone(),
+                    priority,
+                    arrival,
+                };
+            }
+            let (admit, admitted) = channel();
+            state.waiting.push(Waiter {
+                priority,
+                arrival,
+                admit,
+            });
+            admitted
+        };
+        let mut waiting = Waiting {
+            queue: self.clone(),
+            arrival,
+            admitted: Some(admitted),
+        };
+        // The sender is only ever dropped after sending, or while the queue is dropped,
+        // which cannot happen while this waiter holds on to it.
+        if let Some(admitted) = &mut waiting.admitted {
+            let _ = admitted.await;
+        }
+        waiting.admitted = None;
+        Admission {
+            queue: self.clone(),
+            priority,
+            arrival,
+        }
+    }
+
+    /// Hand a slot to the most urgent waiting request, or free it up if none are waiting.
+    fn release(&self) {
+        let mut state = self.0.lock().unwrap();
+        while let Some(waiter) = state.waiting.pop() {
+            // Waiters that stopped waiting are skipped.
+            if waiter.admit.send(()).is_ok() {
+                return;
+            }
+        }
+        state.available += 1;
+    }
+}
+
+impl Admission {
+    /// Whether a more urgent request is waiting for this one's slot.
+    fn preempted(&self) -> bool {
+        let state = self.queue.0.lock().unwrap();
+        state
+            .waiting
+            .peek()
+            .is_some_and(|waiter| waiter.priority > self.priority)
+    }
+}
+
+impl Slot {
+    pub(crate) fn new(admission: Admission) -> Self {
+        Self(Arc::new(Mutex::new(Some(admission))))
+    }
+
+    /// If a more urgent request is waiting, hand it this request's slot,
+    /// and return a future that resolves once this request is admitted again.
+    /// The request keeps its place among waiting requests of equal priority.
+    pub(crate) fn preempt(&self) -> Option<impl Future<Output = ()> + Send + 'static> {
+        let admission = {
+            let mut held = self.0.lock().unwrap();
+            if !held.as_ref().is_some_and(Admission::preempted) {
+                return None;
+            }
+            held.take()?
+        };
+        let (queue, priority, arrival) = (
+            admission.queue.clone(),
+            admission.priority,
+            admission.arrival,
+        );
+        drop(admission);
+        let slot = self.0.clone();
+        Some(async move {
+            let admission = queue.admit_arrival(priority, arrival).await;
+            *slot.lock().unwrap() = Some(admission);
+        })
+    }
+}
+
+impl Drop for Admission {
+    fn drop(&mut self) {
+        self.queue.release();
+    }
+}
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let Some(admitted) = &mut self.admitted else {
+            return;
+        };
+        let mut state = self.queue.0.lock().unwrap();
+        let waiters = state.waiting.len();
+        state
+            .waiting
+            .retain(|waiter| waiter.arrival != self.arrival);
+        if state.waiting.len() < waiters {
+            return;
+        }
+        drop(state);
+        // No longer in the queue, so a slot may already have been handed over without being taken.
+        admitted.close();
+        if admitted.try_recv().is_ok() {
+            self.queue.release();
+        }
+    }
+}
+
+impl Ord for Waiter {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // The max-heap pops the highest priority first, then the earliest arrival.
+        self.priority
+            .cmp(&other.priority)
+            .then_with(|| other.arrival.cmp(&self.arrival))
+    }
+}
+
+impl PartialOrd for Waiter {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Waiter {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Waiter {}
+
+#[cfg(test)]
+mod tests {
+    use std::pin::pin;
+
+    use futures::poll;
+
+    use super::*;
+
+    #[test]
+    fn test_method_priorities() {
+        let priorities = |value: &str| {
+            method_priorities(&HashMap::from([(
+                String::from(PRIORITIES_ANNOTATION),
+                String::from(value),
+            )]))
+        };
+        assert_eq!(
+            *method_priorities(&HashMap::new()).unwrap(),
+            MethodPriorities::default()
+        );
+        let parsed = priorities("shop.Checkout/Pay=9, shop.Reports/Export = 0").unwrap();
+        assert_eq!(parsed.get("shop.Checkout", "Pay"), 9);
+        assert_eq!(parsed.get("shop.Reports", "Export"), 0);
+        assert_eq!(parsed.get("shop.Checkout", "Refund"), 0);
+        assert!(priorities("shop.Checkout/Pay").is_err());
+        assert!(priorities("shop.Checkout/Pay=256").is_err());
+        assert!(priorities("shop.Checkout/Pay=urgent").is_err());
+    }
+
+    #[test]
+    fn test_max_concurrent_requests() {
+        assert_eq!(max_concurrent_requests(0, None), None);
+        assert_eq!(max_concurrent_requests(0, Some(100)), Some(100));
+        // The component's own limit takes precedence, in either direction.
+        assert_eq!(max_concurrent_requests(10, Some(100)), Some(10));
+        assert_eq!(max_concurrent_requests(1000, Some(100)), Some(1000));
+    }
+
+    #[tokio::test]
+    async fn test_priority_admitted_first() {
+        let queue = RequestQueue::new(1);
+        let running = queue.admit(0).await;
+
+        // The pod is saturated, so everything else waits.
+        let mut first_low = pin!(queue.admit(0));
+        let mut second_low = pin!(queue.admit(0));
+        let mut high = pin!(queue.admit(9));
+        assert!(poll!(&mut first_low).is_pending());
+        assert!(poll!(&mut second_low).is_pending());
+        assert!(poll!(&mut high).is_pending());
+
+        // The high-priority request jumps ahead of the low-priority ones queued before it.
+        drop(running);
+        assert!(poll!(&mut first_low).is_pending());
+        assert!(poll!(&mut second_low).is_pending());
+        let running = high.await;
+
+        // Requests of equal priority are admitted in the order they arrived.
+        drop(running);
+        assert!(poll!(&mut second_low).is_pending());
+        let running = first_low.await;
+        drop(running);
+        second_low.await;
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_frees_slot() {
+        let queue = RequestQueue::new(1);
+        let running = queue.admit(0).await;
+
+        // Cancelled before it was handed a slot.
+        let mut cancelled = Box::pin(queue.admit(0));
+        assert!(poll!(&mut cancelled).is_pending());
+        drop(cancelled);
+
+        // Cancelled after it was handed a slot, but before taking it.
+        let mut cancelled = Box::pin(queue.admit(0));
+        assert!(poll!(&mut cancelled).is_pending());
+        drop(running);
+        drop(cancelled);
+
+        // Either way, the slot is still there for the next request.
+        let running = queue.admit(0).await;
+        drop(running);
+        assert_eq!(queue.0.lock().unwrap().available, 1);
+        assert!(queue.0.lock().unwrap().waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_waiter_leaves_queue() {
+        let queue = RequestQueue::new(1);
+        let _running = queue.admit(0).await;
+        let mut cancelled = Box::pin(queue.admit(0));
+        assert!(poll!(&mut cancelled).is_pending());
+        assert_eq!(queue.0.lock().unwrap().waiting.len(), 1);
+        drop(cancelled);
+        assert!(queue.0.lock().unwrap().waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_preemption() {
+        let queue = RequestQueue::new(1);
+        let slot = Slot::new(queue.admit(5).await);
+
+        // Nothing more urgent is waiting, so the running request keeps its slot.
+        let mut low = pin!(queue.admit(0));
+        assert!(poll!(&mut low).is_pending());
+        assert!(slot.preempt().is_none());
+
+        // A more urgent request takes over the slot until it finishes.
+        let mut high = pin!(queue.admit(9));
+        assert!(poll!(&mut high).is_pending());
+        let mut resumed = pin!(slot.preempt().unwrap());
+        assert!(poll!(&mut resumed).is_pending());
+        let running = high.await;
+
+        // The preempted request resumes ahead of the less urgent one.
+        drop(running);
+        assert!(poll!(&mut low).is_pending());
+        resumed.await;
+        assert!(slot.0.lock().unwrap().is_some());
+        drop(slot);
+        low.await;
+    }
+}
@@ -0,0 +1,359 @@
+//! CPU affinity for pod servers.
+//!
+//! Pods with a [`CpuSet`] run their servers on a dedicated Tokio runtime
+//! whose worker threads are pinned to exactly those cores.
+//! All pods pinned to the same set of cores share a runtime,
+//! which shuts down once the last of their servers stops.
+//!
+//! Latency-sensitive pods can request dedicated cores up front with an annotation:
+//!
+//!     vimana.host/cpuset: 2-3
+//!
+//! Kubelet may also assign cores through `UpdateContainerResources`
+//! before the container starts, or between runs.
+//! The cores of a running server cannot change.
+
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter, Result as FmtResult};
+use std::future::Future;
+use std::mem::{size_of, zeroed};
+use std::sync::{Arc, Mutex as SyncMutex, MutexGuard};
+use std::thread;
+
+use anyhow::{anyhow, bail, Context, Result};
+use futures::future::{select, Shared};
+use tokio::runtime::{Builder as RuntimeBuilder, Handle as RuntimeHandle};
+use tokio::sync::oneshot;
+use tokio::task::{spawn, JoinHandle};
+
+use logging::log_warn_globally;
+
+/// Pod annotation listing the cores to which the pod server is pinned,
+/// in the same [format](CpuSet::parse) as the CRI API.
+pub(crate) const CPUSET_ANNOTATION: &str = "vimana.host/cpuset";
+
+/// A non-empty set of CPU cores, sorted and without duplicates.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub(crate) struct CpuSet(Vec<usize>);
+
+/// Registry of Tokio runtimes pinned to specific sets of cores.
+pub(crate) struct PinnedRuntimes {
+    /// Each running runtime, keyed by the cores it is pinned to.
+    /// Runtimes are created and released rarely, so a simple mutex suffices.
+    runtimes: Arc<SyncMutex<HashMap<CpuSet, PinnedRuntime>>>,
+
+    /// Every pinned runtime shuts down upon completion of this shareable future.
+    shutdown: Shared<oneshot::Receiver<()>>,
+}
+
+/// A runtime shared by every task pinned to the same set of cores.
+struct PinnedRuntime {
+    handle: RuntimeHandle,
+
+    /// Number of tasks spawned on the runtime that have not finished yet.
+    tasks: usize,
+
+    /// Dropping this shuts the runtime down.
+    _release: oneshot::Sender<()>,
+}
+
+/// Held by each task spawned on a pinned runtime,
+/// releasing the runtime when the last such task finishes.
+struct Lease {
+    cpuset: CpuSet,
+    runtimes: Arc<SyncMutex<HashMap<CpuSet, PinnedRuntime>>>,
+}
+
+impl CpuSet {
+    /// Parse a CPU set in the Linux list format used by the CRI API
+    /// (e.g. `0-3,7` for cores 0, 1, 2, 3, and 7).
+    /// Every core must exist on this machine.
+    pub(crate) fn parse(cpuset: &str) -> Result<Self> {
+        let configured = configured_cores();
+        let mut cores = Vec::new();
+        for range in cpuset.split(',').map(str::trim) {
+            let (first, last) = match range.split_once('-') {
+                Some((first, last)) => (first.trim(), last.trim()),
+                None => (range, range),
+            };
+            let first: usize = first
+                .parse()
+                .with_context(|| format!("Invalid core: {first:?}"))?;
+            let last: usize = last
+                .parse()
+                .with_context(|| format!("Invalid core: {last:?}"))?;
+            if first > last {
+                bail!("Invalid core range: {range:?}");
+            }
+            if last >= configured {
+                bail!("Core {last} does not exist (only {configured} cores)");
+            }
+            cores.extend(first..=last);
+        }
+        cores.sort_unstable();
+        cores.dedup();
+        Ok(Self(cores))
+    }
+
+    /// Number of cores in the set.
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Restrict the calling thread to run only on cores in this set.
+    pub(crate) fn pin_current_thread(&self) -> Result<()> {
+        let mut set: libc::cpu_set_t = unsafe { zeroed() };
+        for core in &self.0 {
+            unsafe { libc::CPU_SET(*core, &mut set) };
+        }
+        if unsafe { libc::sched_setaffinity(0, size_of::<libc::cpu_set_t>(), &set) } == 0 {
+            Ok(())
+        } else {
+            Err(anyhow!(std::io::Error::last_os_error()).context("Failed setting CPU affinity"))
+        }
+    }
+}
+
+impl Display for CpuSet {
+    /// Format the CPU set in the Linux list format, collapsing consecutive cores into ranges.
+    /// Inverse of [`CpuSet::parse`].
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+        let mut cores = self.0.iter().copied().peekable();
+        let mut separator = "";
+        while let Some(first) = cores.next() {
+            let mut last = first;
+            while cores.next_if_eq(&(last + 1)).is_some() {
+                last += 1;
+            }
+            match last - first {
+                0 => write!(formatter, "{separator}{first}")?,
+                _ => write!(formatter, "{separator}{first}-{last}")?,
+            }
+            separator = ",";
+        }
+        Ok(())
+    }
+}
+
+impl PinnedRuntimes {
+    pub(crate) fn new(shutdown: Shared<oneshot::Receiver<()>>) -> Self {
+        Self {
+            runtimes: Arc::new(SyncMutex::new(HashMap::new())),
+            shutdown,
+        }
+    }
+
+    /// Spawn a task on the runtime pinned to `cpuset`, creating the runtime if necessary.
+    /// The runtime shuts down once every task spawned on it has finished.
+    /// If the runtime cannot be created,
+    /// log a warning and fall back to the current (unpinned) runtime.
+    pub(crate) fn spawn<F>(&self, cpuset: &CpuSet, task: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let mut runtimes = lock(&self.runtimes);
+        let runtime = match runtimes.get_mut(cpuset) {
+            Some(runtime) => runtime,
+            None => match self.spawn_runtime(cpuset) {
+                Ok(runtime) => runtimes.entry(cpuset.clone()).or_insert(runtime),
+                Err(error) => {
+                    log_warn_globally!("Running unpinned on cores {:?}: {:?}", cpuset.0, error);
+                    return spawn(task);
+                }
+            },
+        };
+        runtime.tasks += 1;
+        let lease = Lease {
+            cpuset: cpuset.clone(),
+            runtimes: self.runtimes.clone(),
+        };
+        runtime.handle.spawn(async move {
+            let _lease = lease;
+            task.await
+        })
+    }
+
+    /// Start a new multi-threaded runtime with one worker per core in `cpuset`.
+    /// The runtime is owned by a dedicated thread that drops it once released,
+    /// or upon global shutdown.
+    fn spawn_runtime(&self, cpuset: &CpuSet) -> Result<PinnedRuntime> {
+        let thread_cpuset = cpuset.clone();
+        let runtime = RuntimeBuilder::new_multi_thread()
+            .worker_threads(cpuset.len())
+            .thread_name("vimana-pinned")
+            .on_thread_start(move || {
+                if let Err(error) = thread_cpuset.pin_current_thread() {
+                    log_warn_globally!("{:?}", error);
+                }
+            })
+            .enable_all()
+            .build()
+            .context("Failed building pinned runtime")?;
+        let handle = runtime.handle().clone();
+        let shutdown = self.shutdown.clone();
+        let (release, released) = oneshot::channel();
+        thread::Builder::new()
+            .name(String::from("vimana-pinned-owner"))
+            .spawn(move || {
+                let _ = runtime.block_on(select(shutdown, released));
+            })
+            .context("Failed spawning pinned runtime owner thread")?;
+        Ok(PinnedRuntime {
+            handle,
+            tasks: 0,
+            _release: release,
+        })
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        let mut runtimes = lock(&self.runtimes);
+        if let Some(runtime) = runtimes.get_mut(&self.cpuset) {
+            runtime.tasks -= 1;
+            if runtime.tasks == 0 {
+                runtimes.remove(&self.cpuset);
+            }
+        }
+    }
+}
+
+/// Lock the registry of pinned runtimes.
+fn lock(
+    runtimes: &SyncMutex<HashMap<CpuSet, PinnedRuntime>>,
+) -> MutexGuard<'_, HashMap<CpuSet, PinnedRuntime>> {
+    match runtimes.lock() {
+        Ok(runtimes) => runtimes,
+        // Some other thread panicked while holding the lock.
+        // The map itself is still consistent.
+        Err(poisoned) => poisoned.into_inner(),
+    }
+}
+
+/// Parse the pod's [CPU set](CPUSET_ANNOTATION) from its annotations, if any.
+pub(crate) fn cpuset(annotations: &HashMap<String, String>) -> Result<Option<CpuSet>> {
+    annotations
+        .get(CPUSET_ANNOTATION)
+        .map(|cpuset| {
+            CpuSet::parse(cpuset)
+                .with_context(|| format!("Invalid {CPUSET_ANNOTATION:?} annotation"))
+        })
+        .transpose()
+}
+
+/// Number of cores configured on this machine (not necessarily online).
+fn configured_cores() -> usize {
+    let cores = unsafe { libc::sysconf(libc::_SC_NPROCESSORS_CONF) };
+    usize::try_from(cores)
+        .unwrap_or(1)
+        .min(libc::CPU_SETSIZE as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+
+    use super::*;
+
+    /// Return the set of cores the calling thread is allowed to run on.
+    fn current_thread_affinity() -> CpuSet {
+        let mut set: libc::cpu_set_t = unsafe { zeroed() };
+        assert_eq!(
+            unsafe { libc::sched_getaffinity(0, size_of::<libc::cpu_set_t>(), &mut set) },
+            0,
+        );
+        CpuSet(
+            (0..libc::CPU_SETSIZE as usize)
+                .filter(|core| unsafe { libc::CPU_ISSET(*core, &set) })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(CpuSet::parse("0").unwrap(), CpuSet(vec![0]));
+        assert!(CpuSet::parse("1-0").is_err());
+        assert!(CpuSet::parse("zero").is_err());
+        assert!(CpuSet::parse(&configured_cores().to_string()).is_err());
+        if configured_cores() >= 4 {
+            assert_eq!(CpuSet::parse("3,0-1,1").unwrap(), CpuSet(vec![0, 1, 3]));
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(CpuSet(vec![0]).to_string(), "0");
+        assert_eq!(CpuSet(vec![0, 1, 3]).to_string(), "0-1,3");
+        assert_eq!(CpuSet(vec![2, 4, 5, 6, 9]).to_string(), "2,4-6,9");
+    }
+
+    #[tokio::test]
+    async fn test_pinned_task_affinity() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let runtimes = PinnedRuntimes::new(shutdown_rx.shared());
+        let cpuset = CpuSet::parse("0").unwrap();
+
+        let affinity = runtimes
+            .spawn(&cpuset, async { current_thread_affinity() })
+            .await
+            .unwrap();
+        assert_eq!(affinity, cpuset);
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_pinned_runtime_released() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let runtimes = PinnedRuntimes::new(shutdown_rx.shared());
+        let cpuset = CpuSet::parse("0").unwrap();
+
+        let (first_tx, first_rx) = oneshot::channel::<()>();
+        let first = runtimes.spawn(&cpuset, async move {
+            let _ = first_rx.await;
+            current_thread_affinity()
+        });
+
+        // The same runtime is reused for the same set of cores.
+        let second = runtimes
+            .spawn(&cpuset, async { current_thread_affinity() })
+            .await
+            .unwrap();
+        assert_eq!(second, cpuset);
+        assert_eq!(runtimes.runtimes.lock().unwrap().len(), 1);
+
+        // The runtime is released once the last task on it finishes.
+        let _ = first_tx.send(());
+        assert_eq!(first.await.unwrap(), cpuset);
+        assert!(runtimes.runtimes.lock().unwrap().is_empty());
+
+        // A new runtime starts for the next task on the same cores.
+        let again = runtimes
+            .spawn(&cpuset, async { current_thread_affinity() })
+            .await
+            .unwrap();
+        assert_eq!(again, cpuset);
+
+        let _ = shutdown_tx.send(());
+    }
+
+    #[tokio::test]
+    async fn test_annotated_cpuset() {
+        assert_eq!(cpuset(&HashMap::new()).unwrap(), None);
+        let annotate =
+            |cores: &str| HashMap::from([(String::from(CPUSET_ANNOTATION), String::from(cores))]);
+        assert!(cpuset(&annotate(&configured_cores().to_string())).is_err());
+
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let runtimes = PinnedRuntimes::new(shutdown_rx.shared());
+        let cores = cpuset(&annotate("0")).unwrap().unwrap();
+        let affinity = runtimes
+            .spawn(&cores, async { current_thread_affinity() })
+            .await
+            .unwrap();
+        assert_eq!(affinity, CpuSet(vec![0]));
+
+        let _ = shutdown_tx.send(());
+    }
+}
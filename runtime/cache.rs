@@ -0,0 +1,169 @@
+//! Node-local response caching for idempotent, read-only gRPC methods.
+
+use std::future::Future;
+use std::result::Result as StdResult;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use papaya::HashMap as LockFreeConcurrentHashMap;
+use tonic::Status;
+use wasmtime::component::Val;
+
+use metadata_proto::work::runtime::ResponseCaching;
+
+/// Caches responses for a single method,
+/// keyed by the serialized request bytes exactly as received.
+///
+/// Only suitable for methods whose response depends on nothing but the request message
+/// (in particular, not on request headers).
+pub(crate) struct ResponseCache {
+    /// How long each cached response remains valid.
+    ttl: Duration,
+
+    /// Maximum number of cached responses.
+    max_entries: usize,
+
+    /// Map from serialized requests to cached responses.
+    entries: LockFreeConcurrentHashMap<Bytes, CachedResponse>,
+}
+
+/// A response in a [`ResponseCache`].
+struct CachedResponse {
+    /// Instant after which this response must be recomputed.
+    expires: Instant,
+
+    /// The response as returned by the component.
+    response: Val,
+}
+
+impl ResponseCache {
+    pub(crate) fn new(caching: &ResponseCaching) -> Self {
+        Self {
+            ttl: Duration::from_millis(caching.ttl_millis as u64),
+            max_entries: caching.max_entries as usize,
+            entries: LockFreeConcurrentHashMap::new(),
+        }
+    }
+
+    /// Return the cached response for `key` if it has not yet expired.
+    /// Otherwise, invoke `dispatch` to compute a fresh response and cache it.
+    /// Errors are never cached.
+    pub(crate) async fn get_or_dispatch<F, Fut>(
+        &self,
+        key: Bytes,
+        dispatch: F,
+    ) -> StdResult<Val, Status>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = StdResult<Val, Status>>,
+    {
+        let now = Instant::now();
+        let cached = self
+            .entries
+            .pin()
+            .get(&key)
+            .filter(|cached| cached.expires > now)
+            .map(|cached| cached.response.clone());
+        if let Some(response) = cached {
+            return Ok(response);
+        }
+
+        let response = dispatch().await?;
+        self.insert(key, response.clone());
+        Ok(response)
+    }
+
+    /// Cache a response, first evicting expired entries if the cache is full.
+    /// If the cache is still full after that, the response is simply not cached.
+    fn insert(&self, key: Bytes, response: Val) {
+        let mut entries = self.entries.pin();
+        let now = Instant::now();
+        if entries.len() >= self.max_entries {
+            entries.retain(|_, cached| cached.expires > now);
+            if entries.len() >= self.max_entries {
+                return;
+            }
+        }
+        entries.insert(
+            key,
+            CachedResponse {
+                expires: now + self.ttl,
+                response,
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tokio::time::sleep;
+
+    use super::*;
+
+    /// Return a fresh response while counting how many times it was invoked.
+    async fn dispatch(count: &AtomicUsize) -> StdResult<Val, Status> {
+        Ok(Val::U64(count.fetch_add(1, Ordering::Relaxed) as u64))
+    }
+
+    #[tokio::test]
+    async fn test_cached_request_not_redispatched() {
+        let cache = ResponseCache::new(&ResponseCaching {
+            ttl_millis: 50,
+            max_entries: 8,
+        });
+        let count = AtomicUsize::new(0);
+        let request = Bytes::from_static(&[8, 1]);
+
+        let first = cache
+            .get_or_dispatch(request.clone(), || dispatch(&count))
+            .await
+            .unwrap();
+        let second = cache
+            .get_or_dispatch(request.clone(), || dispatch(&count))
+            .await
+            .unwrap();
+        assert_eq!(first, Val::U64(0));
+        assert_eq!(second, Val::U64(0));
+        assert_eq!(count.load(Ordering::Relaxed), 1);
+
+        // A different request is dispatched separately.
+        cache
+            .get_or_dispatch(Bytes::from_static(&[8, 2]), || dispatch(&count))
+            .await
+            .unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 2);
+
+        // Once the TTL expires, the original request is dispatched again.
+        sleep(Duration::from_millis(60)).await;
+        let third = cache
+            .get_or_dispatch(request, || dispatch(&count))
+            .await
+            .unwrap();
+        assert_eq!(third, Val::U64(2));
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn test_cache_size_bound() {
+        let cache = ResponseCache::new(&ResponseCaching {
+            ttl_millis: 60_000,
+            max_entries: 1,
+        });
+        let count = AtomicUsize::new(0);
+
+        for _ in 0..2 {
+            cache
+                .get_or_dispatch(Bytes::from_static(&[1]), || dispatch(&count))
+                .await
+                .unwrap();
+            // The cache is full, so this one is never cached.
+            cache
+                .get_or_dispatch(Bytes::from_static(&[2]), || dispatch(&count))
+                .await
+                .unwrap();
+        }
+        assert_eq!(count.load(Ordering::Relaxed), 3);
+    }
+}
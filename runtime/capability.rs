@@ -0,0 +1,168 @@
+//! Request-scoped capabilities for host functions.
+//!
+//! By default, every request may use every host function available to its pod.
+//! A pod may instead grant each capability only to certain callers
+//! by annotating the pod sandbox with comma-separated lists of source CIDRs:
+//!
+//!     vimana.host/grant-storage: 10.0.0.0/8
+//!     vimana.host/grant-network: 10.6.0.0/16,fd00::/8
+//!
+//! Once any capability is granted this way, capabilities without a grant are denied to everyone.
+//! Each request's capabilities are derived from its caller's address when it is dispatched,
+//! and host functions outside that set fail as if the resource were unavailable.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+
+use crate::policy::Cidr;
+
+/// Prefix of pod annotations granting a capability, followed by its [name](Capability::name).
+pub(crate) const GRANT_ANNOTATION_PREFIX: &str = "vimana.host/grant-";
+
+/// An operation that host functions only perform on behalf of permitted callers.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Capability {
+    /// Read and write the pod's scratch storage.
+    Storage = 1,
+    /// Open outbound network connections.
+    Network = 2,
+}
+
+/// A set of [capabilities](Capability) held by a single request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Capabilities(u8);
+
+/// Which callers may exercise each capability of a pod.
+#[derive(Debug, PartialEq)]
+pub(crate) struct CapabilityPolicy {
+    grants: Vec<(Capability, Vec<Cidr>)>,
+}
+
+impl Capability {
+    const ALL: [Self; 2] = [Self::Storage, Self::Network];
+
+    fn name(self) -> &'static str {
+        match self {
+            Self::Storage => "storage",
+            Self::Network => "network",
+        }
+    }
+}
+
+impl Capabilities {
+    /// Every capability, for pods without a [policy](CapabilityPolicy).
+    pub(crate) const ALL: Self = Self(Capability::Storage as u8 | Capability::Network as u8);
+
+    /// No capabilities at all.
+    pub(crate) const NONE: Self = Self(0);
+
+    fn with(self, capability: Capability) -> Self {
+        Self(self.0 | capability as u8)
+    }
+
+    /// Return `Ok` iff the set includes `capability`,
+    /// otherwise an error message suitable for the component.
+    pub(crate) fn require(self, capability: Capability) -> Result<(), String> {
+        if self.0 & capability as u8 != 0 {
+            Ok(())
+        } else {
+            Err(format!(
+                "Request lacks the {} capability",
+                capability.name()
+            ))
+        }
+    }
+}
+
+impl CapabilityPolicy {
+    /// Return the capabilities of a request from a caller at `source`.
+    /// Callers of unknown address get no capabilities.
+    pub(crate) fn capabilities(&self, source: Option<IpAddr>) -> Capabilities {
+        let Some(source) = source.map(|source| source.to_canonical()) else {
+            return Capabilities::NONE;
+        };
+        self.grants
+            .iter()
+            .filter(|(_, cidrs)| cidrs.iter().any(|cidr| cidr.contains(source)))
+            .fold(Capabilities::NONE, |capabilities, (capability, _)| {
+                capabilities.with(*capability)
+            })
+    }
+}
+
+/// Parse the pod's [capability policy](CapabilityPolicy) from its annotations.
+/// Return `None` if no capability is granted by annotation.
+pub(crate) fn capability_policy(
+    annotations: &HashMap<String, String>,
+) -> Result<Option<Arc<CapabilityPolicy>>> {
+    let mut grants = Vec::new();
+    for (key, cidrs) in annotations {
+        let Some(name) = key.strip_prefix(GRANT_ANNOTATION_PREFIX) else {
+            continue;
+        };
+        let capability = Capability::ALL
+            .into_iter()
+            .find(|capability| capability.name() == name)
+            .ok_or_else(|| anyhow!("Unknown capability in annotation: {key:?}"))?;
+        let cidrs = cidrs
+            .split(',')
+            .filter(|cidr| !cidr.trim().is_empty())
+            .map(Cidr::parse)
+            .collect::<Result<Vec<_>>>()
+            .with_context(|| format!("Invalid {key:?} annotation"))?;
+        grants.push((capability, cidrs));
+    }
+    if grants.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(CapabilityPolicy { grants })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_capability_policy() {
+        assert_eq!(capability_policy(&HashMap::new()).unwrap(), None);
+        let annotations = |grants: &[(&str, &str)]| {
+            grants
+                .iter()
+                .map(|(capability, cidrs)| {
+                    (
+                        format!("{GRANT_ANNOTATION_PREFIX}{capability}"),
+                        String::from(*cidrs),
+                    )
+                })
+                .collect::<HashMap<_, _>>()
+        };
+        assert!(capability_policy(&annotations(&[("teleport", "0.0.0.0/0")])).is_err());
+        assert!(capability_policy(&annotations(&[("storage", "10.0.0.0")])).is_err());
+
+        let policy = capability_policy(&annotations(&[
+            ("storage", "10.0.0.0/8"),
+            ("network", "10.6.0.0/16"),
+        ]))
+        .unwrap()
+        .unwrap();
+        let trusted = policy.capabilities(Some("10.6.0.1".parse().unwrap()));
+        assert_eq!(trusted, Capabilities::ALL);
+
+        // A caller granted storage but denied network fails an outbound call.
+        let untrusted = policy.capabilities(Some("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(untrusted.require(Capability::Storage).is_ok());
+        assert_eq!(
+            untrusted.require(Capability::Network),
+            Err(String::from("Request lacks the network capability")),
+        );
+
+        assert_eq!(
+            policy.capabilities(Some("192.168.0.1".parse().unwrap())),
+            Capabilities::NONE
+        );
+        assert_eq!(policy.capabilities(None), Capabilities::NONE);
+    }
+}
@@ -0,0 +1,16 @@
+# Run the Protobuf conformance suite against the Vimana testee.
+# Requests outside the supported subset are skipped by the testee,
+# so they never count as failures.
+# The testee prints a summary of outcomes per test category
+# after the runner's own report.
+
+set -e
+
+# The upstream `conformance_test_runner` binary.
+runner="$1"
+# Tests that are known to fail, one per line.
+failure_list="$2"
+# The Vimana testee binary.
+testee="$3"
+
+"$runner" --failure_list "$failure_list" "$testee"
@@ -0,0 +1,405 @@
+//! Testee for the official Protobuf [conformance suite][1],
+//! exercising the request decoder and response encoder
+//! on the binary wire-format vectors for `protobuf_test_messages.proto3.TestAllTypesProto3`.
+//!
+//! The conformance runner writes length-prefixed `ConformanceRequest`s to standard input
+//! and reads a length-prefixed `ConformanceResponse` for each from standard output.
+//! Each binary payload is decoded against a [schema](test_all_types) for the subset of
+//! `TestAllTypesProto3` that Vimana supports, then re-encoded for the runner to check.
+//! Any request outside that subset (other message types, text formats,
+//! or payloads containing unsupported fields) is reported as skipped rather than failed.
+//!
+//! When the runner closes standard input,
+//! a summary of outcomes per test category is written to standard error.
+//!
+//! [1]: https://github.com/protocolbuffers/protobuf/tree/main/conformance
+
+use std::collections::BTreeMap;
+use std::io::{stdin, stdout, ErrorKind, Read, Write};
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use bytes::BytesMut;
+use prost::encoding::{decode_key, skip_field, DecodeContext};
+use prost::Message;
+use tonic::codec::{Decoder as _, Encoder as _};
+
+use conformance_proto::conformance::conformance_request::Payload;
+use conformance_proto::conformance::conformance_response::Result as Outcome;
+use conformance_proto::conformance::{ConformanceRequest, ConformanceResponse, WireFormat};
+use decode::RequestDecoder;
+use encode::ResponseEncoder;
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::{ComponentName, Name};
+use testing::{decode_buf, encode_buf};
+
+/// Fully-qualified name of the only message type under test.
+const MESSAGE_TYPE: &str = "protobuf_test_messages.proto3.TestAllTypesProto3";
+
+/// Older runners first ask the testee for its own list of expected failures.
+const FAILURE_SET_TYPE: &str = "conformance.FailureSet";
+
+/// Arbitrary component name, only used in log messages.
+const COMPONENT_NAME: &str = "00000000000000000000000000000000:conformance-testee@0.0.0";
+
+/// Names and implicit codings of the singular scalar fields of `TestAllTypesProto3`,
+/// in field number order.
+/// The repeated, packed, and unpacked fields follow the same order.
+const SCALARS: [(&str, ScalarCoding); 15] = [
+    ("int32", ScalarCoding::Int32Implicit),
+    ("int64", ScalarCoding::Int64Implicit),
+    ("uint32", ScalarCoding::Uint32Implicit),
+    ("uint64", ScalarCoding::Uint64Implicit),
+    ("sint32", ScalarCoding::Sint32Implicit),
+    ("sint64", ScalarCoding::Sint64Implicit),
+    ("fixed32", ScalarCoding::Fixed32Implicit),
+    ("fixed64", ScalarCoding::Fixed64Implicit),
+    ("sfixed32", ScalarCoding::Sfixed32Implicit),
+    ("sfixed64", ScalarCoding::Sfixed64Implicit),
+    ("float", ScalarCoding::FloatImplicit),
+    ("double", ScalarCoding::DoubleImplicit),
+    ("bool", ScalarCoding::BoolImplicit),
+    ("string", ScalarCoding::StringUtf8Implicit),
+    ("bytes", ScalarCoding::BytesImplicit),
+];
+
+/// First field number of each run of scalar fields in `TestAllTypesProto3`.
+const OPTIONAL_START: u32 = 1;
+const REPEATED_START: u32 = 31;
+const PACKED_START: u32 = 75;
+const UNPACKED_START: u32 = 89;
+
+/// Packed and unpacked runs stop short of strings and bytes, which cannot be packed.
+const PACKABLE: usize = 13;
+
+/// Oneof variants of `TestAllTypesProto3` with scalar payloads.
+const ONEOF_VARIANTS: [(u32, &str, ScalarCoding); 7] = [
+    (111, "oneof_uint32", ScalarCoding::Uint32Explicit),
+    (113, "oneof_string", ScalarCoding::StringUtf8Explicit),
+    (114, "oneof_bytes", ScalarCoding::BytesExplicit),
+    (115, "oneof_bool", ScalarCoding::BoolExplicit),
+    (116, "oneof_uint64", ScalarCoding::Uint64Explicit),
+    (117, "oneof_float", ScalarCoding::FloatExplicit),
+    (118, "oneof_double", ScalarCoding::DoubleExplicit),
+];
+
+/// Outcomes of the requests in a single test category.
+#[derive(Debug, Default, PartialEq)]
+struct Tally {
+    /// Decoded and re-encoded; the runner decides whether the result is correct.
+    round_tripped: u64,
+    /// Rejected by the decoder.
+    parse_errors: u64,
+    /// Rejected by the encoder after decoding successfully.
+    serialize_errors: u64,
+    /// Outside the supported subset.
+    skipped: u64,
+}
+
+struct Testee {
+    decoder: RequestDecoder,
+    encoder: ResponseEncoder,
+    tallies: BTreeMap<&'static str, Tally>,
+}
+
+fn main() -> Result<()> {
+    let mut testee = Testee::new()?;
+    let mut input = stdin().lock();
+    let mut output = stdout().lock();
+
+    loop {
+        let mut length = [0u8; 4];
+        match input.read_exact(&mut length) {
+            Ok(()) => (),
+            // The runner is done.
+            Err(error) if error.kind() == ErrorKind::UnexpectedEof => break,
+            Err(error) => return Err(error).context("Failed to read request length"),
+        }
+        let mut request = vec![0u8; u32::from_le_bytes(length) as usize];
+        input
+            .read_exact(&mut request)
+            .context("Failed to read request")?;
+        let request = ConformanceRequest::decode(request.as_slice())
+            .context("Malformed conformance request")?;
+
+        let response = testee.respond(request).encode_to_vec();
+        output.write_all(&(response.len() as u32).to_le_bytes())?;
+        output.write_all(&response)?;
+        output.flush()?;
+    }
+
+    eprint!("{}", testee.summary());
+    Ok(())
+}
+
+impl Testee {
+    fn new() -> Result<Self> {
+        let schema = test_all_types();
+        let component: Arc<ComponentName> = Arc::new(Name::parse(COMPONENT_NAME).component()?);
+        Ok(Self {
+            decoder: RequestDecoder::new(&schema, component.clone())?,
+            encoder: ResponseEncoder::new(&schema, component)?,
+            tallies: BTreeMap::new(),
+        })
+    }
+
+    fn respond(&mut self, request: ConformanceRequest) -> ConformanceResponse {
+        let category = request.test_category().as_str_name();
+        let outcome = self.outcome(request);
+        let tally = self.tallies.entry(category).or_default();
+        match &outcome {
+            Outcome::ProtobufPayload(_) => tally.round_tripped += 1,
+            Outcome::ParseError(_) => tally.parse_errors += 1,
+            Outcome::SerializeError(_) => tally.serialize_errors += 1,
+            _ => tally.skipped += 1,
+        }
+        ConformanceResponse {
+            result: Some(outcome),
+        }
+    }
+
+    fn outcome(&mut self, request: ConformanceRequest) -> Outcome {
+        if request.message_type == FAILURE_SET_TYPE {
+            // An empty failure set; expected failures are listed for the runner instead.
+            return Outcome::ProtobufPayload(Vec::new());
+        }
+        if request.message_type != MESSAGE_TYPE {
+            return Outcome::Skipped(format!("Unsupported message type {}", request.message_type));
+        }
+        if request.requested_output_format() != WireFormat::Protobuf {
+            return Outcome::Skipped(String::from("Only binary output is supported"));
+        }
+        let Some(Payload::ProtobufPayload(payload)) = request.payload else {
+            return Outcome::Skipped(String::from("Only binary input is supported"));
+        };
+        if let Some(number) = unsupported_field(&payload) {
+            return Outcome::Skipped(format!("Unsupported field #{number}"));
+        }
+
+        let mut buffer = BytesMut::from(payload.as_slice());
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        let value = match self.decoder.decode(&mut decode_buffer) {
+            Ok(Some(value)) => value,
+            Ok(None) => return Outcome::ParseError(String::from("Incomplete message")),
+            Err(status) => return Outcome::ParseError(status.message().to_string()),
+        };
+
+        let mut buffer = BytesMut::new();
+        let mut encode_buffer = encode_buf(&mut buffer);
+        match self.encoder.encode(value, &mut encode_buffer) {
+            Ok(()) => Outcome::ProtobufPayload(buffer.to_vec()),
+            Err(status) => Outcome::SerializeError(status.message().to_string()),
+        }
+    }
+
+    /// Outcomes per test category, one line each.
+    fn summary(&self) -> String {
+        self.tallies
+            .iter()
+            .map(|(category, tally)| {
+                format!(
+                    "{category}: {} round-tripped, {} parse errors, {} serialize errors, {} skipped\n",
+                    tally.round_tripped, tally.parse_errors, tally.serialize_errors, tally.skipped,
+                )
+            })
+            .collect()
+    }
+}
+
+/// Return the number of the first top-level field in a payload
+/// that falls outside the supported subset of `TestAllTypesProto3`.
+///
+/// Payloads that are malformed at the top level pass this check,
+/// so the decoder gets a chance to reject them.
+fn unsupported_field(mut payload: &[u8]) -> Option<u32> {
+    let schema = test_all_types();
+    let supported = |number: u32| {
+        schema.subfields.iter().any(|field| {
+            field.number == number || field.subfields.iter().any(|v| v.number == number)
+        })
+    };
+    while !payload.is_empty() {
+        let Ok((number, wire_type)) = decode_key(&mut payload) else {
+            return None;
+        };
+        if !supported(number) {
+            return Some(number);
+        }
+        if skip_field(wire_type, number, &mut payload, DecodeContext::default()).is_err() {
+            return None;
+        }
+    }
+    None
+}
+
+/// Schema for the supported subset of `TestAllTypesProto3`:
+/// every scalar field, whether singular, repeated, packed, or unpacked,
+/// and the oneof variants with scalar payloads.
+///
+/// Enums, nested messages, maps, and well-known types are not covered.
+fn test_all_types() -> Field {
+    let mut subfields = Vec::new();
+    let mut scalars = |start: u32, prefix: &str, count: usize, offset: fn(i32) -> i32| {
+        for (index, (name, coding)) in SCALARS.iter().take(count).enumerate() {
+            subfields.push(Field {
+                number: start + index as u32,
+                name: format!("{prefix}_{name}"),
+                coding: Some(Coding::ScalarCoding(offset(*coding as i32))),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            });
+        }
+    };
+    scalars(OPTIONAL_START, "optional", SCALARS.len(), |coding| coding);
+    scalars(REPEATED_START, "repeated", SCALARS.len(), |coding| {
+        // Repeated strings and bytes are expanded; everything else is packed.
+        if coding < ScalarCoding::BoolImplicit as i32 {
+            coding + 3
+        } else {
+            coding + 1
+        }
+    });
+    scalars(PACKED_START, "packed", PACKABLE, |coding| coding + 1);
+    scalars(UNPACKED_START, "unpacked", PACKABLE, |coding| coding + 3);
+
+    subfields.push(Field {
+        number: 0, // Ignored.
+        name: String::from("oneof_field"),
+        coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
+        subfields: ONEOF_VARIANTS
+            .iter()
+            .map(|(number, name, coding)| Field {
+                number: *number,
+                name: String::from(*name),
+                coding: Some(Coding::ScalarCoding(*coding as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            })
+            .collect(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    });
+
+    Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use conformance_proto::conformance::TestCategory;
+
+    use super::*;
+
+    fn binary_request(payload: &[u8]) -> ConformanceRequest {
+        ConformanceRequest {
+            message_type: String::from(MESSAGE_TYPE),
+            requested_output_format: WireFormat::Protobuf as i32,
+            test_category: TestCategory::BinaryTest as i32,
+            payload: Some(Payload::ProtobufPayload(payload.to_vec())),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_schema_codings() {
+        let schema = test_all_types();
+        let coding = |number: u32| {
+            schema
+                .subfields
+                .iter()
+                .find(|field| field.number == number)
+                .unwrap()
+                .coding
+        };
+        // `optional_int32`, `repeated_int32`, `packed_int32`, `unpacked_int32`.
+        assert_eq!(
+            coding(1),
+            Some(Coding::ScalarCoding(ScalarCoding::Int32Implicit as i32))
+        );
+        assert_eq!(
+            coding(31),
+            Some(Coding::ScalarCoding(ScalarCoding::Int32Packed as i32))
+        );
+        assert_eq!(
+            coding(75),
+            Some(Coding::ScalarCoding(ScalarCoding::Int32Packed as i32))
+        );
+        assert_eq!(
+            coding(89),
+            Some(Coding::ScalarCoding(ScalarCoding::Int32Expanded as i32))
+        );
+        // `optional_bytes`, `repeated_string`, `unpacked_bool`.
+        assert_eq!(
+            coding(15),
+            Some(Coding::ScalarCoding(ScalarCoding::BytesImplicit as i32))
+        );
+        assert_eq!(
+            coding(44),
+            Some(Coding::ScalarCoding(
+                ScalarCoding::StringUtf8Expanded as i32
+            ))
+        );
+        assert_eq!(
+            coding(101),
+            Some(Coding::ScalarCoding(ScalarCoding::BoolExpanded as i32))
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut testee = Testee::new().unwrap();
+        // `optional_int32: 150`, then `oneof_string: "hi"`.
+        let payload = [0x08, 0x96, 0x01, 0x8a, 0x07, 0x02, b'h', b'i'];
+        assert_eq!(
+            testee.respond(binary_request(&payload)).result,
+            Some(Outcome::ProtobufPayload(payload.to_vec())),
+        );
+    }
+
+    #[test]
+    fn test_unpacked_input_is_packed_on_output() {
+        let mut testee = Testee::new().unwrap();
+        // `repeated_int32: [1, 2]`, unpacked.
+        let payload = [0xf8, 0x01, 0x01, 0xf8, 0x01, 0x02];
+        assert_eq!(
+            testee.respond(binary_request(&payload)).result,
+            Some(Outcome::ProtobufPayload(vec![0xfa, 0x01, 0x02, 0x01, 0x02])),
+        );
+    }
+
+    #[test]
+    fn test_skipped_and_failed() {
+        let mut testee = Testee::new().unwrap();
+        // `optional_nested_message` is outside the supported subset.
+        let response = testee.respond(binary_request(&[0x92, 0x01, 0x00]));
+        assert_eq!(
+            response.result,
+            Some(Outcome::Skipped(String::from("Unsupported field #18")))
+        );
+        // Truncated `optional_string`.
+        let response = testee.respond(binary_request(&[0x72, 0x05, b'h']));
+        assert!(matches!(response.result, Some(Outcome::ParseError(_))));
+        let response = testee.respond(ConformanceRequest {
+            requested_output_format: WireFormat::Json as i32,
+            test_category: TestCategory::JsonTest as i32,
+            ..binary_request(&[])
+        });
+        assert!(matches!(response.result, Some(Outcome::Skipped(_))));
+
+        assert_eq!(
+            testee.summary(),
+            "BINARY_TEST: 0 round-tripped, 1 parse errors, 0 serialize errors, 1 skipped\n\
+            JSON_TEST: 0 round-tripped, 0 parse errors, 0 serialize errors, 1 skipped\n",
+        );
+    }
+}
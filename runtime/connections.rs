@@ -0,0 +1,155 @@
+//! Limits on concurrent connections to each pod server.
+//!
+//! Every node may cap the number of connections each pod holds open at once,
+//! and a component may lower or raise that cap for its own pods in its metadata.
+//! Connections beyond the cap are closed as soon as they are accepted,
+//! so a single client cannot exhaust the connection slots of a component,
+//! while connections already established are unaffected.
+
+use std::io::Result as IoResult;
+use std::pin::Pin;
+use std::result::Result as StdResult;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_stream::{Stream, StreamExt};
+use tonic::transport::server::{Connected, TcpConnectInfo};
+
+use logging::log_warn;
+use names::PodName;
+
+/// An accepted connection, holding one of its pod's connection slots until it is dropped.
+pub(crate) struct LimitedConnection {
+    stream: TcpStream,
+
+    /// Released when the connection closes. Absent if the pod has no limit.
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+/// Return the connection limit for a pod:
+/// the component's own limit from its metadata (if non-zero),
+/// otherwise the node's default limit (if any).
+pub(crate) fn max_connections(component: u32, node: Option<usize>) -> Option<usize> {
+    match component {
+        0 => node,
+        limit => Some(limit as usize),
+    }
+}
+
+/// Close incoming connections beyond the first `max_connections` open at once.
+/// Every connection is let through if there is no limit.
+pub(crate) fn limit_connections<E>(
+    incoming: impl Stream<Item = StdResult<TcpStream, E>>,
+    max_connections: Option<usize>,
+    name: PodName,
+) -> impl Stream<Item = StdResult<LimitedConnection, E>> {
+    let slots = max_connections.map(|max| Arc::new(Semaphore::new(max)));
+    incoming.filter_map(move |connection| {
+        let stream = match connection {
+            Ok(stream) => stream,
+            Err(error) => return Some(Err(error)),
+        };
+        let permit = match &slots {
+            Some(slots) => match slots.clone().try_acquire_owned() {
+                Ok(permit) => Some(permit),
+                Err(_) => {
+                    log_warn!(pod: &name, "Rejected connection beyond the connection limit");
+                    return None;
+                }
+            },
+            None => None,
+        };
+        Some(Ok(LimitedConnection {
+            stream,
+            _permit: permit,
+        }))
+    })
+}
+
+impl Connected for LimitedConnection {
+    type ConnectInfo = TcpConnectInfo;
+
+    fn connect_info(&self) -> Self::ConnectInfo {
+        self.stream.connect_info()
+    }
+}
+
+impl AsyncRead for LimitedConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LimitedConnection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<IoResult<usize>> {
+        Pin::new(&mut self.get_mut().stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<IoResult<()>> {
+        Pin::new(&mut self.get_mut().stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use names::Name;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::task::spawn;
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+
+    const POD_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0#7";
+
+    #[test]
+    fn test_max_connections() {
+        assert_eq!(max_connections(0, None), None);
+        assert_eq!(max_connections(0, Some(100)), Some(100));
+        // The component's own limit takes precedence, in either direction.
+        assert_eq!(max_connections(10, Some(100)), Some(10));
+        assert_eq!(max_connections(1000, Some(100)), Some(1000));
+        assert_eq!(max_connections(10, None), Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_limit_at_accept() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let mut incoming = Box::pin(limit_connections(
+            TcpListenerStream::new(listener),
+            Some(1),
+            Name::parse(POD_NAME).pod().unwrap(),
+        ));
+
+        let mut first = TcpStream::connect(address).await.unwrap();
+        let mut accepted = incoming.next().await.unwrap().unwrap();
+
+        // The second client sees its connection closed as soon as it's accepted.
+        let mut rejected = TcpStream::connect(address).await.unwrap();
+        let next = spawn(async move { incoming.next().await.unwrap().is_ok() });
+        assert_eq!(rejected.read(&mut [0; 1]).await.unwrap(), 0);
+
+        // Meanwhile, the first connection carries on.
+        first.write_all(b"hi").await.unwrap();
+        let mut received = [0; 2];
+        accepted.read_exact(&mut received).await.unwrap();
+        assert_eq!(&received, b"hi");
+
+        // Closing it frees up its slot for the next client.
+        drop(accepted);
+        let _third = TcpStream::connect(address).await.unwrap();
+        assert!(next.await.unwrap());
+    }
+}
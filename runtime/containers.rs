@@ -9,19 +9,28 @@ use std::fs::{
 use std::io::{Read, Write};
 use std::mem::{drop, size_of};
 use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::sync::Mutex as SyncMutex;
+use std::sync::RwLock as SyncRwLock;
+use std::time::Instant;
 
 use anyhow::{anyhow, Context, Error, Result};
 use api_proto::runtime::v1;
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
+use papaya::{Compute, HashMap as LockFreeConcurrentHashMap, Operation};
 use prost::Message;
 use reqwest::header::ACCEPT;
-use reqwest::{Client, StatusCode as HttpStatusCode};
+use reqwest::redirect::Policy as RedirectPolicy;
+use reqwest::{Client, StatusCode as HttpStatusCode, Url};
 use serde::Deserialize;
-use tokio::task::{spawn, spawn_blocking};
+use tokio::select;
+use tokio::sync::watch;
+use tokio::task::{spawn, spawn_blocking, JoinHandle};
 use wasmtime::component::Component;
 use wasmtime::Engine as WasmEngine;
+use zstd::bulk::compress;
+use zstd::stream::decode_all;
 
 use logging::log_info;
 use metadata_proto::work::runtime::Metadata;
@@ -36,6 +45,18 @@ const CONTAINER_FILENAME: &str = "container";
 /// that was originally specified when pulling the image.
 const IMAGE_SPEC_FILENAME: &str = "image-spec.binpb";
 
+/// Every zstd frame starts with these bytes,
+/// whereas an uncompressed pre-compiled component is an ELF file.
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Most memory reserved up front for a blob, whatever length the registry advertises.
+/// Larger blobs grow as their chunks actually arrive.
+const MAX_BLOB_PREALLOCATION: u64 = 16 * 1024 * 1024;
+
+/// Maximum number of redirects followed for a single registry request
+/// (the same as the default policy).
+const MAX_REDIRECTS: usize = 10;
+
 /// Client used to fetch and compile containers from a registry,
 /// caching compiled components and parsed container metadata locally.
 #[derive(Clone)]
@@ -50,9 +71,20 @@ pub(crate) struct ContainerStore {
     /// Means to fetch containers from a remote container registry.
     client: ContainerClient,
 
+    /// Cancellation signals for pulls in progress,
+    /// keyed by the UID and attempt of the pod sandbox
+    /// on whose behalf each image is being pulled.
+    /// A cancelled sandbox keeps its (raised) signal until it is [forgotten](Self::forget_pulls),
+    /// so that pulls requested after cancellation are aborted too.
+    pulls: Arc<LockFreeConcurrentHashMap<(String, u32), watch::Sender<bool>>>,
+
     /// Global Wasm engine to run hosted servers.
     /// This must be the exact same engine used in the [client](ContainerClient).
     wasmtime: WasmEngine,
+
+    /// If set, pre-compiled components are compressed on disk with zstd at this level.
+    /// Either way, compressed and uncompressed components can be loaded.
+    compression_level: Option<i32>,
 }
 
 /// Ready-to-link container.
@@ -76,11 +108,13 @@ pub(crate) struct FilesystemUsage {
 
 impl ContainerStore {
     /// Return a new [store](ContainerStore).
-    /// Components will be instantiated using the provided [`Engine`](WasmEngine).
+    /// Components will be instantiated using the provided [`Engine`](WasmEngine),
+    /// and compressed on disk if a compression level is given.
     pub(crate) fn new(
         root: &str,
         insecure_registries: HashSet<String>,
         wasmtime: &WasmEngine,
+        compression_level: Option<i32>,
     ) -> Result<Self> {
         // The image filesystem root path reported by `ImageFsInfo` to Kubelet must exist,
         // otherwise Kubelet will get confused and evict all the pods,
@@ -95,11 +129,19 @@ impl ContainerStore {
                 bytes: 0,
                 inodes: 0,
             })),
-            client: ContainerClient::new(insecure_registries, wasmtime),
+            client: ContainerClient::new(insecure_registries, wasmtime)?,
+            pulls: Arc::new(LockFreeConcurrentHashMap::new()),
             wasmtime: wasmtime.clone(),
+            compression_level,
         })
     }
 
+    /// Replace the set of registries that should be fetched via HTTP rather than HTTPS.
+    /// Pulls already in progress are unaffected.
+    pub(crate) fn set_insecure_registries(&self, insecure_registries: HashSet<String>) {
+        *self.client.insecure_registries.write().unwrap() = insecure_registries;
+    }
+
     /// Return the path to the root directory where images are stored on pull.
     pub(crate) fn mountpoint(&self) -> String {
         String::from(self.root.to_string_lossy())
@@ -107,15 +149,60 @@ impl ContainerStore {
 
     /// Fetch a container identified by `name` from the given registry.
     /// Subsequent calls to `get` should succeed for that container.
+    ///
+    /// If the pull is on behalf of a pod sandbox,
+    /// it can be aborted with [`cancel_pulls`](Self::cancel_pulls)
+    /// until the container has been fetched.
     pub(crate) async fn pull(
         &self,
         registry: &str,
         name: &ComponentName,
         image_spec: &v1::ImageSpec,
+        pod: Option<&v1::PodSandboxMetadata>,
+    ) -> Result<()> {
+        let container = match pod {
+            Some(pod) => {
+                let mut cancelled = self
+                    .pulls
+                    .pin()
+                    .get_or_insert_with(pull_key(pod), || watch::channel(false).0)
+                    .subscribe();
+                let result = select! {
+                    result = self.client.fetch(registry, name) => result,
+                    _ = cancelled.wait_for(|cancelled| *cancelled) => {
+                        Err(anyhow!("Pull cancelled because the pod was stopped"))
+                    }
+                };
+                drop(cancelled);
+                // Forget the signal unless another pull for the same pod is still listening,
+                // or the pod was cancelled.
+                let pulls = self.pulls.pin();
+                let _: Compute<'_, _, _, ()> = pulls.compute(pull_key(pod), |entry| match entry {
+                    Some((_, signal)) if signal.receiver_count() == 0 && !*signal.borrow() => {
+                        Operation::Remove
+                    }
+                    _ => Operation::Abort(()),
+                });
+                result?
+            }
+            None => self.client.fetch(registry, name).await?,
+        };
+        self.save(name, &container, image_spec).await?;
+        log_info!(component: name, "Successful image pull");
+        Ok(())
+    }
+
+    /// Save a fetched container and the image spec it was pulled with
+    /// so it can be loaded later with [`get`](Self::get).
+    async fn save(
+        &self,
+        name: &ComponentName,
+        container: &Container,
+        image_spec: &v1::ImageSpec,
     ) -> Result<()> {
-        let container = self.client.fetch(registry, name).await?;
         // TODO: Prefer to use wasmtime's `Engine::precompile_component`.
         let serialized_component = container.component.serialize()?;
+        let compression_level = self.compression_level;
         let serialized_metadata = container.metadata.encode_to_vec();
         let serialized_image_spec = image_spec.encode_to_vec();
 
@@ -124,7 +211,14 @@ impl ContainerStore {
         let image_spec_path = component_path.join(IMAGE_SPEC_FILENAME);
         let filesystem_usage = self.filesystem_usage.clone();
 
-        let result = spawn_blocking(move || {
+        spawn_blocking(move || {
+            // Compress before taking the lock; it's the slowest part.
+            let serialized_component = match compression_level {
+                Some(level) => compress(&serialized_component, level)
+                    .context("Failed compressing component")?,
+                None => serialized_component,
+            };
+
             // This locks the mutex so you can't remove or pull anything else until it's finished.
             let mut filesystem_usage = filesystem_usage
                 .lock()
@@ -203,19 +297,29 @@ impl ContainerStore {
             Ok(())
         })
         .await
-        .context("Failed joining blocking thread to pull image")?;
+        .context("Failed joining blocking thread to pull image")?
+    }
 
-        if result.is_ok() {
-            log_info!(component: name, "Successful image pull");
-        }
+    /// Abort every pull on behalf of the given pod sandbox,
+    /// whether already in progress or requested later.
+    pub(crate) fn cancel_pulls(&self, pod: &v1::PodSandboxMetadata) {
+        self.pulls
+            .pin()
+            .get_or_insert_with(pull_key(pod), || watch::channel(false).0)
+            .send_replace(true);
+    }
 
-        result
+    /// Forget that pulls were [cancelled](Self::cancel_pulls) for the given pod sandbox,
+    /// once it is removed and can no longer request any.
+    pub(crate) fn forget_pulls(&self, pod: &v1::PodSandboxMetadata) {
+        self.pulls.pin().remove(&pull_key(pod));
     }
 
     /// Return a compiled component implementation and its metadata.
     pub(crate) async fn get(&self, name: &ComponentName) -> Result<Container> {
         let component_path = self.component_path(name);
         let container_path = component_path.join(CONTAINER_FILENAME);
+        let name = name.clone();
 
         let (serialized_component, serialized_metadata) = spawn_blocking(move || {
             let mut container_file = SyncFile::open(container_path.as_path())
@@ -237,6 +341,19 @@ impl ContainerStore {
                 .read_to_end(&mut serialized_metadata)
                 .context("Failed reading metadata from container file")?;
 
+            if serialized_component.starts_with(&ZSTD_MAGIC) {
+                let started = Instant::now();
+                let compressed_size = serialized_component.len();
+                serialized_component = decode_all(serialized_component.as_slice())
+                    .context("Failed decompressing component")?;
+                log_info!(
+                    component: &name,
+                    "Decompressed component ({compressed_size} to {} bytes) in {:?}",
+                    serialized_component.len(),
+                    started.elapsed(),
+                );
+            }
+
             Ok::<_, Error>((serialized_component, serialized_metadata))
         })
         .await
@@ -392,7 +509,8 @@ struct ContainerClient {
     http: Client,
 
     /// Set of registries that should be fetched via HTTP rather than HTTPS.
-    insecure_registries: Arc<HashSet<String>>,
+    /// Replaced wholesale when the configuration is [reloaded](crate::reload).
+    insecure_registries: Arc<SyncRwLock<HashSet<String>>>,
 
     /// Global Wasm engine to run hosted servers.
     /// This must be the exact same engine used in the [store](ContainerStore).
@@ -402,12 +520,32 @@ struct ContainerClient {
 const MANIFEST_MIME: &str = "application/vnd.oci.image.manifest.v1+json";
 
 impl ContainerClient {
-    fn new(insecure_registries: HashSet<String>, wasmtime: &WasmEngine) -> Self {
-        Self {
-            http: Client::new(),
-            insecure_registries: Arc::new(insecure_registries),
+    fn new(insecure_registries: HashSet<String>, wasmtime: &WasmEngine) -> Result<Self> {
+        let insecure_registries = Arc::new(SyncRwLock::new(insecure_registries));
+        // Registries (and the storage they redirect blob downloads to)
+        // may only be reached in plaintext if they are explicitly listed as insecure.
+        let redirect = RedirectPolicy::custom({
+            let insecure_registries = insecure_registries.clone();
+            move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("Too many redirects")
+                } else if let Err(error) =
+                    check_plaintext(&insecure_registries.read().unwrap(), attempt.url())
+                {
+                    attempt.error(error)
+                } else {
+                    attempt.follow()
+                }
+            }
+        });
+        Ok(Self {
+            http: Client::builder()
+                .redirect(redirect)
+                .build()
+                .context("Failed to build HTTP client")?,
+            insecure_registries,
             wasmtime: wasmtime.clone(),
-        }
+        })
     }
 
     async fn fetch(&self, registry: &str, name: &ComponentName) -> Result<Arc<Container>> {
@@ -417,7 +555,7 @@ impl ContainerClient {
         // would begin with `/v2/1234567890abcdef1234567890abcdef/server-id/`.
         let server_url = format!(
             "{}://{}/v2/{}/{}",
-            if self.insecure_registries.contains(registry) {
+            if self.insecure_registries.read().unwrap().contains(registry) {
                 "http"
             } else {
                 "https"
@@ -446,20 +584,32 @@ impl ContainerClient {
             // All images consist of 2 layers:
             // the component byte code, followed by the serialized metadata.
             if manifest.layers.len() == 2 {
+                let progress = Arc::new(PullProgress::new(
+                    name.clone(),
+                    manifest.layers.iter().map(|layer| layer.size).sum(),
+                ));
+
                 // Fetch the layers in parallel.
-                let component_fetch = spawn(self.clone().fetch_component(format!(
-                    "{server_url}/blobs/{}",
-                    manifest.layers.get(0).unwrap().digest,
+                // The component fetch is aborted if this future is dropped (i.e. cancelled).
+                let mut component_fetch = AbortOnDrop(spawn(self.clone().fetch_component(
+                    format!(
+                        "{server_url}/blobs/{}",
+                        manifest.layers.first().unwrap().digest,
+                    ),
+                    progress.clone(),
                 )));
                 let metadata_result = self
-                    .fetch_metadata(format!(
-                        "{server_url}/blobs/{}",
-                        manifest.layers.get(1).unwrap().digest,
-                    ))
+                    .fetch_metadata(
+                        format!(
+                            "{server_url}/blobs/{}",
+                            manifest.layers.get(1).unwrap().digest,
+                        ),
+                        &progress,
+                    )
                     .await;
 
                 // Propagate compilation errors first, then metadata parsing errors.
-                let component = component_fetch
+                let component = (&mut component_fetch.0)
                     .await
                     .context("Failure joining fetch-component background task")??;
                 let metadata = metadata_result?;
@@ -481,44 +631,129 @@ impl ContainerClient {
         }
     }
 
-    async fn fetch_component(self, url: String) -> Result<Component> {
+    async fn fetch_component(self, url: String, progress: Arc<PullProgress>) -> Result<Component> {
         Component::new(
             &self.wasmtime,
-            self.fetch_blob(&url)
+            self.fetch_blob(&url, &progress)
                 .await
                 .with_context(|| format!("Failure fetching component: {:?}", url))?,
         )
         .context("Component compilation error")
     }
 
-    async fn fetch_metadata(&self, url: String) -> Result<Metadata> {
+    async fn fetch_metadata(&self, url: String, progress: &PullProgress) -> Result<Metadata> {
         // TODO: We're decoding this only to encode it again later.
         //       Avoid the unnecessary work.
         Metadata::decode(
-            self.fetch_blob(&url)
+            self.fetch_blob(&url, progress)
                 .await
                 .with_context(|| format!("Failure fetching metadata: {:?}", url))?,
         )
         .context("Failure decoding metadata")
     }
 
-    async fn fetch_blob(&self, url: &str) -> Result<Bytes> {
-        let response = self.http.get(url).send().await.context(
+    /// Stream a blob into memory, reporting each chunk to `progress` as it arrives.
+    async fn fetch_blob(&self, url: &str, progress: &PullProgress) -> Result<Bytes> {
+        let mut response = self.http.get(url).send().await.context(
             // Fails if there was an error while sending request,
             // redirect loop was detected or redirect limit was exhausted.
             "Error fetching blob",
         )?;
         if response.status() == HttpStatusCode::OK {
-            response.bytes().await.context(
+            // The advertised length is only a hint; don't let it reserve unbounded memory.
+            let mut blob = BytesMut::with_capacity(
+                response
+                    .content_length()
+                    .unwrap_or(0)
+                    .min(MAX_BLOB_PREALLOCATION) as usize,
+            );
+            while let Some(chunk) = response.chunk().await.context(
                 // Not sure when this would ever happen.
                 "Failed reading response",
-            )
+            )? {
+                progress.advance(chunk.len() as u64);
+                blob.extend_from_slice(&chunk);
+            }
+            Ok(blob.freeze())
         } else {
             Err(anyhow!("Got HTTP {}", response.status().as_u16()))
         }
     }
 }
 
+/// Identify a pod sandbox in [`ContainerStore::pulls`].
+fn pull_key(pod: &v1::PodSandboxMetadata) -> (String, u32) {
+    (pod.uid.clone(), pod.attempt)
+}
+
+/// Return an error if the URL uses plaintext HTTP to reach a host
+/// that is not one of the `insecure_registries`.
+fn check_plaintext(insecure_registries: &HashSet<String>, url: &Url) -> Result<()> {
+    if url.scheme() != "http" {
+        return Ok(());
+    }
+    // Registries are listed by host, with the port only if it is explicit.
+    let host = url.host_str().unwrap_or_default();
+    let registry = match url.port() {
+        Some(port) => format!("{host}:{port}"),
+        None => String::from(host),
+    };
+    if insecure_registries.contains(&registry) {
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "Refusing plaintext HTTP to {registry:?}, which is not an insecure registry"
+        ))
+    }
+}
+
+/// Number of bytes pulled so far for a single image, out of the total from its manifest.
+struct PullProgress {
+    /// Name of the component being pulled (used for logs).
+    name: ComponentName,
+
+    /// Total size of all layers according to the image manifest.
+    total: u64,
+
+    /// Number of bytes received so far across all layers.
+    pulled: AtomicU64,
+}
+
+impl PullProgress {
+    fn new(name: ComponentName, total: u64) -> Self {
+        Self {
+            name,
+            total,
+            pulled: AtomicU64::new(0),
+        }
+    }
+
+    /// Record that another `bytes` have been received,
+    /// logging each time another tenth of the image is complete.
+    fn advance(&self, bytes: u64) {
+        let before = self.pulled.fetch_add(bytes, Ordering::Relaxed);
+        let after = before + bytes;
+        if self.total > 0 && after * 10 / self.total > before * 10 / self.total {
+            log_info!(
+                component: &self.name,
+                "Pulled {} of {} bytes",
+                after.min(self.total),
+                self.total,
+            );
+        }
+    }
+}
+
+/// Aborts a background task when dropped,
+/// so abandoned fetches don't keep downloading in the background.
+struct AbortOnDrop<T>(JoinHandle<T>);
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
 /// See [spec](https://specs.opencontainers.org/image-spec/manifest/#image-manifest).
 #[allow(dead_code)]
 #[allow(non_snake_case)]
@@ -599,3 +834,292 @@ struct Descriptor {
     #[serde(default)]
     annotations: HashMap<String, String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use tokio::time::{sleep, timeout};
+
+    use super::*;
+
+    const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0";
+    const POD_UID: &str = "some-pod-uid";
+
+    #[tokio::test]
+    async fn test_cancel_pull() {
+        // A registry that accepts connections, reads requests, and never responds.
+        let registry = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let registry_address = registry.local_addr().unwrap().to_string();
+        spawn(async move {
+            let mut connections = Vec::new();
+            while let Ok((mut connection, _)) = registry.accept().await {
+                let mut request = [0; 1024];
+                let _ = connection.read(&mut request).await;
+                connections.push(connection);
+            }
+        });
+
+        let store = ContainerStore::new(
+            temp_dir()
+                .join(format!("vimana-containers-test-{}", std::process::id()))
+                .to_str()
+                .unwrap(),
+            HashSet::from([registry_address.clone()]),
+            &WasmEngine::default(),
+            None,
+        )
+        .unwrap();
+        let name = names::Name::parse(COMPONENT_NAME).component().unwrap();
+        let pod = v1::PodSandboxMetadata {
+            uid: String::from(POD_UID),
+            ..Default::default()
+        };
+        let pull = |pod: v1::PodSandboxMetadata| {
+            let store = store.clone();
+            let registry_address = registry_address.clone();
+            let name = name.clone();
+            spawn(async move {
+                store
+                    .pull(
+                        &registry_address,
+                        &name,
+                        &v1::ImageSpec::default(),
+                        Some(&pod),
+                    )
+                    .await
+            })
+        };
+        let cancelled = |pull: JoinHandle<Result<()>>| async move {
+            let error = timeout(Duration::from_secs(5), pull)
+                .await
+                .expect("Cancelled pull should finish promptly")
+                .unwrap()
+                .unwrap_err();
+            assert!(format!("{error}").contains("cancelled"));
+        };
+
+        let first = pull(pod.clone());
+        // Give the pull a chance to reach the registry.
+        sleep(Duration::from_millis(50)).await;
+        assert!(!first.is_finished());
+
+        store.cancel_pulls(&pod);
+        cancelled(first).await;
+
+        // A pull requested after cancellation is aborted too.
+        cancelled(pull(pod.clone())).await;
+
+        // A new attempt at the same pod is unaffected.
+        let retry = pull(v1::PodSandboxMetadata {
+            attempt: 1,
+            ..pod.clone()
+        });
+        sleep(Duration::from_millis(50)).await;
+        assert!(!retry.is_finished());
+        retry.abort();
+
+        store.forget_pulls(&pod);
+        assert!(!store.pulls.pin().contains_key(&pull_key(&pod)));
+    }
+
+    /// Format a raw HTTP response.
+    fn response(status: &str, headers: &str, body: &[u8]) -> Vec<u8> {
+        let mut response = format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len(),
+        )
+        .into_bytes();
+        response.extend_from_slice(body);
+        response
+    }
+
+    /// Serve a plaintext HTTP registry, responding to requests for each path in `routes`
+    /// with the given raw response, and to anything else (including TLS handshakes) with a 404.
+    fn serve_registry(registry: TcpListener, routes: HashMap<String, Vec<u8>>) {
+        spawn(async move {
+            while let Ok((mut connection, _)) = registry.accept().await {
+                let mut request = [0; 4096];
+                let length = connection.read(&mut request).await.unwrap_or(0);
+                let request = String::from_utf8_lossy(&request[..length]);
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let not_found = response("404 Not Found", "", &[]);
+                let _ = connection
+                    .write_all(routes.get(path).unwrap_or(&not_found))
+                    .await;
+            }
+        });
+    }
+
+    /// Routes for a registry hosting a single, valid image for [`COMPONENT_NAME`],
+    /// except that its component layer is served by `component`.
+    fn image_routes(metadata: &Metadata, component: Vec<u8>) -> HashMap<String, Vec<u8>> {
+        let name = names::Name::parse(COMPONENT_NAME).component().unwrap();
+        let server = format!("/v2/{}/{}", name.server.domain, name.server.server);
+        let metadata = metadata.encode_to_vec();
+        let manifest = format!(
+            r#"{{
+                "schemaVersion": 2,
+                "config": {{"mediaType": "application/json", "digest": "sha256:config", "size": 0}},
+                "layers": [
+                    {{"mediaType": "application/wasm", "digest": "sha256:component", "size": 0}},
+                    {{"mediaType": "application/protobuf", "digest": "sha256:metadata", "size": {}}}
+                ]
+            }}"#,
+            metadata.len(),
+        );
+        HashMap::from([
+            (
+                format!("{server}/manifests/{}", name.version),
+                response("200 OK", "", manifest.as_bytes()),
+            ),
+            (format!("{server}/blobs/sha256:component"), component),
+            (
+                format!("{server}/blobs/sha256:metadata"),
+                response("200 OK", "", &metadata),
+            ),
+        ])
+    }
+
+    #[tokio::test]
+    async fn test_insecure_registry() {
+        let registry = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let registry_address = registry.local_addr().unwrap().to_string();
+        let metadata = Metadata::default();
+        let component = response("200 OK", "", br#"(component)"#);
+        serve_registry(registry, image_routes(&metadata, component));
+
+        let store = ContainerStore::new(
+            temp_dir()
+                .join(format!("vimana-insecure-test-{}", std::process::id()))
+                .to_str()
+                .unwrap(),
+            HashSet::from([registry_address.clone()]),
+            &WasmEngine::default(),
+            None,
+        )
+        .unwrap();
+        let name = names::Name::parse(COMPONENT_NAME).component().unwrap();
+        let image_spec = v1::ImageSpec::default();
+
+        // Listed as insecure, so the registry is reached over plain HTTP.
+        store
+            .pull(&registry_address, &name, &image_spec, None)
+            .await
+            .unwrap();
+        assert_eq!(store.get(&name).await.unwrap().metadata, metadata);
+        store.remove(&name).await.unwrap();
+
+        // Otherwise, the same registry is only ever reached over HTTPS, which it doesn't speak.
+        store.set_insecure_registries(HashSet::new());
+        assert!(store
+            .pull(&registry_address, &name, &image_spec, None)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_plaintext_redirect() {
+        let registry = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = registry.local_addr().unwrap().port();
+        let registry_address = format!("127.0.0.1:{port}");
+        let storage_address = format!("localhost:{port}");
+        let metadata = Metadata::default();
+        // The component layer is served from storage, which is a different host.
+        let mut routes = image_routes(
+            &metadata,
+            response(
+                "307 Temporary Redirect",
+                &format!("Location: http://{storage_address}/storage/component\r\n"),
+                &[],
+            ),
+        );
+        routes.insert(
+            String::from("/storage/component"),
+            response("200 OK", "", br#"(component)"#),
+        );
+        serve_registry(registry, routes);
+
+        let store = ContainerStore::new(
+            temp_dir()
+                .join(format!("vimana-redirect-test-{}", std::process::id()))
+                .to_str()
+                .unwrap(),
+            HashSet::from([registry_address.clone()]),
+            &WasmEngine::default(),
+            None,
+        )
+        .unwrap();
+        let name = names::Name::parse(COMPONENT_NAME).component().unwrap();
+        let image_spec = v1::ImageSpec::default();
+
+        // Redirects to plain HTTP are refused unless the target is also listed as insecure.
+        let error = store
+            .pull(&registry_address, &name, &image_spec, None)
+            .await
+            .unwrap_err();
+        assert!(format!("{error:?}").contains("not an insecure registry"));
+
+        store.set_insecure_registries(HashSet::from([registry_address.clone(), storage_address]));
+        store
+            .pull(&registry_address, &name, &image_spec, None)
+            .await
+            .unwrap();
+        store.remove(&name).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compressed_round_trip() {
+        let wasmtime = WasmEngine::default();
+        let root = temp_dir().join(format!("vimana-compressed-test-{}", std::process::id()));
+        let store = ContainerStore::new(root.to_str().unwrap(), HashSet::new(), &wasmtime, Some(3))
+            .unwrap();
+        let name = names::Name::parse(COMPONENT_NAME).component().unwrap();
+        let metadata = Metadata {
+            service: vec![Default::default()],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let component = Component::new(
+            &wasmtime,
+            r#"(component (core module (func (export "f"))))"#,
+        )
+        .unwrap();
+        let uncompressed_size = component.serialize().unwrap().len() as u64;
+
+        store
+            .save(
+                &name,
+                &Container {
+                    component,
+                    metadata: metadata.clone(),
+                },
+                &v1::ImageSpec::default(),
+            )
+            .await
+            .unwrap();
+
+        // The component is compressed on disk.
+        let mut stored = Vec::new();
+        SyncFile::open(store.component_path(&name).join(CONTAINER_FILENAME))
+            .unwrap()
+            .read_to_end(&mut stored)
+            .unwrap();
+        assert_eq!(stored[size_of::<u64>()..][..ZSTD_MAGIC.len()], ZSTD_MAGIC);
+        assert!(store.filesystem_usage().await.unwrap().bytes < uncompressed_size);
+
+        // And loads back into the engine just the same.
+        let container = store.get(&name).await.unwrap();
+        assert_eq!(container.metadata, metadata);
+        assert_eq!(
+            container.component.serialize().unwrap().len() as u64,
+            uncompressed_size,
+        );
+
+        store.remove(&name).await.unwrap();
+    }
+}
@@ -0,0 +1,177 @@
+//! Debugging client that tails container lifecycle events from a node's CRI socket.
+//!
+//! Run as `vimanad events [--component <name>]` on the node.
+
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use hyper_util::rt::TokioIo;
+use tokio::net::UnixStream;
+use tonic::transport::Endpoint;
+use tower::service_fn;
+
+use api_proto::runtime::v1::runtime_service_client::RuntimeServiceClient;
+use api_proto::runtime::v1::{ContainerEventResponse, ContainerEventType, GetEventsRequest};
+use names::{ComponentName, Name};
+
+use super::component_name_from_labels;
+
+/// Connect to the CRI socket at `socket` and print each container event as it arrives,
+/// optionally only those for pods of the given component.
+pub(crate) async fn tail(socket: &str, component: Option<&str>) -> Result<()> {
+    let component = component
+        .map(|component| Name::parse(component).component())
+        .transpose()
+        .context("Invalid component filter")?;
+
+    let socket_path = String::from(socket);
+    let channel = Endpoint::from_static("http://unused")
+        .connect_with_connector(service_fn(move |_| {
+            let socket_path = socket_path.clone();
+            async move {
+                Ok::<_, std::io::Error>(TokioIo::new(UnixStream::connect(&socket_path).await?))
+            }
+        }))
+        .await
+        .with_context(|| format!("Unable to connect to CRI socket: {:?}", socket))?;
+
+    let mut events = RuntimeServiceClient::new(channel)
+        .get_container_events(GetEventsRequest {})
+        .await
+        .context("Failed requesting container events")?
+        .into_inner();
+    while let Some(event) = events
+        .message()
+        .await
+        .context("Container event stream failed")?
+    {
+        if matches(&event, component.as_ref()) {
+            println!("{}", format_event(&event));
+        }
+    }
+    Ok(())
+}
+
+/// Return `true` iff no filter is given,
+/// or the event's pod is labeled as belonging to the given component.
+fn matches(event: &ContainerEventResponse, component: Option<&ComponentName>) -> bool {
+    component.is_none_or(|component| {
+        event
+            .pod_sandbox_status
+            .as_ref()
+            .and_then(|pod| component_name_from_labels(&pod.labels).ok())
+            .is_some_and(|actual| &actual == component)
+    })
+}
+
+/// Render an event as a single human-readable line, e.g.:
+///
+///     1700000000.123 started container-id pod=namespace/name component=<domain>:<server>@<version>
+fn format_event(event: &ContainerEventResponse) -> String {
+    let timestamp = Duration::from_nanos(event.created_at.max(0) as u64);
+    let transition = match ContainerEventType::try_from(event.container_event_type) {
+        Ok(ContainerEventType::ContainerCreatedEvent) => "created",
+        Ok(ContainerEventType::ContainerStartedEvent) => "started",
+        Ok(ContainerEventType::ContainerStoppedEvent) => "stopped",
+        Ok(ContainerEventType::ContainerDeletedEvent) => "deleted",
+        Err(_) => "unknown",
+    };
+    let mut line = format!(
+        "{}.{:03} {transition} {}",
+        timestamp.as_secs(),
+        timestamp.subsec_millis(),
+        event.container_id,
+    );
+    if let Some(pod) = &event.pod_sandbox_status {
+        if let Some(metadata) = &pod.metadata {
+            line.push_str(&format!(" pod={}/{}", metadata.namespace, metadata.name));
+        }
+        if let Ok(component) = component_name_from_labels(&pod.labels) {
+            line.push_str(&format!(" component={component}"));
+        }
+    }
+    line
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use api_proto::runtime::v1::{PodSandboxMetadata, PodSandboxStatus};
+
+    use super::super::{LABEL_DOMAIN_KEY, LABEL_SERVER_KEY, LABEL_VERSION_KEY};
+    use super::*;
+
+    const COMPONENT: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0";
+
+    fn event(
+        event_type: ContainerEventType,
+        labels: HashMap<String, String>,
+    ) -> ContainerEventResponse {
+        ContainerEventResponse {
+            container_id: String::from("c-1234567890abcdef1234567890abcdef:some-server@1.0.0#3"),
+            container_event_type: event_type as i32,
+            created_at: 1_700_000_000_123_456_789,
+            pod_sandbox_status: Some(PodSandboxStatus {
+                metadata: Some(PodSandboxMetadata {
+                    name: String::from("some-pod"),
+                    namespace: String::from("default"),
+                    ..Default::default()
+                }),
+                labels,
+                ..Default::default()
+            }),
+            containers_statuses: Vec::new(),
+        }
+    }
+
+    fn vimana_labels() -> HashMap<String, String> {
+        HashMap::from([
+            (
+                String::from(LABEL_DOMAIN_KEY),
+                String::from("1234567890abcdef1234567890abcdef"),
+            ),
+            (String::from(LABEL_SERVER_KEY), String::from("some-server")),
+            (String::from(LABEL_VERSION_KEY), String::from("1.0.0")),
+        ])
+    }
+
+    #[test]
+    fn test_format_event() {
+        assert_eq!(
+            format_event(&event(
+                ContainerEventType::ContainerStartedEvent,
+                vimana_labels()
+            )),
+            format!(
+                "1700000000.123 started c-1234567890abcdef1234567890abcdef:some-server@1.0.0#3 \
+                pod=default/some-pod component={COMPONENT}"
+            ),
+        );
+        // Non-Vimana pods have no component.
+        assert_eq!(
+            format_event(&event(
+                ContainerEventType::ContainerDeletedEvent,
+                HashMap::new()
+            )),
+            "1700000000.123 deleted c-1234567890abcdef1234567890abcdef:some-server@1.0.0#3 \
+            pod=default/some-pod",
+        );
+    }
+
+    #[test]
+    fn test_component_filter() {
+        let component = Name::parse(COMPONENT).component().unwrap();
+        let other = Name::parse("1234567890abcdef1234567890abcdef:other@1.0.0")
+            .component()
+            .unwrap();
+        let vimana = event(ContainerEventType::ContainerCreatedEvent, vimana_labels());
+        let downstream = event(ContainerEventType::ContainerCreatedEvent, HashMap::new());
+
+        assert!(matches(&vimana, None));
+        assert!(matches(&downstream, None));
+        assert!(matches(&vimana, Some(&component)));
+        assert!(!matches(&vimana, Some(&other)));
+        assert!(!matches(&downstream, Some(&component)));
+    }
+}
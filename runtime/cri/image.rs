@@ -21,8 +21,9 @@ use tonic::transport::channel::Channel;
 use tonic::{async_trait, Request, Response};
 
 use crate::containers::ContainerStore;
-use crate::cri::runtime::CONTAINER_RUNTIME_HANDLER;
-use crate::cri::{component_name_from_labels, GlobalLogs, LogErrorToStatus, TonicResult};
+use crate::cri::{
+    component_name_from_labels, proxied, GlobalLogs, LogErrorToStatus, RuntimeHandler, TonicResult,
+};
 use crate::state::now;
 use names::{ComponentName, DomainUuid};
 
@@ -32,6 +33,9 @@ pub(crate) struct ProxyingImageService {
     /// The upstream runtime handler for all Vimana-related business logic.
     containers: ContainerStore,
 
+    /// Images requested with this runtime handler are routed to the upstream runtime.
+    handler: RuntimeHandler,
+
     /// Client to a downstream OCI container runtime (e.g. containerd or cri-o)
     /// so work nodes can run traditional OCI containers as well.
     oci_image: AsyncMutex<ImageServiceClient<Channel>>,
@@ -58,7 +62,8 @@ impl ImageService for ProxyingImageService {
                 .lock()
                 .await
                 .list_images(Request::new(request))
-                .await;
+                .await
+                .map_err(proxied("ListImages"));
         }
 
         todo!()
@@ -72,13 +77,14 @@ impl ImageService for ProxyingImageService {
 
         if let Some(image_spec) = &request.image {
             // Fall back on the downstream runtime for non-Vimana images.
-            if image_spec.runtime_handler != CONTAINER_RUNTIME_HANDLER {
+            if !self.handler.is_upstream(&image_spec.runtime_handler) {
                 return self
                     .oci_image
                     .lock()
                     .await
                     .image_status(Request::new(request))
-                    .await;
+                    .await
+                    .map_err(proxied("ImageStatus"));
             }
         }
 
@@ -117,13 +123,14 @@ impl ImageService for ProxyingImageService {
 
         if let Some(image_spec) = &request.image {
             // Fall back on the downstream runtime for non-Vimana images.
-            if image_spec.runtime_handler != CONTAINER_RUNTIME_HANDLER {
+            if !self.handler.is_upstream(&image_spec.runtime_handler) {
                 return self
                     .oci_image
                     .lock()
                     .await
                     .pull_image(Request::new(request))
-                    .await;
+                    .await
+                    .map_err(proxied("PullImage"));
             }
         }
 
@@ -134,7 +141,8 @@ impl ImageService for ProxyingImageService {
 
         // Invariant check:
         // make sure the component name from the image ID matches that from the pod's labels.
-        let pod_labels = &request.sandbox_config.unwrap_or_default().labels;
+        let sandbox_config = request.sandbox_config.unwrap_or_default();
+        let pod_labels = &sandbox_config.labels;
         let name = component_name_from_labels(pod_labels)
             .with_context(|| format!("Invalid pod labels: {:?}", pod_labels))
             .log_error(&name_from_image)?;
@@ -148,7 +156,12 @@ impl ImageService for ProxyingImageService {
         }
 
         self.containers
-            .pull(&registry, &name, &image_spec)
+            .pull(
+                &registry,
+                &name,
+                &image_spec,
+                sandbox_config.metadata.as_ref(),
+            )
             .await
             .with_context(|| format!("Error pulling image: {:?}", image_spec.image))
             .log_error(&name)?;
@@ -166,13 +179,14 @@ impl ImageService for ProxyingImageService {
 
         if let Some(image_spec) = &request.image {
             // Fall back on the downstream runtime for non-Vimana images.
-            if image_spec.runtime_handler != CONTAINER_RUNTIME_HANDLER {
+            if !self.handler.is_upstream(&image_spec.runtime_handler) {
                 return self
                     .oci_image
                     .lock()
                     .await
                     .remove_image(Request::new(request))
-                    .await;
+                    .await
+                    .map_err(proxied("RemoveImage"));
             }
         }
 
@@ -202,7 +216,8 @@ impl ImageService for ProxyingImageService {
             .lock()
             .await
             .image_fs_info(Request::new(request))
-            .await?;
+            .await
+            .map_err(proxied("ImageFsInfo"))?;
 
         // Insert the upstream info.
         let usage = self
@@ -229,15 +244,22 @@ impl ImageService for ProxyingImageService {
 }
 
 impl ProxyingImageService {
-    pub(crate) fn new(containers: ContainerStore, oci_image: ImageServiceClient<Channel>) -> Self {
+    pub(crate) fn new(
+        containers: ContainerStore,
+        handler: RuntimeHandler,
+        oci_image: ImageServiceClient<Channel>,
+    ) -> Self {
         Self {
             containers,
+            handler,
             oci_image: AsyncMutex::new(oci_image),
         }
     }
 }
 
-fn registry_and_component_from_image_spec(image_id: &str) -> Result<(String, ComponentName)> {
+pub(crate) fn registry_and_component_from_image_spec(
+    image_id: &str,
+) -> Result<(String, ComponentName)> {
     lazy_static! {
         // Use a permissive regex to parse the image ID:
         //     <registry>/<domain-id>/<server-id>:<version>
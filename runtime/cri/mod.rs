@@ -4,13 +4,19 @@
 
 use std::collections::HashMap;
 use std::result::Result as StdResult;
+use std::sync::Arc;
 
 use anyhow::{anyhow, Error, Result};
+use bytes::Bytes;
+use clap::ValueEnum;
+use serde::Deserialize;
+use tonic::metadata::MetadataValue;
 use tonic::{Response, Status};
 
 use logging::{log_error, log_error_globally};
 use names::{ComponentName, DomainUuid, PodName};
 
+pub(crate) mod events;
 pub(crate) mod image;
 pub(crate) mod runtime;
 
@@ -23,6 +29,81 @@ const LABEL_DOMAIN_KEY: &str = "vimana.host/domain";
 const LABEL_SERVER_KEY: &str = "vimana.host/server";
 const LABEL_VERSION_KEY: &str = "vimana.host/version";
 
+/// Name of the runtime handler (the K8s runtime class handler)
+/// that routes pods and images to the Vimana runtime.
+/// Every other handler is served by the downstream OCI runtime,
+/// subject to the [policy](UnknownHandlerPolicy) for handlers it doesn't recognize.
+#[derive(Clone)]
+pub(crate) struct RuntimeHandler(Arc<RuntimeHandlerInner>);
+
+struct RuntimeHandlerInner {
+    /// The Vimana handler.
+    name: String,
+
+    /// Handlers known to be served by the downstream runtime.
+    /// The empty (default) handler is always included.
+    downstream: Vec<String>,
+
+    /// What to do with a handler that is neither Vimana's nor in `downstream`.
+    policy: UnknownHandlerPolicy,
+}
+
+/// How to treat a pod requesting a runtime handler that isn't recognized.
+#[derive(Clone, Copy, Default, PartialEq, Debug, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum UnknownHandlerPolicy {
+    /// Forward the request to the downstream runtime anyway.
+    #[default]
+    Forward,
+
+    /// Fail the request with an error listing the recognized handlers.
+    Reject,
+}
+
+impl RuntimeHandler {
+    pub(crate) fn new(name: &str, downstream: &[String], policy: UnknownHandlerPolicy) -> Self {
+        let mut downstream = downstream.to_vec();
+        if !downstream.iter().any(String::is_empty) {
+            downstream.insert(0, String::new());
+        }
+        Self(Arc::new(RuntimeHandlerInner {
+            name: String::from(name),
+            downstream,
+            policy,
+        }))
+    }
+
+    /// Return `true` iff requests for `handler` belong to the Vimana runtime.
+    fn is_upstream(&self, handler: &str) -> bool {
+        self.0.name == handler
+    }
+
+    /// Check that a non-Vimana `handler` may be forwarded downstream.
+    /// Under the [reject](UnknownHandlerPolicy::Reject) policy,
+    /// this fails for any handler that isn't recognized,
+    /// which usually means a misspelled runtime class.
+    fn check_downstream(&self, handler: &str) -> Result<()> {
+        if self.0.policy == UnknownHandlerPolicy::Forward
+            || self.0.downstream.iter().any(|known| known == handler)
+        {
+            return Ok(());
+        }
+        let recognized = std::iter::once(&self.0.name)
+            .chain(self.0.downstream.iter())
+            .map(|known| format!("{known:?}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        Err(Status::invalid_argument(format!(
+            "Unknown runtime handler {handler:?} (recognized handlers: {recognized})"
+        ))
+        .into())
+    }
+
+    fn name(&self) -> &str {
+        &self.0.name
+    }
+}
+
 fn component_name_from_labels(labels: &HashMap<String, String>) -> Result<ComponentName> {
     ComponentName::new(
         DomainUuid::parse(
@@ -43,6 +124,31 @@ fn component_name_from_labels(labels: &HashMap<String, String>) -> Result<Compon
     )
 }
 
+/// Status metadata key naming the CRI method that failed in the downstream runtime.
+const PROXIED_METADATA_KEY: &str = "vimana-proxied";
+
+/// Attribute errors from requests forwarded to the downstream runtime,
+/// so it's clear from Kubelet's side that Vimana was not at fault.
+///
+/// Return a function (*e.g.* for [`Result::map_err`]) that prefixes the error message
+/// with the proxied `method` and records it under [`PROXIED_METADATA_KEY`].
+/// The status code, details, and any other metadata are preserved.
+fn proxied(method: &'static str) -> impl FnOnce(Status) -> Status {
+    move |status| {
+        let mut metadata = status.metadata().clone();
+        metadata.insert(PROXIED_METADATA_KEY, MetadataValue::from_static(method));
+        Status::with_details_and_metadata(
+            status.code(),
+            format!(
+                "{method} failed in the downstream runtime: {}",
+                status.message()
+            ),
+            Bytes::copy_from_slice(status.details()),
+            metadata,
+        )
+    }
+}
+
 trait LogErrorToStatus<T> {
     #[track_caller]
     fn log_error(self, context: impl ErrorLoggingContext) -> StdResult<T, Status>;
@@ -91,3 +197,68 @@ impl ErrorLoggingContext for &PodName {
         log_error!(pod: *self, "{:?}", error);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_custom_runtime_handler() {
+        let handler = RuntimeHandler::new("wasm-fast", &[], UnknownHandlerPolicy::Forward);
+        assert!(handler.is_upstream("wasm-fast"));
+        // Neither the default handler nor the OCI default (empty) handler is routed upstream.
+        assert!(!handler.is_upstream("vimana-handler"));
+        assert!(!handler.is_upstream(""));
+        assert_eq!(handler.clone().name(), "wasm-fast");
+    }
+
+    #[test]
+    fn test_proxied_error() {
+        let status = proxied("StopPodSandbox")(Status::not_found("sandbox \"abc\" not found"));
+        // Kubelet depends on the code being unchanged.
+        assert_eq!(status.code(), tonic::Code::NotFound);
+        assert_eq!(
+            status.message(),
+            "StopPodSandbox failed in the downstream runtime: sandbox \"abc\" not found",
+        );
+        assert_eq!(
+            status.metadata().get(PROXIED_METADATA_KEY).unwrap(),
+            "StopPodSandbox"
+        );
+    }
+
+    #[test]
+    fn test_unknown_handler_forwarded() {
+        let handler = RuntimeHandler::new(
+            "vimana-handler",
+            &[String::from("runc")],
+            UnknownHandlerPolicy::Forward,
+        );
+        assert!(!handler.is_upstream("vimana-handlr"));
+        assert!(handler.check_downstream("vimana-handlr").is_ok());
+        assert!(handler.check_downstream("runc").is_ok());
+    }
+
+    #[test]
+    fn test_unknown_handler_rejected() {
+        let handler = RuntimeHandler::new(
+            "vimana-handler",
+            &[String::from("runc")],
+            UnknownHandlerPolicy::Reject,
+        );
+        assert!(handler.check_downstream("runc").is_ok());
+        // The default handler is always recognized.
+        assert!(handler.check_downstream("").is_ok());
+
+        let status = handler
+            .check_downstream("vimana-handlr")
+            .log_error(GlobalLogs)
+            .unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+        assert_eq!(
+            status.message(),
+            "Unknown runtime handler \"vimana-handlr\" \
+             (recognized handlers: \"vimana-handler\", \"\", \"runc\")",
+        );
+    }
+}
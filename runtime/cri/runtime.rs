@@ -15,7 +15,7 @@
 //! to each container and pod sandbox ID in responses and requests, respectively,
 //! to distinguish which runtime each belongs to.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt::Display;
 use std::result::Result as StdResult;
 use std::sync::Arc;
@@ -26,22 +26,30 @@ use api_proto::runtime::v1;
 use api_proto::runtime::v1::runtime_service_client::RuntimeServiceClient;
 use api_proto::runtime::v1::runtime_service_server::RuntimeService;
 use papaya::HashSet as LockFreeConcurrentHashSet;
-use tokio::sync::Mutex as AsyncMutex;
+use serde::Serialize;
+use serde_json::to_string;
+use tokio::sync::{mpsc, Mutex as AsyncMutex};
+use tokio::task::spawn;
 use tokio_stream::wrappers::ReceiverStream;
+use tonic::metadata::{MetadataMap, MetadataValue};
 use tonic::transport::channel::Channel;
 use tonic::{async_trait, Request, Response, Status};
 
-use crate::cri::{component_name_from_labels, GlobalLogs, LogErrorToStatus, TonicResult};
-use crate::state::{now, Pod, PodState};
-use crate::WorkRuntime;
+use crate::affinity::CpuSet;
+use crate::cri::{
+    component_name_from_labels, proxied, GlobalLogs, LogErrorToStatus, RuntimeHandler, TonicResult,
+};
+use crate::health::{Checked, Probe, PROBE_COMMAND};
+use crate::state::{now, ContainerEvent, Pod, PodState};
+use crate::usage::UsageSample;
+use crate::{WorkRuntime, WASM_FEATURES};
+use logging::log_warn_globally;
 use names::{Name, PodName};
 
 /// "For now it expects 0.1.0." - https://github.com/cri-o/cri-o/blob/v1.31.3/server/version.go.
 const KUBELET_API_VERSION: &str = "0.1.0";
 /// Name of the Vimana container runtime.
 pub(crate) const CONTAINER_RUNTIME_NAME: &str = "vimana";
-/// Name of the Vimana container runtime handler.
-pub(super) const CONTAINER_RUNTIME_HANDLER: &str = "vimana-handler";
 /// Version of the Vimana container runtime.
 pub(crate) const CONTAINER_RUNTIME_VERSION: &str = "0.0.0";
 /// Version of the CRI API supported by the runtime.
@@ -64,8 +72,9 @@ const POD_STATES_CONTAINER_CREATED: [PodState; 2] = [PodState::Created, PodState
 /// Pod states matching [`v1::ContainerState::ContainerRunning`].
 const POD_STATES_CONTAINER_RUNNING: [PodState; 1] = [PodState::Running];
 /// Pod states matching [`v1::ContainerState::ContainerExited`].
-const POD_STATES_CONTAINER_EXITED: [PodState; 3] =
-    [PodState::Stopped, PodState::Removed, PodState::Killed];
+/// A running pod whose server exited on its own, without waiting for `StopContainer`, also matches.
+/// Removed and killed pods have no container left to report.
+const POD_STATES_CONTAINER_EXITED: [PodState; 2] = [PodState::Running, PodState::Stopped];
 /// Pod states matching [`v1::ContainerState::ContainerUnknown`].
 const POD_STATES_CONTAINER_UNKNOWN: [PodState; 0] = [];
 
@@ -74,12 +83,69 @@ const POD_STATES_CONTAINER_UNKNOWN: [PodState; 0] = [];
 const CONDITION_RUNTIME_READY: &str = "RuntimeReady";
 const CONDITION_NETWORK_READY: &str = "NetworkReady";
 
+// Reasons for Vimana's own conditions not being met:
+
+const REASON_RUNTIME_NOT_READY: &str = "VimanaRuntimeNotReady";
+const REASON_NETWORK_NOT_READY: &str = "VimanaNetworkNotReady";
+
+/// Key of Vimana's own [node information](NodeInfo) in the [`v1::StatusResponse`] info map,
+/// next to whatever keys the downstream runtime reports.
+const INFO_KEY: &str = "vimana";
+
+/// CFS scheduling period in microseconds, as Kubelet sets it by default.
+/// A CPU quota of this many microseconds per period amounts to one whole core.
+const CPU_PERIOD_MICROS: i64 = 100_000;
+
+/// How many container events may be buffered for a `GetContainerEvents` client
+/// before waiting for it to catch up.
+const CONTAINER_EVENT_CHANNEL_SIZE: usize = 64;
+
+/// Request metadata key limiting how many Vimana pods or containers
+/// are returned by one `ListPodSandbox` or `ListContainers` call.
+/// CRI has no notion of pagination, so clients opt in through metadata.
+const PAGE_SIZE_METADATA_KEY: &str = "vimana-page-size";
+
+/// Request and response metadata key carrying the continuation token
+/// from one page of `ListPodSandbox` or `ListContainers` to the next.
+const PAGE_TOKEN_METADATA_KEY: &str = "vimana-page-token";
+
+/// Vimana's view of the node, reported by verbose `Status` requests (e.g. `crictl info`).
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct NodeInfo {
+    /// Version of this runtime.
+    version: &'static str,
+
+    /// Wasm proposals enabled in the engine.
+    engine_features: &'static [&'static str],
+
+    /// Number of pods in each lifecycle state.
+    pods: BTreeMap<String, usize>,
+
+    /// Pod IP address pool utilization.
+    ipam: IpamInfo,
+}
+
+/// See [`NodeInfo::ipam`].
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct IpamInfo {
+    /// Addresses held by pods that have not been killed.
+    allocated: usize,
+
+    /// Total addresses in the pod subnet.
+    capacity: u64,
+}
+
 /// Wrapper around [WorkRuntime] that implements [RuntimeService]
 /// with a downstream server for OCI requests.
 pub(crate) struct ProxyingRuntimeService {
     /// The upstream runtime handler for all Vimana-related business logic.
     runtime: WorkRuntime,
 
+    /// Pods requesting this runtime handler are routed to the upstream runtime.
+    handler: RuntimeHandler,
+
     /// Client to a downstream OCI container runtime (e.g. containerd or cri-o)
     /// so work nodes can run traditional OCI containers as well.
     downstream: AsyncMutex<RuntimeServiceClient<Channel>>,
@@ -134,8 +200,17 @@ impl RuntimeService for ProxyingRuntimeService {
         // Unless `vimanad` is explicitly chosen,
         // forward all requests to the downstream OCI runtime.
         // This supports running K8s control plane pods like `kube-controller-manager` etc.
-        if request.get_ref().runtime_handler != CONTAINER_RUNTIME_HANDLER {
-            let response = self.downstream.lock().await.run_pod_sandbox(request).await;
+        if !self.handler.is_upstream(&request.get_ref().runtime_handler) {
+            self.handler
+                .check_downstream(&request.get_ref().runtime_handler)
+                .log_error(GlobalLogs)?;
+            let response = self
+                .downstream
+                .lock()
+                .await
+                .run_pod_sandbox(request)
+                .await
+                .map_err(proxied("RunPodSandbox"));
             if let Ok(reply) = &response {
                 let pod_sandbox_id = reply.get_ref().pod_sandbox_id.clone();
                 self.downstream_ids.pin().insert(pod_sandbox_id);
@@ -178,7 +253,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::StopPodSandboxRequest>,
     ) -> TonicResult<v1::StopPodSandboxResponse> {
         if self.is_downstream(&request.get_ref().pod_sandbox_id) {
-            return self.downstream.lock().await.stop_pod_sandbox(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .stop_pod_sandbox(request)
+                .await
+                .map_err(proxied("StopPodSandbox"));
         }
 
         let name = parse_pod_prefixed_name(&request.get_ref().pod_sandbox_id)
@@ -201,7 +282,8 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .remove_pod_sandbox(request)
-                .await;
+                .await
+                .map_err(proxied("RemovePodSandbox"));
             if response.is_ok() {
                 self.downstream_ids.pin().remove(&pod_sandbox_id);
             }
@@ -227,7 +309,8 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .pod_sandbox_status(request)
-                .await;
+                .await
+                .map_err(proxied("PodSandboxStatus"));
         }
 
         let name = parse_pod_prefixed_name(&request.get_ref().pod_sandbox_id)
@@ -239,7 +322,7 @@ impl RuntimeService for ProxyingRuntimeService {
             &name,
             &Vec::default(),
             None,
-            &cri_pod_sandbox_status,
+            &|name: &PodName, pod: &Pod| cri_pod_sandbox_status(name, pod, &self.handler),
             &mut pod_sandbox_status,
         );
         let timestamp = now();
@@ -265,22 +348,23 @@ impl RuntimeService for ProxyingRuntimeService {
         // In theory, there might be a filter on pod sandbox ID
         // that would obviate the need to search both runtimes,
         // but in practice kubelet never populates the ID field in the filter.
-        self.downstream
+        let page = requested_page(request.metadata()).map_err(|status| *status)?;
+        let mut items = self
+            .downstream
             .lock()
             .await
             .list_pod_sandbox(Request::new(request.get_ref().clone()))
             .await
-            .and_then(|mut downstream_result| {
-                // Upstream is the Vimana runtime.
-                self.list_pod_sandbox_upstream(request.into_inner())
-                    .map(|upstream_result| {
-                        downstream_result
-                            .get_mut()
-                            .items
-                            .append(&mut upstream_result.into_inner().items);
-                        downstream_result
-                    })
-            })
+            .map_err(proxied("ListPodSandbox"))?
+            .into_inner()
+            .items;
+        // Upstream is the Vimana runtime.
+        let upstream_result = self.list_pod_sandbox_upstream(request.into_inner())?;
+        items.append(&mut upstream_result.into_inner().items);
+
+        let mut response = v1::ListPodSandboxResponse::default();
+        let next = page.collect(items, |item| &item.id, &mut response.items);
+        paged_response(response, next)
     }
 
     async fn create_container(
@@ -288,7 +372,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::CreateContainerRequest>,
     ) -> TonicResult<v1::CreateContainerResponse> {
         if self.is_downstream(&request.get_ref().pod_sandbox_id) {
-            let response = self.downstream.lock().await.create_container(request).await;
+            let response = self
+                .downstream
+                .lock()
+                .await
+                .create_container(request)
+                .await
+                .map_err(proxied("CreateContainer"));
             if let Ok(reply) = &response {
                 self.downstream_ids
                     .pin()
@@ -364,7 +454,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::StartContainerRequest>,
     ) -> TonicResult<v1::StartContainerResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.start_container(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .start_container(request)
+                .await
+                .map_err(proxied("StartContainer"));
         }
 
         let name = parse_container_prefixed_name(&request.get_ref().container_id)
@@ -381,7 +477,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::StopContainerRequest>,
     ) -> TonicResult<v1::StopContainerResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.stop_container(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .stop_container(request)
+                .await
+                .map_err(proxied("StopContainer"));
         }
 
         let name = parse_container_prefixed_name(&request.get_ref().container_id)
@@ -403,7 +505,13 @@ impl RuntimeService for ProxyingRuntimeService {
     ) -> TonicResult<v1::RemoveContainerResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
             let container_id = request.get_ref().container_id.clone();
-            let response = self.downstream.lock().await.remove_container(request).await;
+            let response = self
+                .downstream
+                .lock()
+                .await
+                .remove_container(request)
+                .await
+                .map_err(proxied("RemoveContainer"));
             if response.is_ok() {
                 self.downstream_ids.pin().remove(&container_id);
             }
@@ -427,21 +535,26 @@ impl RuntimeService for ProxyingRuntimeService {
         // In theory, there might be a filter on container ID
         // that would obviate the need to search both runtimes,
         // but in practice kubelet never populates the ID field in the filter.
-        self.downstream
+        let page = requested_page(request.metadata()).map_err(|status| *status)?;
+        let mut containers = self
+            .downstream
             .lock()
             .await
             .list_containers(Request::new(request.get_ref().clone()))
             .await
-            .and_then(|mut downstream_result| {
-                self.list_containers_upstream(request.into_inner())
-                    .map(|upstream_result| {
-                        downstream_result
-                            .get_mut()
-                            .containers
-                            .append(&mut upstream_result.into_inner().containers);
-                        downstream_result
-                    })
-            })
+            .map_err(proxied("ListContainers"))?
+            .into_inner()
+            .containers;
+        let upstream_result = self.list_containers_upstream(request.into_inner())?;
+        containers.append(&mut upstream_result.into_inner().containers);
+
+        let mut response = v1::ListContainersResponse::default();
+        let next = page.collect(
+            containers,
+            |container| &container.id,
+            &mut response.containers,
+        );
+        paged_response(response, next)
     }
 
     async fn container_status(
@@ -449,7 +562,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::ContainerStatusRequest>,
     ) -> TonicResult<v1::ContainerStatusResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.container_status(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .container_status(request)
+                .await
+                .map_err(proxied("ContainerStatus"));
         }
 
         let name = parse_container_prefixed_name(&request.get_ref().container_id)
@@ -486,10 +605,33 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .update_container_resources(request)
-                .await;
+                .await
+                .map_err(proxied("UpdateContainerResources"));
         }
 
-        todo!()
+        let name = parse_container_prefixed_name(&request.get_ref().container_id)
+            .context("Invalid container ID")
+            .log_error(GlobalLogs)?;
+
+        // The CPU set is the only Linux resource that is meaningful for a Wasm component.
+        // Everything else (CPU shares and quota, memory and swap limits, NUMA memory nodes,
+        // hugepages, OOM score, etc.) is deliberately ignored without error.
+        let cpuset = request
+            .get_ref()
+            .linux
+            .as_ref()
+            .map(|linux| linux.cpuset_cpus.as_str())
+            .filter(|cpuset| !cpuset.is_empty());
+        if let Some(cpuset) = cpuset {
+            let cpuset = CpuSet::parse(cpuset)
+                .with_context(|| format!("Invalid cpuset: {cpuset:?}"))
+                .log_error(&name)?;
+            self.runtime
+                .update_container_resources(&name, cpuset)
+                .log_error(&name)?;
+        }
+
+        Ok(Response::new(v1::UpdateContainerResourcesResponse {}))
     }
 
     async fn reopen_container_log(
@@ -502,7 +644,8 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .reopen_container_log(request)
-                .await;
+                .await
+                .map_err(proxied("ReopenContainerLog"));
         }
 
         todo!()
@@ -513,15 +656,47 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::ExecSyncRequest>,
     ) -> TonicResult<v1::ExecSyncResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.exec_sync(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .exec_sync(request)
+                .await
+                .map_err(proxied("ExecSync"));
         }
 
-        todo!()
+        let name = parse_container_prefixed_name(&request.get_ref().container_id)
+            .context("Invalid container ID")
+            .log_error(GlobalLogs)?;
+
+        // Wasm components have nothing to execute.
+        // The only supported commands are exec probes for health checks.
+        let Some(probe) = Probe::from_command(&request.get_ref().cmd) else {
+            return Err(Status::unimplemented(format!(
+                "Vimana containers only support `{PROBE_COMMAND} liveness|readiness` commands",
+            )));
+        };
+        let passing = self.runtime.probe(&name, probe).await.log_error(&name)?;
+        Ok(Response::new(v1::ExecSyncResponse {
+            stdout: Vec::default(),
+            stderr: if passing {
+                Vec::default()
+            } else {
+                format!("{probe:?} check failed").into_bytes()
+            },
+            exit_code: if passing { 0 } else { 1 },
+        }))
     }
 
     async fn exec(&self, request: Request<v1::ExecRequest>) -> TonicResult<v1::ExecResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.exec(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .exec(request)
+                .await
+                .map_err(proxied("Exec"));
         }
 
         todo!()
@@ -529,7 +704,13 @@ impl RuntimeService for ProxyingRuntimeService {
 
     async fn attach(&self, request: Request<v1::AttachRequest>) -> TonicResult<v1::AttachResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.attach(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .attach(request)
+                .await
+                .map_err(proxied("Attach"));
         }
 
         todo!()
@@ -540,7 +721,13 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::PortForwardRequest>,
     ) -> TonicResult<v1::PortForwardResponse> {
         if self.is_downstream(&request.get_ref().pod_sandbox_id) {
-            return self.downstream.lock().await.port_forward(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .port_forward(request)
+                .await
+                .map_err(proxied("PortForward"));
         }
 
         todo!()
@@ -551,22 +738,57 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::ContainerStatsRequest>,
     ) -> TonicResult<v1::ContainerStatsResponse> {
         if self.is_downstream(&request.get_ref().container_id) {
-            return self.downstream.lock().await.container_stats(request).await;
+            return self
+                .downstream
+                .lock()
+                .await
+                .container_stats(request)
+                .await
+                .map_err(proxied("ContainerStats"));
         }
 
-        todo!()
+        let name = parse_container_prefixed_name(&request.get_ref().container_id)
+            .context("Invalid container ID")
+            .log_error(GlobalLogs)?;
+
+        let mut container_stats = Vec::with_capacity(1);
+        self.runtime.get_container(
+            &name,
+            &Vec::default(),
+            &POD_STATES_CONTAINER_ALL,
+            &cri_container_stats,
+            &mut container_stats,
+        );
+
+        container_stats.pop().map_or_else(
+            || Err(Status::not_found(name.to_string())),
+            |stats| {
+                Ok(Response::new(v1::ContainerStatsResponse {
+                    stats: Some(stats),
+                }))
+            },
+        )
     }
 
     async fn list_container_stats(
         &self,
         request: Request<v1::ListContainerStatsRequest>,
     ) -> TonicResult<v1::ListContainerStatsResponse> {
-        // TODO: Figure out how to list container stats upstream as well.
+        // Combine the results of both runtimes, as with `ListContainers`.
         self.downstream
             .lock()
             .await
-            .list_container_stats(request)
+            .list_container_stats(Request::new(request.get_ref().clone()))
             .await
+            .map_err(proxied("ListContainerStats"))
+            .map(|mut downstream_result| {
+                let mut upstream_result = self.list_container_stats_upstream(request.into_inner());
+                downstream_result
+                    .get_mut()
+                    .stats
+                    .append(&mut upstream_result.get_mut().stats);
+                downstream_result
+            })
     }
 
     async fn pod_sandbox_stats(
@@ -579,7 +801,8 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .pod_sandbox_stats(request)
-                .await;
+                .await
+                .map_err(proxied("PodSandboxStats"));
         }
 
         todo!()
@@ -595,6 +818,7 @@ impl RuntimeService for ProxyingRuntimeService {
             .await
             .list_pod_sandbox_stats(request)
             .await
+            .map_err(proxied("ListPodSandboxStats"))
     }
 
     async fn update_runtime_config(
@@ -607,55 +831,51 @@ impl RuntimeService for ProxyingRuntimeService {
             .await
             .update_runtime_config(request)
             .await
+            .map_err(proxied("UpdateRuntimeConfig"))
     }
 
     async fn status(&self, request: Request<v1::StatusRequest>) -> TonicResult<v1::StatusResponse> {
-        //// These are the only 2 required conditions.
-        //let mut runtime_ready_condition = v1::RuntimeCondition {
-        //    r#type: String::from(CONDITION_RUNTIME_READY),
-        //    status: true,
-        //    reason: String::default(),
-        //    message: String::default(),
-        //};
-        //let mut network_ready_condition = v1::RuntimeCondition {
-        //    r#type: String::from(CONDITION_NETWORK_READY),
-        //    status: true,
-        //    reason: String::default(),
-        //    message: String::default(),
-        //};
-
-        //// TODO: Populate these with relevant information.
-        //let mut info = HashMap::default();
-        //let mut runtime_handlers = Vec::default();
-
-        match self
+        let verbose = request.get_ref().verbose;
+        // A downstream hiccup alone should not make the whole node unschedulable.
+        let downstream_response = match self
             .downstream
             .lock()
             .await
             .status(Request::new(request.get_ref().clone()))
             .await
+            .map_err(proxied("Status"))
         {
-            Ok(downstream_response) => {
-                return Ok(downstream_response);
-                //let downstream_response = downstream_response.into_inner();
-                //// TODO: Adjust upstream conditions based on downstream conditions.
-                //info.extend(downstream_response.info);
-                //runtime_handlers.extend(downstream_response.runtime_handlers);
+            Ok(response) => Some(response.into_inner()),
+            Err(status) => {
+                log_warn_globally!("{}", status.message());
+                None
             }
-            Err(downstream_error) => {
-                // TODO: Don't fail closed on the downstream runtime if it's not necessary.
-                return Err(downstream_error);
-            }
-        }
-
-        //Ok(Response::new(v1::StatusResponse {
-        //    status: Some(v1::RuntimeStatus {
-        //        conditions: vec![runtime_ready_condition, network_ready_condition],
-        //    }),
-        //    info,
-        //    runtime_handlers,
-        //    features: None,
-        //}))
+        };
+        let conditions = vec![
+            cri_condition(
+                CONDITION_RUNTIME_READY,
+                REASON_RUNTIME_NOT_READY,
+                self.runtime.check_runtime(),
+            ),
+            cri_condition(
+                CONDITION_NETWORK_READY,
+                REASON_NETWORK_NOT_READY,
+                self.runtime.check_network(),
+            ),
+        ];
+
+        // Info is only expected in verbose mode; don't bother assembling it otherwise.
+        let info = verbose
+            .then(|| self.node_info())
+            .map(|info| to_string(&info))
+            .transpose()
+            .map_err(|error| Status::internal(format!("Failed serializing node info: {error}")))?;
+        Ok(Response::new(merge_status(
+            self.handler.name(),
+            conditions,
+            info,
+            downstream_response,
+        )))
     }
 
     async fn checkpoint_container(
@@ -668,7 +888,8 @@ impl RuntimeService for ProxyingRuntimeService {
                 .lock()
                 .await
                 .checkpoint_container(request)
-                .await;
+                .await
+                .map_err(proxied("CheckpointContainer"));
         }
 
         todo!()
@@ -680,8 +901,61 @@ impl RuntimeService for ProxyingRuntimeService {
         &self,
         request: Request<v1::GetEventsRequest>,
     ) -> TonicResult<Self::GetContainerEventsStream> {
-        // TODO: Figure out how streaming works.
-        return Err(Status::internal("GetContainerEvents TODO"));
+        // Subscribe before contacting the downstream runtime
+        // so no upstream events are missed in the meantime.
+        let mut upstream_events = self.runtime.subscribe_events();
+        let downstream_events = self
+            .downstream
+            .lock()
+            .await
+            .get_container_events(request)
+            .await;
+
+        let (sender, receiver) = mpsc::channel(CONTAINER_EVENT_CHANNEL_SIZE);
+        match downstream_events {
+            Ok(response) => {
+                let mut events = response.into_inner();
+                let sender = sender.clone();
+                spawn(async move {
+                    loop {
+                        match events.message().await {
+                            Ok(Some(event)) => {
+                                if sender.send(Ok(event)).await.is_err() {
+                                    // The client hung up.
+                                    break;
+                                }
+                            }
+                            Ok(None) => break,
+                            Err(status) => {
+                                // End the merged stream so the client knows to resubscribe.
+                                let _ = sender.send(Err(status)).await;
+                                break;
+                            }
+                        }
+                    }
+                });
+            }
+            // Not every downstream runtime supports streaming events.
+            Err(status) => log_warn_globally!(
+                "Downstream runtime does not stream container events: {}",
+                status.message(),
+            ),
+        }
+
+        let handler = self.handler.clone();
+        spawn(async move {
+            let transform = |event: &ContainerEvent, pod: Option<&Pod>| {
+                cri_container_event(event, pod, &handler)
+            };
+            while let Some(event) = upstream_events.next(&transform).await {
+                if sender.send(Ok(event)).await.is_err() {
+                    // The client hung up.
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(receiver)))
     }
 
     async fn list_metric_descriptors(
@@ -694,6 +968,7 @@ impl RuntimeService for ProxyingRuntimeService {
             .await
             .list_metric_descriptors(request)
             .await
+            .map_err(proxied("ListMetricDescriptors"))
     }
 
     async fn list_pod_sandbox_metrics(
@@ -706,6 +981,7 @@ impl RuntimeService for ProxyingRuntimeService {
             .await
             .list_pod_sandbox_metrics(request)
             .await
+            .map_err(proxied("ListPodSandboxMetrics"))
     }
 
     async fn runtime_config(
@@ -713,7 +989,12 @@ impl RuntimeService for ProxyingRuntimeService {
         request: Request<v1::RuntimeConfigRequest>,
     ) -> TonicResult<v1::RuntimeConfigResponse> {
         // TODO: Also merge in stats about the upstream system!
-        self.downstream.lock().await.runtime_config(request).await
+        self.downstream
+            .lock()
+            .await
+            .runtime_config(request)
+            .await
+            .map_err(proxied("RuntimeConfig"))
     }
 
     async fn update_pod_sandbox_resources(
@@ -727,6 +1008,7 @@ impl RuntimeService for ProxyingRuntimeService {
 impl ProxyingRuntimeService {
     pub(crate) async fn new(
         runtime: WorkRuntime,
+        handler: RuntimeHandler,
         mut downstream: RuntimeServiceClient<Channel>,
     ) -> Result<Self> {
         // On startup, list any pre-existing pod sandboxes or containers in the downstream runtime,
@@ -755,6 +1037,7 @@ impl ProxyingRuntimeService {
 
         Ok(Self {
             runtime,
+            handler,
             downstream: AsyncMutex::new(downstream),
             downstream_ids,
         })
@@ -769,6 +1052,29 @@ impl ProxyingRuntimeService {
             || !(id.starts_with(POD_PREFIX) || id.starts_with(CONTAINER_PREFIX))
     }
 
+    /// Summarize the Vimana side of the node for verbose status requests.
+    fn node_info(&self) -> NodeInfo {
+        let counts = self.runtime.pod_counts();
+        // Killed pods have released their IP addresses.
+        let allocated = counts
+            .iter()
+            .filter(|(state, _)| **state != PodState::Killed)
+            .map(|(_, count)| count)
+            .sum();
+        NodeInfo {
+            version: CONTAINER_RUNTIME_VERSION,
+            engine_features: &WASM_FEATURES,
+            pods: counts
+                .into_iter()
+                .map(|(state, count)| (format!("{state:?}"), count))
+                .collect(),
+            ipam: IpamInfo {
+                allocated,
+                capacity: self.runtime.ip_pool_size(),
+            },
+        }
+    }
+
     /// Perform sandbox listing in the Vimana runtime.
     fn list_pod_sandbox_upstream(
         &self,
@@ -784,6 +1090,7 @@ impl ProxyingRuntimeService {
         let readiness = filter
             .state
             .map(|state| state.state == v1::PodSandboxState::SandboxReady as i32);
+        let transform = |name: &PodName, pod: &Pod| cri_pod_sandbox(name, pod, &self.handler);
 
         // Filter ID, if present, can speed things up a lot.
         if filter.id.len() > 0 {
@@ -793,13 +1100,8 @@ impl ProxyingRuntimeService {
             if let Ok(name) = parse_pod_prefixed_name(&filter.id) {
                 // If it's a complete, parseable pod name (after the prefix),
                 // look it up and return it, if the other conditions are met.
-                self.runtime.get_pod(
-                    &name,
-                    &labels,
-                    readiness,
-                    &cri_pod_sandbox,
-                    &mut response.items,
-                );
+                self.runtime
+                    .get_pod(&name, &labels, readiness, &transform, &mut response.items);
             }
             // Otherwise, the whole filter fails to match anything,
             // because all conditions are required and the ID condition is impossible.
@@ -807,7 +1109,7 @@ impl ProxyingRuntimeService {
             // If the ID filter is absent,
             // search exhaustively based on the state and labels filters.
             self.runtime
-                .list_pods(&labels, readiness, &cri_pod_sandbox, &mut response.items);
+                .list_pods(&labels, readiness, &transform, &mut response.items);
         }
 
         Ok(Response::new(response))
@@ -825,6 +1127,9 @@ impl ProxyingRuntimeService {
         let filter = request.filter.unwrap_or_default();
         // Collect the required labels as a vector for easier iteration.
         let labels: Vec<(&String, &String)> = filter.label_selector.iter().collect();
+        // A running pod may have exited already, so its state alone is not enough
+        // to tell which state its container is reported in.
+        let reported_state = filter.state.as_ref().map(|state| state.state);
         let matching_states: &[PodState] = filter
             .state
             .map_or(&POD_STATES_CONTAINER_ALL, cri_container_state_to_pod_states);
@@ -857,17 +1162,77 @@ impl ProxyingRuntimeService {
                 &mut response.containers,
             );
         }
+        // Filter here, before the merged listing is paged, so no page comes up short.
+        if let Some(reported_state) = reported_state {
+            response
+                .containers
+                .retain(|container| container.state == reported_state);
+        }
 
         Ok(Response::new(response))
     }
+
+    fn list_container_stats_upstream(
+        &self,
+        request: v1::ListContainerStatsRequest,
+    ) -> Response<v1::ListContainerStatsResponse> {
+        let mut response = v1::ListContainerStatsResponse::default();
+
+        // Every condition in the filter is composed with AND.
+        // The default filter if none is provided has no conditions (always passes).
+        let filter = request.filter.unwrap_or_default();
+        let labels: Vec<(&String, &String)> = filter.label_selector.iter().collect();
+
+        // A container has the same name as its pod sandbox, just with a different prefix,
+        // so either ID condition identifies at most one container.
+        let upstream_name = |id: &str, parse: fn(&str) -> Result<PodName>| {
+            (!self.is_downstream(id)).then(|| parse(id).ok()).flatten()
+        };
+        let container_name = (!filter.id.is_empty())
+            .then(|| upstream_name(&filter.id, parse_container_prefixed_name));
+        let pod_name = (!filter.pod_sandbox_id.is_empty())
+            .then(|| upstream_name(&filter.pod_sandbox_id, parse_pod_prefixed_name));
+        let name = match (container_name, pod_name) {
+            (None, None) => None,
+            (Some(name), None) | (None, Some(name)) => Some(name),
+            (Some(container_name), Some(pod_name)) => {
+                Some(container_name.filter(|name| Some(name) == pod_name.as_ref()))
+            }
+        };
+
+        match name {
+            // An ID condition, if present, can speed things up a lot.
+            Some(Some(name)) => self.runtime.get_container(
+                &name,
+                &labels,
+                &POD_STATES_CONTAINER_ALL,
+                &cri_container_stats,
+                &mut response.stats,
+            ),
+            // Otherwise, the whole filter fails to match anything,
+            // because all conditions are required and the ID condition is impossible.
+            Some(None) => {}
+            // If there is no ID condition, search exhaustively based on the labels.
+            None => {
+                self.runtime.list_containers(
+                    &labels,
+                    &POD_STATES_CONTAINER_ALL,
+                    &cri_container_stats,
+                    &mut response.stats,
+                );
+            }
+        }
+
+        Response::new(response)
+    }
 }
 
 /// Convert the internal pod to a CRI-API [v1::PodSandbox] to return in `ListPodSandbox`.
-fn cri_pod_sandbox(name: &PodName, pod: &Pod) -> v1::PodSandbox {
+fn cri_pod_sandbox(name: &PodName, pod: &Pod, handler: &RuntimeHandler) -> v1::PodSandbox {
     v1::PodSandbox {
         id: pod_prefix(name),
         // All Vimana containers use the same runtime.
-        runtime_handler: String::from(CONTAINER_RUNTIME_HANDLER),
+        runtime_handler: String::from(handler.name()),
         // Pod sandboxes are always ready (containers might not be).
         state: pod_state_to_cri_pod_state(pod.state) as i32,
         // The rest are just cloned from the controller:
@@ -886,7 +1251,7 @@ fn cri_container(name: &PodName, pod: &Pod) -> v1::Container {
         metadata: pod.container_metadata.clone(),
         image: pod.image_spec.clone(),
         image_ref: cri_image_ref(),
-        state: pod_state_to_cri_container_state(pod.state) as i32,
+        state: cri_container_state(pod) as i32,
         created_at: pod.container_created_at,
         labels: pod.container_labels.clone(),
         annotations: pod.container_annotations.clone(),
@@ -900,6 +1265,7 @@ fn cri_container(name: &PodName, pod: &Pod) -> v1::Container {
 fn cri_pod_sandbox_status(
     name: &PodName,
     pod: &Pod,
+    handler: &RuntimeHandler,
 ) -> (v1::PodSandboxStatus, Vec<v1::ContainerStatus>) {
     (
         v1::PodSandboxStatus {
@@ -914,7 +1280,7 @@ fn cri_pod_sandbox_status(
             linux: None,
             labels: pod.pod_labels.clone(),
             annotations: pod.pod_annotations.clone(),
-            runtime_handler: String::from(CONTAINER_RUNTIME_HANDLER),
+            runtime_handler: String::from(handler.name()),
         },
         match pod.state {
             PodState::Initiated | PodState::Removed | PodState::Killed => Vec::default(),
@@ -927,25 +1293,31 @@ fn cri_pod_sandbox_status(
 
 /// Convert the internal pod to a CRI-API [v1::ContainerStatus] to return in `ContainerStatus`.
 fn cri_container_status(name: &PodName, pod: &Pod) -> v1::ContainerStatus {
+    let exit = pod.exit.get();
     v1::ContainerStatus {
         id: container_prefix(name),
         metadata: pod.container_metadata.clone(),
-        state: pod_state_to_cri_container_state(pod.state) as i32,
+        state: cri_container_state(pod) as i32,
         created_at: pod.container_created_at,
         started_at: pod.container_started_at,
-        finished_at: pod.container_finished_at,
-        exit_code: 0, // TODO: Populate this in case a container fails at runtime.
+        finished_at: match exit {
+            Some(exit) if pod.container_finished_at == 0 => exit.finished_at,
+            _ => pod.container_finished_at,
+        },
+        exit_code: exit.map_or(0, |exit| exit.code),
         image: pod.image_spec.clone(),
         image_ref: cri_image_ref(),
-        reason: String::from("TODO"),
-        message: String::from("TODO"),
+        reason: match exit {
+            Some(exit) => String::from(exit.reason),
+            None => cri_container_health_reason(pod),
+        },
+        message: exit.map_or_else(String::default, |exit| exit.message.clone()),
         labels: pod.container_labels.clone(),
         annotations: pod.container_annotations.clone(),
         // Vimana containers never have volume mounts.
         mounts: Vec::default(),
         log_path: cri_container_log_path(),
-        // TODO: Resource limiting information.
-        resources: None,
+        resources: cri_container_resources(pod.cpuset.as_ref()),
         image_id: cri_image_id(),
         // Wasm modules do not use user-based privileges.
         user: None,
@@ -953,6 +1325,106 @@ fn cri_container_status(name: &PodName, pod: &Pod) -> v1::ContainerStatus {
     }
 }
 
+/// Convert a container event to a CRI-API [v1::ContainerEventResponse]
+/// to stream from `GetContainerEvents`,
+/// along with the current status of the pod, unless it no longer exists.
+fn cri_container_event(
+    event: &ContainerEvent,
+    pod: Option<&Pod>,
+    handler: &RuntimeHandler,
+) -> v1::ContainerEventResponse {
+    let (pod_sandbox_status, containers_statuses) = match pod {
+        Some(pod) => {
+            let (pod_status, container_statuses) =
+                cri_pod_sandbox_status(&event.name, pod, handler);
+            (Some(pod_status), container_statuses)
+        }
+        None => (None, Vec::default()),
+    };
+    v1::ContainerEventResponse {
+        container_id: container_prefix(&event.name),
+        container_event_type: event.event_type as i32,
+        created_at: event.created_at,
+        pod_sandbox_status,
+        containers_statuses,
+    }
+}
+
+/// Report the limits actually enforced on a container as CRI-API [v1::ContainerResources].
+///
+/// A pod pinned to a [CPU set](CpuSet) runs on exactly those cores,
+/// which amounts to a CPU quota of one whole core for each.
+/// Pods are otherwise unconstrained:
+/// linear memory is not capped, so no memory limit is reported.
+fn cri_container_resources(cpuset: Option<&CpuSet>) -> Option<v1::ContainerResources> {
+    cpuset.map(|cpuset| v1::ContainerResources {
+        linux: Some(v1::LinuxContainerResources {
+            cpu_period: CPU_PERIOD_MICROS,
+            cpu_quota: CPU_PERIOD_MICROS * cpuset.len() as i64,
+            cpuset_cpus: cpuset.to_string(),
+            ..v1::LinuxContainerResources::default()
+        }),
+        windows: None,
+    })
+}
+
+/// Convert the internal pod to a CRI-API [v1::ContainerStats] to return in `ContainerStats`.
+fn cri_container_stats(name: &PodName, pod: &Pod) -> v1::ContainerStats {
+    // Only a running container has any instances using resources.
+    let usage = match pod.state {
+        PodState::Running => pod.usage.sample(),
+        _ => UsageSample::default(),
+    };
+    let timestamp = now();
+    v1::ContainerStats {
+        attributes: Some(v1::ContainerAttributes {
+            id: container_prefix(name),
+            metadata: pod.container_metadata.clone(),
+            labels: pod.container_labels.clone(),
+            annotations: pod.container_annotations.clone(),
+        }),
+        cpu: Some(v1::CpuUsage {
+            timestamp,
+            usage_core_nano_seconds: Some(v1::UInt64Value {
+                value: usage.cpu_nanos,
+            }),
+            usage_nano_cores: Some(v1::UInt64Value {
+                value: usage.nano_cores,
+            }),
+        }),
+        memory: Some(v1::MemoryUsage {
+            timestamp,
+            // Linear memory is all that a component can touch,
+            // so it's the working set and the total usage alike.
+            working_set_bytes: Some(v1::UInt64Value {
+                value: usage.memory_bytes,
+            }),
+            usage_bytes: Some(v1::UInt64Value {
+                value: usage.memory_bytes,
+            }),
+            ..Default::default()
+        }),
+        // Vimana containers have no writable layer, swap, etc.
+        ..Default::default()
+    }
+}
+
+/// Reflect the latest health checks in the container status reason,
+/// distinguishing a component that is alive but not ready from a broken one.
+fn cri_container_health_reason(pod: &Pod) -> String {
+    if pod.state != PodState::Running {
+        return String::from("TODO");
+    }
+    match (
+        pod.health.get(Probe::Liveness),
+        pod.health.get(Probe::Readiness),
+    ) {
+        (Checked::Failing, _) => String::from("Unhealthy"),
+        (_, Checked::Failing) => String::from("NotReady"),
+        _ => String::from("TODO"),
+    }
+}
+
 fn pod_state_to_cri_pod_state(state: PodState) -> v1::PodSandboxState {
     match state {
         PodState::Initiated
@@ -982,6 +1454,19 @@ fn cri_container_state_to_pod_states(state: v1::ContainerStateValue) -> &'static
     }
 }
 
+/// The state of the pod's container, as reported in both `ListContainers` and `ContainerStatus`.
+///
+/// A clean exit and a forced kill both leave the container exited;
+/// Kubelet tells them apart (*e.g.* to apply an `OnFailure` restart policy)
+/// by the [exit code and reason](crate::state::Exit) in the container status.
+fn cri_container_state(pod: &Pod) -> v1::ContainerState {
+    match (pod.state, pod.exit.get()) {
+        // The server exited on its own, without waiting for `StopContainer`.
+        (PodState::Running, Some(_)) => v1::ContainerState::ContainerExited,
+        (state, _) => pod_state_to_cri_container_state(state),
+    }
+}
+
 fn pod_state_to_cri_container_state(state: PodState) -> v1::ContainerState {
     match state {
         PodState::Initiated | PodState::Removed | PodState::Killed => {
@@ -1001,7 +1486,468 @@ fn cri_image_id() -> String {
     String::from("TODO")
 }
 
+/// Combine Vimana's status with the downstream runtime's, if it reported one.
+///
+/// Vimana is only ready if the downstream runtime is too,
+/// since Kubelet relies on the same status for both.
+/// If the downstream status is unavailable, Vimana's own `conditions` stand alone.
+/// The runtime handlers and info maps of both are merged.
+fn merge_status(
+    handler: &str,
+    mut conditions: Vec<v1::RuntimeCondition>,
+    info: Option<String>,
+    downstream: Option<v1::StatusResponse>,
+) -> v1::StatusResponse {
+    let downstream = downstream.unwrap_or_default();
+    for condition in downstream.status.unwrap_or_default().conditions {
+        match conditions
+            .iter_mut()
+            .find(|upstream| upstream.r#type == condition.r#type)
+        {
+            Some(upstream) => {
+                if !condition.status {
+                    *upstream = condition;
+                }
+            }
+            None => conditions.push(condition),
+        }
+    }
+
+    let mut runtime_handlers = downstream.runtime_handlers;
+    runtime_handlers.push(v1::RuntimeHandler {
+        name: String::from(handler),
+        features: Some(v1::RuntimeHandlerFeatures {
+            recursive_read_only_mounts: false,
+            user_namespaces: false,
+        }),
+    });
+
+    let mut info_map = downstream.info;
+    if let Some(info) = info {
+        info_map.insert(String::from(INFO_KEY), info);
+    }
+
+    v1::StatusResponse {
+        status: Some(v1::RuntimeStatus { conditions }),
+        info: info_map,
+        runtime_handlers,
+        features: downstream.features,
+    }
+}
+
+/// Convert the result of a readiness check to a CRI-API [v1::RuntimeCondition].
+/// The `reason` and the error's message are only reported if the condition is not met.
+fn cri_condition(r#type: &str, reason: &str, ready: Result<()>) -> v1::RuntimeCondition {
+    match ready {
+        Ok(()) => v1::RuntimeCondition {
+            r#type: String::from(r#type),
+            status: true,
+            reason: String::default(),
+            message: String::default(),
+        },
+        Err(error) => v1::RuntimeCondition {
+            r#type: String::from(r#type),
+            status: false,
+            reason: String::from(reason),
+            message: format!("{error:#}"),
+        },
+    }
+}
+
+/// A window onto the merged listing of pod sandboxes or containers from both runtimes.
+///
+/// Listings are ordered by CRI ID, which never changes for the lifetime of a pod or container,
+/// so paging through a listing never repeats or skips one that exists throughout.
+/// The downstream runtime has no notion of pagination,
+/// so it lists everything for every page, and the merged listing is paged afterwards.
+#[derive(Clone, Debug, Default, PartialEq)]
+struct Page {
+    /// Continuation token returned with the previous page:
+    /// only IDs that sort after it are listed.
+    /// `None` starts from the beginning.
+    after: Option<String>,
+
+    /// Maximum number of results in the page.
+    /// `None` lists everything that remains.
+    size: Option<usize>,
+}
+
+impl Page {
+    /// Return `true` iff the given ID sorts after the continuation token.
+    fn includes(&self, id: &str) -> bool {
+        self.after.as_deref().is_none_or(|after| id > after)
+    }
+
+    /// Sort `items` by ID and push at most one page of those after the continuation token
+    /// into `results`.
+    /// Return the continuation token for the next page, if any items were left out.
+    fn collect<T>(
+        &self,
+        mut items: Vec<T>,
+        id: fn(&T) -> &String,
+        results: &mut Vec<T>,
+    ) -> Option<String> {
+        items.retain(|item| self.includes(id(item)));
+        items.sort_unstable_by(|left, right| id(left).cmp(id(right)));
+        let next = match self.size {
+            Some(size) if size > 0 && items.len() > size => {
+                items.truncate(size);
+                items.last().map(|item| id(item).clone())
+            }
+            _ => None,
+        };
+        results.append(&mut items);
+        next
+    }
+}
+
+/// Read the [page](Page) of a `ListPodSandbox` or `ListContainers` request from its metadata.
+/// Requests without pagination metadata list everything at once.
+fn requested_page(metadata: &MetadataMap) -> StdResult<Page, Box<Status>> {
+    let invalid = |key: &str| {
+        Box::new(Status::invalid_argument(format!(
+            "Invalid '{key}' metadata"
+        )))
+    };
+    let after = metadata
+        .get(PAGE_TOKEN_METADATA_KEY)
+        .map(|value| {
+            value
+                .to_str()
+                .map(String::from)
+                .map_err(|_| invalid(PAGE_TOKEN_METADATA_KEY))
+        })
+        .transpose()?;
+    let size = metadata
+        .get(PAGE_SIZE_METADATA_KEY)
+        .map(|value| {
+            value
+                .to_str()
+                .ok()
+                .and_then(|value| value.parse::<usize>().ok())
+                .ok_or_else(|| invalid(PAGE_SIZE_METADATA_KEY))
+        })
+        .transpose()?;
+    Ok(Page {
+        after,
+        // A page size of zero is the same as none at all.
+        size: size.filter(|size| *size > 0),
+    })
+}
+
+/// Wrap a merged listing response,
+/// attaching the continuation token for the next page, if there is one.
+fn paged_response<T>(response: T, next: Option<String>) -> TonicResult<T> {
+    let mut response = Response::new(response);
+    if let Some(next) = next {
+        let next = MetadataValue::try_from(next)
+            .map_err(|_| Status::internal("Page token is not valid metadata"))?;
+        response
+            .metadata_mut()
+            .insert(PAGE_TOKEN_METADATA_KEY, next);
+    }
+    Ok(response)
+}
+
 fn cri_container_log_path() -> String {
     // Logging happens entirely via OTLP, not files.
     String::from("/dev/null")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn healthy_conditions() -> Vec<v1::RuntimeCondition> {
+        vec![
+            cri_condition(CONDITION_RUNTIME_READY, REASON_RUNTIME_NOT_READY, Ok(())),
+            cri_condition(CONDITION_NETWORK_READY, REASON_NETWORK_NOT_READY, Ok(())),
+        ]
+    }
+
+    #[test]
+    fn test_status_merges_downstream() {
+        let downstream = v1::StatusResponse {
+            status: Some(v1::RuntimeStatus {
+                conditions: vec![
+                    v1::RuntimeCondition {
+                        r#type: String::from(CONDITION_RUNTIME_READY),
+                        status: true,
+                        reason: String::default(),
+                        message: String::default(),
+                    },
+                    v1::RuntimeCondition {
+                        r#type: String::from(CONDITION_NETWORK_READY),
+                        status: false,
+                        reason: String::from("NetworkPluginNotReady"),
+                        message: String::from("cni plugin not initialized"),
+                    },
+                ],
+            }),
+            info: HashMap::from([(String::from("config"), String::from("{}"))]),
+            runtime_handlers: vec![v1::RuntimeHandler {
+                name: String::from("runc"),
+                features: None,
+            }],
+            features: None,
+        };
+        let info = to_string(&NodeInfo {
+            version: CONTAINER_RUNTIME_VERSION,
+            engine_features: &WASM_FEATURES,
+            pods: BTreeMap::from([(format!("{:?}", PodState::Running), 2)]),
+            ipam: IpamInfo {
+                allocated: 2,
+                capacity: 65536,
+            },
+        })
+        .unwrap();
+
+        let status = merge_status(
+            "vimana-handler",
+            healthy_conditions(),
+            Some(info),
+            Some(downstream.clone()),
+        );
+
+        // Both Vimana's and the downstream runtime's information are reported.
+        assert_eq!(status.info["config"], "{}");
+        let vimana: serde_json::Value = serde_json::from_str(&status.info[INFO_KEY]).unwrap();
+        assert_eq!(vimana["version"], CONTAINER_RUNTIME_VERSION);
+        assert_eq!(vimana["pods"]["Running"], 2);
+        assert_eq!(vimana["ipam"]["capacity"], 65536);
+        assert_eq!(vimana["engineFeatures"][0], "component-model");
+        assert_eq!(
+            status
+                .runtime_handlers
+                .iter()
+                .map(|handler| handler.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["runc", "vimana-handler"],
+        );
+        // The downstream network is not ready, so neither is the node.
+        let conditions = status.status.unwrap().conditions;
+        assert_eq!(conditions.len(), 2);
+        assert!(conditions[0].status);
+        assert!(!conditions[1].status);
+        assert_eq!(conditions[1].reason, "NetworkPluginNotReady");
+
+        // Without verbosity, only the downstream info (if any) is passed along.
+        let status = merge_status(
+            "vimana-handler",
+            healthy_conditions(),
+            None,
+            Some(downstream),
+        );
+        assert!(!status.info.contains_key(INFO_KEY));
+    }
+
+    #[test]
+    fn test_status_without_downstream() {
+        // The downstream runtime failed, but Vimana itself is healthy.
+        let status = merge_status("vimana-handler", healthy_conditions(), None, None);
+        let conditions = status.status.unwrap().conditions;
+        assert_eq!(
+            conditions
+                .iter()
+                .map(|condition| (condition.r#type.as_str(), condition.status))
+                .collect::<Vec<_>>(),
+            vec![
+                (CONDITION_RUNTIME_READY, true),
+                (CONDITION_NETWORK_READY, true),
+            ],
+        );
+        assert_eq!(status.runtime_handlers.len(), 1);
+        assert_eq!(status.runtime_handlers[0].name, "vimana-handler");
+
+        // Vimana's own conditions still count without the downstream runtime.
+        let error = "IPAM plugin not found: \"/opt/cni/bin/host-local\"";
+        let conditions = vec![
+            cri_condition(CONDITION_RUNTIME_READY, REASON_RUNTIME_NOT_READY, Ok(())),
+            cri_condition(
+                CONDITION_NETWORK_READY,
+                REASON_NETWORK_NOT_READY,
+                Err(anyhow!(error)),
+            ),
+        ];
+        let conditions = merge_status("vimana-handler", conditions, None, None)
+            .status
+            .unwrap()
+            .conditions;
+        assert!(conditions[0].status);
+        assert!(!conditions[1].status);
+        assert_eq!(conditions[1].reason, REASON_NETWORK_NOT_READY);
+        assert_eq!(conditions[1].message, error);
+    }
+
+    #[test]
+    fn test_sandbox_ready_until_killed() {
+        // Kubelet recreates any sandbox it sees as not ready,
+        // so the sandbox is ready even before its server is listening.
+        for state in [
+            PodState::Initiated,
+            PodState::Created,
+            PodState::Starting,
+            PodState::Running,
+            PodState::Stopped,
+            PodState::Removed,
+        ] {
+            assert_eq!(
+                pod_state_to_cri_pod_state(state),
+                v1::PodSandboxState::SandboxReady,
+            );
+        }
+        assert_eq!(
+            pod_state_to_cri_pod_state(PodState::Killed),
+            v1::PodSandboxState::SandboxNotready,
+        );
+
+        // Instead, traffic waits for the container to run.
+        assert_eq!(
+            pod_state_to_cri_container_state(PodState::Starting),
+            v1::ContainerState::ContainerCreated,
+        );
+        assert!(!PodState::Starting.is_ready());
+        assert!(PodState::Running.is_ready());
+    }
+
+    #[test]
+    fn test_container_state_of_each_pod_state() {
+        use v1::ContainerState::{
+            ContainerCreated, ContainerExited, ContainerRunning, ContainerUnknown,
+        };
+
+        for (state, expected) in [
+            (PodState::Initiated, ContainerUnknown),
+            (PodState::Created, ContainerCreated),
+            (PodState::Starting, ContainerCreated),
+            (PodState::Running, ContainerRunning),
+            (PodState::Stopped, ContainerExited),
+            (PodState::Removed, ContainerUnknown),
+            (PodState::Killed, ContainerUnknown),
+        ] {
+            assert_eq!(pod_state_to_cri_container_state(state), expected);
+            // Filtering by the reported state finds the container,
+            // as long as it still exists.
+            let filter = v1::ContainerStateValue {
+                state: expected as i32,
+            };
+            assert_eq!(
+                cri_container_state_to_pod_states(filter).contains(&state),
+                POD_STATES_CONTAINER_ALL.contains(&state),
+            );
+        }
+
+        // A running pod whose server exited on its own is found by filtering for exited containers.
+        assert!(cri_container_state_to_pod_states(v1::ContainerStateValue {
+            state: ContainerExited as i32,
+        })
+        .contains(&PodState::Running));
+    }
+
+    #[test]
+    fn test_container_resources_of_cpuset() {
+        assert_eq!(cri_container_resources(None), None);
+
+        let cpuset = CpuSet::parse("0").unwrap();
+        let resources = cri_container_resources(Some(&cpuset)).unwrap();
+        let linux = resources.linux.unwrap();
+        assert_eq!(linux.cpuset_cpus, "0");
+        assert_eq!(linux.cpu_quota, linux.cpu_period);
+        // Linear memory is not capped.
+        assert_eq!(linux.memory_limit_in_bytes, 0);
+    }
+
+    const COMPONENT: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0";
+
+    #[test]
+    fn test_requested_page() {
+        let mut request = Request::new(v1::ListPodSandboxRequest::default());
+        assert_eq!(requested_page(request.metadata()).unwrap(), Page::default());
+
+        // The token from one response continues the listing in the next request.
+        let token = pod_prefix(PodName::new(
+            Name::parse(COMPONENT).component().unwrap(),
+            1234,
+        ));
+        let response =
+            paged_response(v1::ListPodSandboxResponse::default(), Some(token.clone())).unwrap();
+        let metadata = request.metadata_mut();
+        metadata.insert(
+            PAGE_TOKEN_METADATA_KEY,
+            response
+                .metadata()
+                .get(PAGE_TOKEN_METADATA_KEY)
+                .unwrap()
+                .clone(),
+        );
+        metadata.insert(PAGE_SIZE_METADATA_KEY, MetadataValue::from_static("100"));
+        assert_eq!(
+            requested_page(request.metadata()).unwrap(),
+            Page {
+                after: Some(token),
+                size: Some(100),
+            },
+        );
+
+        // The last page has no token.
+        let response = paged_response(v1::ListPodSandboxResponse::default(), None).unwrap();
+        assert!(response.metadata().get(PAGE_TOKEN_METADATA_KEY).is_none());
+
+        let metadata = request.metadata_mut();
+        metadata.insert(PAGE_SIZE_METADATA_KEY, MetadataValue::from_static("many"));
+        let status = requested_page(request.metadata()).unwrap_err();
+        assert_eq!(status.code(), tonic::Code::InvalidArgument);
+    }
+
+    #[test]
+    fn test_paging() {
+        // A large set of sandboxes from both runtimes, interleaved out of order:
+        // downstream IDs are opaque hex strings, upstream IDs are prefixed pod names.
+        let component = Name::parse(COMPONENT).component().unwrap();
+        let mut items: Vec<v1::PodSandbox> = Vec::new();
+        for index in 0..5_000 {
+            let id = (index * 7_919) % 5_000;
+            items.push(v1::PodSandbox {
+                id: format!("{:064x}", (id as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)),
+                ..Default::default()
+            });
+            items.push(v1::PodSandbox {
+                id: pod_prefix(PodName::new(component.clone(), id)),
+                ..Default::default()
+            });
+        }
+        let list = |page: &Page, results: &mut Vec<v1::PodSandbox>| {
+            page.collect(items.clone(), |item| &item.id, results)
+        };
+
+        let mut listed = Vec::new();
+        let mut page = Page {
+            after: None,
+            size: Some(100),
+        };
+        let mut pages = 0;
+        loop {
+            let mut results = Vec::new();
+            let next = list(&page, &mut results);
+            assert!(results.len() <= 100);
+            listed.append(&mut results);
+            pages += 1;
+            match next {
+                Some(token) => page.after = Some(token),
+                None => break,
+            }
+        }
+
+        // Every sandbox from either runtime is listed exactly once, in order.
+        let mut expected: Vec<String> = items.iter().map(|item| item.id.clone()).collect();
+        expected.sort_unstable();
+        let listed: Vec<String> = listed.into_iter().map(|item| item.id).collect();
+        assert_eq!(listed, expected);
+        assert_eq!(pages, 100);
+
+        // Without a page size, everything is listed at once.
+        let mut everything = Vec::new();
+        assert_eq!(list(&Page::default(), &mut everything), None);
+        assert_eq!(everything.len(), expected.len());
+    }
+}
@@ -0,0 +1,63 @@
+//! Pooled storage for decoded records.
+//!
+//! Wasmtime's [`Val`] owns plain `Vec`s and `Box`es, so decoded values cannot borrow from an arena.
+//! The next best thing is to keep the record field vectors of previous requests around:
+//! once a request has been handled, [`recycle`] returns the storage of the decoded value
+//! to a per-thread pool, and decoding the next request on that thread reuses it
+//! rather than allocating a fresh vector for every (sub)message.
+//!
+//! Field names and scalar contents are still allocated per request.
+
+use std::cell::RefCell;
+
+use wasmtime::component::Val;
+
+/// Upper bound on the number of idle field vectors each thread holds on to,
+/// so a single huge request cannot pin memory indefinitely.
+const MAX_POOLED_RECORDS: usize = 4096;
+
+/// Field vectors wider than this are left to the allocator.
+const MAX_POOLED_FIELDS: usize = 64;
+
+thread_local! {
+    /// Empty field vectors ready for reuse.
+    static RECORDS: RefCell<Vec<Vec<(String, Val)>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Return a new record value populated with `defaults`,
+/// using a pooled field vector if one is available.
+#[inline(always)]
+pub(crate) fn record(defaults: &[(String, Val)]) -> Val {
+    let mut fields = RECORDS
+        .with_borrow_mut(|records| records.pop())
+        .unwrap_or_default();
+    fields.extend_from_slice(defaults);
+    Val::Record(fields)
+}
+
+/// Release a decoded value (e.g. a request that has been fully handled),
+/// returning its record storage to the current thread's pool for later decoding.
+pub fn recycle(value: Val) {
+    match value {
+        Val::Record(mut fields) => {
+            for (_name, field) in fields.drain(..) {
+                recycle(field);
+            }
+            if fields.capacity() <= MAX_POOLED_FIELDS {
+                RECORDS.with_borrow_mut(|records| {
+                    if records.len() < MAX_POOLED_RECORDS {
+                        records.push(fields);
+                    }
+                });
+            }
+        }
+        Val::Option(Some(value)) | Val::Variant(_, Some(value)) => recycle(*value),
+        Val::List(items) => {
+            for item in items {
+                recycle(item);
+            }
+        }
+        // Nothing else is produced with pooled storage.
+        _ => {}
+    }
+}
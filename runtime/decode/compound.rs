@@ -1,21 +1,25 @@
-//! Decoding logic for compound protobuf fields (messages, enums, and oneofs).
+//! Decoding logic for compound protobuf fields (messages, enums, oneofs, and maps).
 
+use std::collections::hash_map::Entry;
 use std::collections::HashMap;
-use std::mem::ManuallyDrop;
+use std::mem::{replace, take, ManuallyDrop};
+use std::ptr::fn_addr_eq;
 use std::result::Result as StdResult;
+use std::sync::atomic::Ordering;
 
 use anyhow::{anyhow, Context, Result};
 use prost::encoding::{decode_varint, encoded_len_varint, WireType};
-use tonic::codec::DecodeBuf;
 use wasmtime::component::Val;
 
 use crate::{
-    decode_tag, explicit_scalar, read_length_check_overflow, skip, CompoundMerger, DecodeError,
-    MergeFn, Merger, BUFFER_OVERFLOW, ENUM_NO_DEFAULT, FIELD_INDEX_OUT_OF_BOUNDS, INVALID_VARINT,
-    MESSAGE_NON_RECORD, NON_EXPLICIT_ONEOF_VARIANT, OVERFLOW_32BIT, REPEATED_NON_LIST,
+    arena, check_repeated_elements, decode_tag, explicit_scalar, read_length_check_overflow,
+    read_varint, skip, CompoundMerger, DecodeError, EnumVariants, MapKeys, MergeFn, Merger,
+    ENUM_VARIANT_UNRECOGNIZED, FIELD_INDEX_OUT_OF_BOUNDS, FIELD_NUMBER_OUT_OF_RANGE,
+    INVALID_VARINT, MAP_ENTRY_NON_TUPLE, MAP_KEY_DUPLICATE, MAP_KEY_NON_SCALAR, MESSAGE_NON_RECORD,
+    NON_EXPLICIT_ONEOF_VARIANT, OVERFLOW_32BIT, REPEATED_NON_LIST, UNRECOGNIZED_VARIANT,
     WIRETYPE_NON_LENGTH_DELIMITED, WIRETYPE_NON_VARINT,
 };
-use metadata_proto::work::runtime::field::{Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
 use metadata_proto::work::runtime::Field;
 use names::ComponentName;
 
@@ -32,34 +36,41 @@ impl Merger {
 fn compile_message(field: &Field, merge: MergeFn, component: &ComponentName) -> Result<Merger> {
     let mut subfields: HashMap<u32, (u32, Merger)> = HashMap::with_capacity(field.subfields.len());
     let mut defaults: Vec<(String, Val)> = Vec::with_capacity(field.subfields.len());
+    // Name of the subfield (or oneof variant) claiming each field number so far.
+    let mut names: HashMap<u32, &str> = HashMap::with_capacity(field.subfields.len());
 
     for (index, subfield) in field.subfields.iter().enumerate() {
         let (subfield_merger, subfield_default) = match subfield
             .coding
             .ok_or_else(|| anyhow!("Field #{} missing required coding", subfield.number))?
         {
-            Coding::ScalarCoding(scalar_coding) => {
-                Merger::scalar(ScalarCoding::try_from(scalar_coding).with_context(|| {
-                    format!(
-                        "Invalid ScalarCoding for field #{}: {:?}",
-                        subfield.number, scalar_coding,
-                    )
-                })?)
-            }
+            Coding::ScalarCoding(scalar_coding) => Merger::scalar(
+                known_scalar_coding(scalar_coding)
+                    .with_context(|| format!("Invalid coding for field #{}", subfield.number))?,
+                known_charset(subfield.charset)
+                    .with_context(|| format!("Invalid charset for field #{}", subfield.number))?,
+            ),
             Coding::CompoundCoding(compound_coding) => {
-                match CompoundCoding::try_from(compound_coding).with_context(|| {
-                    format!(
-                        "Invalid CompoundCoding for field #{}: {:?}",
-                        subfield.number, compound_coding,
-                    )
-                })? {
+                match known_compound_coding(compound_coding)
+                    .with_context(|| format!("Invalid coding for field #{}", subfield.number))?
+                {
                     CompoundCoding::EnumImplicit => {
                         let merger = compile_enum_variants(subfield, enum_implicit_merge);
 
-                        // The enum must have a default zero value.
-                        if let Some(default) = unsafe { &merger.compound.enum_variants }.get(&0) {
-                            let default = default.clone();
-                            (merger, Val::Enum(default))
+                        // The enum must have a default value:
+                        // either the designated default variant, or else the zero value.
+                        let enum_variants = unsafe { &merger.compound.enum_variants };
+                        let default = if subfield.default_variant.is_empty() {
+                            enum_variants.names.get(&0)
+                        } else {
+                            enum_variants
+                                .names
+                                .values()
+                                .find(|name| **name == subfield.default_variant)
+                        };
+                        if let Some(default) = default {
+                            let default = enum_variant(enum_variants, default);
+                            (merger, default)
                         } else {
                             return Err(anyhow!(
                                 "Implicit enum at field #{} must have a default value",
@@ -85,10 +96,28 @@ fn compile_message(field: &Field, merge: MergeFn, component: &ComponentName) ->
                         )?,
                         Val::Option(None),
                     ),
-                    CompoundCoding::MessageExpanded => (
-                        compile_message(subfield, message_repeated_merge, component).with_context(
-                            || format!("Invalid expanded message for field #{}", subfield.number),
-                        )?,
+                    CompoundCoding::MessageExpanded => {
+                        let mut merger =
+                            compile_message(subfield, message_repeated_merge, component)
+                                .with_context(|| {
+                                    format!(
+                                        "Invalid expanded message for field #{}",
+                                        subfield.number
+                                    )
+                                })?;
+                        merger.repeated_tag =
+                            (subfield.number << 3) | WireType::LengthDelimited as u32;
+                        (merger, Val::List(Vec::new()))
+                    }
+                    CompoundCoding::Duration => (Merger::duration(), Val::Option(None)),
+                    CompoundCoding::Timestamp => (Merger::timestamp(), Val::Option(None)),
+                    CompoundCoding::Struct => (Merger::dynamic_struct(), Val::Option(None)),
+                    CompoundCoding::Value => (Merger::dynamic_value(), Val::Option(None)),
+                    CompoundCoding::ListValue => (Merger::dynamic_list(), Val::Option(None)),
+                    CompoundCoding::Map => (
+                        compile_map(subfield, component).with_context(|| {
+                            format!("Invalid map for field #{}", subfield.number)
+                        })?,
                         Val::List(Vec::new()),
                     ),
                     CompoundCoding::Oneof => {
@@ -96,6 +125,7 @@ fn compile_message(field: &Field, merge: MergeFn, component: &ComponentName) ->
                         // each variant field number is mapped
                         // to the same subfield of the outer message.
                         for variant in subfield.subfields.iter() {
+                            claim_field_number(&mut names, variant)?;
                             subfields.insert(
                                 variant.number,
                                 (
@@ -119,43 +149,80 @@ fn compile_message(field: &Field, merge: MergeFn, component: &ComponentName) ->
             }
         };
 
+        claim_field_number(&mut names, subfield)?;
         subfields.insert(subfield.number, (index as u32, subfield_merger));
         defaults.push((subfield.name.clone(), subfield_default));
     }
 
+    let map_keys =
+        if field.subfields.iter().any(|subfield| {
+            subfield.coding == Some(Coding::CompoundCoding(CompoundCoding::Map as i32))
+        }) {
+            MapKeys::LastWins
+        } else {
+            MapKeys::Absent
+        };
     Ok(Merger {
         merge,
         defaults,
+        repeated_tag: 0,
+        presence: None,
+        max_field_number: u32::MAX,
+        max_elements: u32::MAX,
+        map_keys,
         compound: CompoundMerger {
             subfields: ManuallyDrop::new(subfields),
         },
     })
 }
 
+/// Claim the number of a subfield (or oneof variant) within its message.
+/// Two fields with the same number would silently shadow one another.
+fn claim_field_number<'a>(names: &mut HashMap<u32, &'a str>, field: &'a Field) -> Result<()> {
+    match names.insert(field.number, &field.name) {
+        Some(existing) => Err(anyhow!(
+            "Fields '{existing}' and '{}' have the same number #{}",
+            field.name,
+            field.number
+        )),
+        None => Ok(()),
+    }
+}
+
 fn compile_oneof_variant(variant: &Field, component: &ComponentName) -> Result<Merger> {
-    let merger = match variant.coding.ok_or(anyhow!("Missing required coding"))? {
+    let payload = match variant.coding.ok_or(anyhow!("Missing required coding"))? {
         Coding::ScalarCoding(scalar_coding) => {
+            let scalar_coding = known_scalar_coding(scalar_coding)?;
             // Enforce explicit-only coding.
-            if explicit_scalar(scalar_coding) {
+            if explicit_scalar(scalar_coding as i32) {
                 // We know the default will be an empty optional
                 // because we enforce explicit-only coding.
-                let (merger, _default) = Merger::scalar(
-                    ScalarCoding::try_from(scalar_coding)
-                        .with_context(|| format!("Invalid ScalarCoding: {:?}", scalar_coding))?,
-                );
-                merger
+                let (merger, _default) =
+                    Merger::scalar(scalar_coding, known_charset(variant.charset)?);
+                Some(Box::new(merger))
             } else {
                 return Err(anyhow!("Oneof variants must use explicit coding"));
             }
         }
         Coding::CompoundCoding(compound_coding) => {
-            match CompoundCoding::try_from(compound_coding)
-                .with_context(|| format!("Invalid CompoundCoding: {:?}", compound_coding))?
-            {
-                CompoundCoding::EnumExplicit => compile_enum_variants(variant, enum_explicit_merge),
-                CompoundCoding::Message => {
-                    compile_message(variant, message_outer_merge, component)?
-                }
+            match known_compound_coding(compound_coding)? {
+                CompoundCoding::EnumExplicit => Some(Box::new(compile_enum_variants(
+                    variant,
+                    enum_explicit_merge,
+                ))),
+                // An empty message (like `google.protobuf.Empty`) is a case without a payload,
+                // since WIT records cannot be empty.
+                CompoundCoding::Message if variant.subfields.is_empty() => None,
+                CompoundCoding::Message => Some(Box::new(compile_message(
+                    variant,
+                    message_outer_merge,
+                    component,
+                )?)),
+                CompoundCoding::Duration => Some(Box::new(Merger::duration())),
+                CompoundCoding::Timestamp => Some(Box::new(Merger::timestamp())),
+                CompoundCoding::Struct => Some(Box::new(Merger::dynamic_struct())),
+                CompoundCoding::Value => Some(Box::new(Merger::dynamic_value())),
+                CompoundCoding::ListValue => Some(Box::new(Merger::dynamic_list())),
                 _coding => {
                     return Err(anyhow!("Oneof variants must use explicit coding"));
                 }
@@ -166,21 +233,96 @@ fn compile_oneof_variant(variant: &Field, component: &ComponentName) -> Result<M
     Ok(Merger {
         merge: oneof_variant_merge,
         defaults: Vec::new(),
+        repeated_tag: 0,
+        presence: None,
+        max_field_number: u32::MAX,
+        max_elements: u32::MAX,
+        map_keys: MapKeys::Absent,
         compound: CompoundMerger {
-            oneof_variant: ManuallyDrop::new((variant.name.clone(), Box::new(merger))),
+            oneof_variant: ManuallyDrop::new((variant.name.clone(), payload)),
         },
     })
 }
 
+/// Initialization logic for maps.
+/// Each entry is compiled like a message with a key (#1) and a value (#2),
+/// then the two subfield mergers are moved into a dedicated map merger.
+fn compile_map(map: &Field, component: &ComponentName) -> Result<Merger> {
+    let key_coding = map
+        .subfields
+        .first()
+        .and_then(|key| key.coding)
+        .ok_or_else(|| anyhow!("Map key missing required coding"))?;
+    match key_coding {
+        Coding::ScalarCoding(scalar_coding) if !explicit_scalar(scalar_coding) => {}
+        _ => return Err(anyhow!("Map keys must use implicit scalar coding")),
+    }
+
+    let mut entry = compile_message(map, message_inner_merge, component)?;
+    let subfields = unsafe { &mut entry.compound.subfields };
+    match (subfields.remove(&1), subfields.remove(&2)) {
+        (Some((0, key)), Some((1, value))) if subfields.is_empty() => Ok(Merger {
+            merge: map_merge,
+            defaults: take(&mut entry.defaults),
+            repeated_tag: 0,
+            presence: None,
+            max_field_number: u32::MAX,
+            max_elements: u32::MAX,
+            map_keys: MapKeys::Absent,
+            compound: CompoundMerger {
+                map_entry: ManuallyDrop::new(Box::new((key, value))),
+            },
+        }),
+        _ => Err(anyhow!(
+            "Map entries must have exactly a key (#1) followed by a value (#2)"
+        )),
+    }
+}
+
+/// Convert a raw scalar coding number from metadata into a known [`ScalarCoding`].
+/// Unknown numbers typically mean the metadata was compiled by a newer compiler
+/// than this runtime supports, which must fail pod initialization.
+fn known_scalar_coding(scalar_coding: i32) -> Result<ScalarCoding> {
+    ScalarCoding::try_from(scalar_coding).map_err(|_| {
+        anyhow!("Unrecognized ScalarCoding {scalar_coding} (metadata may be from a newer compiler)")
+    })
+}
+
+/// Like [`known_scalar_coding`], but for [`Charset`].
+fn known_charset(charset: i32) -> Result<Charset> {
+    Charset::try_from(charset).map_err(|_| {
+        anyhow!("Unrecognized Charset {charset} (metadata may be from a newer compiler)")
+    })
+}
+
+/// Like [`known_scalar_coding`], but for [`CompoundCoding`].
+fn known_compound_coding(compound_coding: i32) -> Result<CompoundCoding> {
+    CompoundCoding::try_from(compound_coding).map_err(|_| {
+        anyhow!(
+            "Unrecognized CompoundCoding {compound_coding} (metadata may be from a newer compiler)"
+        )
+    })
+}
+
 /// Initialization logic for enumerations.
 fn compile_enum_variants(enumeration: &Field, merge: MergeFn) -> Merger {
-    let mut variants = HashMap::with_capacity(enumeration.subfields.len());
+    let mut names = HashMap::with_capacity(enumeration.subfields.len());
     for subfield in &enumeration.subfields {
-        variants.insert(subfield.number, subfield.name.clone());
+        names.insert(subfield.number, subfield.name.clone());
     }
+    let variants = EnumVariants {
+        names,
+        open: enumeration.open_enum,
+        strict: false,
+    };
     Merger {
         merge,
         defaults: Vec::new(),
+        repeated_tag: 0,
+        presence: None,
+        max_field_number: u32::MAX,
+        max_elements: u32::MAX,
+        map_keys: MapKeys::Absent,
         compound: CompoundMerger {
             enum_variants: ManuallyDrop::new(variants),
         },
@@ -191,7 +333,7 @@ pub(crate) fn message_inner_merge(
     merger: &Merger,
     _wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     // Inner message contents always decode to a complete record.
@@ -207,6 +349,9 @@ pub(crate) fn message_inner_merge(
             {
                 // Get a mutable pointer to the relevant subvalue within this record.
                 if let Some(subdst) = fields.get_mut(*index as usize) {
+                    if let Some(presence) = &subfield_merger.presence {
+                        presence.fetch_add(1, Ordering::Relaxed);
+                    }
                     // Call the field's merge function into that subvalue.
                     (subfield_merger.merge)(&subfield_merger, wire_type, limit, src, &mut subdst.1)
                         .map_err(|e| e.with_field(field_number))?;
@@ -217,11 +362,17 @@ pub(crate) fn message_inner_merge(
                         DecodeError::new(FIELD_INDEX_OUT_OF_BOUNDS).with_field(field_number)
                     );
                 }
+            } else if field_number > merger.max_field_number {
+                // Unknown and beyond anything the schema declares.
+                return Err(DecodeError::new(FIELD_NUMBER_OUT_OF_RANGE).with_field(field_number));
             } else {
                 // Unknown field number. Use wire type information to skip it.
                 skip(wire_type, limit, src).map_err(|e| e.with_field(field_number))?;
             }
         }
+        if merger.map_keys != MapKeys::Absent {
+            resolve_duplicate_keys(merger, fields)?;
+        }
         Ok(())
     } else {
         // API violation - this method should always be called for a `Record`.
@@ -233,13 +384,13 @@ pub(crate) fn message_outer_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     if wire_type == WireType::LengthDelimited {
         let mut length = read_length_check_overflow(limit, src)?;
 
-        let mut value = Val::Record(merger.defaults.clone());
+        let mut value = arena::record(&merger.defaults);
         message_inner_merge(merger, wire_type, &mut length, src, &mut value)?;
 
         *dst = Val::Option(Some(Box::new(value)));
@@ -255,19 +406,23 @@ pub(crate) fn message_repeated_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     if let Val::List(items) = dst {
         if wire_type == WireType::LengthDelimited {
+            check_repeated_elements(merger, items)?;
             let mut length =
                 read_length_check_overflow(limit, src).map_err(|e| e.with_index(items.len()))?;
 
-            let mut value = Val::Record(merger.defaults.clone());
+            let entry_length = length;
+
+            let mut value = arena::record(&merger.defaults);
             message_inner_merge(merger, wire_type, &mut length, src, &mut value)
                 .map_err(|e| e.with_index(items.len()))?;
 
             items.push(value);
+            reserve_contiguous(merger, entry_length, *limit, src, items);
             Ok(())
         } else {
             Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED))
@@ -277,18 +432,59 @@ pub(crate) fn message_repeated_merge(
     }
 }
 
+/// Minimum number of entries to reserve at once for a repeated message.
+const MIN_REPEATED_RESERVE: usize = 4;
+
+/// If the list of entries is full and another entry for the same field immediately follows,
+/// reserve room for more entries than `Vec` would on its own:
+/// double the capacity, or enough for every remaining byte to be an entry
+/// the same size as the previous one, whichever is less.
+///
+/// Non-contiguous entries are still correct; they just grow the list one at a time.
+#[inline(always)]
+fn reserve_contiguous(
+    merger: &Merger,
+    entry_length: u32,
+    limit: u32,
+    src: &[u8],
+    items: &mut Vec<Val>,
+) {
+    if items.len() < items.capacity() || limit == 0 {
+        return;
+    }
+    // Peek at the next tag without consuming it.
+    let mut next = src;
+    if next.is_empty() || decode_varint(&mut next).ok() != Some(merger.repeated_tag as u64) {
+        return;
+    }
+    let entry_size = encoded_len_varint(merger.repeated_tag as u64)
+        + encoded_len_varint(entry_length as u64)
+        + entry_length as usize;
+    let remaining = (limit as usize / entry_size).min(merger.max_elements as usize - items.len());
+    items.reserve_exact(items.len().max(MIN_REPEATED_RESERVE).min(remaining.max(1)));
+}
+
 /// Decode a oneof variant.
 /// These are never repeated, and always explicitly presence-tracked.
 pub(crate) fn oneof_variant_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     let variant = unsafe { &merger.compound.oneof_variant };
     let variant_name = variant.0.clone();
-    let variant_merger = variant.1.as_ref();
+    let Some(variant_merger) = variant.1.as_deref() else {
+        // A payload-less case is still encoded as an (empty) message on the wire.
+        // Anything inside it is unknown, so skip the whole thing.
+        if wire_type != WireType::LengthDelimited {
+            return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+        }
+        skip(wire_type, limit, src)?;
+        *dst = Val::Option(Some(Box::new(Val::Variant(variant_name, None))));
+        return Ok(());
+    };
     let mut value = Val::Option(None);
 
     // Call the inner merge function, then wrap the result as a named variant.
@@ -303,27 +499,175 @@ pub(crate) fn oneof_variant_merge(
     }
 }
 
-#[inline(always)]
-fn enum_inner(
+/// Decode a single map entry and add it to the list of key-value tuples.
+/// A missing key or value takes its default.
+/// Entries with duplicate keys are [resolved](resolve_duplicate_keys)
+/// once the enclosing message is decoded.
+pub(crate) fn map_merge(
     merger: &Merger,
+    wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
-) -> StdResult<Val, DecodeError> {
-    let varint = decode_varint(src).map_err(|_| DecodeError::new(INVALID_VARINT))?;
-    let bytes_read = encoded_len_varint(varint) as u32;
-    if bytes_read > *limit {
-        return Err(DecodeError::new(BUFFER_OVERFLOW));
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    let Val::List(entries) = dst else {
+        return Err(DecodeError::new(REPEATED_NON_LIST));
+    };
+    if wire_type != WireType::LengthDelimited {
+        return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+    }
+    let index = entries.len();
+    let mut length = read_length_check_overflow(limit, src).map_err(|e| e.with_index(index))?;
+
+    let (key_merger, value_merger) = unsafe { &**merger.compound.map_entry };
+    let mut key = merger.defaults[0].1.clone();
+    let mut value = merger.defaults[1].1.clone();
+    while length > 0 {
+        let (field_number, wire_type) =
+            decode_tag(&mut length, src).map_err(|e| e.with_index(index))?;
+        match field_number {
+            1 => (key_merger.merge)(key_merger, wire_type, &mut length, src, &mut key),
+            2 => (value_merger.merge)(value_merger, wire_type, &mut length, src, &mut value),
+            _ => skip(wire_type, &mut length, src),
+        }
+        .map_err(|e| e.with_field(field_number).with_index(index))?;
+    }
+
+    check_repeated_elements(merger, entries)?;
+    entries.push(Val::Tuple(vec![key, value]));
+    Ok(())
+}
+
+/// A hashable view of a map key.
+/// Protobuf only allows integral, boolean, and string keys.
+#[derive(PartialEq, Eq, Hash)]
+enum MapKey<'a> {
+    Bool(bool),
+    Signed(i64),
+    Unsigned(u64),
+    String(&'a str),
+}
+
+impl<'a> MapKey<'a> {
+    fn of(entry: &'a Val) -> StdResult<Self, DecodeError> {
+        let Val::Tuple(pair) = entry else {
+            return Err(DecodeError::new(MAP_ENTRY_NON_TUPLE));
+        };
+        Ok(match &pair[0] {
+            Val::Bool(key) => Self::Bool(*key),
+            Val::S32(key) => Self::Signed(*key as i64),
+            Val::S64(key) => Self::Signed(*key),
+            Val::U32(key) => Self::Unsigned(*key as u64),
+            Val::U64(key) => Self::Unsigned(*key),
+            Val::String(key) => Self::String(key),
+            _ => return Err(DecodeError::new(MAP_KEY_NON_SCALAR)),
+        })
+    }
+}
+
+/// Resolve duplicate keys within every map field of a decoded message:
+/// a later entry replaces the value of an earlier one with the same key, in place,
+/// unless [unique keys](MapKeys::Unique) are required.
+///
+/// Runs once per message rather than once per entry,
+/// hashing each key once instead of comparing it against every earlier entry.
+fn resolve_duplicate_keys(
+    merger: &Merger,
+    fields: &mut [(String, Val)],
+) -> StdResult<(), DecodeError> {
+    for (number, (index, subfield)) in unsafe { &merger.compound.subfields }.iter() {
+        if !fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+            continue;
+        }
+        if let Some((_name, Val::List(entries))) = fields.get_mut(*index as usize) {
+            deduplicate_entries(entries, merger.map_keys).map_err(|e| e.with_field(*number))?;
+        }
+    }
+    Ok(())
+}
+
+fn deduplicate_entries(entries: &mut Vec<Val>, map_keys: MapKeys) -> StdResult<(), DecodeError> {
+    if entries.len() < 2 {
+        return Ok(());
+    }
+    // Index of each duplicate entry, paired with that of the first entry with the same key.
+    let mut duplicates: Vec<(usize, usize)> = Vec::new();
+    let mut first: HashMap<MapKey, usize> = HashMap::with_capacity(entries.len());
+    for (index, entry) in entries.iter().enumerate() {
+        match first.entry(MapKey::of(entry)?) {
+            Entry::Occupied(_) if map_keys == MapKeys::Unique => {
+                return Err(DecodeError::new(MAP_KEY_DUPLICATE).with_index(index));
+            }
+            Entry::Occupied(original) => duplicates.push((index, *original.get())),
+            Entry::Vacant(vacant) => {
+                vacant.insert(index);
+            }
+        }
+    }
+    if duplicates.is_empty() {
+        return Ok(());
     }
-    *limit -= bytes_read;
 
-    let value = u32::try_from(varint).map_err(|_| DecodeError::new(OVERFLOW_32BIT))?;
+    // Later duplicates come later in the list, so the last value for each key wins.
+    for (duplicate, original) in duplicates.iter() {
+        let value = match &mut entries[*duplicate] {
+            // Any placeholder will do, since the duplicate entry is removed below.
+            Val::Tuple(pair) => replace(&mut pair[1], Val::Bool(false)),
+            _ => return Err(DecodeError::new(MAP_ENTRY_NON_TUPLE)),
+        };
+        if let Val::Tuple(pair) = &mut entries[*original] {
+            pair[1] = value;
+        }
+    }
+    let mut duplicates = duplicates
+        .iter()
+        .map(|(duplicate, _)| *duplicate)
+        .peekable();
+    let mut index = 0;
+    entries.retain(|_| {
+        let keep = duplicates.peek() != Some(&index);
+        if !keep {
+            duplicates.next();
+        }
+        index += 1;
+        keep
+    });
+    Ok(())
+}
+
+#[inline(always)]
+fn enum_inner(merger: &Merger, limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
+    let varint = read_varint(limit, src, INVALID_VARINT)?;
+
     let enum_variants = unsafe { &merger.compound.enum_variants };
-    if let Some(name) = enum_variants.get(&value).or_else(|| enum_variants.get(&0)) {
-        Ok(Val::Enum(name.clone()))
+    // Enum numbers are 32-bit signed integers, sign-extended to 64 bits on the wire.
+    let value = if enum_variants.strict {
+        i32::try_from(varint as i64).map_err(|_| DecodeError::new(OVERFLOW_32BIT))?
+    } else {
+        varint as i32
+    };
+    // Variant numbers are declared unsigned, so negative ones are stored in two's complement.
+    if let Some(name) = enum_variants.names.get(&(value as u32)) {
+        Ok(enum_variant(enum_variants, name))
+    } else if enum_variants.open {
+        // Open enums keep unrecognized numbers (proto3 behavior).
+        Ok(Val::Variant(
+            String::from(UNRECOGNIZED_VARIANT),
+            Some(Box::new(Val::S32(value))),
+        ))
+    } else {
+        // Closed enums have no representation for other numbers.
+        Err(DecodeError::new(ENUM_VARIANT_UNRECOGNIZED))
+    }
+}
+
+/// Represent a declared variant of an enum as a WIT variant if open, or a WIT enum if closed.
+#[inline(always)]
+fn enum_variant(enum_variants: &EnumVariants, name: &str) -> Val {
+    if enum_variants.open {
+        Val::Variant(String::from(name), None)
     } else {
-        // According to Protobuf spec,
-        // all enums must have at least a default zero value.
-        Err(DecodeError::new(ENUM_NO_DEFAULT))
+        Val::Enum(String::from(name))
     }
 }
 
@@ -331,7 +675,7 @@ pub(crate) fn enum_explicit_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     if wire_type == WireType::Varint {
@@ -346,7 +690,7 @@ pub(crate) fn enum_implicit_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     if wire_type == WireType::Varint {
@@ -361,19 +705,21 @@ pub(crate) fn enum_repeated_merge(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError> {
     if let Val::List(items) = dst {
         if wire_type == WireType::LengthDelimited {
             let mut length = read_length_check_overflow(limit, src)?;
             while length > 0 {
+                check_repeated_elements(merger, items)?;
                 items.push(
                     enum_inner(merger, &mut length, src).map_err(|e| e.with_index(items.len()))?,
                 );
             }
             Ok(())
         } else if wire_type == WireType::Varint {
+            check_repeated_elements(merger, items)?;
             items.push(enum_inner(&merger, limit, src).map_err(|e| e.with_index(items.len()))?);
             Ok(())
         } else {
@@ -0,0 +1,247 @@
+//! Lenient decoding to diagnose malformed requests, e.g. when debugging a client.
+//!
+//! The [strict decoder](crate::RequestDecoder::decode_bytes) stops at the first error,
+//! which hides any others in the same request.
+//! [Diagnosis](crate::RequestDecoder::diagnose) instead skips over each malformed field
+//! and keeps going, descending into nested messages so sibling fields are checked too.
+//! Only errors that lose track of where the next field starts
+//! (a corrupt tag or a truncated field) stop a message early.
+//!
+//! None of this is used to serve requests.
+
+use std::ptr::fn_addr_eq;
+use std::result::Result as StdResult;
+
+use prost::bytes::Buf;
+use prost::encoding::{decode_varint, WireType};
+use wasmtime::component::Val;
+
+use crate::compound::{message_outer_merge, message_repeated_merge};
+use crate::{
+    arena, check_repeated_elements, DecodeError, MergeFn, Merger, RequestDecoder, BUFFER_OVERFLOW,
+    FIELD_INDEX_OUT_OF_BOUNDS, FIELD_NUMBER_OUT_OF_RANGE, INVALID_FIELD_NUMBER,
+    INVALID_LENGTH_VARINT, INVALID_TAG_VARINT, INVALID_VARINT, INVALID_WIRE_TYPE,
+    MESSAGE_NON_RECORD, REPEATED_NON_LIST, REQUEST_TOO_BIG,
+};
+
+impl RequestDecoder {
+    /// Decode a whole request from a byte slice as far as possible,
+    /// returning every problem encountered (in order) rather than just the first.
+    ///
+    /// The value is a best effort:
+    /// fields that fail to decode keep whatever value they had before,
+    /// usually their default.
+    /// Like [`decode_bytes`](Self::decode_bytes),
+    /// malformed requests are neither counted nor logged.
+    pub fn diagnose(&self, bytes: &[u8]) -> (Val, Vec<DecodeError>) {
+        let inner = &self.0.inner;
+        let mut value = arena::record(&inner.defaults);
+        let mut errors = Vec::new();
+        if u32::try_from(bytes.len()).is_err() {
+            errors.push(DecodeError::new(REQUEST_TOO_BIG));
+        } else {
+            diagnose_message(inner, bytes, 0, &mut value, &mut errors);
+        }
+        (value, errors)
+    }
+}
+
+/// Leniently merge the fields of a message in `bytes` into the record `dst`,
+/// collecting errors whose offsets are relative to `base`.
+fn diagnose_message(
+    merger: &Merger,
+    bytes: &[u8],
+    base: usize,
+    dst: &mut Val,
+    errors: &mut Vec<DecodeError>,
+) {
+    let Val::Record(fields) = dst else {
+        errors.push(DecodeError::new(MESSAGE_NON_RECORD).with_offset(base));
+        return;
+    };
+    let mut position = 0;
+    while position < bytes.len() {
+        let (field_number, wire_type, tag_length) = match read_tag(&bytes[position..]) {
+            Ok(tag) => tag,
+            Err((error, consumed)) => {
+                errors.push(error.with_offset(base + position + consumed));
+                return;
+            }
+        };
+        let start = position + tag_length;
+        let length = match value_length(wire_type, &bytes[start..]) {
+            Ok(length) => length,
+            Err((error, consumed)) => {
+                errors.push(
+                    error
+                        .with_field(field_number)
+                        .with_offset(base + start + consumed),
+                );
+                return;
+            }
+        };
+        position = start + length;
+        let value = &bytes[start..position];
+
+        let Some((index, subfield)) = unsafe { &merger.compound.subfields }.get(&field_number)
+        else {
+            if field_number > merger.max_field_number {
+                errors.push(
+                    DecodeError::new(FIELD_NUMBER_OUT_OF_RANGE)
+                        .with_field(field_number)
+                        .with_offset(base + start),
+                );
+            }
+            // Otherwise, unknown fields are skipped like usual.
+            continue;
+        };
+        let Some((_name, subdst)) = fields.get_mut(*index as usize) else {
+            errors.push(
+                DecodeError::new(FIELD_INDEX_OUT_OF_BOUNDS)
+                    .with_field(field_number)
+                    .with_offset(base + start),
+            );
+            continue;
+        };
+
+        let mut field_errors = Vec::new();
+        diagnose_field(
+            subfield,
+            wire_type,
+            value,
+            base + start,
+            subdst,
+            &mut field_errors,
+        );
+        errors.extend(
+            field_errors
+                .into_iter()
+                .map(|error| error.with_field(field_number)),
+        );
+    }
+}
+
+/// Leniently merge a single field's `value` (everything after its tag) into `dst`.
+fn diagnose_field(
+    merger: &Merger,
+    wire_type: WireType,
+    value: &[u8],
+    base: usize,
+    dst: &mut Val,
+    errors: &mut Vec<DecodeError>,
+) {
+    // Messages are checked field by field.
+    // Anything else is merged strictly, all or nothing.
+    if wire_type == WireType::LengthDelimited {
+        if fn_addr_eq(merger.merge, message_outer_merge as MergeFn) {
+            let (header, payload) = split_length(value);
+            let mut record = arena::record(&merger.defaults);
+            diagnose_message(merger, payload, base + header, &mut record, errors);
+            *dst = Val::Option(Some(Box::new(record)));
+            return;
+        }
+        if fn_addr_eq(merger.merge, message_repeated_merge as MergeFn) {
+            let Val::List(items) = dst else {
+                errors.push(DecodeError::new(REPEATED_NON_LIST).with_offset(base));
+                return;
+            };
+            if let Err(error) = check_repeated_elements(merger, items) {
+                errors.push(error.with_offset(base));
+                return;
+            }
+            let (header, payload) = split_length(value);
+            let mut record = arena::record(&merger.defaults);
+            let mut item_errors = Vec::new();
+            diagnose_message(
+                merger,
+                payload,
+                base + header,
+                &mut record,
+                &mut item_errors,
+            );
+            let index = items.len();
+            errors.extend(item_errors.into_iter().map(|error| error.with_index(index)));
+            items.push(record);
+            return;
+        }
+    }
+
+    let mut src = value;
+    // Merge into a copy, so a failure leaves the field as it was.
+    let mut merged = dst.clone();
+    let mut limit = value.len() as u32;
+    match (merger.merge)(merger, wire_type, &mut limit, &mut src, &mut merged) {
+        Ok(()) => *dst = merged,
+        Err(error) => errors.push(error.with_offset(base + value.len() - src.remaining())),
+    }
+}
+
+/// Read a tag from the start of `bytes`,
+/// returning the field number, wire type, and length of the tag.
+/// On failure, also return how many bytes were read.
+fn read_tag(bytes: &[u8]) -> StdResult<(u32, WireType, usize), (DecodeError, usize)> {
+    let mut rest = bytes;
+    let tag = decode_varint(&mut rest).map_err(|_| {
+        (
+            DecodeError::new(INVALID_TAG_VARINT),
+            bytes.len() - rest.len(),
+        )
+    })?;
+    let length = bytes.len() - rest.len();
+    let field_number =
+        u32::try_from(tag >> 3).map_err(|_| (DecodeError::new(INVALID_FIELD_NUMBER), length))?;
+    let wire_type = WireType::try_from(tag & 0b111).map_err(|_| {
+        (
+            DecodeError::new(INVALID_WIRE_TYPE).with_field(field_number),
+            length,
+        )
+    })?;
+    Ok((field_number, wire_type, length))
+}
+
+/// Return the length of a field's value at the start of `bytes`, without decoding it,
+/// so decoding can resume after the field even if the value itself is malformed.
+/// On failure, also return how many bytes were read.
+fn value_length(wire_type: WireType, bytes: &[u8]) -> StdResult<usize, (DecodeError, usize)> {
+    let fixed = |length: usize| {
+        if length <= bytes.len() {
+            Ok(length)
+        } else {
+            Err((DecodeError::new(BUFFER_OVERFLOW), bytes.len()))
+        }
+    };
+    let mut rest = bytes;
+    match wire_type {
+        WireType::Varint => {
+            decode_varint(&mut rest)
+                .map_err(|_| (DecodeError::new(INVALID_VARINT), bytes.len() - rest.len()))?;
+            Ok(bytes.len() - rest.len())
+        }
+        WireType::SixtyFourBit => fixed(8),
+        WireType::LengthDelimited => {
+            let length = decode_varint(&mut rest).map_err(|_| {
+                (
+                    DecodeError::new(INVALID_LENGTH_VARINT),
+                    bytes.len() - rest.len(),
+                )
+            })?;
+            let header = bytes.len() - rest.len();
+            match usize::try_from(length) {
+                Ok(length) if length <= rest.len() => Ok(header + length),
+                _ => Err((DecodeError::new(BUFFER_OVERFLOW), header)),
+            }
+        }
+        WireType::ThirtyTwoBit => fixed(4),
+        // Deprecated groups have no payload.
+        WireType::StartGroup | WireType::EndGroup => Ok(0),
+    }
+}
+
+/// Split a length-delimited value (already [measured](value_length))
+/// into the length of its length prefix and its payload.
+fn split_length(value: &[u8]) -> (usize, &[u8]) {
+    let mut payload = value;
+    // The length was already read successfully once.
+    let _ = decode_varint(&mut payload);
+    (value.len() - payload.len(), payload)
+}
@@ -0,0 +1,84 @@
+//! Decoding logic for `google.protobuf.Duration`,
+//! which becomes a signed number of nanoseconds rather than a record.
+
+use std::result::Result as StdResult;
+
+use prost::encoding::WireType;
+use wasmtime::component::Val;
+
+use crate::{
+    decode_tag, read_length_check_overflow, read_varint, skip, CompoundMerger, DecodeError,
+    MapKeys, Merger, DURATION_OUT_OF_RANGE, DURATION_SIGN_MISMATCH, INVALID_VARINT,
+    WIRETYPE_NON_LENGTH_DELIMITED, WIRETYPE_NON_VARINT,
+};
+
+/// Largest magnitude of `seconds` allowed by the Protobuf spec (roughly 10,000 years).
+const MAX_SECONDS: u64 = 315_576_000_000;
+
+/// Largest magnitude of `nanos` allowed by the Protobuf spec.
+const MAX_NANOS: u64 = 999_999_999;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+impl Merger {
+    pub(crate) fn duration() -> Self {
+        Self {
+            merge: duration_merge,
+            defaults: Vec::new(),
+            repeated_tag: 0,
+            presence: None,
+            max_field_number: u32::MAX,
+            max_elements: u32::MAX,
+            map_keys: MapKeys::Absent,
+            compound: CompoundMerger { scalar: () },
+        }
+    }
+}
+
+/// Decode a duration message. Always explicitly presence-tracked.
+pub(crate) fn duration_merge(
+    _merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    if wire_type != WireType::LengthDelimited {
+        return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+    }
+    let mut length = read_length_check_overflow(limit, src)?;
+
+    let (mut seconds, mut nanos) = (0, 0);
+    while length > 0 {
+        let (field_number, wire_type) = decode_tag(&mut length, src)?;
+        match field_number {
+            1 | 2 if wire_type != WireType::Varint => {
+                return Err(DecodeError::new(WIRETYPE_NON_VARINT).with_field(field_number));
+            }
+            1 => seconds = read_varint(&mut length, src, INVALID_VARINT)? as i64,
+            // Negative `int32` values are sign-extended to 64 bits on the wire.
+            2 => nanos = read_varint(&mut length, src, INVALID_VARINT)? as i32 as i64,
+            _ => skip(wire_type, &mut length, src).map_err(|e| e.with_field(field_number))?,
+        }
+    }
+
+    *dst = Val::Option(Some(Box::new(Val::S64(total_nanos(seconds, nanos)?))));
+    Ok(())
+}
+
+/// Combine the `seconds` and `nanos` of a duration into a single number of nanoseconds,
+/// enforcing the range and sign rules of the Protobuf spec.
+fn total_nanos(seconds: i64, nanos: i64) -> StdResult<i64, DecodeError> {
+    if seconds.unsigned_abs() > MAX_SECONDS || nanos.unsigned_abs() > MAX_NANOS {
+        return Err(DecodeError::new(DURATION_OUT_OF_RANGE));
+    }
+    // Zero is compatible with either sign.
+    if (seconds < 0 && nanos > 0) || (seconds > 0 && nanos < 0) {
+        return Err(DecodeError::new(DURATION_SIGN_MISMATCH));
+    }
+    // Valid durations beyond about 292 years do not fit in 64 bits of nanoseconds.
+    seconds
+        .checked_mul(NANOS_PER_SECOND)
+        .and_then(|total| total.checked_add(nanos))
+        .ok_or_else(|| DecodeError::new(DURATION_OUT_OF_RANGE))
+}
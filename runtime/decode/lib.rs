@@ -1,28 +1,42 @@
 //! Decode incoming requests into Wasm component record values.
 
+mod arena;
 mod compound;
+mod diagnose;
+mod duration;
+mod mask;
 mod scalar;
+mod timestamp;
+mod value;
 
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter, Result as FmtResult, Write};
 use std::mem::{transmute, ManuallyDrop};
 use std::ptr::fn_addr_eq;
 use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
+use anyhow::{anyhow, Context, Result};
+use metadata_proto::work::runtime::field::Charset;
 use metadata_proto::work::runtime::Field;
 use prost::bytes::Buf;
-use prost::encoding::{decode_varint, encoded_len_varint, WireType};
+use prost::encoding::{decode_varint, WireType};
 use tonic::codec::{DecodeBuf, Decoder as TonicDecoder};
 use tonic::Status;
 use wasmtime::component::Val;
 
 use compound::{
-    enum_explicit_merge, enum_implicit_merge, enum_repeated_merge, message_inner_merge,
+    enum_explicit_merge, enum_implicit_merge, enum_repeated_merge, map_merge, message_inner_merge,
     message_outer_merge, message_repeated_merge, oneof_variant_merge,
 };
+use logging::log_warn;
 use names::ComponentName;
+use value::{list_value_merge, struct_merge, value_merge};
+
+pub use arena::recycle;
+pub use mask::FieldMask;
 
 /// Decodes a top-level request message.
 ///
@@ -38,6 +52,103 @@ struct RequestDecoderInner {
 
     /// Component name used for error logging only, shared to save memory.
     component: Arc<ComponentName>,
+
+    /// Number of malformed requests rejected since the last warning about them.
+    malformed: AtomicU64,
+
+    /// When the next warning about malformed requests may be logged,
+    /// in milliseconds since the decoder was [created](Self::created).
+    next_warning: AtomicU64,
+
+    /// Reference point for [`next_warning`](Self::next_warning).
+    created: Instant,
+
+    /// Presence counters for every field, by dot-separated path.
+    /// Empty unless presence tracking was requested.
+    presence: Vec<(String, Arc<AtomicU64>)>,
+
+    /// How much detail about decoding errors to return to clients.
+    error_verbosity: ErrorVerbosity,
+}
+
+/// Default for [`DecoderOptions::max_depth`].
+pub const DEFAULT_MAX_DEPTH: u32 = 100;
+
+/// Default for [`DecoderOptions::max_repeated_elements`].
+pub const DEFAULT_MAX_REPEATED_ELEMENTS: u32 = 1_000_000;
+
+/// Minimum time between warnings about malformed requests from any one decoder.
+const MALFORMED_WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Name of the case carrying the number of an unrecognized variant of an open enumeration.
+pub const UNRECOGNIZED_VARIANT: &str = "unrecognized";
+
+/// Optional decoding behavior, all disabled by default.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DecoderOptions {
+    /// Count how often each field appears in decoded requests.
+    /// See [`RequestDecoder::field_presence`].
+    pub field_presence: bool,
+
+    /// Reject any field number greater than the largest one declared anywhere in the request
+    /// (including nested messages), instead of skipping it like other unknown fields.
+    /// Tightens parsing for schemas that are not expected to grow on the client side.
+    pub limit_field_numbers: bool,
+
+    /// Reject enum numbers outside the range of a 32-bit signed integer,
+    /// instead of truncating them to their low 32 bits like other Protobuf parsers.
+    /// Negative numbers are accepted either way,
+    /// as long as they are sign-extended to 64 bits on the wire as the encoding requires.
+    pub strict_enum_numbers: bool,
+
+    /// Reject maps in which the same key appears more than once,
+    /// instead of keeping the last value like other Protobuf parsers.
+    pub unique_map_keys: bool,
+
+    /// Maximum number of submessage levels below the top-level request.
+    /// Any deeper message is rejected as soon as it is encountered,
+    /// bounding the stack used by mutually recursive merge functions.
+    /// Messages nested within dynamic values (`google.protobuf.Struct`, etc.) count too.
+    /// [`DEFAULT_MAX_DEPTH`] if unset.
+    pub max_depth: Option<u32>,
+
+    /// Maximum number of elements in any one repeated field (or entries in any one map).
+    /// Tiny elements like packed varints cost far more memory once decoded than on the wire,
+    /// so the byte length of a request alone does not bound the size of its lists.
+    /// [`DEFAULT_MAX_REPEATED_ELEMENTS`] if unset.
+    pub max_repeated_elements: Option<u32>,
+
+    /// How much detail about decoding errors to return to clients.
+    pub error_verbosity: ErrorVerbosity,
+}
+
+impl DecoderOptions {
+    /// Tighten the [maximum depth](Self::max_depth) to the depth declared by a component,
+    /// unless that is zero (undeclared).
+    /// A component can only lower the limit, never raise it.
+    pub fn with_component_depth(self, declared: u32) -> Self {
+        let node = self.max_depth.unwrap_or(DEFAULT_MAX_DEPTH);
+        Self {
+            max_depth: match declared {
+                0 => self.max_depth,
+                declared => Some(declared.min(node)),
+            },
+            ..self
+        }
+    }
+}
+
+/// How much detail a malformed-request [status](Status) reveals to the client.
+/// The full error is logged (sampled) regardless.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum ErrorVerbosity {
+    /// Include the field path, offset, and cause, e.g. for development.
+    #[default]
+    Full,
+
+    /// Return only a generic message,
+    /// so field paths and offsets cannot be used to probe the schema.
+    Redacted,
 }
 
 /// Decodes a component [value](Val) for any specific Protobuf field,
@@ -47,14 +158,48 @@ struct Merger {
     merge: MergeFn,
 
     /// For records only: default values for each field, if not encoded.
+    /// For maps: default values for the key and value of each entry.
     defaults: Vec<(String, Val)>,
 
+    /// For repeated messages only: the tag that precedes each entry,
+    /// used to detect contiguous entries and reserve capacity for them.
+    /// Zero (an impossible tag) otherwise.
+    repeated_tag: u32,
+
+    /// Number of times this field has appeared on the wire,
+    /// if [field presence](RequestDecoder::field_presence) is tracked.
+    presence: Option<Arc<AtomicU64>>,
+
+    /// For messages only: unknown field numbers above this are rejected rather than skipped.
+    /// [`u32::MAX`] unless [limited](DecoderOptions::limit_field_numbers).
+    max_field_number: u32,
+
+    /// For repeated fields and maps only: elements beyond this count are rejected.
+    /// See [`DecoderOptions::max_repeated_elements`].
+    max_elements: u32,
+
+    /// For messages only: how duplicate keys are resolved in any map subfields,
+    /// once the message is decoded.
+    map_keys: MapKeys,
+
     /// Information for decoding compound types (messages, oneofs, enumerations).
     /// Ignored for scalar types.
     compound: CompoundMerger,
 }
 
-/// Information for a [`Merger`] for compound types (messages, oneofs, enumerations).
+/// How a message [`Merger`] resolves duplicate keys within its map subfields.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum MapKeys {
+    /// The message has no map subfields.
+    Absent,
+    /// A later entry replaces the value of an earlier one with the same key,
+    /// like other Protobuf parsers.
+    LastWins,
+    /// A duplicate key is rejected. See [`DecoderOptions::unique_map_keys`].
+    Unique,
+}
+
+/// Information for a [`Merger`] for compound types (messages, oneofs, enumerations, maps).
 ///
 /// Implemented as a union to save space while keeping a known size.
 /// Each specific decoding function will know how to deal with this appropriately,
@@ -66,16 +211,42 @@ union CompoundMerger {
     /// in which to merge the value.
     subfields: ManuallyDrop<HashMap<u32, (u32, Merger)>>,
 
-    /// Map from enum variant numbers to variant names (for enumerations only).
-    enum_variants: ManuallyDrop<HashMap<u32, String>>,
+    /// Variant names and openness (for enumerations only).
+    enum_variants: ManuallyDrop<EnumVariants>,
 
     /// Inner value merge function and variant name for a single oneof variant.
-    oneof_variant: ManuallyDrop<(String, Box<Merger>)>,
+    /// The merger is absent for variants without a payload.
+    oneof_variant: ManuallyDrop<(String, Option<Box<Merger>>)>,
+
+    /// Key and value merge functions for each entry of a map.
+    map_entry: ManuallyDrop<Box<(Merger, Merger)>>,
+
+    /// Character constraint for strings with a declared [charset](Charset).
+    charset: Charset,
+
+    /// Number of message levels allowed within a dynamic value (`google.protobuf.Struct`, etc.),
+    /// whose own messages are all decoded by the same recursive merge functions.
+    depth: u32,
 
     /// Set this placeholder value for scalars.
     scalar: (),
 }
 
+/// Information for a [`Merger`] for enumerations.
+struct EnumVariants {
+    /// Map from enum variant numbers to variant names.
+    names: HashMap<u32, String>,
+
+    /// Open enumerations decode unrecognized variant numbers
+    /// into an [`UNRECOGNIZED_VARIANT`] case.
+    /// Closed enumerations reject them.
+    open: bool,
+
+    /// Reject numbers outside the 32-bit signed range rather than truncating them.
+    /// See [`DecoderOptions::strict_enum_numbers`].
+    strict: bool,
+}
+
 /// Decode a [value](Val) from the [buffer](Buf), reading only up to `limit` bytes.
 /// Merge it into `dst`.
 /// `limit` is decremented by the number of bytes read.
@@ -86,21 +257,28 @@ type MergeFn = fn(
     merger: &Merger,
     wire_type: WireType,
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     dst: &mut Val,
 ) -> StdResult<(), DecodeError>;
 
-/// An error encountered during response decoding.
-struct DecodeError {
+/// An error encountered during request decoding.
+#[derive(Debug)]
+pub struct DecodeError {
     /// Basic error message.
     message: &'static str,
 
     /// Traceback of mutual recursion during decoding (most recent first).
     traceback: Vec<DecodeLevel>,
+
+    /// Number of bytes consumed from the top-level request when decoding stopped
+    /// (just past the malformed data).
+    /// Only known once the error reaches the top level.
+    offset: Option<usize>,
 }
 
 /// Represents a level of mutual recursion among compound subtypes
 /// in an error traceback.
+#[derive(Debug)]
 enum DecodeLevel {
     /// Message field number (*no* wire type).
     Field(u32),
@@ -110,12 +288,149 @@ enum DecodeLevel {
 
 impl RequestDecoder {
     pub fn new(request: &Field, component: Arc<ComponentName>) -> Result<Self> {
+        Self::with_options(request, component, DecoderOptions::default())
+    }
+
+    /// Like [`new`](Self::new), with [optional behavior](DecoderOptions) enabled.
+    pub fn with_options(
+        request: &Field,
+        component: Arc<ComponentName>,
+        options: DecoderOptions,
+    ) -> Result<Self> {
+        let mut inner = Merger::message_inner(request, component.as_ref())
+            .context("Invalid request decoder")?;
+        inner.limit_depth(options.max_depth.unwrap_or(DEFAULT_MAX_DEPTH));
+        inner.limit_repeated_elements(
+            options
+                .max_repeated_elements
+                .unwrap_or(DEFAULT_MAX_REPEATED_ELEMENTS),
+        );
+        let mut presence = Vec::new();
+        if options.field_presence {
+            inner.track_presence("", &mut presence);
+            presence.sort_by(|(left, _), (right, _)| left.cmp(right));
+        }
+        if options.limit_field_numbers {
+            inner.limit_field_numbers(max_declared_field_number(request));
+        }
+        if options.strict_enum_numbers {
+            inner.strict_enum_numbers();
+        }
+        if options.unique_map_keys {
+            inner.unique_map_keys();
+        }
         Ok(Self(Arc::new(RequestDecoderInner {
-            inner: Merger::message_inner(request, component.as_ref())
-                .context("Invalid request decoder")?,
-            component: component,
+            inner,
+            component,
+            malformed: AtomicU64::new(0),
+            next_warning: AtomicU64::new(0),
+            created: Instant::now(),
+            presence,
+            error_verbosity: options.error_verbosity,
         })))
     }
+
+    /// Number of times each field (by dot-separated path, e.g. `user.name`)
+    /// has appeared on the wire in requests decoded so far, sorted by path.
+    /// Every occurrence of a field's tag counts once,
+    /// so expanded repeated elements count separately while a packed run counts as one.
+    ///
+    /// Empty unless the decoder was created [with field presence](DecoderOptions::field_presence).
+    pub fn field_presence(&self) -> Vec<(String, u64)> {
+        self.0
+            .presence
+            .iter()
+            .map(|(path, count)| (path.clone(), count.load(Ordering::Relaxed)))
+            .collect()
+    }
+
+    /// Decode a whole request from a byte slice, outside of Tonic (*e.g.* for fuzzing).
+    /// Any input, however malformed, results in either a value or an error, never a panic.
+    ///
+    /// Unlike [decoding through Tonic](TonicDecoder::decode),
+    /// malformed requests are neither counted nor logged.
+    pub fn decode_bytes(&self, bytes: &[u8]) -> StdResult<Val, DecodeError> {
+        let length = u32::try_from(bytes.len()).map_err(|_| DecodeError::new(REQUEST_TOO_BIG))?;
+        self.0.decode(length, &mut &bytes[..])
+    }
+
+    /// Check whether a whole request decodes, discarding the value,
+    /// *e.g.* to reproduce a client's `INVALID_ARGUMENT` error without standing up a pod.
+    /// On failure, return the error as it is [displayed](DecodeError) in full.
+    pub fn validate(&self, bytes: &[u8]) -> StdResult<(), String> {
+        self.decode_bytes(bytes)
+            .map(recycle)
+            .map_err(|error| error.to_string())
+    }
+
+    /// Decode a partial update from a byte slice,
+    /// and merge only the fields selected by the mask into `existing`,
+    /// a request previously decoded by the same decoder
+    /// (*e.g.* for an update method that takes a `google.protobuf.FieldMask`).
+    ///
+    /// Like [`decode_bytes`](Self::decode_bytes), malformed updates are neither counted nor logged.
+    pub fn merge_masked(&self, bytes: &[u8], mask: &FieldMask, existing: &mut Val) -> Result<()> {
+        let update = self
+            .decode_bytes(bytes)
+            .map_err(|error| anyhow!("Malformed update: {error}"))?;
+        mask.merge(update, existing)
+    }
+}
+
+impl RequestDecoderInner {
+    /// Decode a whole request of the given length from the buffer.
+    /// On failure, the error notes how far into the request decoding got.
+    fn decode(&self, mut length: u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
+        let total_length = src.remaining();
+        let mut value = arena::record(&self.inner.defaults);
+        (self.inner.merge)(
+            &self.inner,
+            WireType::LengthDelimited,
+            &mut length,
+            src,
+            &mut value,
+        )
+        // Every byte read so far has been consumed from `src`,
+        // so the offset falls out of the remaining length for free.
+        .map_err(|error| error.with_offset(total_length - src.remaining()))?;
+        Ok(value)
+    }
+
+    /// Count a malformed request.
+    ///
+    /// Individual decoding errors are not logged,
+    /// but a warning is emitted at most once per [interval](MALFORMED_WARNING_INTERVAL),
+    /// summarizing every error since the last one,
+    /// so that sustained malformed traffic remains visible to operators
+    /// without flooding the logs.
+    #[cold]
+    fn record_malformed(&self, error: &DecodeError) {
+        self.malformed.fetch_add(1, Ordering::Relaxed);
+        let now = self.created.elapsed().as_millis() as u64;
+        let next_warning = self.next_warning.load(Ordering::Relaxed);
+        if now < next_warning {
+            return;
+        }
+        let interval = MALFORMED_WARNING_INTERVAL.as_millis() as u64;
+        // Only one of any concurrent callers wins the right to log.
+        if self
+            .next_warning
+            .compare_exchange(
+                next_warning,
+                now + interval,
+                Ordering::Relaxed,
+                Ordering::Relaxed,
+            )
+            .is_ok()
+        {
+            let count = self.malformed.swap(0, Ordering::Relaxed);
+            log_warn!(
+                component: &self.component,
+                field_path = error.field_path(),
+                "Rejected {count} malformed requests since the last warning (latest: {error})",
+            );
+        }
+    }
 }
 
 impl TonicDecoder for RequestDecoder {
@@ -123,45 +438,287 @@ impl TonicDecoder for RequestDecoder {
     type Error = Status;
 
     /// Decode a message from a readable buffer.
+    ///
+    /// Tonic reassembles each message from however many chunks it arrived in
+    /// before calling this, so the buffer always holds exactly one whole message.
+    /// Running out of bytes partway through a field therefore means the message is truncated,
+    /// which is reported as an error rather than waiting for more bytes.
     fn decode(&mut self, src: &mut DecodeBuf<'_>) -> StdResult<Option<Self::Item>, Self::Error> {
-        let mut length = u32::try_from(src.remaining())
-            .map_err(|_| Status::invalid_argument("Request is too big"))?;
-        let mut value = Val::Record(self.0.inner.defaults.clone());
-        (self.0.inner.merge)(
-            &self.0.inner,
-            WireType::LengthDelimited,
-            &mut length,
-            src,
-            &mut value,
-        )
-        .map_err(|error| {
+        let length = u32::try_from(src.remaining())
+            .map_err(|_| Status::invalid_argument(REQUEST_TOO_BIG))?;
+        // Tonic holds the message in a single `BytesMut`, so this splits it off without copying.
+        let message = src.copy_to_bytes(length as usize);
+        let value = self.0.decode(length, &mut &message[..]).map_err(|error| {
             // A decoding error indicates that the client sent a malformed request.
             // Report this as an INVALID_ARGUMENT status to the caller and *do not* log it,
             // because this is considered a normal client error and could occur very frequently.
-            Status::invalid_argument(error.to_string())
+            // It is only counted, with sampled logging.
+            self.0.record_malformed(&error);
+            match self.0.error_verbosity {
+                ErrorVerbosity::Full => Status::invalid_argument(error.to_string()),
+                ErrorVerbosity::Redacted => Status::invalid_argument(REDACTED_ERROR),
+            }
         })?;
         Ok(Some(value))
     }
 }
 
+impl Merger {
+    /// Attach a presence counter to every subfield of a message merger, recursively,
+    /// collecting the counters by path under `prefix`.
+    fn track_presence(&mut self, prefix: &str, counters: &mut Vec<(String, Arc<AtomicU64>)>) {
+        if !self.is_message() {
+            return;
+        }
+        let defaults = &self.defaults;
+        for (index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            let name = if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                // Oneof variants are flattened into the message, so use the variant name.
+                unsafe { &subfield.compound.oneof_variant.0 }
+            } else {
+                &defaults[*index as usize].0
+            };
+            let path = format!("{prefix}{name}");
+            let counter = Arc::new(AtomicU64::new(0));
+            subfield.presence = Some(counter.clone());
+            counters.push((path.clone(), counter));
+
+            let nested = if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+            } else {
+                Some(subfield)
+            };
+            if let Some(nested) = nested {
+                nested.track_presence(&format!("{path}."), counters);
+            }
+        }
+    }
+
+    /// Set the maximum field number of a message merger and every message nested within it.
+    fn limit_field_numbers(&mut self, max_field_number: u32) {
+        if !self.is_message() {
+            return;
+        }
+        self.max_field_number = max_field_number;
+        for (_index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                if let Some(payload) =
+                    unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+                {
+                    payload.limit_field_numbers(max_field_number);
+                }
+            } else if fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+                unsafe {
+                    (*subfield.compound.map_entry)
+                        .1
+                        .limit_field_numbers(max_field_number)
+                };
+            } else {
+                subfield.limit_field_numbers(max_field_number);
+            }
+        }
+    }
+
+    /// Make every enumeration within a message merger, however deeply nested,
+    /// reject numbers outside the 32-bit signed range.
+    fn strict_enum_numbers(&mut self) {
+        if self.is_enum() {
+            unsafe { (*self.compound.enum_variants).strict = true };
+            return;
+        }
+        if !self.is_message() {
+            return;
+        }
+        for (_index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                if let Some(payload) =
+                    unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+                {
+                    payload.strict_enum_numbers();
+                }
+            } else if fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+                unsafe { (*subfield.compound.map_entry).1.strict_enum_numbers() };
+            } else {
+                subfield.strict_enum_numbers();
+            }
+        }
+    }
+
+    /// Make every message within a message merger, however deeply nested,
+    /// reject duplicate keys in its maps.
+    fn unique_map_keys(&mut self) {
+        if !self.is_message() {
+            return;
+        }
+        if self.map_keys == MapKeys::LastWins {
+            self.map_keys = MapKeys::Unique;
+        }
+        for (_index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                if let Some(payload) =
+                    unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+                {
+                    payload.unique_map_keys();
+                }
+            } else if fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+                unsafe { (*subfield.compound.map_entry).1.unique_map_keys() };
+            } else {
+                subfield.unique_map_keys();
+            }
+        }
+    }
+
+    /// Set the maximum element count of every repeated field and map within a merger,
+    /// however deeply nested.
+    fn limit_repeated_elements(&mut self, max_elements: u32) {
+        self.max_elements = max_elements;
+        if !self.is_message() {
+            return;
+        }
+        for (_index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                if let Some(payload) =
+                    unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+                {
+                    payload.limit_repeated_elements(max_elements);
+                }
+            } else if fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+                subfield.max_elements = max_elements;
+                unsafe {
+                    (*subfield.compound.map_entry)
+                        .1
+                        .limit_repeated_elements(max_elements)
+                };
+            } else {
+                subfield.limit_repeated_elements(max_elements);
+            }
+        }
+    }
+
+    /// Replace every message nested more than `remaining` levels below this one
+    /// with a merger that always fails.
+    fn limit_depth(&mut self, remaining: u32) {
+        if !self.is_message() {
+            return;
+        }
+        for (_index, subfield) in unsafe { (*self.compound.subfields).values_mut() } {
+            if fn_addr_eq(subfield.merge, oneof_variant_merge as MergeFn) {
+                if let Some(payload) =
+                    unsafe { (*subfield.compound.oneof_variant).1.as_deref_mut() }
+                {
+                    payload.limit_nested_depth(remaining);
+                }
+            } else if fn_addr_eq(subfield.merge, map_merge as MergeFn) {
+                unsafe {
+                    (*subfield.compound.map_entry)
+                        .1
+                        .limit_nested_depth(remaining)
+                };
+            } else {
+                subfield.limit_nested_depth(remaining);
+            }
+        }
+    }
+
+    /// Like [`limit_depth`](Self::limit_depth), for a message one level down.
+    fn limit_nested_depth(&mut self, remaining: u32) {
+        if self.is_dynamic() {
+            // Dynamic values check their depth as they go.
+            self.compound.depth = remaining;
+            return;
+        }
+        if !self.is_message() {
+            return;
+        }
+        match remaining.checked_sub(1) {
+            Some(remaining) => self.limit_depth(remaining),
+            None => *self = Merger::too_deep(),
+        }
+    }
+
+    /// A merger for messages nested beyond the [maximum depth](DecoderOptions::max_depth).
+    fn too_deep() -> Self {
+        Self {
+            merge: too_deep_merge,
+            defaults: Vec::new(),
+            repeated_tag: 0,
+            presence: None,
+            max_field_number: u32::MAX,
+            max_elements: u32::MAX,
+            map_keys: MapKeys::Absent,
+            compound: CompoundMerger { scalar: () },
+        }
+    }
+
+    fn is_message(&self) -> bool {
+        fn_addr_eq(self.merge, message_inner_merge as MergeFn)
+            || fn_addr_eq(self.merge, message_outer_merge as MergeFn)
+            || fn_addr_eq(self.merge, message_repeated_merge as MergeFn)
+    }
+
+    fn is_dynamic(&self) -> bool {
+        fn_addr_eq(self.merge, struct_merge as MergeFn)
+            || fn_addr_eq(self.merge, value_merge as MergeFn)
+            || fn_addr_eq(self.merge, list_value_merge as MergeFn)
+    }
+
+    fn is_enum(&self) -> bool {
+        fn_addr_eq(self.merge, enum_explicit_merge as MergeFn)
+            || fn_addr_eq(self.merge, enum_implicit_merge as MergeFn)
+            || fn_addr_eq(self.merge, enum_repeated_merge as MergeFn)
+    }
+}
+
+/// Reject a message nested beyond the [maximum depth](DecoderOptions::max_depth)
+/// without reading any of it.
+fn too_deep_merge(
+    _merger: &Merger,
+    _wire_type: WireType,
+    _limit: &mut u32,
+    _src: &mut &[u8],
+    _dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    Err(DecodeError::new(RECURSION_LIMIT))
+}
+
+/// Check that a repeated field has room for another element
+/// within its [maximum element count](DecoderOptions::max_repeated_elements).
+#[inline(always)]
+pub(crate) fn check_repeated_elements(
+    merger: &Merger,
+    items: &[Val],
+) -> StdResult<(), DecodeError> {
+    if items.len() < merger.max_elements as usize {
+        Ok(())
+    } else {
+        Err(DecodeError::new(REPEATED_LIMIT).with_index(items.len()))
+    }
+}
+
+/// Return the largest field number declared anywhere within a message field.
+fn max_declared_field_number(field: &Field) -> u32 {
+    field
+        .subfields
+        .iter()
+        .map(|subfield| subfield.number.max(max_declared_field_number(subfield)))
+        .max()
+        .unwrap_or(0)
+}
+
 /// [`Merger`] uses a union internally which must be dropped manually.
 impl Drop for Merger {
     fn drop(&mut self) {
         // Mergers are dropped when a container shuts down (infrequently)
         // so we can exhaustively check against the known compound encoding functions
         // to figure out which type-specific data to drop.
-        if fn_addr_eq(self.merge, message_inner_merge as MergeFn)
-            || fn_addr_eq(self.merge, message_outer_merge as MergeFn)
-            || fn_addr_eq(self.merge, message_repeated_merge as MergeFn)
-        {
+        if self.is_message() {
             unsafe { ManuallyDrop::drop(&mut self.compound.subfields) }
-        } else if fn_addr_eq(self.merge, enum_explicit_merge as MergeFn)
-            || fn_addr_eq(self.merge, enum_implicit_merge as MergeFn)
-            || fn_addr_eq(self.merge, enum_repeated_merge as MergeFn)
-        {
+        } else if self.is_enum() {
             unsafe { ManuallyDrop::drop(&mut self.compound.enum_variants) }
         } else if fn_addr_eq(self.merge, oneof_variant_merge as MergeFn) {
             unsafe { ManuallyDrop::drop(&mut self.compound.oneof_variant) }
+        } else if fn_addr_eq(self.merge, map_merge as MergeFn) {
+            unsafe { ManuallyDrop::drop(&mut self.compound.map_entry) }
         }
     }
 }
@@ -172,6 +729,7 @@ impl DecodeError {
         Self {
             message,
             traceback: Vec::new(),
+            offset: None,
         }
     }
 
@@ -186,19 +744,39 @@ impl DecodeError {
         self.traceback.push(DecodeLevel::Index(i));
         self
     }
+
+    #[cold]
+    pub(crate) fn with_offset(mut self, offset: usize) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    /// Render only the location of the error within the request, e.g. `.0.123[0][4].5`,
+    /// as it appears in the [displayed](Display) error.
+    #[cold]
+    pub(crate) fn field_path(&self) -> String {
+        let mut path = String::new();
+        // Writing to a string cannot fail.
+        let _ = format_decode_error_trace(self, &mut path);
+        path
+    }
 }
 
 #[inline(always)]
 fn read_varint(
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
     error: &'static str,
 ) -> StdResult<u64, DecodeError> {
+    let remaining = src.remaining();
     let varint = decode_varint(src).map_err(
         // Overflowed 64 bits or incomplete at end of buffer.
         |_| DecodeError::new(error),
     )?;
-    let bytes_read = encoded_len_varint(varint) as u32;
+    // Count the bytes actually consumed rather than `encoded_len_varint(varint)`:
+    // an overlong encoding (e.g. zero as `0x80 0x00`) is valid, but longer than the minimum,
+    // and undercounting it would leave `limit` claiming bytes that aren't there.
+    let bytes_read = (remaining - src.remaining()) as u32;
     if bytes_read > *limit {
         return Err(DecodeError::new(BUFFER_OVERFLOW));
     }
@@ -209,7 +787,7 @@ fn read_varint(
 /// Decode a tag from `src`, returning the field number and wire type.
 /// Decrement `limit` by the number of bytes read.
 #[inline(always)]
-fn decode_tag(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<(u32, WireType), DecodeError> {
+fn decode_tag(limit: &mut u32, src: &mut &[u8]) -> StdResult<(u32, WireType), DecodeError> {
     let tag = read_varint(limit, src, INVALID_TAG_VARINT)?;
     let field_number = u32::try_from(tag >> 3).map_err(|_| {
         // Indicates the field number exceeded 32 bits.
@@ -227,10 +805,7 @@ fn decode_tag(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<(u32, WireT
 /// check that there are at least as many bytes left in the buffer,
 /// then return that varint.
 #[inline(always)]
-fn read_length_check_overflow(
-    limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
-) -> StdResult<u32, DecodeError> {
+fn read_length_check_overflow(limit: &mut u32, src: &mut &[u8]) -> StdResult<u32, DecodeError> {
     let length = read_varint(limit, src, INVALID_LENGTH_VARINT)?;
     let length = u32::try_from(length).map_err(|_| DecodeError::new(INVALID_LENGTH_VARINT))?;
     if length > *limit {
@@ -242,11 +817,7 @@ fn read_length_check_overflow(
 
 /// Use wire type information to skip an unknown field.
 #[inline(always)]
-fn skip(
-    wire_type: WireType,
-    limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
-) -> StdResult<(), DecodeError> {
+fn skip(wire_type: WireType, limit: &mut u32, src: &mut &[u8]) -> StdResult<(), DecodeError> {
     match wire_type {
         WireType::Varint => {
             // To skip a varint, just decode and forget it.
@@ -286,55 +857,74 @@ fn explicit_scalar(scalar_coding: i32) -> bool {
 
 /// When returning an error status to a client,
 /// a decoding error should be displayed like this:
-///     Malformed request (.0.123[0][4].5.5): <message>
+///     Malformed request (.0.123[0][4].5.5) @offset 42: <message>
 ///
 /// Numbers following dots indicate field numbers.
 /// Those between square brackets indicate repeated field indices.
+/// The offset is the number of bytes consumed when decoding stopped.
 impl Display for DecodeError {
     fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
         formatter.write_str("Malformed request (")?;
         format_decode_error_trace(self, formatter)?;
-        formatter.write_str("): ")?;
+        formatter.write_char(')')?;
+        if let Some(offset) = self.offset {
+            formatter.write_str(" @offset ")?;
+            Display::fmt(&offset, formatter)?;
+        }
+        formatter.write_str(": ")?;
         formatter.write_str(&self.message)
     }
 }
 
 #[inline(always)]
-fn format_decode_error_trace(error: &DecodeError, formatter: &mut Formatter<'_>) -> FmtResult {
+fn format_decode_error_trace(error: &DecodeError, output: &mut impl Write) -> FmtResult {
     for level in error.traceback.iter().rev() {
         match level {
-            DecodeLevel::Field(number) => {
-                formatter.write_char('.')?;
-                Display::fmt(number, formatter)?;
-            }
-            DecodeLevel::Index(index) => {
-                formatter.write_char('[')?;
-                Display::fmt(index, formatter)?;
-                formatter.write_char(']')?;
-            }
+            DecodeLevel::Field(number) => write!(output, ".{number}")?,
+            DecodeLevel::Index(index) => write!(output, "[{index}]")?,
         }
     }
     Ok(())
 }
 
+/// Client-facing message for every decoding error when [redacted](ErrorVerbosity::Redacted).
+const REDACTED_ERROR: &str = "Malformed request";
+
+const REQUEST_TOO_BIG: &str = "Request is too big";
+
 const BUFFER_UNDERFLOW: &str = "Buffer underflow";
 const BUFFER_OVERFLOW: &str = "Buffer overflow";
 const INVALID_TAG_VARINT: &str = "Invalid varint for tag";
 const INVALID_LENGTH_VARINT: &str = "Invalid varint for length";
 const INVALID_VARINT: &str = "Invalid varint";
 const INVALID_FIELD_NUMBER: &str = "Invalid field number";
+const FIELD_NUMBER_OUT_OF_RANGE: &str = "Field number exceeds any in the schema";
 const INVALID_WIRE_TYPE: &str = "Invalid wire type";
 const WIRETYPE_NON_VARINT: &str = "Wire type should be varint";
 const WIRETYPE_NON_LENGTH_DELIMITED: &str = "Wire type should be length-delimited";
 const WIRETYPE_NON_32BIT: &str = "Wire type should be 32-bit";
-const WIRETYPE_NON_64BIT: &str = "Wire type should be 64-bit";
+const WIRETYPE_NON_SFIXED64: &str = "Wire type of sfixed64 field should be 64-bit (I64)";
+const WIRETYPE_NON_FIXED64: &str = "Wire type of fixed64 field should be 64-bit (I64)";
+const WIRETYPE_NON_DOUBLE: &str = "Wire type of double field should be 64-bit (I64)";
 const OVERFLOW_32BIT: &str = "Overflowed 32 bits";
 const INVALID_UTF8: &str = "Invalid UTF-8";
 const INVALID_PERMISSIVE_STRING: &str = "Invalid permissive string";
+const NON_ASCII_STRING: &str = "String contains non-ASCII characters";
+const CONTROL_CHARACTER_STRING: &str = "String contains control characters";
+const NON_PRINTABLE_ASCII_STRING: &str = "String contains characters besides printable ASCII";
 const INVALID_BOOL: &str = "Invalid boolean value";
+const DURATION_OUT_OF_RANGE: &str = "Duration out of range";
+const DURATION_SIGN_MISMATCH: &str = "Duration seconds and nanos have different signs";
+const TIMESTAMP_OUT_OF_RANGE: &str = "Timestamp out of range";
+const RECURSION_LIMIT: &str = "Message nesting exceeds the recursion limit";
+const REPEATED_LIMIT: &str = "Repeated field exceeds the maximum number of elements";
 
-const ENUM_NO_DEFAULT: &str = "Enum has no default value";
+const ENUM_VARIANT_UNRECOGNIZED: &str = "Unrecognized variant of closed enum";
 const NON_EXPLICIT_ONEOF_VARIANT: &str = "Oneof variant is not explicitly presence-tracked";
 const MESSAGE_NON_RECORD: &str = "Message is not a record";
 const FIELD_INDEX_OUT_OF_BOUNDS: &str = "Field index out of bounds";
 const REPEATED_NON_LIST: &str = "Repeated value is not a list";
+const MAP_ENTRY_NON_TUPLE: &str = "Map entry is not a tuple";
+const MAP_KEY_NON_SCALAR: &str = "Map key is not a scalar";
+const MAP_KEY_DUPLICATE: &str = "Map key appears more than once";
+const INVALID_DYNAMIC_VALUE: &str = "Dynamic value is not a list of nodes";
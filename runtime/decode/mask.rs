@@ -0,0 +1,122 @@
+//! Partial merges guided by [`google.protobuf.FieldMask`](https://protobuf.dev/reference/protobuf/google.protobuf/#field-mask).
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, bail, Result};
+use wasmtime::component::Val;
+
+/// Name of the only field in a decoded `google.protobuf.FieldMask` record.
+const PATHS_FIELD_NAME: &str = "paths";
+
+/// A parsed field mask, organized as a tree of field names.
+///
+/// Each path in the original mask (e.g. `user.display_name`)
+/// contributes one branch to the tree.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FieldMask {
+    /// Map from WIT field names to the mask for that field's subfields,
+    /// or `None` if the entire field is selected.
+    children: HashMap<String, Option<FieldMask>>,
+}
+
+impl FieldMask {
+    /// Build a field mask from a list of dot-separated Protobuf field paths.
+    pub fn from_paths<'a, I: IntoIterator<Item = &'a str>>(paths: I) -> Self {
+        let mut mask = Self::default();
+        for path in paths {
+            let mut node = &mut mask;
+            let mut parts = path.split('.').filter(|part| !part.is_empty()).peekable();
+            while let Some(part) = parts.next() {
+                let child = node
+                    .children
+                    .entry(wit_field_name(part))
+                    .or_insert_with(|| Some(Self::default()));
+                if parts.peek().is_none() {
+                    // Selecting the whole field discards any narrower selection.
+                    *child = None;
+                    break;
+                }
+                match child {
+                    Some(child) => node = child,
+                    // The whole field is already selected by a shorter path.
+                    None => break,
+                }
+            }
+        }
+        mask
+    }
+
+    /// Recognize a decoded `google.protobuf.FieldMask` value:
+    /// a record with a single `paths` field holding a list of strings.
+    /// The record may be wrapped in an option, as it would be for a message field.
+    pub fn from_val(value: &Val) -> Result<Self> {
+        let fields = match value {
+            Val::Record(fields) => fields,
+            Val::Option(Some(inner)) => return Self::from_val(inner),
+            Val::Option(None) => return Ok(Self::default()),
+            _ => bail!("Field mask is not a record"),
+        };
+        let paths = fields
+            .iter()
+            .find(|(name, _)| name == PATHS_FIELD_NAME)
+            .ok_or_else(|| anyhow!("Field mask has no '{PATHS_FIELD_NAME}' field"))?;
+        if let Val::List(paths) = &paths.1 {
+            let paths = paths
+                .iter()
+                .map(|path| match path {
+                    Val::String(path) => Ok(path.as_str()),
+                    _ => Err(anyhow!("Field mask path is not a string")),
+                })
+                .collect::<Result<Vec<&str>>>()?;
+            Ok(Self::from_paths(paths))
+        } else {
+            bail!("Field mask paths are not a list")
+        }
+    }
+
+    /// Whether the mask selects no fields at all.
+    pub fn is_empty(&self) -> bool {
+        self.children.is_empty()
+    }
+
+    /// Merge the masked fields from `src` into `dst`, leaving all other fields untouched.
+    ///
+    /// Both values must be records (or optional records) of the same type.
+    /// If a nested message in `dst` is absent but the mask selects some of its subfields,
+    /// the corresponding message from `src` is taken as a whole.
+    pub fn merge(&self, src: Val, dst: &mut Val) -> Result<()> {
+        match (src, dst) {
+            (Val::Record(src_fields), Val::Record(dst_fields)) => {
+                for (name, value) in src_fields {
+                    if let Some(child) = self.children.get(&name) {
+                        let subdst = dst_fields
+                            .iter_mut()
+                            .find(|(dst_name, _)| dst_name == &name)
+                            .ok_or_else(|| anyhow!("Masked field '{name}' missing from target"))?;
+                        match child {
+                            Some(child) => child.merge(value, &mut subdst.1)?,
+                            None => subdst.1 = value,
+                        }
+                    }
+                }
+                Ok(())
+            }
+            (Val::Option(Some(src)), Val::Option(Some(dst))) => self.merge(*src, dst),
+            (src @ Val::Option(Some(_)), dst @ Val::Option(None)) => {
+                *dst = src;
+                Ok(())
+            }
+            // Clearing a masked message in the source clears it in the target.
+            (Val::Option(None), dst @ Val::Option(_)) => {
+                *dst = Val::Option(None);
+                Ok(())
+            }
+            _ => bail!("Field mask applied to a non-message value"),
+        }
+    }
+}
+
+/// Protobuf field names are `snake_case` while WIT field names are `kebab-case`.
+fn wit_field_name(proto_name: &str) -> String {
+    proto_name.replace('_', "-")
+}
@@ -6,19 +6,21 @@ use std::result::Result as StdResult;
 
 use prost::bytes::Buf;
 use prost::encoding::WireType;
-use tonic::codec::DecodeBuf;
 use wasmtime::component::Val;
 
 use crate::{
-    read_length_check_overflow, read_varint, CompoundMerger, DecodeError, MergeFn, Merger,
-    BUFFER_OVERFLOW, BUFFER_UNDERFLOW, INVALID_BOOL, INVALID_PERMISSIVE_STRING, INVALID_UTF8,
-    INVALID_VARINT, OVERFLOW_32BIT, REPEATED_NON_LIST, WIRETYPE_NON_32BIT, WIRETYPE_NON_64BIT,
-    WIRETYPE_NON_LENGTH_DELIMITED, WIRETYPE_NON_VARINT,
+    check_repeated_elements, read_length_check_overflow, read_varint, CompoundMerger, DecodeError,
+    MapKeys, MergeFn, Merger, BUFFER_OVERFLOW, BUFFER_UNDERFLOW, CONTROL_CHARACTER_STRING,
+    INVALID_BOOL, INVALID_PERMISSIVE_STRING, INVALID_UTF8, INVALID_VARINT, NON_ASCII_STRING,
+    NON_PRINTABLE_ASCII_STRING, OVERFLOW_32BIT, REPEATED_NON_LIST, WIRETYPE_NON_32BIT,
+    WIRETYPE_NON_DOUBLE, WIRETYPE_NON_FIXED64, WIRETYPE_NON_LENGTH_DELIMITED,
+    WIRETYPE_NON_SFIXED64, WIRETYPE_NON_VARINT,
 };
-use metadata_proto::work::runtime::field::ScalarCoding;
+use metadata_proto::work::runtime::field::{Charset, ScalarCoding};
 
 impl Merger {
-    pub(crate) fn scalar(coding: ScalarCoding) -> (Self, Val) {
+    /// The `charset` is ignored unless the coding is a string.
+    pub(crate) fn scalar(coding: ScalarCoding, charset: Charset) -> (Self, Val) {
         // Called in control plane, so O(n) exhaustive match is OK.
         let (merge, default): (MergeFn, Val) = match coding {
             ScalarCoding::BytesImplicit => (bytes_implicit_merge, Val::List(Vec::new())),
@@ -91,12 +93,32 @@ impl Merger {
             ScalarCoding::DoubleExplicit => (double_explicit_merge, Val::Option(None)),
             ScalarCoding::DoubleExpanded => (double_repeated_merge, Val::List(Vec::new())),
         };
+        // Strings with a declared charset check each value after decoding it.
+        let merge: MergeFn = match (charset, coding) {
+            (Charset::Unrestricted, _) => merge,
+            (_, ScalarCoding::StringUtf8Implicit | ScalarCoding::StringPermissiveImplicit) => {
+                string_charset_implicit_merge
+            }
+            (_, ScalarCoding::StringUtf8Explicit | ScalarCoding::StringPermissiveExplicit) => {
+                string_charset_explicit_merge
+            }
+            (_, ScalarCoding::StringUtf8Expanded | ScalarCoding::StringPermissiveExpanded) => {
+                string_charset_repeated_merge
+            }
+            _ => merge,
+        };
         (
             Self {
                 merge,
-                // `defaults` and `compound` are ignored for scalars.
+                // `defaults` are ignored for scalars,
+                // and `compound` only matters for strings with a declared charset.
                 defaults: Vec::new(),
-                compound: CompoundMerger { scalar: () },
+                repeated_tag: 0,
+                presence: None,
+                max_field_number: u32::MAX,
+                max_elements: u32::MAX,
+                map_keys: MapKeys::Absent,
+                compound: CompoundMerger { charset },
             },
             // Return the default value to the caller
             // (which is always a message merger being instantiated).
@@ -114,7 +136,7 @@ macro_rules! singular_merge_fns {
             _merger: &Merger,
             wire_type: WireType,
             limit: &mut u32,
-            src: &mut DecodeBuf<'_>,
+            src: &mut &[u8],
             dst: &mut Val,
         ) -> StdResult<(), DecodeError> {
             if wire_type == $wire_type {
@@ -129,7 +151,7 @@ macro_rules! singular_merge_fns {
             _merger: &Merger,
             wire_type: WireType,
             limit: &mut u32,
-            src: &mut DecodeBuf<'_>,
+            src: &mut &[u8],
             dst: &mut Val,
         ) -> StdResult<(), DecodeError> {
             if wire_type == $wire_type {
@@ -155,15 +177,16 @@ macro_rules! stringy_mergers {
         );
 
         fn $repeated_name(
-            _merger: &Merger,
+            merger: &Merger,
             wire_type: WireType,
             limit: &mut u32,
-            src: &mut DecodeBuf<'_>,
+            src: &mut &[u8],
             dst: &mut Val,
         ) -> StdResult<(), DecodeError> {
             // Strings and bytes cannot be packed. They can only be repeated expanded.
             if let Val::List(items) = dst {
                 if wire_type == WireType::LengthDelimited {
+                    check_repeated_elements(merger, items)?;
                     items.push(($decode_inner)(limit, src).map_err(|e| e.with_index(items.len()))?);
                     Ok(())
                 } else {
@@ -176,13 +199,23 @@ macro_rules! stringy_mergers {
     };
 }
 
+/// Bytes decode to a list of individual `u8` values (Wasmtime has no dedicated byte vector),
+/// but the source is still copied a whole contiguous chunk at a time
+/// rather than byte-by-byte through [`Buf::get_u8`].
 #[inline(always)]
-fn bytes_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
-    let mut length = read_length_check_overflow(limit, src)?;
-    let mut bytes = Vec::with_capacity(length as usize);
+fn bytes_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
+    let mut length = read_length_check_overflow(limit, src)? as usize;
+    let mut bytes = Vec::with_capacity(length);
     while length > 0 {
-        bytes.push(Val::U8(src.get_u8()));
-        length -= 1;
+        let chunk = src.chunk();
+        if chunk.is_empty() {
+            // The buffer ended before the declared length.
+            return Err(DecodeError::new(BUFFER_UNDERFLOW));
+        }
+        let count = chunk.len().min(length);
+        bytes.extend(chunk[..count].iter().map(|byte| Val::U8(*byte)));
+        src.advance(count);
+        length -= count;
     }
     Ok(Val::List(bytes))
 }
@@ -195,13 +228,13 @@ stringy_mergers!(
 );
 
 #[inline(always)]
-fn string_utf8_decode_inner(
+pub(crate) fn string_utf8_decode_inner(
     limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
+    src: &mut &[u8],
 ) -> StdResult<Val, DecodeError> {
     let length = read_length_check_overflow(limit, src)? as usize;
     let mut string = String::with_capacity(length);
-    src.take(length)
+    Buf::take(&mut *src, length)
         .reader()
         .read_to_string(&mut string)
         .map_err(|_| DecodeError::new(INVALID_UTF8))?;
@@ -216,13 +249,10 @@ stringy_mergers!(
 );
 
 #[inline(always)]
-fn string_permissive_decode_inner(
-    limit: &mut u32,
-    src: &mut DecodeBuf<'_>,
-) -> StdResult<Val, DecodeError> {
+fn string_permissive_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let length = read_length_check_overflow(limit, src)? as usize;
     let mut string = String::with_capacity(length);
-    src.take(length)
+    Buf::take(&mut *src, length)
         .reader()
         .read_to_end(unsafe { string.as_mut_vec() })
         .map_err(|_| DecodeError::new(INVALID_PERMISSIVE_STRING))?;
@@ -236,6 +266,73 @@ stringy_mergers!(
     string_permissive_decode_inner,
 );
 
+/// Check a decoded string against the merger's [charset](Charset).
+/// Anything besides a string passes unchecked.
+#[inline(always)]
+fn check_charset(merger: &Merger, value: &Val) -> StdResult<(), DecodeError> {
+    let Val::String(string) = value else {
+        return Ok(());
+    };
+    match unsafe { merger.compound.charset } {
+        Charset::Unrestricted => Ok(()),
+        Charset::Ascii if !string.is_ascii() => Err(DecodeError::new(NON_ASCII_STRING)),
+        Charset::NoControl if string.chars().any(char::is_control) => {
+            Err(DecodeError::new(CONTROL_CHARACTER_STRING))
+        }
+        Charset::PrintableAscii if !string.bytes().all(|byte| matches!(byte, b' '..=b'~')) => {
+            Err(DecodeError::new(NON_PRINTABLE_ASCII_STRING))
+        }
+        Charset::Ascii | Charset::NoControl | Charset::PrintableAscii => Ok(()),
+    }
+}
+
+/// Strings with a declared charset are always decoded as UTF-8,
+/// even if the coding is permissive, then [checked](check_charset).
+fn string_charset_implicit_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    string_utf8_implicit_merge(merger, wire_type, limit, src, dst)?;
+    check_charset(merger, dst)
+}
+
+/// Like [`string_charset_implicit_merge`], for explicit presence.
+fn string_charset_explicit_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    string_utf8_explicit_merge(merger, wire_type, limit, src, dst)?;
+    match dst {
+        Val::Option(Some(value)) => check_charset(merger, value),
+        _ => Ok(()),
+    }
+}
+
+/// Like [`string_charset_implicit_merge`], for expanded repetition.
+/// Only the newly decoded item is checked.
+fn string_charset_repeated_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    string_utf8_repeated_merge(merger, wire_type, limit, src, dst)?;
+    match dst {
+        Val::List(items) => match items.last() {
+            Some(value) => check_charset(merger, value).map_err(|e| e.with_index(items.len() - 1)),
+            None => Ok(()),
+        },
+        _ => Ok(()),
+    }
+}
+
 /// Merge function boilerplate for all the "non-stringy" scalars:
 /// Everything besides strings and bytes.
 /// These can be both packed and expanded for repetition.
@@ -251,10 +348,10 @@ macro_rules! numeric_mergers {
         );
 
         fn $repeated_name(
-            _merger: &Merger,
+            merger: &Merger,
             wire_type: WireType,
             limit: &mut u32,
-            src: &mut DecodeBuf<'_>,
+            src: &mut &[u8],
             dst: &mut Val,
         ) -> StdResult<(), DecodeError> {
             // Protocol buffer parsers must be able to parse repeated fields
@@ -266,6 +363,7 @@ macro_rules! numeric_mergers {
                 if wire_type == WireType::LengthDelimited {
                     let mut length = read_length_check_overflow(limit, src)?;
                     while length > 0 {
+                        check_repeated_elements(merger, items)?;
                         items.push(
                             ($decode_inner)(&mut length, src)
                                 .map_err(|e| e.with_index(items.len()))?,
@@ -273,6 +371,7 @@ macro_rules! numeric_mergers {
                     }
                     Ok(())
                 } else if wire_type == $wire_type {
+                    check_repeated_elements(merger, items)?;
                     items.push(($decode_inner)(limit, src).map_err(|e| e.with_index(items.len()))?);
                     Ok(())
                 } else {
@@ -286,7 +385,7 @@ macro_rules! numeric_mergers {
 }
 
 #[inline(always)]
-fn bool_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+pub(crate) fn bool_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 1 {
         let byte = src.get_u8();
         *limit -= 1;
@@ -309,10 +408,11 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn int32_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn int32_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let varint = read_varint(limit, src, INVALID_VARINT)?;
-    let value = i32::try_from(varint).map_err(|_| DecodeError::new(OVERFLOW_32BIT))?;
-    Ok(Val::S32(value))
+    // Negative values are sign-extended to 64 bits on the wire,
+    // so truncate like every other Protobuf implementation.
+    Ok(Val::S32(varint as i32))
 }
 numeric_mergers!(
     int32_explicit_merge,
@@ -324,7 +424,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn sint32_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn sint32_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let varint = read_varint(limit, src, INVALID_VARINT)?;
     let value = u32::try_from(varint).map_err(|_| DecodeError::new(OVERFLOW_32BIT))?;
     Ok(Val::S32(((value >> 1) as i32) ^ (-((value & 1) as i32))))
@@ -339,7 +439,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn sfixed32_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn sfixed32_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 4 {
         *limit -= 4;
         Ok(Val::S32(src.get_i32_le()))
@@ -357,7 +457,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn uint32_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn uint32_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let varint = read_varint(limit, src, INVALID_VARINT)?;
     let value = u32::try_from(varint).map_err(|_| DecodeError::new(OVERFLOW_32BIT))?;
     Ok(Val::U32(value))
@@ -372,7 +472,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn fixed32_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn fixed32_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 4 {
         *limit -= 4;
         Ok(Val::U32(src.get_u32_le()))
@@ -390,7 +490,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn int64_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn int64_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let varint = read_varint(limit, src, INVALID_VARINT)?;
     Ok(Val::S64(varint as i64))
 }
@@ -404,10 +504,10 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn sint64_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn sint64_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let varint = read_varint(limit, src, INVALID_VARINT)?;
-    let value = varint as i64;
-    Ok(Val::S64(((value >> 1) as i64) ^ (-((value & 1) as i64))))
+    // Shift the unsigned varint, so the sign bit of large values is not smeared downwards.
+    Ok(Val::S64(((varint >> 1) as i64) ^ (-((varint & 1) as i64))))
 }
 numeric_mergers!(
     sint64_explicit_merge,
@@ -419,7 +519,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn sfixed64_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn sfixed64_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 8 {
         *limit -= 8;
         Ok(Val::S64(src.get_i64_le()))
@@ -432,12 +532,12 @@ numeric_mergers!(
     sfixed64_implicit_merge,
     sfixed64_repeated_merge,
     WireType::SixtyFourBit,
-    WIRETYPE_NON_64BIT,
+    WIRETYPE_NON_SFIXED64,
     sfixed64_decode_inner,
 );
 
 #[inline(always)]
-fn uint64_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn uint64_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     let value = read_varint(limit, src, INVALID_VARINT)?;
     Ok(Val::U64(value))
 }
@@ -451,7 +551,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn fixed64_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn fixed64_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 8 {
         *limit -= 8;
         Ok(Val::U64(src.get_u64_le()))
@@ -464,12 +564,12 @@ numeric_mergers!(
     fixed64_implicit_merge,
     fixed64_repeated_merge,
     WireType::SixtyFourBit,
-    WIRETYPE_NON_64BIT,
+    WIRETYPE_NON_FIXED64,
     fixed64_decode_inner,
 );
 
 #[inline(always)]
-fn float_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+fn float_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 4 {
         *limit -= 4;
         Ok(Val::Float32(src.get_f32_le()))
@@ -487,7 +587,7 @@ numeric_mergers!(
 );
 
 #[inline(always)]
-fn double_decode_inner(limit: &mut u32, src: &mut DecodeBuf<'_>) -> StdResult<Val, DecodeError> {
+pub(crate) fn double_decode_inner(limit: &mut u32, src: &mut &[u8]) -> StdResult<Val, DecodeError> {
     if *limit >= 8 {
         *limit -= 8;
         Ok(Val::Float64(src.get_f64_le()))
@@ -500,6 +600,6 @@ numeric_mergers!(
     double_implicit_merge,
     double_repeated_merge,
     WireType::SixtyFourBit,
-    WIRETYPE_NON_64BIT,
+    WIRETYPE_NON_DOUBLE,
     double_decode_inner,
 );
@@ -0,0 +1,110 @@
+//! Benchmarks comparing pooled and freshly-allocated record storage
+//! when decoding a nested message over and over.
+#![feature(test)]
+
+extern crate test;
+
+use std::mem::drop;
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use test::Bencher;
+use tonic::codec::Decoder;
+use wasmtime::component::Val;
+
+use decode::{recycle, RequestDecoder};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::decode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// Number of entries in the repeated submessage field.
+const ENTRIES: usize = 100;
+
+/// A message with a repeated submessage field (`items`, #1),
+/// each entry having a `uint32` field (`id`, #1) and a nested submessage (`inner`, #2)
+/// with a single `uint32` field (`value`, #1).
+fn nested_decoder() -> RequestDecoder {
+    let scalar = |name: &str| Field {
+        name: String::from(name),
+        number: 1,
+        coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+        subfields: Vec::new(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("items"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(
+                    CompoundCoding::MessageExpanded as i32,
+                )),
+                subfields: vec![
+                    scalar("id"),
+                    Field {
+                        name: String::from("inner"),
+                        number: 2,
+                        coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
+                        subfields: vec![scalar("value")],
+                        charset: Charset::Unrestricted as i32,
+                        ..Default::default()
+                    },
+                ],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap()
+}
+
+fn nested_encoded() -> Vec<u8> {
+    // Each entry is 8 bytes.
+    let mut encoded = Vec::with_capacity(ENTRIES * 8);
+    for index in 0..ENTRIES {
+        encoded.extend_from_slice(&[
+            10, // 'items' tag: (1 << 3) + 2
+            6,  // length of entry
+            8,  //   'id' tag: (1 << 3) + 0
+            (index % 128) as u8,
+            18, //   'inner' tag: (2 << 3) + 2
+            2,  //   length of submessage
+            8,  //     'value' tag: (1 << 3) + 0
+            1,
+        ]);
+    }
+    encoded
+}
+
+fn decode(decoder: &mut RequestDecoder, encoded: &[u8]) -> Val {
+    let mut buffer = BytesMut::from(encoded);
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+    decoder.decode(&mut decode_buffer).unwrap().unwrap()
+}
+
+/// Drop every decoded request, as if there were no pool.
+#[bench]
+fn bench_decode_nested_standard(bencher: &mut Bencher) {
+    let mut decoder = nested_decoder();
+    let encoded = nested_encoded();
+    bencher.iter(|| drop(decode(&mut decoder, &encoded)));
+}
+
+/// Recycle every decoded request, so the next iteration reuses its record storage.
+#[bench]
+fn bench_decode_nested_pooled(bencher: &mut Bencher) {
+    let mut decoder = nested_decoder();
+    let encoded = nested_encoded();
+    bencher.iter(|| recycle(decode(&mut decoder, &encoded)));
+}
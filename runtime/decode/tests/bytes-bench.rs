@@ -0,0 +1,66 @@
+//! Benchmarks for decoding large bytes fields.
+#![feature(test)]
+
+extern crate test;
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use test::Bencher;
+use tonic::codec::Decoder;
+use wasmtime::component::Val;
+
+use decode::RequestDecoder;
+use metadata_proto::work::runtime::field::{Charset, Coding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::decode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// Length of the bytes field (under the 1MiB request limit).
+const LENGTH: usize = 512 * 1024;
+
+/// Decode a message with a single 512KiB bytes field (`data`, #1).
+#[bench]
+fn bench_decode_bytes(bencher: &mut Bencher) {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("data"),
+                number: 1,
+                coding: Some(Coding::ScalarCoding(ScalarCoding::BytesImplicit as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+
+    let mut encoded = vec![
+        10, // 'data' tag: (1 << 3) + 2
+        0x80, 0x80, 0x20, // length: 512KiB as a varint
+    ];
+    encoded.extend((0..LENGTH).map(|index| index as u8));
+
+    bencher.iter(|| {
+        let mut buffer = BytesMut::from(&encoded[..]);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        let result = decoder.decode(&mut decode_buffer).unwrap();
+        match result {
+            Some(Val::Record(fields)) => match &fields[0].1 {
+                Val::List(bytes) => assert_eq!(bytes.len(), LENGTH),
+                _ => panic!("Expected a list"),
+            },
+            _ => panic!("Expected a record"),
+        }
+    });
+}
@@ -0,0 +1,1318 @@
+use std::io::{Result as IoResult, Write};
+use std::sync::{Arc, Mutex};
+
+use bytes::BytesMut;
+use tonic::codec::Decoder;
+use tonic::Code;
+use tracing::subscriber::with_default;
+use wasmtime::component::Val;
+
+use decode::{
+    DecoderOptions, ErrorVerbosity, RequestDecoder, DEFAULT_MAX_DEPTH,
+    DEFAULT_MAX_REPEATED_ELEMENTS,
+};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::decode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+fn decoder() -> RequestDecoder {
+    decoder_with_options(DecoderOptions::default())
+}
+
+fn decoder_with_options(options: DecoderOptions) -> RequestDecoder {
+    RequestDecoder::with_options(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("a"),
+                number: 1,
+                coding: Some(Coding::ScalarCoding(
+                    ScalarCoding::StringUtf8Implicit as i32,
+                )),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+        options,
+    )
+    .unwrap()
+}
+
+/// Log output captured in memory.
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IoResult<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_malformed_request_warnings_rate_limited() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .with_ansi(false)
+        .finish();
+    let mut decoder = decoder();
+
+    with_default(subscriber, || {
+        for _ in 0..3 {
+            let mut buffer = BytesMut::from(
+                &[
+                    10, // 'a' tag: (1 << 3) + 2
+                    5,  // length of string (but only 2 bytes follow)
+                    104, 105,
+                ][..],
+            );
+            let length = buffer.len();
+            let mut decode_buffer = decode_buf(&mut buffer, length);
+
+            let status = decoder.decode(&mut decode_buffer).unwrap_err();
+            assert_eq!(status.code(), Code::InvalidArgument);
+        }
+    });
+
+    // Only the first error is reported within the interval.
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert_eq!(logs.matches("malformed requests").count(), 1);
+    assert!(logs.contains("Rejected 1 malformed requests since the last warning"));
+}
+
+#[test]
+fn test_error_offset() {
+    let mut decoder = decoder();
+    let mut buffer = BytesMut::from(
+        &[
+            10, // 'a' tag: (1 << 3) + 2
+            2,  // length of "hi"
+            104, 105, //   "hi"
+            15,  // corrupt tag: (1 << 3) + 7 (invalid wire type)
+            0,
+        ][..],
+    );
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+
+    let status = decoder.decode(&mut decode_buffer).unwrap_err();
+    // Decoding stops right after the corrupt tag at offset 4.
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 5: Invalid wire type",
+    );
+}
+
+#[test]
+fn test_validate() {
+    let decoder = decoder();
+    assert_eq!(
+        decoder.validate(&[
+            10, // 'a' tag: (1 << 3) + 2
+            2,  // length of "hi"
+            104, 105, //   "hi"
+        ]),
+        Ok(()),
+    );
+    assert_eq!(
+        decoder.validate(&[
+            10,  // 'a' tag: (1 << 3) + 2
+            2,   // length of "hi"
+            104, //   truncated
+        ]),
+        Err(String::from(
+            "Malformed request (.1) @offset 2: Buffer overflow"
+        )),
+    );
+}
+
+#[test]
+fn test_redacted_error() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer({
+            let logs = logs.clone();
+            move || logs.clone()
+        })
+        .with_ansi(false)
+        .finish();
+    let mut decoder = decoder_with_options(DecoderOptions {
+        error_verbosity: ErrorVerbosity::Redacted,
+        ..DecoderOptions::default()
+    });
+    let mut buffer = BytesMut::from(
+        &[
+            10, // 'a' tag: (1 << 3) + 2
+            2,  // length of "hi"
+            104, 105, //   "hi"
+            15,  // corrupt tag: (1 << 3) + 7 (invalid wire type)
+            0,
+        ][..],
+    );
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+
+    let status = with_default(subscriber, || {
+        decoder.decode(&mut decode_buffer).unwrap_err()
+    });
+    // The client learns nothing about where or why decoding failed.
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(status.message(), "Malformed request");
+    // The full error is still logged for operators.
+    let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+    assert!(logs.contains("Malformed request (.1) @offset 5: Invalid wire type"));
+    // The location is also a separate field, so logs can be filtered by it.
+    assert!(logs.contains(r#"field_path=".1""#));
+}
+
+#[test]
+fn test_unrecognized_coding() {
+    // Codings that a newer compiler might emit but this decoder does not know.
+    for (coding, expected) in [
+        (Coding::ScalarCoding(9999), "Unrecognized ScalarCoding 9999"),
+        (
+            Coding::CompoundCoding(9999),
+            "Unrecognized CompoundCoding 9999",
+        ),
+    ] {
+        let error = RequestDecoder::new(
+            &Field {
+                number: 0,       // Ignored.
+                name: "".into(), // Ignored.
+                coding: None,    // Ignored.
+                subfields: vec![Field {
+                    name: String::from("a"),
+                    number: 3,
+                    coding: Some(coding),
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                }],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            },
+            Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+        )
+        .err()
+        .unwrap();
+        let message = format!("{error:#}");
+        assert!(message.contains("field #3"), "{message}");
+        assert!(message.contains(expected), "{message}");
+    }
+
+    // Unrecognized codings are rejected in oneof variants too,
+    // rather than mistaken for implicit codings.
+    let error = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("choice"),
+                number: 0,
+                coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
+                subfields: vec![Field {
+                    name: String::from("b"),
+                    number: 4,
+                    coding: Some(Coding::ScalarCoding(10002)),
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                }],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .err()
+    .unwrap();
+    assert!(format!("{error:#}").contains("Unrecognized ScalarCoding 10002"));
+}
+
+#[test]
+fn test_field_number_out_of_schema_range() {
+    let request = Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields: vec![
+            Field {
+                name: String::from("a"),
+                number: 1,
+                coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            },
+            Field {
+                name: String::from("m"),
+                number: 2,
+                coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
+                subfields: vec![Field {
+                    name: String::from("b"),
+                    number: 7,
+                    coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                }],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            },
+        ],
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let mut lenient = RequestDecoder::new(&request, component.clone()).unwrap();
+    let mut strict = RequestDecoder::with_options(
+        &request,
+        component,
+        DecoderOptions {
+            limit_field_numbers: true,
+            ..DecoderOptions::default()
+        },
+    )
+    .unwrap();
+
+    let decode = |decoder: &mut RequestDecoder, encoded: &[u8]| {
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer).map(|_| ())
+    };
+
+    let within_range = [
+        8,  // 'a' tag: (1 << 3) + 0
+        1,  // 1
+        40, // unknown tag: (5 << 3) + 0
+        3,  // 3
+    ];
+    let huge_field_number = [
+        8, // 'a' tag: (1 << 3) + 0
+        1, // 1
+        128, 128, 229, 154, 119, // unknown tag: (4_000_000_000 << 3) + 0
+        3,   // 3
+    ];
+    let nested = [
+        18, // 'm' tag: (2 << 3) + 2
+        2,  // length of submessage
+        64, //   unknown tag: (8 << 3) + 0
+        3,  //   3
+    ];
+
+    // Without a limit, every unknown field is skipped.
+    for encoded in [&within_range[..], &huge_field_number, &nested] {
+        decode(&mut lenient, encoded).unwrap();
+    }
+
+    // Unknown fields within the schema's range are still skipped.
+    decode(&mut strict, &within_range).unwrap();
+    // Anything beyond field number 7 is rejected right after its tag.
+    let status = decode(&mut strict, &huge_field_number).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.4000000000) @offset 7: Field number exceeds any in the schema",
+    );
+    let status = decode(&mut strict, &nested).unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.2.8) @offset 3: Field number exceeds any in the schema",
+    );
+}
+
+/// A request schema with `depth` levels of messages nested in field #1.
+fn nested_request(depth: usize) -> Field {
+    let mut message = Vec::new();
+    for _ in 0..depth {
+        message = vec![Field {
+            name: String::from("m"),
+            number: 1,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
+            subfields: message,
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }];
+    }
+    Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields: message,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+/// An encoded request with `depth` levels of (otherwise empty) messages nested in field #1.
+fn nested_buffer(depth: usize) -> Vec<u8> {
+    let mut encoded = Vec::new();
+    for _ in 0..depth {
+        let mut outer = vec![10]; // 'm' tag: (1 << 3) + 2
+        let mut length = encoded.len();
+        while length >= 0x80 {
+            outer.push((length as u8 & 0x7f) | 0x80);
+            length >>= 7;
+        }
+        outer.push(length as u8);
+        outer.extend(encoded);
+        encoded = outer;
+    }
+    encoded
+}
+
+#[test]
+fn test_recursion_limit() {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let request = nested_request(DEFAULT_MAX_DEPTH as usize + 1);
+    let mut decoder = RequestDecoder::new(&request, component.clone()).unwrap();
+    let mut shallow = RequestDecoder::with_options(
+        &request,
+        component,
+        DecoderOptions {
+            max_depth: Some(3),
+            ..DecoderOptions::default()
+        },
+    )
+    .unwrap();
+
+    let decode = |decoder: &mut RequestDecoder, encoded: &[u8]| {
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer).map(|_| ())
+    };
+
+    // Exactly at the limit is fine.
+    decode(&mut decoder, &nested_buffer(DEFAULT_MAX_DEPTH as usize)).unwrap();
+    decode(&mut shallow, &nested_buffer(3)).unwrap();
+
+    // One level past the limit is rejected as a client error.
+    let status = decode(&mut decoder, &nested_buffer(DEFAULT_MAX_DEPTH as usize + 1)).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status
+        .message()
+        .ends_with(": Message nesting exceeds the recursion limit"));
+    let status = decode(&mut shallow, &nested_buffer(4)).unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1.1.1.1) @offset 7: Message nesting exceeds the recursion limit",
+    );
+}
+
+#[test]
+fn test_dynamic_value_recursion_limit() {
+    let mut decoder = RequestDecoder::with_options(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("v"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(CompoundCoding::Value as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+        DecoderOptions {
+            max_depth: Some(3),
+            ..DecoderOptions::default()
+        },
+    )
+    .unwrap();
+    let mut decode = |encoded: &[u8]| {
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer).map(|_| ())
+    };
+
+    // Value, list, and value: exactly at the limit.
+    decode(&[
+        10, // 'v' tag: (1 << 3) + 2
+        4,  // length of value
+        50, //   'list_value' tag: (6 << 3) + 2
+        2,  //   length of list
+        10, //     'values' tag: (1 << 3) + 2
+        0,  //     length of value
+    ])
+    .unwrap();
+
+    // Another list within the innermost value is one level too deep.
+    let status = decode(&[
+        10, // 'v' tag: (1 << 3) + 2
+        6,  // length of value
+        50, //   'list_value' tag: (6 << 3) + 2
+        4,  //   length of list
+        10, //     'values' tag: (1 << 3) + 2
+        2,  //     length of value
+        50, //       'list_value' tag: (6 << 3) + 2
+        0,  //       length of list
+    ])
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1.6.1[0].6) @offset 7: Message nesting exceeds the recursion limit",
+    );
+}
+
+#[test]
+fn test_repeated_element_limit() {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let request = Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields: vec![
+            Field {
+                name: String::from("numbers"),
+                number: 1,
+                coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Packed as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            },
+            Field {
+                name: String::from("messages"),
+                number: 2,
+                coding: Some(Coding::CompoundCoding(
+                    CompoundCoding::MessageExpanded as i32,
+                )),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            },
+        ],
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let decode = |options: DecoderOptions, encoded: &[u8]| {
+        let mut decoder =
+            RequestDecoder::with_options(&request, component.clone(), options).unwrap();
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer).map(|_| ())
+    };
+    let packed_zeros = |count: usize| {
+        let mut encoded = vec![10]; // 'numbers' tag: (1 << 3) + 2
+        let mut length = count;
+        while length >= 0x80 {
+            encoded.push((length as u8 & 0x7f) | 0x80);
+            length >>= 7;
+        }
+        encoded.push(length as u8);
+        encoded.resize(encoded.len() + count, 0);
+        encoded
+    };
+    let few = DecoderOptions {
+        max_repeated_elements: Some(3),
+        ..DecoderOptions::default()
+    };
+
+    // Exactly at the limit is fine.
+    decode(few, &packed_zeros(3)).unwrap();
+    decode(few, &[18, 0, 18, 0, 18, 0]).unwrap();
+
+    // A single byte per element stays well within the byte limit,
+    // but one element past the limit is rejected as a client error.
+    let status = decode(
+        DecoderOptions::default(),
+        &packed_zeros(DEFAULT_MAX_REPEATED_ELEMENTS as usize + 1),
+    )
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert!(status
+        .message()
+        .ends_with(": Repeated field exceeds the maximum number of elements"));
+    let status = decode(few, &packed_zeros(4)).unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1[3]) @offset 5: Repeated field exceeds the maximum number of elements",
+    );
+
+    // Packed and expanded elements count toward the same limit.
+    let status = decode(few, &[10, 2, 0, 0, 8, 0, 8, 0]).unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1[3]) @offset 7: Repeated field exceeds the maximum number of elements",
+    );
+
+    // Repeated messages too.
+    let status = decode(few, &[18, 0, 18, 0, 18, 0, 18, 0]).unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.2[3]) @offset 7: Repeated field exceeds the maximum number of elements",
+    );
+}
+
+#[test]
+fn test_duplicate_map_keys() {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let uint32 = |name: &str, number: u32| Field {
+        name: String::from(name),
+        number,
+        coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+        subfields: Vec::new(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let request = Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields: vec![Field {
+            name: String::from("counts"),
+            number: 1,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Map as i32)),
+            subfields: vec![uint32("key", 1), uint32("value", 2)],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }],
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let decode = |options: DecoderOptions, encoded: &[u8]| {
+        let mut decoder =
+            RequestDecoder::with_options(&request, component.clone(), options).unwrap();
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer)
+    };
+    let encoded = [
+        10, 4, 8, 1, 16, 10, // 'counts' entry: 1 => 10
+        10, 4, 8, 2, 16, 20, // 'counts' entry: 2 => 20
+        10, 4, 8, 1, 16, 30, // 'counts' entry: 1 => 30
+    ];
+
+    // By default, the last value wins, in the position of the first entry.
+    let result = decode(DecoderOptions::default(), &encoded).unwrap();
+    assert_eq!(
+        result,
+        Some(Val::Record(vec![(
+            String::from("counts"),
+            Val::List(vec![
+                Val::Tuple(vec![Val::U32(1), Val::U32(30)]),
+                Val::Tuple(vec![Val::U32(2), Val::U32(20)]),
+            ]),
+        )])),
+    );
+
+    // In strict mode, the duplicate is rejected as a client error.
+    let unique = DecoderOptions {
+        unique_map_keys: true,
+        ..DecoderOptions::default()
+    };
+    let status = decode(unique, &encoded).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1[2]) @offset 18: Map key appears more than once",
+    );
+
+    // Distinct keys are fine either way.
+    decode(unique, &encoded[..12]).unwrap();
+}
+
+#[test]
+fn test_component_depth() {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    // The schema itself allows 5 levels of nesting, but the component declares only 2.
+    let request = nested_request(5);
+    let decode = |options: DecoderOptions, encoded: &[u8]| {
+        let mut decoder =
+            RequestDecoder::with_options(&request, component.clone(), options).unwrap();
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.decode(&mut decode_buffer).map(|_| ())
+    };
+
+    let declared = DecoderOptions::default().with_component_depth(2);
+    decode(declared, &nested_buffer(2)).unwrap();
+    let status = decode(declared, &nested_buffer(3)).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1.1.1) @offset 5: Message nesting exceeds the recursion limit",
+    );
+
+    // A component cannot raise the node's limit.
+    let node = DecoderOptions {
+        max_depth: Some(1),
+        ..DecoderOptions::default()
+    };
+    assert_eq!(node.with_component_depth(2).max_depth, Some(1));
+    decode(node.with_component_depth(2), &nested_buffer(2)).unwrap_err();
+
+    // Zero declares nothing, deferring to the node's limit (or the default).
+    assert_eq!(node.with_component_depth(0).max_depth, Some(1));
+    assert_eq!(
+        DecoderOptions::default().with_component_depth(0).max_depth,
+        None
+    );
+    decode(
+        DecoderOptions::default().with_component_depth(0),
+        &nested_buffer(5),
+    )
+    .unwrap();
+}
+
+#[test]
+fn test_duration_sign_mismatch() {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("timeout"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(CompoundCoding::Duration as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut buffer = BytesMut::from(
+        &[
+            10, // 'timeout' tag: (1 << 3) + 2
+            16, // length of submessage
+            8,  //   'seconds' tag: (1 << 3) + 0
+            254, 255, 255, 255, 255, 255, 255, 255, 255, 1,  // -2 [sign-extended]
+            16, //   'nanos' tag: (2 << 3) + 0
+            128, 229, 154, 119, // 250000000
+        ][..],
+    );
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+
+    let status = decoder.decode(&mut decode_buffer).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 18: Duration seconds and nanos have different signs",
+    );
+}
+
+#[test]
+fn test_timestamp_nanos_out_of_range() {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("created"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(CompoundCoding::Timestamp as i32)),
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut buffer = BytesMut::from(
+        &[
+            10, // 'created' tag: (1 << 3) + 2
+            12, // length of submessage
+            8,  //   'seconds' tag: (1 << 3) + 0
+            128, 226, 207, 170, 6,  // 1700000000
+            16, //   'nanos' tag: (2 << 3) + 0
+            128, 168, 214, 185, 7, // 2000000000
+        ][..],
+    );
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+
+    let status = decoder.decode(&mut decode_buffer).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 14: Timestamp out of range",
+    );
+}
+
+#[test]
+fn test_charset_violation() {
+    let string = |name: &str, number, coding: ScalarCoding, charset: Charset| Field {
+        name: String::from(name),
+        number,
+        coding: Some(Coding::ScalarCoding(coding as i32)),
+        subfields: Vec::new(),
+        charset: charset as i32,
+        ..Default::default()
+    };
+    let decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                string("code", 1, ScalarCoding::StringUtf8Implicit, Charset::Ascii),
+                string(
+                    "labels",
+                    2,
+                    ScalarCoding::StringPermissiveExpanded,
+                    Charset::PrintableAscii,
+                ),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let decode = |bytes: &[u8]| {
+        let mut buffer = BytesMut::from(bytes);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.clone().decode(&mut decode_buffer)
+    };
+
+    // Plain ASCII is fine.
+    decode(&[
+        10, // 'code' tag: (1 << 3) + 2
+        2, 104, 105, // "hi"
+        18,  // 'labels' tag: (2 << 3) + 2
+        2, 104, 105, // "hi"
+    ])
+    .unwrap();
+
+    let status = decode(&[
+        10, // 'code' tag: (1 << 3) + 2
+        3, 104, 195, 169, // "hé"
+    ])
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 5: String contains non-ASCII characters",
+    );
+
+    // Only the offending item of a repeated field is reported.
+    let status = decode(&[
+        18, // 'labels' tag: (2 << 3) + 2
+        2, 104, 105, // "hi"
+        18,  // 'labels' tag: (2 << 3) + 2
+        2, 104, 9, // "h\t"
+    ])
+    .unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.2[1]) @offset 8: String contains characters besides printable ASCII",
+    );
+}
+
+#[test]
+fn test_closed_enum_unrecognized() {
+    let enumeration = |name: &str, number, coding: CompoundCoding| Field {
+        name: String::from(name),
+        number,
+        coding: Some(Coding::CompoundCoding(coding as i32)),
+        subfields: ["red", "green"]
+            .iter()
+            .enumerate()
+            .map(|(number, name)| Field {
+                name: String::from(*name),
+                number: number as u32,
+                coding: None, // Ignored.
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            })
+            .collect(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                enumeration("one", 1, CompoundCoding::EnumImplicit),
+                enumeration("many", 2, CompoundCoding::EnumPacked),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let decode = |bytes: &[u8]| {
+        let mut buffer = BytesMut::from(bytes);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.clone().decode(&mut decode_buffer)
+    };
+
+    let status = decode(&[
+        8, // 'one' tag: (1 << 3) + 0
+        7, // unrecognized
+    ])
+    .unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 2: Unrecognized variant of closed enum",
+    );
+
+    let status = decode(&[
+        18, // 'many' tag: (2 << 3) + 2
+        3, 1, 128, 1, // packed [1, 128]
+    ])
+    .unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.2[1]) @offset 5: Unrecognized variant of closed enum",
+    );
+}
+
+#[test]
+fn test_strict_enum_numbers() {
+    let enumeration = |number| Field {
+        name: String::from("sign"),
+        number,
+        coding: Some(Coding::CompoundCoding(CompoundCoding::EnumImplicit as i32)),
+        subfields: [("zero", 0), ("minus-one", -1i32 as u32)]
+            .iter()
+            .map(|(name, number)| Field {
+                name: String::from(*name),
+                number: *number,
+                coding: None, // Ignored.
+                subfields: Vec::new(),
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            })
+            .collect(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let decoder = RequestDecoder::with_options(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                enumeration(1),
+                Field {
+                    name: String::from("m"),
+                    number: 2,
+                    coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
+                    subfields: vec![enumeration(1)],
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                },
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+        DecoderOptions {
+            strict_enum_numbers: true,
+            ..DecoderOptions::default()
+        },
+    )
+    .unwrap();
+    let decode = |bytes: &[u8]| {
+        let mut buffer = BytesMut::from(bytes);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        decoder.clone().decode(&mut decode_buffer)
+    };
+
+    // Sign-extended negative numbers are fine.
+    decode(&[
+        8, // 'sign' tag: (1 << 3) + 0
+        255, 255, 255, 255, 255, 255, 255, 255, 255, 1, // -1
+    ])
+    .unwrap();
+
+    // The same number encoded in only 32 bits is out of range.
+    let status = decode(&[
+        8, // 'sign' tag: (1 << 3) + 0
+        255, 255, 255, 255, 15, // 4294967295
+    ])
+    .unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1) @offset 6: Overflowed 32 bits",
+    );
+
+    // Nested enumerations are just as strict.
+    let status = decode(&[
+        18, // 'm' tag: (2 << 3) + 2
+        6,  // length of submessage
+        8,  //   'sign' tag: (1 << 3) + 0
+        255, 255, 255, 255, 15, // 4294967295
+    ])
+    .unwrap_err();
+    assert_eq!(
+        status.message(),
+        "Malformed request (.2.1) @offset 8: Overflowed 32 bits",
+    );
+}
+
+#[test]
+fn test_oneof_in_repeated_message_error_path() {
+    let variant = |name: &str, number, coding| Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields: Vec::new(),
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("items"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(
+                    CompoundCoding::MessageExpanded as i32,
+                )),
+                subfields: vec![Field {
+                    name: String::from("choice"),
+                    number: 0, // Ignored.
+                    coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
+                    subfields: vec![
+                        variant(
+                            "number",
+                            1,
+                            Coding::ScalarCoding(ScalarCoding::Int32Explicit as i32),
+                        ),
+                        Field {
+                            subfields: vec![variant(
+                                "x",
+                                1,
+                                Coding::ScalarCoding(ScalarCoding::Sint32Implicit as i32),
+                            )],
+                            ..variant(
+                                "nested",
+                                3,
+                                Coding::CompoundCoding(CompoundCoding::Message as i32),
+                            )
+                        },
+                    ],
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                }],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut buffer = BytesMut::from(
+        &[
+            10, // 'items' tag: (1 << 3) + 2
+            2,  // length of submessage
+            8,  //   'number' tag: (1 << 3) + 0
+            5,  //   5
+            10, // 'items' tag: (1 << 3) + 2
+            4,  // length of submessage
+            26, //   'nested' tag: (3 << 3) + 2
+            2,  //   length of submessage
+            15, //     corrupt tag: (1 << 3) + 7 (invalid wire type)
+            0,
+        ][..],
+    );
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+
+    // The path leads through the second element into the oneof case.
+    let status = decoder.decode(&mut decode_buffer).unwrap_err();
+    assert_eq!(status.code(), Code::InvalidArgument);
+    assert_eq!(
+        status.message(),
+        "Malformed request (.1[1].3.1) @offset 9: Invalid wire type",
+    );
+}
+
+#[test]
+fn test_duplicate_field_number() {
+    let field = |name: &str, number: u32, coding: Coding, subfields: Vec<Field>| Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let int32 = || Coding::ScalarCoding(ScalarCoding::Int32Explicit as i32);
+    let request = |subfields: Vec<Field>| Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let error = |request: Field| {
+        let error = RequestDecoder::new(
+            &request,
+            Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+        )
+        .err()
+        .unwrap();
+        format!("{error:#}")
+    };
+
+    assert_eq!(
+        error(request(vec![
+            field("a", 1, int32(), Vec::new()),
+            field("b", 2, int32(), Vec::new()),
+            field("c", 1, int32(), Vec::new()),
+        ])),
+        "Invalid request decoder: Fields 'a' and 'c' have the same number #1",
+    );
+
+    // Oneof variants share the field numbers of their containing message.
+    let oneof = Coding::CompoundCoding(CompoundCoding::Oneof as i32);
+    assert_eq!(
+        error(request(vec![
+            field("a", 1, int32(), Vec::new()),
+            field("choice", 0, oneof, vec![field("b", 1, int32(), Vec::new())]),
+        ])),
+        "Invalid request decoder: Fields 'a' and 'b' have the same number #1",
+    );
+
+    // Nested messages are checked too.
+    let message = Coding::CompoundCoding(CompoundCoding::Message as i32);
+    let nested = vec![
+        field("x", 7, int32(), Vec::new()),
+        field("y", 7, int32(), Vec::new()),
+    ];
+    assert_eq!(
+        error(request(vec![field("a", 1, message, nested)])),
+        "Invalid request decoder: Invalid message for field #1: Fields 'x' and 'y' have the same number #7",
+    );
+}
+
+#[test]
+fn test_overlong_varint() {
+    // Overlong varints are valid, but take more bytes than their value needs,
+    // which must still count against the length of the enclosing message.
+    let error = decoder()
+        .decode_bytes(&[
+            16, // unknown field tag: (2 << 3) + 0
+            128, 128, 128, 0,  // 0, in 4 bytes instead of 1
+            10, // 'a' tag: (1 << 3) + 2
+            5,  // length of 'a', longer than what's left
+            97, // 'a'
+        ])
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Malformed request (.1) @offset 7: Buffer overflow",
+    );
+}
+
+#[test]
+fn test_64bit_wire_type_mismatch() {
+    use ScalarCoding::*;
+
+    for (codings, name) in [
+        (
+            [
+                Sfixed64Implicit,
+                Sfixed64Packed,
+                Sfixed64Explicit,
+                Sfixed64Expanded,
+            ],
+            "sfixed64",
+        ),
+        (
+            [
+                Fixed64Implicit,
+                Fixed64Packed,
+                Fixed64Explicit,
+                Fixed64Expanded,
+            ],
+            "fixed64",
+        ),
+        (
+            [DoubleImplicit, DoublePacked, DoubleExplicit, DoubleExpanded],
+            "double",
+        ),
+    ] {
+        for coding in codings {
+            let decoder = RequestDecoder::new(
+                &Field {
+                    number: 0,       // Ignored.
+                    name: "".into(), // Ignored.
+                    coding: None,    // Ignored.
+                    subfields: vec![Field {
+                        name: String::from("a"),
+                        number: 1,
+                        coding: Some(Coding::ScalarCoding(coding as i32)),
+                        subfields: Vec::new(),
+                        charset: Charset::Unrestricted as i32,
+                        ..Default::default()
+                    }],
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                },
+                Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+            )
+            .unwrap();
+            let error = decoder
+                .decode_bytes(&[
+                    8, // 'a' tag: (1 << 3) + 0 (varint instead of 64-bit)
+                    1, // 1
+                ])
+                .unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                format!(
+                    "Malformed request (.1) @offset 1: \
+                     Wire type of {name} field should be 64-bit (I64)"
+                ),
+                "{coding:?}",
+            );
+        }
+    }
+}
+
+#[test]
+fn test_diagnose_reports_every_error() {
+    let field = |name: &str, number, coding, subfields| Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                field(
+                    "a",
+                    1,
+                    Coding::ScalarCoding(ScalarCoding::StringUtf8Implicit as i32),
+                    Vec::new(),
+                ),
+                field(
+                    "b",
+                    2,
+                    Coding::CompoundCoding(CompoundCoding::Message as i32),
+                    vec![
+                        field(
+                            "x",
+                            1,
+                            Coding::ScalarCoding(ScalarCoding::BoolImplicit as i32),
+                            Vec::new(),
+                        ),
+                        field(
+                            "y",
+                            2,
+                            Coding::ScalarCoding(ScalarCoding::Int32Implicit as i32),
+                            Vec::new(),
+                        ),
+                    ],
+                ),
+                field(
+                    "c",
+                    3,
+                    Coding::ScalarCoding(ScalarCoding::Int32Implicit as i32),
+                    Vec::new(),
+                ),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let request = [
+        10,  // 'a' tag: (1 << 3) + 2
+        1,   // length of string
+        255, //   invalid UTF-8
+        18,  // 'b' tag: (2 << 3) + 2
+        4,   // length of submessage
+        8,   //   'x' tag: (1 << 3) + 0
+        2,   //   invalid boolean
+        16,  //   'y' tag: (2 << 3) + 0
+        7,   //   7
+        24,  // 'c' tag: (3 << 3) + 0
+        5,   // 5
+    ];
+
+    // The strict decoder stops at the first error.
+    assert_eq!(
+        decoder.decode_bytes(&request).unwrap_err().to_string(),
+        "Malformed request (.1) @offset 3: Invalid UTF-8",
+    );
+
+    // Diagnosis keeps going, and decodes everything else.
+    let (value, errors) = decoder.diagnose(&request);
+    assert_eq!(
+        errors.iter().map(ToString::to_string).collect::<Vec<_>>(),
+        vec![
+            "Malformed request (.1) @offset 3: Invalid UTF-8",
+            "Malformed request (.2.1) @offset 7: Invalid boolean value",
+        ],
+    );
+    assert_eq!(
+        value,
+        Val::Record(vec![
+            (String::from("a"), Val::String(String::new())),
+            (
+                String::from("b"),
+                Val::Option(Some(Box::new(Val::Record(vec![
+                    (String::from("x"), Val::Bool(false)),
+                    (String::from("y"), Val::S32(7)),
+                ])))),
+            ),
+            (String::from("c"), Val::S32(5)),
+        ]),
+    );
+}
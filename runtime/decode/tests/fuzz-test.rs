@@ -0,0 +1,233 @@
+//! Feeds the decoder randomly corrupted requests, checking that none of them makes it panic.
+//!
+//! Corruptions start from valid requests produced by the encoder,
+//! so they reach deep into the decoder rather than failing on the first tag.
+//! The same entrypoint ([`RequestDecoder::decode_bytes`]) can be driven by any fuzzing engine;
+//! this test just keeps a small, deterministic run of it in the regular test suite.
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use tonic::codec::Encoder as _;
+use wasmtime::component::Val;
+
+use decode::RequestDecoder;
+use encode::ResponseEncoder;
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::encode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// Number of corrupted requests to try.
+const ITERATIONS: usize = 20_000;
+
+/// Minimal deterministic pseudo-random number generator (xorshift64).
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A random number in `0..bound` (which must be non-zero).
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+}
+
+fn field(name: &str, number: u32, coding: Coding, subfields: Vec<Field>) -> Field {
+    Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+/// Every known scalar coding.
+fn scalar_codings() -> impl Iterator<Item = ScalarCoding> {
+    (0..64).filter_map(|number| ScalarCoding::try_from(number).ok())
+}
+
+/// One field per scalar coding, numbered after the coding.
+fn scalar_fields() -> Vec<Field> {
+    scalar_codings()
+        .map(|coding| {
+            let number = coding as i32;
+            field(
+                &format!("s{number}"),
+                number as u32 + 1,
+                Coding::ScalarCoding(number),
+                Vec::new(),
+            )
+        })
+        .collect()
+}
+
+/// A non-zero value for each scalar field. See `encode/tests/round-trip-test.rs`.
+fn scalar_values() -> Vec<(String, Val)> {
+    scalar_codings()
+        .map(|coding| {
+            let number = coding as i32;
+            let sample = match number / 4 {
+                0 => Val::List(vec![Val::U8(7), Val::U8(0)]),
+                1 | 2 => Val::String(String::from("hi")),
+                3 => Val::Bool(true),
+                4..=6 => Val::S32(-3),
+                7 | 8 => Val::U32(3),
+                9..=11 => Val::S64(-3_000_000_000),
+                12 | 13 => Val::U64(3_000_000_000),
+                14 => Val::Float32(-1.5),
+                15 => Val::Float64(-1.5),
+                _ => unreachable!("Unknown ScalarCoding {number}"),
+            };
+            // Codings cycle through [implicit, packed, explicit, expanded].
+            let value = match number % 4 {
+                0 => sample,
+                2 => Val::Option(Some(Box::new(sample))),
+                _ => Val::List(vec![sample.clone(), sample]),
+            };
+            (format!("s{number}"), value)
+        })
+        .collect()
+}
+
+/// A request with every scalar coding, a nested message, repeated messages, and an enum.
+fn request() -> Field {
+    let enum_variants = ["zero", "one"]
+        .into_iter()
+        .enumerate()
+        .map(|(number, name)| Field {
+            name: String::from(name),
+            number: number as u32,
+            coding: None, // Ignored.
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        })
+        .collect();
+    let mut subfields = scalar_fields();
+    subfields.push(field(
+        "nested",
+        100,
+        Coding::CompoundCoding(CompoundCoding::Message as i32),
+        scalar_fields(),
+    ));
+    subfields.push(field(
+        "repeated",
+        101,
+        Coding::CompoundCoding(CompoundCoding::MessageExpanded as i32),
+        scalar_fields(),
+    ));
+    subfields.push(field(
+        "variants",
+        102,
+        Coding::CompoundCoding(CompoundCoding::EnumPacked as i32),
+        enum_variants,
+    ));
+    Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+fn request_value() -> Val {
+    let nested = Val::Record(scalar_values());
+    let mut fields = scalar_values();
+    fields.push((
+        String::from("nested"),
+        Val::Option(Some(Box::new(nested.clone()))),
+    ));
+    fields.push((
+        String::from("repeated"),
+        Val::List(vec![nested.clone(), nested]),
+    ));
+    fields.push((
+        String::from("variants"),
+        Val::List(vec![
+            Val::Enum(String::from("one")),
+            Val::Enum(String::from("zero")),
+        ]),
+    ));
+    Val::Record(fields)
+}
+
+/// Encode a request with the encoder, which shares the decoder's metadata format.
+fn encode(request: &Field, value: Val) -> Vec<u8> {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let mut encoder = ResponseEncoder::new(request, component).unwrap();
+    let mut buffer = BytesMut::new();
+    let mut encode_buffer = encode_buf(&mut buffer);
+    encoder.encode(value, &mut encode_buffer).unwrap();
+    buffer.to_vec()
+}
+
+/// Apply a random corruption to the input.
+fn corrupt(input: &mut Vec<u8>, random: &mut Random) {
+    if input.is_empty() {
+        input.push(random.next() as u8);
+        return;
+    }
+    let position = random.below(input.len());
+    match random.below(6) {
+        0 => input[position] ^= 1 << random.below(8),
+        1 => input[position] = random.next() as u8,
+        2 => input.truncate(position),
+        3 => input.insert(position, random.next() as u8),
+        4 => {
+            input.remove(position);
+        }
+        _ => {
+            // Repeat a run of bytes, e.g. to duplicate a field.
+            let end = position + random.below(input.len() - position) + 1;
+            let run = input[position..end].to_vec();
+            input.extend(run);
+        }
+    }
+}
+
+#[test]
+fn test_corrupted_requests_never_panic() {
+    let request = request();
+    let value = request_value();
+    let decoder = RequestDecoder::new(
+        &request,
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+
+    let seeds = [
+        encode(&request, value.clone()),
+        // Everything absent.
+        Vec::new(),
+    ];
+    // The uncorrupted seed decodes to exactly what was encoded.
+    assert_eq!(decoder.decode_bytes(&seeds[0]).unwrap(), value);
+
+    // Every truncation of a valid request.
+    for length in 0..seeds[0].len() {
+        let _ = decoder.decode_bytes(&seeds[0][..length]);
+    }
+
+    let mut random = Random(0x5eed);
+    for _ in 0..ITERATIONS {
+        let mut input = seeds[random.below(seeds.len())].clone();
+        for _ in 0..=random.below(4) {
+            corrupt(&mut input, &mut random);
+        }
+        // Any outcome but a panic is fine.
+        let _ = decoder.decode_bytes(&input);
+        let _ = decoder.diagnose(&input);
+    }
+}
@@ -0,0 +1,177 @@
+use std::sync::Arc;
+
+use wasmtime::component::Val;
+
+use decode::{FieldMask, RequestDecoder};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// For messages nested in oneofs or lists.
+macro_rules! bare_record {
+    // Records cannot be empty as per the Wasm spec.
+    ($($name:literal $value:expr);+) => {
+        Val::Record(vec![$((String::from($name), $value)),*])
+    };
+}
+
+/// For nested message subfields.
+macro_rules! record {
+    // Records cannot be empty as per the Wasm spec.
+    ($($name:literal $value:expr);+) => {
+        Val::Option(Some(Box::new(bare_record!($($name $value);+))))
+    };
+}
+
+#[test]
+fn test_merge_updates_only_masked_fields() {
+    let mask = FieldMask::from_paths(["display_name", "address.city"]);
+
+    let update = bare_record!(
+        "id" Val::U64(0);
+        "display-name" Val::String("New Name".into());
+        "age" Val::U32(0);
+        "address" record!(
+            "street" Val::String("".into());
+            "city" Val::String("Montréal".into())
+        )
+    );
+    let mut existing = bare_record!(
+        "id" Val::U64(42);
+        "display-name" Val::String("Old Name".into());
+        "age" Val::U32(33);
+        "address" record!(
+            "street" Val::String("123 Main St".into());
+            "city" Val::String("Toronto".into())
+        )
+    );
+
+    mask.merge(update, &mut existing).unwrap();
+
+    assert_eq!(
+        existing,
+        bare_record!(
+            "id" Val::U64(42);
+            "display-name" Val::String("New Name".into());
+            "age" Val::U32(33);
+            "address" record!(
+                "street" Val::String("123 Main St".into());
+                "city" Val::String("Montréal".into())
+            )
+        ),
+    );
+}
+
+#[test]
+fn test_from_val_recognizes_field_mask() {
+    let value = record!(
+        "paths" Val::List(vec![
+            Val::String("address".into()),
+            Val::String("address.city".into()),
+        ])
+    );
+
+    // A shorter path selecting a whole message subsumes longer paths.
+    assert_eq!(
+        FieldMask::from_val(&value).unwrap(),
+        FieldMask::from_paths(["address"]),
+    );
+    assert!(FieldMask::from_val(&Val::Option(None)).unwrap().is_empty());
+    assert!(FieldMask::from_val(&Val::U32(1)).is_err());
+}
+
+fn field(name: &str, number: u32, coding: Coding, subfields: Vec<Field>) -> Field {
+    Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+fn scalar(name: &str, number: u32, coding: ScalarCoding) -> Field {
+    field(
+        name,
+        number,
+        Coding::ScalarCoding(coding as i32),
+        Vec::new(),
+    )
+}
+
+#[test]
+fn test_merge_masked_update() {
+    let decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                scalar("id", 1, ScalarCoding::Uint64Implicit),
+                scalar("display-name", 2, ScalarCoding::StringUtf8Implicit),
+                scalar("age", 3, ScalarCoding::Uint32Implicit),
+                field(
+                    "address",
+                    4,
+                    Coding::CompoundCoding(CompoundCoding::Message as i32),
+                    vec![
+                        scalar("street", 1, ScalarCoding::StringUtf8Implicit),
+                        scalar("city", 2, ScalarCoding::StringUtf8Implicit),
+                    ],
+                ),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut existing = decoder
+        .decode_bytes(&[
+            8, 42, // 'id' tag: (1 << 3) + 0
+            18, 2, 65, 108, // 'display_name' tag: (2 << 3) + 2, "Al"
+            24, 33, // 'age' tag: (3 << 3) + 0
+            34, 12, // 'address' tag: (4 << 3) + 2
+            10, 4, 77, 97, 105, 110, //   'street' tag: (1 << 3) + 2, "Main"
+            18, 4, 82, 111, 109, 101, //   'city' tag: (2 << 3) + 2, "Rome"
+        ])
+        .unwrap();
+    let mask = FieldMask::from_paths(["display_name", "address.city"]);
+
+    // The update also sets the age, which the mask leaves out.
+    decoder
+        .merge_masked(
+            &[
+                18, 2, 66, 111, // 'display_name' tag: (2 << 3) + 2, "Bo"
+                24, 7, // 'age' tag: (3 << 3) + 0
+                34, 6, // 'address' tag: (4 << 3) + 2
+                18, 4, 79, 115, 108, 111, //   'city' tag: (2 << 3) + 2, "Oslo"
+            ],
+            &mask,
+            &mut existing,
+        )
+        .unwrap();
+
+    assert_eq!(
+        existing,
+        bare_record!(
+            "id" Val::U64(42);
+            "display-name" Val::String("Bo".into());
+            "age" Val::U32(33);
+            "address" record!(
+                "street" Val::String("Main".into());
+                "city" Val::String("Oslo".into())
+            )
+        ),
+    );
+
+    // A malformed update leaves the request untouched.
+    let before = existing.clone();
+    assert!(decoder
+        .merge_masked(&[18, 5, 66], &mask, &mut existing)
+        .is_err());
+    assert_eq!(existing, before);
+}
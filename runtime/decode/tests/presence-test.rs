@@ -0,0 +1,136 @@
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use tonic::codec::Decoder;
+
+use decode::{DecoderOptions, RequestDecoder};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::decode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+fn field(name: &str, number: u32, coding: Coding, subfields: Vec<Field>) -> Field {
+    Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+fn scalar(name: &str, number: u32, coding: ScalarCoding) -> Field {
+    field(
+        name,
+        number,
+        Coding::ScalarCoding(coding as i32),
+        Vec::new(),
+    )
+}
+
+fn compound(name: &str, number: u32, coding: CompoundCoding, subfields: Vec<Field>) -> Field {
+    field(
+        name,
+        number,
+        Coding::CompoundCoding(coding as i32),
+        subfields,
+    )
+}
+
+fn decode(decoder: &mut RequestDecoder, encoded: &[u8]) {
+    let mut buffer = BytesMut::from(encoded);
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+    decoder.decode(&mut decode_buffer).unwrap().unwrap();
+}
+
+#[test]
+fn test_field_presence() {
+    let request = Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields: vec![
+            scalar("id", 1, ScalarCoding::Uint32Implicit),
+            compound(
+                "user",
+                2,
+                CompoundCoding::Message,
+                vec![
+                    scalar("name", 1, ScalarCoding::StringUtf8Implicit),
+                    scalar("nickname", 2, ScalarCoding::StringUtf8Implicit),
+                ],
+            ),
+            scalar("tags", 3, ScalarCoding::StringUtf8Expanded),
+            compound(
+                "choice",
+                0, // Ignored.
+                CompoundCoding::Oneof,
+                vec![
+                    scalar("flag", 4, ScalarCoding::BoolExplicit),
+                    scalar("count", 5, ScalarCoding::Int32Explicit),
+                ],
+            ),
+        ],
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    };
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+
+    // Presence is not tracked by default.
+    let untracked = RequestDecoder::new(&request, component.clone()).unwrap();
+    assert!(untracked.field_presence().is_empty());
+
+    let mut decoder = RequestDecoder::with_options(
+        &request,
+        component,
+        DecoderOptions {
+            field_presence: true,
+            ..DecoderOptions::default()
+        },
+    )
+    .unwrap();
+    decode(
+        &mut decoder,
+        &[
+            8,   // 'id' tag: (1 << 3) + 0
+            1,   // 1
+            18,  // 'user' tag: (2 << 3) + 2
+            3,   // length of submessage
+            10,  //   'name' tag: (1 << 3) + 2
+            1,   //   length of "a"
+            97,  //     "a"
+            26,  // 'tags' tag: (3 << 3) + 2
+            1,   // length of "x"
+            120, //   "x"
+            26,  // 'tags' tag: (3 << 3) + 2
+            1,   // length of "y"
+            121, //   "y"
+            32,  // 'flag' tag: (4 << 3) + 0
+            1,   // true
+        ],
+    );
+    decode(
+        &mut decoder,
+        &[
+            8, // 'id' tag: (1 << 3) + 0
+            2, // 2
+        ],
+    );
+
+    assert_eq!(
+        decoder.field_presence(),
+        vec![
+            (String::from("count"), 0),
+            (String::from("flag"), 1),
+            (String::from("id"), 2),
+            (String::from("tags"), 2),
+            (String::from("user"), 1),
+            (String::from("user.name"), 1),
+            (String::from("user.nickname"), 0),
+        ],
+    );
+}
@@ -0,0 +1,81 @@
+//! Benchmarks for decoding large repeated fields.
+#![feature(test)]
+
+extern crate test;
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use test::Bencher;
+use tonic::codec::Decoder;
+use wasmtime::component::Val;
+
+use decode::RequestDecoder;
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::decode_buf;
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// Number of entries in the repeated submessage field.
+const ENTRIES: usize = 10_000;
+
+/// Decode a message with a repeated submessage field (`items`, #1)
+/// with 10,000 contiguous entries, each having a single `uint32` field (`id`, #1).
+#[bench]
+fn bench_decode_repeated_message(bencher: &mut Bencher) {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![Field {
+                name: String::from("items"),
+                number: 1,
+                coding: Some(Coding::CompoundCoding(
+                    CompoundCoding::MessageExpanded as i32,
+                )),
+                subfields: vec![Field {
+                    name: String::from("id"),
+                    number: 1,
+                    coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                }],
+                charset: Charset::Unrestricted as i32,
+                ..Default::default()
+            }],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+
+    // Each entry is 4 bytes: the 'items' tag, a length of 2, the 'id' tag, and a 1-byte varint.
+    let mut encoded = Vec::with_capacity(ENTRIES * 4);
+    for index in 0..ENTRIES {
+        encoded.extend_from_slice(&[
+            10, // 'items' tag: (1 << 3) + 2
+            2,  // length of entry
+            8,  // 'id' tag: (1 << 3) + 0
+            (index % 128) as u8,
+        ]);
+    }
+
+    bencher.iter(|| {
+        let mut buffer = BytesMut::from(&encoded[..]);
+        let length = buffer.len();
+        let mut decode_buffer = decode_buf(&mut buffer, length);
+        let result = decoder.decode(&mut decode_buffer).unwrap();
+        match result {
+            Some(Val::Record(fields)) => match &fields[0].1 {
+                Val::List(items) => assert_eq!(items.len(), ENTRIES),
+                _ => panic!("Expected a list"),
+            },
+            _ => panic!("Expected a record"),
+        }
+    });
+}
@@ -1,12 +1,17 @@
 use std::mem::{drop, transmute};
 use std::sync::Arc;
 
-use bytes::BytesMut;
-use tonic::codec::Decoder;
+use bytes::{Bytes, BytesMut};
+use futures::executor::block_on;
+use futures::stream::iter;
+use http_body::Frame;
+use http_body_util::StreamBody;
+use tonic::codec::{Decoder, Streaming};
+use tonic::Status;
 use wasmtime::component::Val;
 
-use decode::RequestDecoder;
-use metadata_proto::work::runtime::field::{Coding, CompoundCoding, ScalarCoding};
+use decode::{recycle, RequestDecoder};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
 use metadata_proto::work::runtime::Field;
 use names::Name;
 
@@ -52,6 +57,8 @@ macro_rules! test_success {
                     name: "".into(), // Ignored.
                     coding: None,    // Ignored.
                     subfields: vec![$(field!($field_name $field),)*],
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
                 },
                 Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
             ).unwrap();
@@ -77,22 +84,111 @@ macro_rules! field {
             number: $number,
             coding: Some(Coding::ScalarCoding($coding as i32)),
             subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
-    ($name:literal (message $number:literal $($subfield_name:literal $subfield:tt)+)) => {
+    ($name:literal (message $number:literal $($subfield_name:literal $subfield:tt)*)) => {
         Field {
             name: String::from($name),
             number: $number,
             coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
             subfields: vec![$(field!($subfield_name $subfield),)*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
+    ($name:literal (repeated $number:literal $($subfield_name:literal $subfield:tt)*)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::MessageExpanded as i32)),
+            subfields: vec![$(field!($subfield_name $subfield),)*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (duration $number:literal)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Duration as i32)),
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (timestamp $number:literal)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Timestamp as i32)),
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (dynamic ($coding:expr) $number:literal)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(($coding) as i32)),
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (map $number:literal $key_name:literal $key:tt $value_name:literal $value:tt)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Map as i32)),
+            subfields: vec![field!($key_name $key), field!($value_name $value)],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (enumeration ($coding:expr) $number:literal $($variant_name:literal $variant_number:literal)+)) => {
+        enumeration!($name $coding, $number, false, "", $($variant_name $variant_number)+)
+    };
+    ($name:literal (open_enumeration ($coding:expr) $number:literal $($variant_name:literal $variant_number:literal)+)) => {
+        enumeration!($name $coding, $number, true, "", $($variant_name $variant_number)+)
+    };
+    ($name:literal (default_enumeration ($coding:expr) $number:literal $default:literal $($variant_name:literal $variant_number:literal)+)) => {
+        enumeration!($name $coding, $number, false, $default, $($variant_name $variant_number)+)
+    };
     ($name:literal (oneof $($subfield_name:literal $subfield:tt)+)) => {
         Field {
             name: String::from($name),
             number: 0, // Ignored.
             coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
             subfields: vec![$(field!($subfield_name $subfield),)*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+}
+
+macro_rules! enumeration {
+    ($name:literal $coding:expr, $number:literal, $open:literal, $default:literal, $($variant_name:literal $variant_number:literal)+) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(($coding) as i32)),
+            subfields: vec![$(
+                Field {
+                    name: String::from($variant_name),
+                    number: $variant_number,
+                    coding: None, // Ignored.
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                },
+            )+],
+            charset: Charset::Unrestricted as i32,
+            open_enum: $open,
+            default_variant: String::from($default),
+            ..Default::default()
         }
     };
 }
@@ -125,6 +221,13 @@ macro_rules! variant {
     };
 }
 
+/// For oneof variants without a payload.
+macro_rules! empty_variant {
+    ($name:literal) => {
+        Val::Option(Some(Box::new(Val::Variant(String::from($name), None))))
+    };
+}
+
 /// This has to be an exact clone of [`tonic::codec::DecodeBuf`],
 /// which has a private constructor that prevents instantiation here.
 /// We get around that by unsafely transmuting a structurally-equivalent clone.
@@ -204,6 +307,117 @@ test_success!(
     ),
 );
 
+// An empty message in a oneof decodes as a case without a payload.
+test_success!(
+    test_oneof_empty_variant,
+    fields = (
+        "a" (oneof
+            "number" (scalar 1 ScalarCoding::Int32Explicit)
+            "nothing" (message 2)
+            "text" (scalar 3 ScalarCoding::StringUtf8Explicit)
+        )
+        "b" (oneof
+            "nothing" (message 4)
+            "number" (scalar 5 ScalarCoding::Int32Explicit)
+        )
+    ),
+    buffer = &[
+        18,             // 'nothing' tag: (2 << 3) + 2
+        2,              // length of submessage
+          8,            //   unknown tag: (1 << 3) + 0
+          1,            //   1
+        40,             // 'number' tag: (5 << 3) + 0
+        7,              // 7
+    ],
+    expect = (
+        "a" empty_variant!("nothing");
+        "b" variant!("number" Val::S32(7));
+    ),
+);
+
+// The last case on the wire wins, whether or not it has a payload.
+test_success!(
+    test_oneof_empty_variant_overwritten,
+    fields = (
+        "a" (oneof
+            "nothing" (message 1)
+            "text" (scalar 2 ScalarCoding::StringUtf8Explicit)
+        )
+        "b" (oneof
+            "nothing" (message 3)
+            "text" (scalar 4 ScalarCoding::StringUtf8Explicit)
+        )
+    ),
+    buffer = &[
+        10,             // 'nothing' tag: (1 << 3) + 2
+        0,              // length of submessage
+        18,             // 'text' tag: (2 << 3) + 2
+        2,              // length of "hi"
+          104, 105,     //   "hi"
+        34,             // 'text' tag: (4 << 3) + 2
+        0,              // length of ""
+        26,             // 'nothing' tag: (3 << 3) + 2
+        0,              // length of submessage
+    ],
+    expect = (
+        "a" variant!("text" Val::String("hi".into()));
+        "b" empty_variant!("nothing");
+    ),
+);
+
+// Each element of a repeated message tracks its own oneof case.
+test_success!(
+    test_oneof_in_repeated_message,
+    fields = (
+        "items" (repeated 1
+            "choice" (oneof
+                "number" (scalar 1 ScalarCoding::Int32Explicit)
+                "text" (scalar 2 ScalarCoding::StringUtf8Explicit)
+                "nested" (message 3
+                    "x" (scalar 1 ScalarCoding::Sint32Implicit)
+                )
+            )
+            "tag" (scalar 4 ScalarCoding::Int32Implicit)
+        )
+    ),
+    buffer = &[
+        10,             // 'items' tag: (1 << 3) + 2
+        2,              // length of submessage
+          8,            //   'number' tag: (1 << 3) + 0
+          5,            //   5
+        10,             // 'items' tag: (1 << 3) + 2
+        4,              // length of submessage
+          18,           //   'text' tag: (2 << 3) + 2
+          2,            //   length of "hi"
+            104, 105,   //     "hi"
+        10,             // 'items' tag: (1 << 3) + 2
+        2,              // length of submessage
+          32,           //   'tag' tag: (4 << 3) + 0
+          7,            //   7
+        10,             // 'items' tag: (1 << 3) + 2
+        6,              // length of submessage
+          8,            //   'number' tag: (1 << 3) + 0
+          1,            //   1
+          26,           //   'nested' tag: (3 << 3) + 2
+          2,            //   length of submessage
+            8,          //     'x' tag: (1 << 3) + 0
+            1,          //     -1 [zig-zag-encoded]
+    ],
+    expect = (
+        "items" Val::List(vec![
+            bare_record!("choice" variant!("number" Val::S32(5)); "tag" Val::S32(0)),
+            bare_record!("choice" variant!("text" Val::String("hi".into())); "tag" Val::S32(0)),
+            // No case from the previous element bleeds into this one.
+            bare_record!("choice" Val::Option(None); "tag" Val::S32(7)),
+            // The last case on the wire wins within an element.
+            bare_record!(
+                "choice" variant!("nested" bare_record!("x" Val::S32(-1)));
+                "tag" Val::S32(0)
+            ),
+        ]);
+    ),
+);
+
 test_success!(
     test_bytes_implicit,
     fields = (
@@ -254,6 +468,28 @@ test_success!(
     ),
 );
 
+// Proto3 `optional` scalars decode to `option<T>`:
+// present (even with the default value) or absent, never the scalar default.
+test_success!(
+    test_int32_explicit,
+    fields = (
+        "present" (scalar 1 ScalarCoding::Int32Explicit)
+        "present-zero" (scalar 2 ScalarCoding::Int32Explicit)
+        "absent" (scalar 3 ScalarCoding::Int32Explicit)
+    ),
+    buffer = &[
+        8,              // 'present' tag: (1 << 3) + 0
+        42,             // 42
+        16,             // 'present-zero' tag: (2 << 3) + 0
+        0,              // 0
+    ],
+    expect = (
+        "present" Val::Option(Some(Box::new(Val::S32(42))));
+        "present-zero" Val::Option(Some(Box::new(Val::S32(0))));
+        "absent" Val::Option(None);
+    ),
+);
+
 test_success!(
     test_bytes_repeated,
     fields = (
@@ -277,6 +513,350 @@ test_success!(
     ),
 );
 
+test_success!(
+    test_duration_positive,
+    fields = (
+        "timeout" (duration 1)
+        "unset" (duration 2)
+    ),
+    buffer = &[
+        10,                           // 'timeout' tag: (1 << 3) + 2
+        8,                            // length of submessage
+          8,                          //   'seconds' tag: (1 << 3) + 0
+          3,                          //   3
+          16,                         //   'nanos' tag: (2 << 3) + 0
+          128, 202, 181, 238, 1,      //   500000000
+    ],
+    expect = (
+        "timeout" Val::Option(Some(Box::new(Val::S64(3_500_000_000))));
+        "unset" Val::Option(None);
+    ),
+);
+
+test_success!(
+    test_duration_negative,
+    fields = (
+        "offset" (duration 1)
+    ),
+    buffer = &[
+        10,                           // 'offset' tag: (1 << 3) + 2
+        22,                           // length of submessage
+          8,                          //   'seconds' tag: (1 << 3) + 0
+          255, 255, 255, 255, 255,    //   -1 [sign-extended]
+          255, 255, 255, 255, 1,
+          16,                         //   'nanos' tag: (2 << 3) + 0
+          128, 182, 202, 145, 254,    //   -500000000 [sign-extended]
+          255, 255, 255, 255, 1,
+    ],
+    expect = (
+        "offset" Val::Option(Some(Box::new(Val::S64(-1_500_000_000))));
+    ),
+);
+
+test_success!(
+    test_timestamp,
+    fields = (
+        "created" (timestamp 1)
+        "updated" (timestamp 2)
+    ),
+    buffer = &[
+        10,                           // 'created' tag: (1 << 3) + 2
+        9,                            // length of submessage
+          8,                          //   'seconds' tag: (1 << 3) + 0
+          128, 226, 207, 170, 6,      //   1700000000
+          16,                         //   'nanos' tag: (2 << 3) + 0
+          244, 3,                     //   500
+    ],
+    expect = (
+        "created" record!(
+            "seconds" Val::S64(1_700_000_000);
+            "nanos" Val::U32(500)
+        );
+        "updated" Val::Option(None);
+    ),
+);
+
+test_success!(
+    test_map_string_int32,
+    fields = (
+        "counts" (map 1
+            "key" (scalar 1 ScalarCoding::StringUtf8Implicit)
+            "value" (scalar 2 ScalarCoding::Int32Implicit))
+        "empty" (map 2
+            "key" (scalar 1 ScalarCoding::Uint64Implicit)
+            "value" (scalar 2 ScalarCoding::BoolImplicit))
+    ),
+    buffer = &[
+        10,                           // 'counts' tag: (1 << 3) + 2
+        5,                            // length of entry
+          10, 1, 97,                  //   key: "a"
+          16, 1,                      //   value: 1
+        10,                           // 'counts' tag: (1 << 3) + 2
+        3,                            // length of entry
+          10, 1, 98,                  //   key: "b", value missing
+        10,                           // 'counts' tag: (1 << 3) + 2
+        5,                            // length of entry
+          10, 1, 97,                  //   key: "a" (again)
+          16, 7,                      //   value: 7
+        10,                           // 'counts' tag: (1 << 3) + 2
+        2,                            // length of entry
+          16, 2,                      //   key missing, value: 2
+    ],
+    expect = (
+        "counts" Val::List(vec![
+            Val::Tuple(vec![Val::String(String::from("a")), Val::S32(7)]),
+            Val::Tuple(vec![Val::String(String::from("b")), Val::S32(0)]),
+            Val::Tuple(vec![Val::String(String::new()), Val::S32(2)]),
+        ]);
+        "empty" Val::List(Vec::new());
+    ),
+);
+
+test_success!(
+    test_map_many_duplicate_keys,
+    fields = (
+        "flags" (map 1
+            "key" (scalar 1 ScalarCoding::Uint32Implicit)
+            "value" (scalar 2 ScalarCoding::Uint32Implicit))
+    ),
+    // Ten thousand entries cycling through three keys, each with a larger value than the last.
+    // Every value takes exactly two bytes as a varint.
+    buffer = (128..10_128u32)
+        .flat_map(|index| [10, 5, 8, (index % 3) as u8, 16, (index as u8) | 0x80, (index >> 7) as u8])
+        .collect::<Vec<u8>>(),
+    expect = (
+        "flags" Val::List(vec![
+            Val::Tuple(vec![Val::U32(2), Val::U32(10_127)]),
+            Val::Tuple(vec![Val::U32(0), Val::U32(10_125)]),
+            Val::Tuple(vec![Val::U32(1), Val::U32(10_126)]),
+        ]);
+    ),
+);
+
+/// Nodes of a decoded dynamic value.
+macro_rules! node {
+    (null) => {
+        Val::Variant(String::from("null"), None)
+    };
+    ($case:literal $payload:expr) => {
+        Val::Variant(String::from($case), Some(Box::new($payload)))
+    };
+    (list $($index:literal)*) => {
+        node!("list" Val::List(vec![$(Val::U32($index)),*]))
+    };
+    (struct $($key:literal $index:literal)*) => {
+        node!("struct" Val::List(vec![$(
+            Val::Tuple(vec![Val::String(String::from($key)), Val::U32($index)])
+        ),*]))
+    };
+}
+
+test_success!(
+    test_struct_nested,
+    fields = (
+        "attributes" (dynamic (CompoundCoding::Struct) 1)
+        "extra" (dynamic (CompoundCoding::Value) 2)
+    ),
+    buffer = &[
+        10,                           // 'attributes' tag: (1 << 3) + 2
+        78,                           // length of struct
+          10,                         //   'fields' tag: (1 << 3) + 2
+          12,                         //   length of entry
+            10, 4, 110, 97, 109, 101, //     key: "name"
+            18,                       //     value tag: (2 << 3) + 2
+            4,                        //     length of value
+              26, 2, 104, 105,        //       'string_value': "hi"
+          10,                         //   'fields' tag: (1 << 3) + 2
+          18,                         //   length of entry
+            10, 5, 99, 111, 117, 110, 116, // key: "count"
+            18,                       //     value tag: (2 << 3) + 2
+            9,                        //     length of value
+              17,                     //       'number_value' tag: (2 << 3) + 1
+              0, 0, 0, 0, 0, 0, 4, 64, //      2.5
+          10,                         //   'fields' tag: (1 << 3) + 2
+          18,                         //   length of entry
+            10, 4, 116, 97, 103, 115, //     key: "tags"
+            18,                       //     value tag: (2 << 3) + 2
+            10,                       //     length of value
+              50,                     //       'list_value' tag: (6 << 3) + 2
+              8,                      //       length of list
+                10, 2, 32, 1,         //         'values': 'bool_value' true
+                10, 2, 8, 0,          //         'values': 'null_value'
+          10,                         //   'fields' tag: (1 << 3) + 2
+          22,                         //   length of entry
+            10, 6, 110, 101, 115, 116, 101, 100, // key: "nested"
+            18,                       //     value tag: (2 << 3) + 2
+            12,                       //     length of value
+              42,                     //       'struct_value' tag: (5 << 3) + 2
+              10,                     //       length of struct
+                10,                   //         'fields' tag: (1 << 3) + 2
+                8,                    //         length of entry
+                  10, 2, 111, 107,    //           key: "ok"
+                  18, 2, 32, 0,       //           value: 'bool_value' false
+    ],
+    expect = (
+        "attributes" Val::Option(Some(Box::new(Val::List(vec![
+            node!(struct "name" 1 "count" 2 "tags" 3 "nested" 6),
+            node!("string" Val::String(String::from("hi"))),
+            node!("number" Val::Float64(2.5)),
+            node!(list 4 5),
+            node!("bool" Val::Bool(true)),
+            node!(null),
+            node!(struct "ok" 7),
+            node!("bool" Val::Bool(false)),
+        ]))));
+        "extra" Val::Option(None);
+    ),
+);
+
+/// A bytes field that ends exactly at the end of the message,/// A bytes field that ends exactly at the end of the message,
+/// where the underlying buffer continues with the next message's bytes.
+/// Decoding must not copy past the message boundary.
+#[test]
+fn test_bytes_at_buffer_boundary() {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![field!("bytes-implicit" (scalar 1 ScalarCoding::BytesImplicit))],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut buffer = BytesMut::from(
+        &[
+            10, // tag: (1 << 3) + 2
+            3,  // length of bytes
+            1, 2, 3, //   bytes
+            // Next message:
+            10, 0,
+        ][..],
+    );
+    let mut decode_buffer = unsafe {
+        transmute(DecodeBufClone {
+            buf: &mut buffer,
+            len: 5,
+        })
+    };
+
+    let result = decoder.decode(&mut decode_buffer).unwrap();
+
+    assert_eq!(
+        result,
+        Some(bare_record!(
+            "bytes-implicit" Val::List(vec![Val::U8(1), Val::U8(2), Val::U8(3)])
+        )),
+    );
+    // The next message is untouched.
+    assert_eq!(&buffer[..], &[10, 0]);
+}
+
+/// A request may arrive in several chunks, split anywhere (even within a field),
+/// but Tonic only hands it to the decoder once every byte of it has arrived.
+#[test]
+fn test_message_split_across_chunks() {
+    let decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                field!("a" (scalar 1 ScalarCoding::StringUtf8Implicit)),
+                field!("b" (scalar 2 ScalarCoding::Int32Implicit)),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let message = [
+        10, // 'a' tag: (1 << 3) + 2
+        5,  // length of "hello"
+        104, 101, 108, 108, 111, //   "hello"
+        16,  // 'b' tag: (2 << 3) + 0
+        42,  // 42
+    ];
+    // gRPC framing: an uncompressed flag and a big-endian length, followed by the message.
+    let mut frame = vec![0];
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+    // The first chunk ends in the middle of the string.
+    let (first, second) = frame.split_at(9);
+    let body =
+        StreamBody::new(iter([first, second].map(|chunk| {
+            Ok::<_, Status>(Frame::data(Bytes::copy_from_slice(chunk)))
+        })));
+    let mut requests = Streaming::new_request(decoder, body, None, None);
+
+    assert_eq!(
+        block_on(requests.message()).unwrap(),
+        Some(bare_record!(
+            "a" Val::String("hello".into());
+            "b" Val::S32(42)
+        )),
+    );
+    assert_eq!(block_on(requests.message()).unwrap(), None);
+}
+
+/// Decoding into recycled storage gives the same result as decoding into fresh storage.
+#[test]
+fn test_decode_recycled() {
+    let mut decoder = RequestDecoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![field!("x" (message 1
+                "a" (scalar 1 ScalarCoding::Sint32Implicit)
+                "b" (scalar 2 ScalarCoding::BoolImplicit)
+            ))],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut decode = |encoded: &[u8]| {
+        let mut buffer = BytesMut::from(encoded);
+        let length = buffer.len();
+        let mut decode_buffer = unsafe {
+            transmute(DecodeBufClone {
+                buf: &mut buffer,
+                len: length,
+            })
+        };
+        decoder.decode(&mut decode_buffer).unwrap().unwrap()
+    };
+
+    recycle(decode(&[
+        10, // 'x' tag: (1 << 3) + 2
+        4,  // length of submessage
+        8,  //   'a' tag: (1 << 3) + 0
+        9,  //   -5 [zig-zag-encoded]
+        16, //   'b' tag: (2 << 3) + 0
+        1,  //   true
+    ]));
+    // Fields left unset this time must not carry over from the recycled request.
+    assert_eq!(
+        decode(&[
+            10, // 'x' tag: (1 << 3) + 2
+            2,  // length of submessage
+            8,  //   'a' tag: (1 << 3) + 0
+            4,  //   2 [zig-zag-encoded]
+        ]),
+        bare_record!(
+            "x" record!(
+                "a" Val::S32(2);
+                "b" Val::Bool(false)
+            )
+        ),
+    );
+}
+
 test_success!(
     test_string_implicit,
     fields = (
@@ -338,3 +918,99 @@ test_success!(
         ]);
     ),
 );
+
+test_success!(
+    test_enum_closed,
+    fields = (
+        "one" (enumeration (CompoundCoding::EnumImplicit) 1 "red" 0 "green" 1)
+        "many" (enumeration (CompoundCoding::EnumPacked) 2 "red" 0 "green" 1)
+    ),
+    buffer = &[
+        8,              // tag: (1 << 3) + 0
+        1,              // "green"
+        18,             // tag: (2 << 3) + 2
+        2,              // length of packed repeated enum
+          1,            //   "green"
+          0,            //   "red"
+    ],
+    expect = (
+        "one" Val::Enum("green".into());
+        "many" Val::List(vec![
+            Val::Enum("green".into()),
+            Val::Enum("red".into()),
+        ]);
+    ),
+);
+
+// Open enums preserve unrecognized numbers rather than rejecting them,
+// so they are represented as WIT variants.
+test_success!(
+    test_enum_open_unrecognized,
+    fields = (
+        "one" (open_enumeration (CompoundCoding::EnumImplicit) 1 "red" 0 "green" 1)
+        "many" (open_enumeration (CompoundCoding::EnumPacked) 2 "red" 0 "green" 1)
+        "absent" (open_enumeration (CompoundCoding::EnumImplicit) 3 "red" 0 "green" 1)
+    ),
+    buffer = &[
+        8,              // tag: (1 << 3) + 0
+        7,              // unrecognized
+        18,             // tag: (2 << 3) + 2
+        4,              // length of packed repeated enum
+          1,            //   "green"
+          128, 1,       //   unrecognized (128)
+          0,            //   "red"
+    ],
+    expect = (
+        "one" Val::Variant("unrecognized".into(), Some(Box::new(Val::S32(7))));
+        "many" Val::List(vec![
+            Val::Variant("green".into(), None),
+            Val::Variant("unrecognized".into(), Some(Box::new(Val::S32(128)))),
+            Val::Variant("red".into(), None),
+        ]);
+        "absent" Val::Variant("red".into(), None);
+    ),
+);
+
+// Proto2 enums designate a default variant, which need not be numbered zero.
+test_success!(
+    test_enum_designated_default,
+    fields = (
+        "present" (default_enumeration (CompoundCoding::EnumImplicit) 1 "green" "red" 0 "green" 1)
+        "absent" (default_enumeration (CompoundCoding::EnumImplicit) 2 "green" "red" 0 "green" 1)
+        "nonzero" (default_enumeration (CompoundCoding::EnumImplicit) 3 "blue" "green" 1 "blue" 2)
+    ),
+    buffer = &[
+        8,              // tag: (1 << 3) + 0
+        0,              // "red"
+    ],
+    expect = (
+        "present" Val::Enum("red".into());
+        "absent" Val::Enum("green".into());
+        "nonzero" Val::Enum("blue".into());
+    ),
+);
+
+// Negative numbers are sign-extended to 64 bits on the wire,
+// but numbers encoded in only 32 bits are also truncated like any other `int32`.
+test_success!(
+    test_enum_negative,
+    fields = (
+        "one" (enumeration (CompoundCoding::EnumImplicit) 1 "zero" 0 "minus-one" 4294967295)
+        "many" (enumeration (CompoundCoding::EnumPacked) 2 "zero" 0 "minus-one" 4294967295)
+    ),
+    buffer = &[
+        8,                                            // tag: (1 << 3) + 0
+        255, 255, 255, 255, 255, 255, 255, 255, 255, 1, // -1
+        18,                                           // tag: (2 << 3) + 2
+        15,                                           // length of packed repeated enum
+          255, 255, 255, 255, 255, 255, 255, 255, 255, 1, //   -1
+          255, 255, 255, 255, 15,                     //   -1 (truncated from 32 bits)
+    ],
+    expect = (
+        "one" Val::Enum("minus-one".into());
+        "many" Val::List(vec![
+            Val::Enum("minus-one".into()),
+            Val::Enum("minus-one".into()),
+        ]);
+    ),
+);
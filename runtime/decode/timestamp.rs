@@ -0,0 +1,75 @@
+//! Decoding logic for `google.protobuf.Timestamp`,
+//! which becomes a record of `seconds` and `nanos` with validated ranges.
+
+use std::result::Result as StdResult;
+
+use prost::encoding::WireType;
+use wasmtime::component::Val;
+
+use crate::{
+    decode_tag, read_length_check_overflow, read_varint, skip, CompoundMerger, DecodeError,
+    MapKeys, Merger, INVALID_VARINT, TIMESTAMP_OUT_OF_RANGE, WIRETYPE_NON_LENGTH_DELIMITED,
+    WIRETYPE_NON_VARINT,
+};
+
+/// Earliest `seconds` allowed by the Protobuf spec: `0001-01-01T00:00:00Z`.
+const MIN_SECONDS: i64 = -62_135_596_800;
+
+/// Latest `seconds` allowed by the Protobuf spec: `9999-12-31T23:59:59Z`.
+const MAX_SECONDS: i64 = 253_402_300_799;
+
+/// Largest `nanos` allowed by the Protobuf spec.
+const MAX_NANOS: i64 = 999_999_999;
+
+impl Merger {
+    pub(crate) fn timestamp() -> Self {
+        Self {
+            merge: timestamp_merge,
+            defaults: Vec::new(),
+            repeated_tag: 0,
+            presence: None,
+            max_field_number: u32::MAX,
+            max_elements: u32::MAX,
+            map_keys: MapKeys::Absent,
+            compound: CompoundMerger { scalar: () },
+        }
+    }
+}
+
+/// Decode a timestamp message into `record { seconds: s64, nanos: u32 }`.
+/// Always explicitly presence-tracked.
+pub(crate) fn timestamp_merge(
+    _merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    if wire_type != WireType::LengthDelimited {
+        return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+    }
+    let mut length = read_length_check_overflow(limit, src)?;
+
+    let (mut seconds, mut nanos) = (0, 0);
+    while length > 0 {
+        let (field_number, wire_type) = decode_tag(&mut length, src)?;
+        match field_number {
+            1 | 2 if wire_type != WireType::Varint => {
+                return Err(DecodeError::new(WIRETYPE_NON_VARINT).with_field(field_number));
+            }
+            1 => seconds = read_varint(&mut length, src, INVALID_VARINT)? as i64,
+            // Negative `int32` values are sign-extended to 64 bits on the wire.
+            2 => nanos = read_varint(&mut length, src, INVALID_VARINT)? as i32 as i64,
+            _ => skip(wire_type, &mut length, src).map_err(|e| e.with_field(field_number))?,
+        }
+    }
+
+    if !(MIN_SECONDS..=MAX_SECONDS).contains(&seconds) || !(0..=MAX_NANOS).contains(&nanos) {
+        return Err(DecodeError::new(TIMESTAMP_OUT_OF_RANGE));
+    }
+    *dst = Val::Option(Some(Box::new(Val::Record(vec![
+        (String::from("seconds"), Val::S64(seconds)),
+        (String::from("nanos"), Val::U32(nanos as u32)),
+    ]))));
+    Ok(())
+}
@@ -0,0 +1,378 @@
+//! Decoding logic for `google.protobuf.Struct`, `Value`, and `ListValue`,
+//! which become a flat list of nodes rather than a (recursive) tree.
+//!
+//! The root node comes first.
+//! List and struct nodes refer to their items by index within the same list,
+//! and every item comes after its parent.
+//! A value replaced on the wire by a duplicate struct key leaves its nodes behind, unreachable.
+
+use std::result::Result as StdResult;
+
+use prost::encoding::WireType;
+use wasmtime::component::Val;
+
+use crate::scalar::{bool_decode_inner, double_decode_inner, string_utf8_decode_inner};
+use crate::{
+    check_repeated_elements, decode_tag, read_length_check_overflow, read_varint, skip,
+    CompoundMerger, DecodeError, MapKeys, MergeFn, Merger, INVALID_DYNAMIC_VALUE, INVALID_VARINT,
+    RECURSION_LIMIT, WIRETYPE_NON_DOUBLE, WIRETYPE_NON_LENGTH_DELIMITED, WIRETYPE_NON_VARINT,
+};
+
+/// Names of the cases of a node.
+const NULL: &str = "null";
+const BOOL: &str = "bool";
+const NUMBER: &str = "number";
+const STRING: &str = "string";
+const LIST: &str = "list";
+const STRUCT: &str = "struct";
+
+impl Merger {
+    pub(crate) fn dynamic_struct() -> Self {
+        Self::dynamic(struct_merge)
+    }
+
+    pub(crate) fn dynamic_value() -> Self {
+        Self::dynamic(value_merge)
+    }
+
+    pub(crate) fn dynamic_list() -> Self {
+        Self::dynamic(list_value_merge)
+    }
+
+    fn dynamic(merge: MergeFn) -> Self {
+        Self {
+            merge,
+            defaults: Vec::new(),
+            repeated_tag: 0,
+            presence: None,
+            max_field_number: u32::MAX,
+            max_elements: u32::MAX,
+            map_keys: MapKeys::Absent,
+            compound: CompoundMerger { depth: u32::MAX },
+        }
+    }
+}
+
+/// Decode a struct message, whose root node is always a struct.
+/// Always explicitly presence-tracked.
+pub(crate) fn struct_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    let nodes = nodes(wire_type, dst, || compound_node(STRUCT))?;
+    let depth = unsafe { merger.compound.depth };
+    struct_inner(merger, depth, limit, src, nodes, 0)
+}
+
+/// Decode a value message, whose root node can be anything.
+/// Always explicitly presence-tracked.
+pub(crate) fn value_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    let nodes = nodes(wire_type, dst, null_node)?;
+    let depth = unsafe { merger.compound.depth };
+    value_inner(merger, depth, limit, src, nodes, 0)
+}
+
+/// Decode a list value message, whose root node is always a list.
+/// Always explicitly presence-tracked.
+pub(crate) fn list_value_merge(
+    merger: &Merger,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    dst: &mut Val,
+) -> StdResult<(), DecodeError> {
+    let nodes = nodes(wire_type, dst, || compound_node(LIST))?;
+    let depth = unsafe { merger.compound.depth };
+    list_inner(merger, depth, limit, src, nodes, 0)
+}
+
+/// Check the wire type of a dynamic value, then return its nodes,
+/// starting with a new `root` if the value was absent.
+fn nodes(
+    wire_type: WireType,
+    dst: &mut Val,
+    root: impl FnOnce() -> Val,
+) -> StdResult<&mut Vec<Val>, DecodeError> {
+    if wire_type != WireType::LengthDelimited {
+        return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+    }
+    if let Val::Option(None) = dst {
+        *dst = Val::Option(Some(Box::new(Val::List(vec![root()]))));
+    }
+    if let Val::Option(Some(nodes)) = dst {
+        if let Val::List(nodes) = nodes.as_mut() {
+            if !nodes.is_empty() {
+                return Ok(nodes);
+            }
+        }
+    }
+    // API violation - the value should have been produced by these merge functions.
+    Err(DecodeError::new(INVALID_DYNAMIC_VALUE))
+}
+
+/// A value without a kind is null, as in JSON.
+fn null_node() -> Val {
+    Val::Variant(String::from(NULL), None)
+}
+
+/// A list or struct node without any items yet.
+fn compound_node(case: &str) -> Val {
+    Val::Variant(String::from(case), Some(Box::new(Val::List(Vec::new()))))
+}
+
+/// Return the items of a list node, or the entries of a struct node,
+/// if the node is of the given case.
+fn node_items<'a>(nodes: &'a mut [Val], node: usize, case: &str) -> Option<&'a mut Vec<Val>> {
+    match nodes.get_mut(node) {
+        Some(Val::Variant(name, Some(payload))) if name == case => match payload.as_mut() {
+            Val::List(items) => Some(items),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Enter a message nested within a dynamic value,
+/// returning the number of levels still allowed below it.
+#[inline(always)]
+fn descend(depth: u32) -> StdResult<u32, DecodeError> {
+    depth
+        .checked_sub(1)
+        .ok_or_else(|| DecodeError::new(RECURSION_LIMIT))
+}
+
+/// Decode a `Value` message into an existing node.
+fn value_inner(
+    merger: &Merger,
+    depth: u32,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let depth = descend(depth)?;
+    let mut length = read_length_check_overflow(limit, src)?;
+    while length > 0 {
+        let (field_number, wire_type) = decode_tag(&mut length, src)?;
+        value_kind(
+            merger,
+            depth,
+            field_number,
+            wire_type,
+            &mut length,
+            src,
+            nodes,
+            node,
+        )
+        .map_err(|e| e.with_field(field_number))?;
+    }
+    Ok(())
+}
+
+/// Decode a single field of a `Value` message (one of its `kind` oneof).
+/// Like any oneof, a different kind replaces the node, while a struct or list merges into it.
+///
+/// Everything after the node is part of its subtree while it is being decoded,
+/// so replacing the node also discards those.
+#[allow(clippy::too_many_arguments)]
+fn value_kind(
+    merger: &Merger,
+    depth: u32,
+    field_number: u32,
+    wire_type: WireType,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let kind = match field_number {
+        1 | 4 if wire_type != WireType::Varint => {
+            return Err(DecodeError::new(WIRETYPE_NON_VARINT));
+        }
+        2 if wire_type != WireType::SixtyFourBit => {
+            return Err(DecodeError::new(WIRETYPE_NON_DOUBLE));
+        }
+        3 | 5 | 6 if wire_type != WireType::LengthDelimited => {
+            return Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED));
+        }
+        // `NullValue` has a single variant, but unrecognized numbers still mean null.
+        1 => {
+            read_varint(limit, src, INVALID_VARINT)?;
+            null_node()
+        }
+        2 => Val::Variant(
+            String::from(NUMBER),
+            Some(Box::new(double_decode_inner(limit, src)?)),
+        ),
+        3 => Val::Variant(
+            String::from(STRING),
+            Some(Box::new(string_utf8_decode_inner(limit, src)?)),
+        ),
+        4 => Val::Variant(
+            String::from(BOOL),
+            Some(Box::new(bool_decode_inner(limit, src)?)),
+        ),
+        5 => {
+            if node_items(nodes, node, STRUCT).is_none() {
+                replace_node(nodes, node, compound_node(STRUCT));
+            }
+            return struct_inner(merger, depth, limit, src, nodes, node);
+        }
+        6 => {
+            if node_items(nodes, node, LIST).is_none() {
+                replace_node(nodes, node, compound_node(LIST));
+            }
+            return list_inner(merger, depth, limit, src, nodes, node);
+        }
+        _ => return skip(wire_type, limit, src),
+    };
+    replace_node(nodes, node, kind);
+    Ok(())
+}
+
+/// Replace a node that is being decoded, discarding its subtree.
+fn replace_node(nodes: &mut Vec<Val>, node: usize, kind: Val) {
+    nodes.truncate(node + 1);
+    nodes[node] = kind;
+}
+
+/// Decode a `Struct` message into an existing struct node.
+fn struct_inner(
+    merger: &Merger,
+    depth: u32,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let depth = descend(depth)?;
+    let mut length = read_length_check_overflow(limit, src)?;
+    while length > 0 {
+        let (field_number, wire_type) = decode_tag(&mut length, src)?;
+        match field_number {
+            1 if wire_type != WireType::LengthDelimited => {
+                Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED))
+            }
+            1 => struct_entry(merger, depth, &mut length, src, nodes, node),
+            _ => skip(wire_type, &mut length, src),
+        }
+        .map_err(|e| e.with_field(field_number))?;
+    }
+    Ok(())
+}
+
+/// Decode a single entry of the `fields` map of a `Struct` message into a struct node.
+/// Like any map, a duplicate key replaces the earlier value.
+fn struct_entry(
+    merger: &Merger,
+    depth: u32,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let index = node_items(nodes, node, STRUCT)
+        .ok_or_else(|| DecodeError::new(INVALID_DYNAMIC_VALUE))?
+        .len();
+    let mut length = read_length_check_overflow(limit, src).map_err(|e| e.with_index(index))?;
+
+    let mut key = Val::String(String::new());
+    let mut value = None;
+    while length > 0 {
+        let (field_number, wire_type) =
+            decode_tag(&mut length, src).map_err(|e| e.with_index(index))?;
+        match field_number {
+            1 | 2 if wire_type != WireType::LengthDelimited => {
+                Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED))
+            }
+            1 => string_utf8_decode_inner(&mut length, src).map(|string| key = string),
+            2 => {
+                // A repeated value merges into the same node, like any message.
+                let child = *value.get_or_insert_with(|| {
+                    nodes.push(null_node());
+                    nodes.len() - 1
+                });
+                value_inner(merger, depth, &mut length, src, nodes, child)
+            }
+            _ => skip(wire_type, &mut length, src),
+        }
+        .map_err(|e| e.with_field(field_number).with_index(index))?;
+    }
+    let child = value.unwrap_or_else(|| {
+        nodes.push(null_node());
+        nodes.len() - 1
+    });
+
+    let entries =
+        node_items(nodes, node, STRUCT).ok_or_else(|| DecodeError::new(INVALID_DYNAMIC_VALUE))?;
+    // Structs in requests are expected to be small, like any map.
+    for entry in entries.iter_mut() {
+        if let Val::Tuple(pair) = entry {
+            if pair[0] == key {
+                pair[1] = Val::U32(child as u32);
+                return Ok(());
+            }
+        }
+    }
+    check_repeated_elements(merger, entries)?;
+    entries.push(Val::Tuple(vec![key, Val::U32(child as u32)]));
+    Ok(())
+}
+
+/// Decode a `ListValue` message into an existing list node.
+fn list_inner(
+    merger: &Merger,
+    depth: u32,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let depth = descend(depth)?;
+    let mut length = read_length_check_overflow(limit, src)?;
+    while length > 0 {
+        let (field_number, wire_type) = decode_tag(&mut length, src)?;
+        match field_number {
+            1 if wire_type != WireType::LengthDelimited => {
+                Err(DecodeError::new(WIRETYPE_NON_LENGTH_DELIMITED))
+            }
+            1 => list_item(merger, depth, &mut length, src, nodes, node),
+            _ => skip(wire_type, &mut length, src),
+        }
+        .map_err(|e| e.with_field(field_number))?;
+    }
+    Ok(())
+}
+
+/// Decode a single item of the `values` of a `ListValue` message into a list node.
+fn list_item(
+    merger: &Merger,
+    depth: u32,
+    limit: &mut u32,
+    src: &mut &[u8],
+    nodes: &mut Vec<Val>,
+    node: usize,
+) -> StdResult<(), DecodeError> {
+    let items =
+        node_items(nodes, node, LIST).ok_or_else(|| DecodeError::new(INVALID_DYNAMIC_VALUE))?;
+    check_repeated_elements(merger, items)?;
+    let index = items.len();
+
+    let child = nodes.len();
+    nodes.push(null_node());
+    value_inner(merger, depth, limit, src, nodes, child).map_err(|e| e.with_index(index))?;
+
+    node_items(nodes, node, LIST)
+        .ok_or_else(|| DecodeError::new(INVALID_DYNAMIC_VALUE))?
+        .push(Val::U32(child as u32));
+    Ok(())
+}
@@ -0,0 +1,509 @@
+//! Protobuf descriptors reconstructed from component metadata.
+//!
+//! Gateways that route on full service descriptors can fetch them from a running pod
+//! (see [`DESCRIPTOR_PATH`]) instead of needing the original `.proto` files.
+//!
+//! Metadata does not record the original message and enum names,
+//! so every type is named after the method or field that uses it:
+//! the request and response of `MethodName` are `MethodNameRequest` and `MethodNameResponse`,
+//! and a field `field-name` of message or enum type gets a nested type called `FieldName`.
+//! Field numbers, types, labels, and presence are preserved exactly,
+//! so the descriptors are wire-compatible with the originals.
+
+use std::collections::BTreeSet;
+
+use anyhow::{anyhow, Result};
+use prost_types::field_descriptor_proto::{Label, Type};
+use prost_types::{
+    DescriptorProto, EnumDescriptorProto, EnumValueDescriptorProto, FieldDescriptorProto,
+    FieldOptions, FileDescriptorProto, FileDescriptorSet, MessageOptions, MethodDescriptorProto,
+    OneofDescriptorProto, ServiceDescriptorProto,
+};
+
+use metadata_proto::work::runtime::field::{Coding, CompoundCoding};
+use metadata_proto::work::runtime::{Field, GrpcArity, GrpcService, Metadata};
+
+/// Path on every gRPC pod serving the component's [descriptors](descriptor_set),
+/// if enabled, as a binary-encoded `google.protobuf.FileDescriptorSet`.
+pub(crate) const DESCRIPTOR_PATH: &str = "/.vimana/descriptors";
+
+/// Import needed by any file with a `google.protobuf.Duration` field.
+const DURATION_FILE: &str = "google/protobuf/duration.proto";
+
+/// Fully-qualified name of the well-known duration type.
+const DURATION_TYPE: &str = ".google.protobuf.Duration";
+
+/// Import needed by any file with a `google.protobuf.Timestamp` field.
+const TIMESTAMP_FILE: &str = "google/protobuf/timestamp.proto";
+
+/// Fully-qualified name of the well-known timestamp type.
+const TIMESTAMP_TYPE: &str = ".google.protobuf.Timestamp";
+
+/// Import needed by any file with a `google.protobuf.Struct`, `Value`, or `ListValue` field.
+const STRUCT_FILE: &str = "google/protobuf/struct.proto";
+
+/// Fully-qualified names of the well-known dynamic value types.
+const STRUCT_TYPE: &str = ".google.protobuf.Struct";
+const VALUE_TYPE: &str = ".google.protobuf.Value";
+const LIST_VALUE_TYPE: &str = ".google.protobuf.ListValue";
+
+/// Protobuf types of the scalar codings, in groups of four
+/// following the order of `ScalarCoding` in the metadata.
+const SCALAR_TYPES: [Type; 16] = [
+    Type::Bytes,
+    Type::String,
+    Type::String,
+    Type::Bool,
+    Type::Int32,
+    Type::Sint32,
+    Type::Sfixed32,
+    Type::Uint32,
+    Type::Fixed32,
+    Type::Int64,
+    Type::Sint64,
+    Type::Sfixed64,
+    Type::Uint64,
+    Type::Fixed64,
+    Type::Float,
+    Type::Double,
+];
+
+/// Return one file per service of the component.
+pub(crate) fn descriptor_set(metadata: &Metadata) -> Result<FileDescriptorSet> {
+    Ok(FileDescriptorSet {
+        file: metadata
+            .service
+            .iter()
+            .map(file_descriptor)
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// Return a file containing the service and the request and response types of its methods.
+pub(crate) fn file_descriptor(service: &GrpcService) -> Result<FileDescriptorProto> {
+    let (package, service_name) = service.name.rsplit_once('.').unwrap_or(("", &service.name));
+    let scope = if package.is_empty() {
+        String::new()
+    } else {
+        format!(".{package}")
+    };
+
+    // Sort methods by name so the descriptor is deterministic.
+    let mut methods = service.methods.iter().collect::<Vec<_>>();
+    methods.sort_by_key(|(name, _)| *name);
+
+    let mut file = Types::default();
+    let mut method_descriptors = Vec::new();
+    for (method_name, method) in methods {
+        let request = method
+            .request
+            .as_ref()
+            .ok_or_else(|| anyhow!("Metadata missing request for {method_name:?}"))?;
+        let response = method
+            .response
+            .as_ref()
+            .ok_or_else(|| anyhow!("Metadata missing response for {method_name:?}"))?;
+        let input_type = format!("{method_name}Request");
+        let output_type = format!("{method_name}Response");
+        file.messages
+            .push(message(&input_type, &scope, request, &mut file.imports)?);
+        file.messages
+            .push(message(&output_type, &scope, response, &mut file.imports)?);
+
+        let arity = method.arity();
+        method_descriptors.push(MethodDescriptorProto {
+            name: Some(method_name.clone()),
+            input_type: Some(format!("{scope}.{input_type}")),
+            output_type: Some(format!("{scope}.{output_type}")),
+            client_streaming: Some(matches!(
+                arity,
+                GrpcArity::ClientStreaming | GrpcArity::BidiStreaming
+            )),
+            server_streaming: Some(matches!(
+                arity,
+                GrpcArity::ServerStreaming | GrpcArity::BidiStreaming
+            )),
+            options: None,
+        });
+    }
+
+    Ok(FileDescriptorProto {
+        name: Some(format!("{}.proto", service.name.replace('.', "/"))),
+        package: (!package.is_empty()).then(|| String::from(package)),
+        dependency: file.imports.into_iter().map(String::from).collect(),
+        message_type: file.messages,
+        service: vec![ServiceDescriptorProto {
+            name: Some(String::from(service_name)),
+            method: method_descriptors,
+            options: None,
+        }],
+        syntax: Some(String::from("proto3")),
+        ..Default::default()
+    })
+}
+
+/// Top-level types of a file under construction.
+#[derive(Default)]
+struct Types {
+    messages: Vec<DescriptorProto>,
+    /// Files defining the well-known types used by any field.
+    imports: BTreeSet<&'static str>,
+}
+
+/// Return a message type called `name`, nested in `scope`, with the subfields of `field`.
+fn message(
+    name: &str,
+    scope: &str,
+    field: &Field,
+    imports: &mut BTreeSet<&'static str>,
+) -> Result<DescriptorProto> {
+    let scope = format!("{scope}.{name}");
+    let mut descriptor = DescriptorProto {
+        name: Some(String::from(name)),
+        ..Default::default()
+    };
+    // Proto3 optional fields each need a synthetic one-of, declared after all real one-ofs.
+    let mut optional = Vec::new();
+    for subfield in field.subfields.iter() {
+        if is_oneof(subfield) {
+            let oneof_index = descriptor.oneof_decl.len() as i32;
+            descriptor.oneof_decl.push(OneofDescriptorProto {
+                name: Some(snake_case(&subfield.name)),
+                options: None,
+            });
+            for variant in subfield.subfields.iter() {
+                let mut variant = member(variant, &scope, &mut descriptor, imports)?;
+                // Members of a one-of have explicit presence by virtue of the one-of alone.
+                variant.proto3_optional = None;
+                variant.oneof_index = Some(oneof_index);
+                descriptor.field.push(variant);
+            }
+        } else {
+            let member = member(subfield, &scope, &mut descriptor, imports)?;
+            if member.proto3_optional == Some(true) {
+                optional.push(descriptor.field.len());
+            }
+            descriptor.field.push(member);
+        }
+    }
+    for index in optional {
+        let member = &mut descriptor.field[index];
+        member.oneof_index = Some(descriptor.oneof_decl.len() as i32);
+        descriptor.oneof_decl.push(OneofDescriptorProto {
+            name: Some(format!("_{}", member.name())),
+            options: None,
+        });
+    }
+    Ok(descriptor)
+}
+
+/// Return a (non-one-of) field of the message `parent`, declared in `scope`,
+/// adding any nested message or enum type it needs to `parent`.
+fn member(
+    field: &Field,
+    scope: &str,
+    parent: &mut DescriptorProto,
+    imports: &mut BTreeSet<&'static str>,
+) -> Result<FieldDescriptorProto> {
+    let mut descriptor = FieldDescriptorProto {
+        name: Some(snake_case(&field.name)),
+        number: Some(field.number as i32),
+        ..Default::default()
+    };
+    // Both coding enumerations cycle through [implicit, packed, explicit, expanded]
+    // for every type that supports all four.
+    let presence = match field.coding {
+        Some(Coding::ScalarCoding(scalar_coding)) => {
+            let scalar_type = SCALAR_TYPES
+                .get(scalar_coding as usize / 4)
+                .ok_or_else(|| anyhow!("Unrecognized ScalarCoding {scalar_coding}"))?;
+            descriptor.r#type = Some(*scalar_type as i32);
+            scalar_coding % 4
+        }
+        Some(Coding::CompoundCoding(compound_coding)) => {
+            let type_name = pascal_case(&field.name);
+            let presence = match known_compound_coding(compound_coding)? {
+                CompoundCoding::EnumImplicit
+                | CompoundCoding::EnumPacked
+                | CompoundCoding::EnumExplicit
+                | CompoundCoding::EnumExpanded => {
+                    descriptor.r#type = Some(Type::Enum as i32);
+                    parent.enum_type.push(enumeration(&type_name, field));
+                    compound_coding % 4
+                }
+                // Message fields always track presence without being `optional`.
+                CompoundCoding::Message => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    parent
+                        .nested_type
+                        .push(message(&type_name, scope, field, imports)?);
+                    0
+                }
+                CompoundCoding::MessageExpanded => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    parent
+                        .nested_type
+                        .push(message(&type_name, scope, field, imports)?);
+                    3
+                }
+                CompoundCoding::Duration => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(String::from(DURATION_TYPE));
+                    imports.insert(DURATION_FILE);
+                    0
+                }
+                CompoundCoding::Timestamp => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(String::from(TIMESTAMP_TYPE));
+                    imports.insert(TIMESTAMP_FILE);
+                    0
+                }
+                CompoundCoding::Struct => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(String::from(STRUCT_TYPE));
+                    imports.insert(STRUCT_FILE);
+                    0
+                }
+                CompoundCoding::Value => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(String::from(VALUE_TYPE));
+                    imports.insert(STRUCT_FILE);
+                    0
+                }
+                CompoundCoding::ListValue => {
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(String::from(LIST_VALUE_TYPE));
+                    imports.insert(STRUCT_FILE);
+                    0
+                }
+                // Maps are repeated entry messages named like `FieldNameEntry`.
+                CompoundCoding::Map => {
+                    let entry_name = format!("{type_name}Entry");
+                    let mut entry = message(&entry_name, scope, field, imports)?;
+                    entry.options = Some(MessageOptions {
+                        map_entry: Some(true),
+                        ..Default::default()
+                    });
+                    parent.nested_type.push(entry);
+                    descriptor.r#type = Some(Type::Message as i32);
+                    descriptor.type_name = Some(format!("{scope}.{entry_name}"));
+                    1
+                }
+                CompoundCoding::Oneof => {
+                    return Err(anyhow!("Nested one-of {:?}", field.name));
+                }
+            };
+            if descriptor.type_name.is_none() {
+                descriptor.type_name = Some(format!("{scope}.{type_name}"));
+            }
+            presence
+        }
+        None => return Err(anyhow!("Field {:?} has no coding", field.name)),
+    };
+    descriptor.label = Some(match presence {
+        0 => Label::Optional,
+        1 => Label::Repeated,
+        2 => {
+            descriptor.proto3_optional = Some(true);
+            Label::Optional
+        }
+        _ => {
+            descriptor.options = Some(FieldOptions {
+                packed: Some(false),
+                ..Default::default()
+            });
+            Label::Repeated
+        }
+    } as i32);
+    Ok(descriptor)
+}
+
+/// Return an enum type called `name` whose values are the subfields of `field`.
+fn enumeration(name: &str, field: &Field) -> EnumDescriptorProto {
+    EnumDescriptorProto {
+        name: Some(String::from(name)),
+        value: field
+            .subfields
+            .iter()
+            .map(|variant| EnumValueDescriptorProto {
+                name: Some(snake_case(&variant.name).to_uppercase()),
+                number: Some(variant.number as i32),
+                options: None,
+            })
+            .collect(),
+        ..Default::default()
+    }
+}
+
+/// Return whether the field is a one-of.
+fn is_oneof(field: &Field) -> bool {
+    field.coding == Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32))
+}
+
+/// Convert a raw compound coding number from metadata into a known [`CompoundCoding`].
+fn known_compound_coding(compound_coding: i32) -> Result<CompoundCoding> {
+    CompoundCoding::try_from(compound_coding)
+        .map_err(|_| anyhow!("Unrecognized CompoundCoding {compound_coding}"))
+}
+
+/// Convert a WIT name (`field-name`) to a Protobuf field name (`field_name`).
+fn snake_case(name: &str) -> String {
+    name.replace('-', "_")
+}
+
+/// Convert a WIT name (`field-name`) to a Protobuf type name (`FieldName`).
+fn pascal_case(name: &str) -> String {
+    name.split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars.next().map_or(String::new(), |first| {
+                first.to_uppercase().chain(chars).collect()
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use metadata_proto::work::runtime::field::{Charset, ScalarCoding};
+    use metadata_proto::work::runtime::GrpcMethod;
+
+    use super::*;
+
+    fn field(number: u32, name: &str, coding: Coding, subfields: Vec<Field>) -> Field {
+        Field {
+            number,
+            name: String::from(name),
+            coding: Some(coding),
+            subfields,
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    }
+
+    fn scalar(number: u32, name: &str, coding: ScalarCoding) -> Field {
+        field(
+            number,
+            name,
+            Coding::ScalarCoding(coding as i32),
+            Vec::new(),
+        )
+    }
+
+    fn compound(number: u32, name: &str, coding: CompoundCoding, subfields: Vec<Field>) -> Field {
+        field(
+            number,
+            name,
+            Coding::CompoundCoding(coding as i32),
+            subfields,
+        )
+    }
+
+    #[test]
+    fn test_descriptor_matches_methods() {
+        let request = compound(
+            0,
+            "",
+            CompoundCoding::Message,
+            vec![
+                scalar(1, "user-id", ScalarCoding::StringUtf8Implicit),
+                scalar(2, "limit", ScalarCoding::Uint32Explicit),
+                compound(
+                    3,
+                    "order",
+                    CompoundCoding::EnumImplicit,
+                    vec![
+                        scalar(0, "oldest-first", ScalarCoding::BytesImplicit),
+                        scalar(1, "newest-first", ScalarCoding::BytesImplicit),
+                    ],
+                ),
+                compound(4, "timeout", CompoundCoding::Duration, Vec::new()),
+            ],
+        );
+        let response = compound(
+            0,
+            "",
+            CompoundCoding::Message,
+            vec![compound(
+                1,
+                "post",
+                CompoundCoding::MessageExpanded,
+                vec![scalar(1, "text", ScalarCoding::StringUtf8Implicit)],
+            )],
+        );
+        let service = GrpcService {
+            name: String::from("social.Feed"),
+            methods: HashMap::from([
+                (
+                    String::from("Watch"),
+                    GrpcMethod {
+                        arity: GrpcArity::ServerStreaming as i32,
+                        request: Some(request.clone()),
+                        response: Some(response.clone()),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    String::from("List"),
+                    GrpcMethod {
+                        request: Some(request),
+                        response: Some(response),
+                        ..Default::default()
+                    },
+                ),
+            ]),
+        };
+
+        let file = file_descriptor(&service).unwrap();
+        assert_eq!(file.package(), "social");
+        assert_eq!(file.dependency, vec![DURATION_FILE]);
+
+        let methods = &file.service[0].method;
+        assert_eq!(file.service[0].name(), "Feed");
+        assert_eq!(
+            methods
+                .iter()
+                .map(|method| (
+                    method.name(),
+                    method.input_type(),
+                    method.output_type(),
+                    method.server_streaming()
+                ))
+                .collect::<Vec<_>>(),
+            vec![
+                ("List", ".social.ListRequest", ".social.ListResponse", false),
+                (
+                    "Watch",
+                    ".social.WatchRequest",
+                    ".social.WatchResponse",
+                    true
+                ),
+            ],
+        );
+
+        let list_request = &file.message_type[0];
+        assert_eq!(list_request.name(), "ListRequest");
+        let fields = &list_request.field;
+        assert_eq!(
+            fields.iter().map(|field| field.name()).collect::<Vec<_>>(),
+            vec!["user_id", "limit", "order", "timeout"],
+        );
+        assert_eq!(fields[1].r#type(), Type::Uint32);
+        assert!(fields[1].proto3_optional());
+        assert_eq!(fields[1].oneof_index, Some(0));
+        assert_eq!(list_request.oneof_decl[0].name(), "_limit");
+        assert_eq!(fields[2].type_name(), ".social.ListRequest.Order");
+        assert_eq!(list_request.enum_type[0].value[1].name(), "NEWEST_FIRST");
+        assert_eq!(fields[3].type_name(), DURATION_TYPE);
+
+        let list_response = &file.message_type[1];
+        assert_eq!(list_response.field[0].label(), Label::Repeated);
+        assert_eq!(
+            list_response.field[0].type_name(),
+            ".social.ListResponse.Post"
+        );
+        assert_eq!(list_response.nested_type[0].field[0].name(), "text");
+    }
+}
@@ -10,10 +10,10 @@ use tonic::codec::EncodeBuf;
 use wasmtime::component::Val;
 
 use crate::{
-    explicit_scalar, tag, CompoundEncoder, EncodeError, Encoder, ENUM_NON_ENUM,
-    ENUM_VARIANT_UNRECOGNIZED, LENGTH_INCONSISTENCY, MESSAGE_NON_OPTIONAL, MESSAGE_NON_RECORD,
-    NO_ENCODER_FOR_FIELD, ONEOF_NON_OPTIONAL, ONEOF_NON_VARIANT, ONEOF_VARIANT_NO_PAYLOAD,
-    ONEOF_VARIANT_UNRECOGNIZED, REPEATED_NON_LIST,
+    explicit_scalar, packed_scalar, tag, CompoundEncoder, EncodeError, Encoder, ENUM_NON_ENUM,
+    ENUM_VARIANT_UNRECOGNIZED, EXPLICIT_NON_OPTION, LENGTH_INCONSISTENCY, MESSAGE_NON_OPTIONAL,
+    MESSAGE_NON_RECORD, NO_ENCODER_FOR_FIELD, ONEOF_NON_OPTIONAL, ONEOF_NON_VARIANT,
+    ONEOF_VARIANT_NO_PAYLOAD, ONEOF_VARIANT_UNRECOGNIZED, REPEATED_NON_LIST,
 };
 use metadata_proto::work::runtime::field::{Coding, CompoundCoding, ScalarCoding};
 use metadata_proto::work::runtime::Field;
@@ -147,10 +147,12 @@ fn compile_compound(
                 )
             }
             Coding::CompoundCoding(compound_coding) => {
-                // There are only two compound types allowed in a oneof.
+                // There are only four compound types allowed in a oneof.
                 if is_oneof
                     && compound_coding != (CompoundCoding::Message as i32)
                     && compound_coding != (CompoundCoding::EnumExplicit as i32)
+                    && compound_coding != (CompoundCoding::Duration as i32)
+                    && compound_coding != (CompoundCoding::Timestamp as i32)
                 {
                     return Err(anyhow!(
                         "Variant #{} must use explicit compound coding: {:?}",
@@ -178,6 +180,20 @@ fn compile_compound(
                             format!("Invalid repeated message for field #{}", subfield.number)
                         })?
                     }
+                    CompoundCoding::Duration => Encoder::duration(subfield),
+                    CompoundCoding::Timestamp => Encoder::timestamp(subfield),
+                    CompoundCoding::Map => {
+                        return Err(anyhow!(
+                            "Map field #{} is not supported in responses",
+                            subfield.number,
+                        ));
+                    }
+                    CompoundCoding::Struct | CompoundCoding::Value | CompoundCoding::ListValue => {
+                        return Err(anyhow!(
+                            "Dynamic value field #{} is not supported in responses",
+                            subfield.number,
+                        ));
+                    }
                     CompoundCoding::Oneof => {
                         Encoder::oneof(subfield, component).context("Invalid oneof")?
                     }
@@ -191,6 +207,44 @@ fn compile_compound(
     Ok(ManuallyDrop::new(subfields))
 }
 
+/// Estimate how many lengths the [length pre-pass](crate::LengthFn) pushes for a message,
+/// assuming every submessage is present and every repeated field holds a single element.
+pub(crate) fn lengths_hint(message: &Field) -> usize {
+    message.subfields.iter().fold(0, |hint, subfield| {
+        hint.saturating_add(field_lengths_hint(subfield))
+    })
+}
+
+/// See [`lengths_hint`]. Only one variant of a oneof can be present at a time.
+fn field_lengths_hint(field: &Field) -> usize {
+    match field.coding {
+        Some(Coding::ScalarCoding(scalar_coding)) if packed_scalar(scalar_coding) => 1,
+        Some(Coding::CompoundCoding(compound_coding)) => {
+            match CompoundCoding::try_from(compound_coding) {
+                Ok(CompoundCoding::Message | CompoundCoding::MessageExpanded) => {
+                    lengths_hint(field).saturating_add(1)
+                }
+                Ok(CompoundCoding::EnumPacked) => 1,
+                Ok(CompoundCoding::Oneof) => field
+                    .subfields
+                    .iter()
+                    .map(field_lengths_hint)
+                    .max()
+                    .unwrap_or(0),
+                _ => 0,
+            }
+        }
+        _ => 0,
+    }
+}
+
+/// Enum numbers are 32-bit signed integers, sign-extended to 64 bits on the wire,
+/// but variant numbers are declared unsigned (so negative ones are stored in two's complement).
+#[inline(always)]
+fn enum_number(number: u32) -> u64 {
+    number as i32 as u64
+}
+
 /// Initialization logic for enumerations.
 fn compile_enum_variants(enumeration: &Field) -> ManuallyDrop<HashMap<String, u32>> {
     let mut variants = HashMap::with_capacity(enumeration.subfields.len());
@@ -346,9 +400,12 @@ fn message_repeated_length(
 ) -> StdResult<u32, EncodeError> {
     if let Val::List(items) = value {
         let mut total = 0;
-        for (index, value) in items.iter().enumerate() {
+        // Iterate over the items in reverse, pushing each one's length after its sublengths,
+        // so they are popped in the opposite order during encoding.
+        for (index, value) in items.iter().enumerate().rev() {
             let sublength =
                 message_inner_length(encoder, value, lengths).map_err(|e| e.with_index(index))?;
+            lengths.push(sublength);
             total = u32::saturating_add(
                 total,
                 u32::saturating_add(
@@ -464,10 +521,17 @@ pub(crate) fn enum_explicit_encode(
     _lengths: &mut Vec<u32>,
     buf: &mut EncodeBuf<'_>,
 ) -> StdResult<(), EncodeError> {
-    if let Val::Enum(name) = value {
+    let Val::Option(value) = value else {
+        return Err(EncodeError::new(EXPLICIT_NON_OPTION));
+    };
+    let Some(value) = value else {
+        // Do nothing if absent.
+        return Ok(());
+    };
+    if let Val::Enum(name) = value.as_ref() {
         if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
             encode_varint(encoder.tag, buf);
-            encode_varint(*number as u64, buf);
+            encode_varint(enum_number(*number), buf);
             Ok(())
         } else {
             // Got an unexpected enum variant name.
@@ -484,10 +548,17 @@ fn enum_explicit_length(
     value: &Val,
     _lengths: &mut Vec<u32>,
 ) -> StdResult<u32, EncodeError> {
-    if let Val::Enum(name) = value {
+    let Val::Option(value) = value else {
+        return Err(EncodeError::new(EXPLICIT_NON_OPTION));
+    };
+    let Some(value) = value else {
+        // Do nothing if absent.
+        return Ok(0);
+    };
+    if let Val::Enum(name) = value.as_ref() {
         // Look up the enum variant number by name.
         if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
-            Ok((encoded_len_varint(encoder.tag) + encoded_len_varint(*number as u64)) as u32)
+            Ok((encoded_len_varint(encoder.tag) + encoded_len_varint(enum_number(*number))) as u32)
         } else {
             // Got an unexpected enum variant name.
             Err(EncodeError::new(ENUM_VARIANT_UNRECOGNIZED))
@@ -508,7 +579,7 @@ pub(crate) fn enum_implicit_encode(
         if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
             if *number != 0 {
                 encode_varint(encoder.tag, buf);
-                encode_varint(*number as u64, buf);
+                encode_varint(enum_number(*number), buf);
             }
             Ok(())
         } else {
@@ -529,7 +600,7 @@ fn enum_implicit_length(
     if let Val::Enum(name) = value {
         if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
             Ok(if *number != 0 {
-                (encoded_len_varint(encoder.tag) + encoded_len_varint(*number as u64)) as u32
+                (encoded_len_varint(encoder.tag) + encoded_len_varint(enum_number(*number))) as u32
             } else {
                 0
             })
@@ -557,7 +628,7 @@ pub(crate) fn enum_packed_encode(
                 for (index, value) in items.iter().enumerate() {
                     if let Val::Enum(name) = value {
                         if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
-                            encode_varint(*number as u64, buf);
+                            encode_varint(enum_number(*number), buf);
                         } else {
                             return Err(
                                 EncodeError::new(ENUM_VARIANT_UNRECOGNIZED).with_index(index)
@@ -588,7 +659,7 @@ fn enum_packed_length(
             for (index, value) in items.iter().enumerate() {
                 if let Val::Enum(name) = value {
                     if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
-                        total += encoded_len_varint(*number as u64) as u32;
+                        total += encoded_len_varint(enum_number(*number)) as u32;
                     } else {
                         return Err(EncodeError::new(ENUM_VARIANT_UNRECOGNIZED).with_index(index));
                     }
@@ -619,7 +690,7 @@ pub(crate) fn enum_expanded_encode(
             if let Val::Enum(name) = value {
                 if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
                     encode_varint(encoder.tag, buf);
-                    encode_varint(*number as u64, buf);
+                    encode_varint(enum_number(*number), buf);
                 } else {
                     return Err(EncodeError::new(ENUM_VARIANT_UNRECOGNIZED).with_index(index));
                 }
@@ -646,7 +717,7 @@ fn enum_expanded_length(
                 if let Some(number) = unsafe { &encoder.compound.variants }.get(name) {
                     total = u32::saturating_add(
                         total,
-                        tag_length + encoded_len_varint(*number as u64) as u32,
+                        tag_length + encoded_len_varint(enum_number(*number)) as u32,
                     );
                 } else {
                     return Err(EncodeError::new(ENUM_VARIANT_UNRECOGNIZED).with_index(index));
@@ -0,0 +1,111 @@
+//! Encoding logic for `google.protobuf.Duration`,
+//! which is represented as a signed number of nanoseconds rather than a record.
+
+use std::result::Result as StdResult;
+
+use prost::encoding::{encode_varint, encoded_len_varint, WireType};
+use tonic::codec::EncodeBuf;
+use wasmtime::component::Val;
+
+use crate::{tag, CompoundEncoder, EncodeError, Encoder, DURATION_NON_S64, EXPLICIT_NON_OPTION};
+use metadata_proto::work::runtime::Field;
+
+const NANOS_PER_SECOND: i64 = 1_000_000_000;
+
+/// Tag of the `seconds` field: `(1 << 3) + 0`.
+const SECONDS_TAG: u64 = 8;
+
+/// Tag of the `nanos` field: `(2 << 3) + 0`.
+const NANOS_TAG: u64 = 16;
+
+impl Encoder {
+    pub(crate) fn duration(duration: &Field) -> Self {
+        Self {
+            encode: duration_encode,
+            length: duration_length,
+            tag: tag(duration.number, WireType::LengthDelimited),
+            compound: CompoundEncoder { scalar: () },
+        }
+    }
+}
+
+/// Split a number of nanoseconds into `seconds` and `nanos`.
+/// Division truncates toward zero, so both always have the same sign, as Protobuf requires.
+#[inline(always)]
+fn split(total: i64) -> (i64, i64) {
+    (total / NANOS_PER_SECOND, total % NANOS_PER_SECOND)
+}
+
+/// Length of the contents of a duration message (excluding its own tag and length).
+/// Zero-valued fields are omitted.
+#[inline(always)]
+fn content_length(seconds: i64, nanos: i64) -> u32 {
+    let mut length = 0;
+    if seconds != 0 {
+        length += 1 + encoded_len_varint(seconds as u64);
+    }
+    if nanos != 0 {
+        // `int32` values are sign-extended to 64 bits on the wire.
+        length += 1 + encoded_len_varint(nanos as u64);
+    }
+    length as u32
+}
+
+/// Encode a duration message. Always explicitly presence-tracked.
+///
+/// The content length is cheap to recompute, so nothing is queued in `lengths`.
+pub(crate) fn duration_encode(
+    encoder: &Encoder,
+    value: &Val,
+    _lengths: &mut Vec<u32>,
+    buf: &mut EncodeBuf<'_>,
+) -> StdResult<(), EncodeError> {
+    if let Val::Option(option) = value {
+        if let Some(value) = option {
+            if let Val::S64(total) = value.as_ref() {
+                let (seconds, nanos) = split(*total);
+                encode_varint(encoder.tag, buf);
+                encode_varint(content_length(seconds, nanos) as u64, buf);
+                if seconds != 0 {
+                    encode_varint(SECONDS_TAG, buf);
+                    encode_varint(seconds as u64, buf);
+                }
+                if nanos != 0 {
+                    encode_varint(NANOS_TAG, buf);
+                    encode_varint(nanos as u64, buf);
+                }
+                Ok(())
+            } else {
+                Err(EncodeError::new(DURATION_NON_S64))
+            }
+        } else {
+            // Absent durations are ignored.
+            Ok(())
+        }
+    } else {
+        Err(EncodeError::new(EXPLICIT_NON_OPTION))
+    }
+}
+
+fn duration_length(
+    encoder: &Encoder,
+    value: &Val,
+    _lengths: &mut Vec<u32>,
+) -> StdResult<u32, EncodeError> {
+    if let Val::Option(option) = value {
+        Ok(if let Some(value) = option {
+            if let Val::S64(total) = value.as_ref() {
+                let (seconds, nanos) = split(*total);
+                let length = content_length(seconds, nanos);
+                length
+                    + (encoded_len_varint(encoder.tag) + encoded_len_varint(length as u64)) as u32
+            } else {
+                return Err(EncodeError::new(DURATION_NON_S64));
+            }
+        } else {
+            0 // Absent durations are ignored.
+        })
+    } else {
+        Err(EncodeError::new(EXPLICIT_NON_OPTION))
+    }
+}
@@ -1,7 +1,9 @@
 #![feature(box_as_ptr)]
 
 mod compound;
+mod duration;
 mod scalar;
+mod timestamp;
 
 use std::collections::HashMap;
 use std::fmt::{Debug, Display, Formatter, Result as FmtResult, Write};
@@ -12,6 +14,7 @@ use std::sync::Arc;
 
 use anyhow::{Context, Result};
 use metadata_proto::work::runtime::Field;
+use prost::bytes::BufMut;
 use prost::encoding::WireType;
 use tonic::codec::{EncodeBuf, Encoder as TonicEncoder};
 use tonic::Status;
@@ -19,6 +22,10 @@ use wasmtime::component::Val;
 
 use names::ComponentName;
 
+/// Upper limit on the [lengths hint](ResponseEncoder::lengths_hint),
+/// so pathologically large schemas don't pre-allocate excessively.
+const MAX_LENGTHS_HINT: usize = 256;
+
 /// Encodes a top-level response message (*without* tag or length).
 ///
 /// Reference-counted because Tonic's [codec](tonic::codec::Codec)
@@ -33,6 +40,9 @@ struct ResponseEncoderInner {
 
     /// Component name used for error logging only, shared to save memory.
     component: Arc<ComponentName>,
+
+    /// Initial capacity of the [lengths](LengthFn) queue for each response.
+    lengths_hint: usize,
 }
 
 /// An instance of an encoder is essentially hard-wired
@@ -109,8 +119,24 @@ impl ResponseEncoder {
             inner: Encoder::message_inner(response, component.as_ref())
                 .context("Invalid response encoder")?,
             component: component,
+            lengths_hint: compound::lengths_hint(response).min(MAX_LENGTHS_HINT),
         })))
     }
+
+    /// Number of lengths pre-allocated for each encoded response,
+    /// so typical responses never reallocate during the length pre-pass.
+    pub fn lengths_hint(&self) -> usize {
+        self.0.lengths_hint
+    }
+
+    /// Return the number of bytes that [encoding](TonicEncoder::encode) the value would produce,
+    /// without actually encoding it.
+    pub fn encoded_len(&self, value: &Val) -> StdResult<usize, Box<Status>> {
+        let mut lengths = Vec::with_capacity(self.0.lengths_hint);
+        (self.0.inner.length)(&self.0.inner, value, &mut lengths)
+            .map(|length| length as usize)
+            .map_err(|error| Box::new(Status::internal(error.to_string())))
+    }
 }
 
 impl TonicEncoder for ResponseEncoder {
@@ -119,10 +145,15 @@ impl TonicEncoder for ResponseEncoder {
 
     /// Encode a message to a writable buffer.
     fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> Result<(), Self::Error> {
-        // TODO: Pre-allocate some space for lengths?
-        let mut lengths = Vec::new();
+        let mut lengths = Vec::with_capacity(self.0.lengths_hint);
         let result = (self.0.inner.length)(&self.0.inner, &item, &mut lengths)
-            .and_then(|_length| (self.0.inner.encode)(&self.0.inner, &item, &mut lengths, dst))
+            .and_then(|length| {
+                // Writing past the end of the buffer would panic,
+                // so check for room up front and for the exact length afterwards.
+                let remaining = check_capacity(length, dst)?;
+                (self.0.inner.encode)(&self.0.inner, &item, &mut lengths, dst)?;
+                check_written(length, remaining, dst)
+            })
             .map_err(|error| {
                 // An encoding error indicates that the Wasm component returned an invalid value.
                 // Report this as an INTERNAL status to the caller and log it,
@@ -131,7 +162,9 @@ impl TonicEncoder for ResponseEncoder {
                 Status::internal(error.to_string())
             });
         // In tests, make sure we used all the pre-computed lengths as expected.
-        debug_assert!(lengths.is_empty());
+        // A failed encoding may leave some behind, but they are dropped with the queue,
+        // so nothing carries over to the next item of a streaming response.
+        debug_assert!(result.is_err() || lengths.is_empty());
         result
     }
 }
@@ -185,6 +218,28 @@ impl EncodeError {
     }
 }
 
+/// Make sure the buffer has room for the pre-computed `length` of a response
+/// before writing any of it.
+/// Returns the remaining capacity beforehand, for [`check_written`].
+#[inline(always)]
+fn check_capacity(length: u32, buf: &impl BufMut) -> StdResult<usize, EncodeError> {
+    let remaining = buf.remaining_mut();
+    if remaining < length as usize {
+        return Err(EncodeError::new(BUFFER_TOO_SMALL));
+    }
+    Ok(remaining)
+}
+
+/// Make sure exactly the pre-computed `length` was written to the buffer,
+/// given its capacity `before` writing.
+#[inline(always)]
+fn check_written(length: u32, before: usize, buf: &impl BufMut) -> StdResult<(), EncodeError> {
+    if before - buf.remaining_mut() != length as usize {
+        return Err(EncodeError::new(LENGTH_INCONSISTENCY));
+    }
+    Ok(())
+}
+
 /// Given a Protobuf field number and wire type,
 /// return the Protobuf field tag.
 #[inline(always)]
@@ -199,6 +254,13 @@ fn explicit_scalar(scalar_coding: i32) -> bool {
     scalar_coding % 4 == 2
 }
 
+/// Return whether the given `ScalarCoding` uses packed repetition.
+#[inline(always)]
+fn packed_scalar(scalar_coding: i32) -> bool {
+    // Packed scalar coding numbers all happen to equal `4n+1` for some `n`.
+    scalar_coding % 4 == 1
+}
+
 /// When returning an error status to a client,
 /// an encoding error should be displayed like this:
 ///     Response serialization error
@@ -265,6 +327,8 @@ const UINT64_NON_UINT64: &str = "Uint64 field is not U64";
 const FIXED64_NON_FIXED64: &str = "Fixed64 field is not U64";
 const FLOAT_NON_FLOAT: &str = "Float field is not Float32";
 const DOUBLE_NON_DOUBLE: &str = "Double field is not Float64";
+const DURATION_NON_S64: &str = "Duration field is not S64";
+const TIMESTAMP_NON_RECORD: &str = "Timestamp field is not a record of S64 and U32";
 const ENUM_NON_ENUM: &str = "Enum field is not an enumeration";
 const ENUM_VARIANT_UNRECOGNIZED: &str = "Unrecognized enum variant";
 const ONEOF_NON_OPTIONAL: &str = "Oneof field is not optional";
@@ -275,3 +339,33 @@ const ONEOF_VARIANT_NO_PAYLOAD: &str = "Oneof variant lacks a payload";
 // This would indicate a fundamental issue with the algorithm
 // that pre-computes the lengths of length-delimited fields for the encoder.
 const LENGTH_INCONSISTENCY: &str = "Length pre-computation algorithm error";
+// The output buffer cannot fit the pre-computed length of the response.
+const BUFFER_TOO_SMALL: &str = "Insufficient buffer capacity";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buffer_too_small() {
+        let mut storage = [0u8; 4];
+        let mut buf = &mut storage[..];
+
+        // A buffer too small for the pre-computed length is rejected before anything is written.
+        let error = check_capacity(5, &buf).unwrap_err();
+        assert_eq!(
+            format!("{error:?}"),
+            "EncodeError(): Insufficient buffer capacity"
+        );
+
+        // If the encoders disagree with the pre-computed length, that's an error too.
+        let before = check_capacity(3, &buf).unwrap();
+        buf.put_slice(&[1, 2]);
+        let error = check_written(3, before, &buf).unwrap_err();
+        assert_eq!(
+            format!("{error:?}"),
+            "EncodeError(): Length pre-computation algorithm error",
+        );
+        assert!(check_written(2, before, &buf).is_ok());
+    }
+}
@@ -0,0 +1,373 @@
+//! Cross-checks that the encoder and decoder agree on the semantics of every scalar coding.
+//! Both sides dispatch on `ScalarCoding` independently,
+//! so a coding handled one way on encode and another on decode
+//! would otherwise silently corrupt values that pass through both.
+//!
+//! Besides a fixed sample of each coding, arbitrary values generated from a schema
+//! covering every coding (plus enums, nested and repeated messages, and a oneof)
+//! must survive the round trip unchanged.
+
+use std::sync::Arc;
+
+use bytes::BytesMut;
+use tonic::codec::{Decoder as _, Encoder as _};
+use wasmtime::component::Val;
+
+use decode::{DecoderOptions, RequestDecoder};
+use encode::ResponseEncoder;
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::Field;
+use names::Name;
+use testing::{decode_buf, encode_buf};
+
+const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server-id@1.2.3";
+
+/// Number of arbitrary values to round-trip.
+const ITERATIONS: usize = 1_000;
+
+/// A zero value and a non-zero value of the element type of each group of four scalar codings.
+fn samples(scalar_coding: i32) -> (Val, Val) {
+    match scalar_coding / 4 {
+        0 => (
+            Val::List(Vec::new()),
+            Val::List(vec![Val::U8(7), Val::U8(0)]),
+        ),
+        1 | 2 => (Val::String(String::new()), Val::String(String::from("hi"))),
+        3 => (Val::Bool(false), Val::Bool(true)),
+        4..=6 => (Val::S32(0), Val::S32(-3)),
+        7 | 8 => (Val::U32(0), Val::U32(3)),
+        9..=11 => (Val::S64(0), Val::S64(-3_000_000_000)),
+        12 | 13 => (Val::U64(0), Val::U64(3_000_000_000)),
+        14 => (Val::Float32(0.0), Val::Float32(-1.5)),
+        15 => (Val::Float64(0.0), Val::Float64(-1.5)),
+        _ => unreachable!("Unknown ScalarCoding {scalar_coding}"),
+    }
+}
+
+/// Encode a value as a single-field message, then decode it again.
+fn round_trip(scalar_coding: i32, value: Val) -> Val {
+    let message = message(vec![field(
+        "a",
+        1,
+        Coding::ScalarCoding(scalar_coding),
+        Vec::new(),
+    )]);
+    let Val::Record(mut fields) =
+        round_trip_message(&message, Val::Record(vec![(String::from("a"), value)]))
+    else {
+        panic!("Decoded message is not a record");
+    };
+    fields.pop().unwrap().1
+}
+
+/// Encode a whole message, then decode it again.
+fn round_trip_message(message: &Field, value: Val) -> Val {
+    let component = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+    let mut encoder = ResponseEncoder::new(message, component.clone()).unwrap();
+    // Strict enum numbers catch negative numbers that are not sign-extended on the wire,
+    // which more lenient decoders would silently truncate back to the right number.
+    let options = DecoderOptions {
+        strict_enum_numbers: true,
+        ..DecoderOptions::default()
+    };
+    let mut decoder = RequestDecoder::with_options(message, component, options).unwrap();
+
+    let mut buffer = BytesMut::new();
+    let mut encode_buffer = encode_buf(&mut buffer);
+    encoder.encode(value, &mut encode_buffer).unwrap();
+
+    let length = buffer.len();
+    let mut decode_buffer = decode_buf(&mut buffer, length);
+    decoder.decode(&mut decode_buffer).unwrap().unwrap()
+}
+
+fn field(name: &str, number: u32, coding: Coding, subfields: Vec<Field>) -> Field {
+    Field {
+        name: String::from(name),
+        number,
+        coding: Some(coding),
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+/// A top-level message (or oneof) with the given subfields.
+fn message(subfields: Vec<Field>) -> Field {
+    Field {
+        number: 0,       // Ignored.
+        name: "".into(), // Ignored.
+        coding: None,    // Ignored.
+        subfields,
+        charset: Charset::Unrestricted as i32,
+        ..Default::default()
+    }
+}
+
+/// One field per scalar coding, numbered after the coding.
+fn scalar_fields() -> Vec<Field> {
+    (0..64)
+        .filter_map(|number| ScalarCoding::try_from(number).ok())
+        .map(|coding| {
+            let number = coding as i32;
+            field(
+                &format!("s{number}"),
+                number as u32 + 1,
+                Coding::ScalarCoding(number),
+                Vec::new(),
+            )
+        })
+        .collect()
+}
+
+/// A schema with every scalar coding, every enum coding,
+/// nested and repeated messages, and a oneof.
+fn schema() -> Field {
+    let variants = || {
+        // Negative variant numbers are stored in two's complement.
+        [
+            ("zero", 0),
+            ("one", 1),
+            ("big", 300),
+            ("negative", -2i32 as u32),
+        ]
+        .into_iter()
+        .map(|(name, number)| Field {
+            name: String::from(name),
+            number,
+            coding: None, // Ignored.
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>()
+    };
+    let compound = |coding: CompoundCoding| Coding::CompoundCoding(coding as i32);
+
+    let mut subfields = scalar_fields();
+    subfields.push(field(
+        "nested",
+        100,
+        compound(CompoundCoding::Message),
+        scalar_fields(),
+    ));
+    subfields.push(field(
+        "repeated",
+        101,
+        compound(CompoundCoding::MessageExpanded),
+        scalar_fields(),
+    ));
+    for (index, coding) in [
+        CompoundCoding::EnumImplicit,
+        CompoundCoding::EnumPacked,
+        CompoundCoding::EnumExplicit,
+        CompoundCoding::EnumExpanded,
+    ]
+    .into_iter()
+    .enumerate()
+    {
+        subfields.push(field(
+            &format!("e{index}"),
+            102 + index as u32,
+            compound(coding),
+            variants(),
+        ));
+    }
+    subfields.push(field(
+        "choice",
+        0, // Ignored.
+        compound(CompoundCoding::Oneof),
+        vec![
+            field(
+                "number",
+                106,
+                Coding::ScalarCoding(ScalarCoding::Sint64Explicit as i32),
+                Vec::new(),
+            ),
+            field(
+                "text",
+                107,
+                Coding::ScalarCoding(ScalarCoding::StringUtf8Explicit as i32),
+                Vec::new(),
+            ),
+            field(
+                "variant",
+                108,
+                compound(CompoundCoding::EnumExplicit),
+                variants(),
+            ),
+            field(
+                "inner",
+                109,
+                compound(CompoundCoding::Message),
+                scalar_fields(),
+            ),
+        ],
+    ));
+    message(subfields)
+}
+
+/// Minimal deterministic pseudo-random number generator (xorshift64).
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A random number in `0..bound` (which must be non-zero).
+    fn below(&mut self, bound: usize) -> usize {
+        (self.next() % bound as u64) as usize
+    }
+
+    /// Random bits biased towards the edge cases of integer encodings:
+    /// zero, small numbers, (sign-extended) negative numbers, and every varint length.
+    fn bits(&mut self) -> u64 {
+        match self.below(4) {
+            0 => self.below(3) as u64,
+            1 => !(self.below(3) as u64),
+            2 => self.next() >> self.below(64),
+            _ => self.next(),
+        }
+    }
+
+    /// Random bits for a floating-point number, other than `NaN` (which never equals itself).
+    fn float(&mut self, bits: u32) -> u64 {
+        loop {
+            let value = self.bits() & (u64::MAX >> (64 - bits));
+            let exponent_bits = if bits == 32 {
+                0x7f80_0000
+            } else {
+                0x7ff0_0000_0000_0000
+            };
+            let mantissa_bits = if bits == 32 {
+                0x007f_ffff
+            } else {
+                0x000f_ffff_ffff_ffff
+            };
+            if value & exponent_bits != exponent_bits || value & mantissa_bits == 0 {
+                return value;
+            }
+        }
+    }
+}
+
+/// An arbitrary value of the element type of a scalar coding.
+fn arbitrary_scalar(scalar_coding: i32, random: &mut Random) -> Val {
+    match scalar_coding / 4 {
+        0 => Val::List(
+            (0..random.below(5))
+                .map(|_| Val::U8(random.next() as u8))
+                .collect(),
+        ),
+        1 | 2 => Val::String(
+            (0..random.below(5))
+                .map(|_| ['a', 'é', '\0', '€', '😀'][random.below(5)])
+                .collect(),
+        ),
+        3 => Val::Bool(random.below(2) == 1),
+        4..=6 => Val::S32(random.bits() as i32),
+        7 | 8 => Val::U32(random.bits() as u32),
+        9..=11 => Val::S64(random.bits() as i64),
+        12 | 13 => Val::U64(random.bits()),
+        14 => Val::Float32(f32::from_bits(random.float(32) as u32)),
+        15 => Val::Float64(f64::from_bits(random.float(64))),
+        _ => unreachable!("Unknown ScalarCoding {scalar_coding}"),
+    }
+}
+
+/// An arbitrary value conforming to a field of the [schema].
+fn arbitrary(field: &Field, random: &mut Random) -> Val {
+    // Both coding enumerations cycle through [implicit, packed, explicit, expanded].
+    let (coding, element): (i32, Box<dyn Fn(&mut Random) -> Val>) = match field.coding.unwrap() {
+        Coding::ScalarCoding(scalar_coding) => (
+            scalar_coding,
+            Box::new(move |random| arbitrary_scalar(scalar_coding, random)),
+        ),
+        Coding::CompoundCoding(compound_coding) => {
+            match CompoundCoding::try_from(compound_coding).unwrap() {
+                CompoundCoding::EnumImplicit
+                | CompoundCoding::EnumPacked
+                | CompoundCoding::EnumExplicit
+                | CompoundCoding::EnumExpanded => (
+                    compound_coding,
+                    Box::new(|random| {
+                        let variant = &field.subfields[random.below(field.subfields.len())];
+                        Val::Enum(variant.name.clone())
+                    }),
+                ),
+                CompoundCoding::Message => (
+                    2, // Explicit.
+                    Box::new(|random| arbitrary_record(field, random)),
+                ),
+                CompoundCoding::MessageExpanded => (
+                    3, // Expanded.
+                    Box::new(|random| arbitrary_record(field, random)),
+                ),
+                CompoundCoding::Oneof => {
+                    // Variants are presence-tracked themselves, so use the bare payload.
+                    let variant = &field.subfields[random.below(field.subfields.len())];
+                    let Val::Option(Some(payload)) = arbitrary(variant, random) else {
+                        return Val::Option(None);
+                    };
+                    return Val::Option(Some(Box::new(Val::Variant(
+                        variant.name.clone(),
+                        Some(payload),
+                    ))));
+                }
+                coding => unreachable!("Unsupported CompoundCoding {coding:?}"),
+            }
+        }
+    };
+    match coding % 4 {
+        0 => element(random),
+        2 => match random.below(3) {
+            0 => Val::Option(None),
+            _ => Val::Option(Some(Box::new(element(random)))),
+        },
+        _ => Val::List((0..random.below(4)).map(|_| element(random)).collect()),
+    }
+}
+
+/// An arbitrary record conforming to a message of the [schema].
+fn arbitrary_record(message: &Field, random: &mut Random) -> Val {
+    Val::Record(
+        message
+            .subfields
+            .iter()
+            .map(|subfield| (subfield.name.clone(), arbitrary(subfield, random)))
+            .collect(),
+    )
+}
+
+#[test]
+fn test_every_scalar_coding_round_trips() {
+    let codings = (0..64).filter_map(|number| ScalarCoding::try_from(number).ok());
+    for coding in codings {
+        let number = coding as i32;
+        let (zero, nonzero) = samples(number);
+        // Codings cycle through [implicit, packed, explicit, expanded].
+        let (present, absent) = match number % 4 {
+            0 => (nonzero, zero.clone()),
+            // Explicit presence must survive even for zero values.
+            2 => (Val::Option(Some(Box::new(zero))), Val::Option(None)),
+            _ => (Val::List(vec![zero, nonzero]), Val::List(Vec::new())),
+        };
+        for value in [present, absent] {
+            assert_eq!(round_trip(number, value.clone()), value, "{coding:?}");
+        }
+    }
+}
+
+#[test]
+fn test_arbitrary_values_round_trip() {
+    let schema = schema();
+    let mut random = Random(0x5eed);
+    for _ in 0..ITERATIONS {
+        let value = arbitrary_record(&schema, &mut random);
+        assert_eq!(round_trip_message(&schema, value.clone()), value);
+    }
+}
@@ -6,7 +6,7 @@ use tonic::codec::Encoder;
 use wasmtime::component::Val;
 
 use encode::ResponseEncoder;
-use metadata_proto::work::runtime::field::{Coding, CompoundCoding, ScalarCoding};
+use metadata_proto::work::runtime::field::{Charset, Coding, CompoundCoding, ScalarCoding};
 use metadata_proto::work::runtime::Field;
 use names::Name;
 
@@ -40,6 +40,8 @@ macro_rules! test_success {
                     name: "".into(), // Ignored.
                     coding: None,    // Ignored.
                     subfields: vec![$(field!($field_name $field),)*],
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
                 },
                 Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
             ).unwrap();
@@ -47,9 +49,11 @@ macro_rules! test_success {
             let mut buffer = BytesMut::new();
             let mut encode_buffer = unsafe { transmute(EncodeBufClone { buf: &mut buffer }) };
 
+            let encoded_len = encoder.encoded_len(&value).unwrap();
             encoder.encode(value, &mut encode_buffer).unwrap();
 
             assert_eq!(buffer.as_ref(), $expected);
+            assert_eq!(encoded_len, buffer.len());
 
             // Make sure the decoder's drop method does not panic.
             drop(encoder);
@@ -65,6 +69,8 @@ macro_rules! field {
             number: $number,
             coding: Some(Coding::ScalarCoding($coding as i32)),
             subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
     ($name:literal (message $number:literal $($subfield_name:literal $subfield:tt)+)) => {
@@ -73,6 +79,18 @@ macro_rules! field {
             number: $number,
             coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
             subfields: vec![$(field!($subfield_name $subfield),)*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    };
+    ($name:literal (duration $number:literal)) => {
+        Field {
+            name: String::from($name),
+            number: $number,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Duration as i32)),
+            subfields: Vec::new(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
     ($name:literal (oneof $($variant_name:literal $variant:tt)+)) => {
@@ -81,6 +99,8 @@ macro_rules! field {
             number: 0, // Ignored.
             coding: Some(Coding::CompoundCoding(CompoundCoding::Oneof as i32)),
             subfields: vec![$(field!($variant_name $variant),)*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
     ($name:literal (enumeration ($coding:expr) $number:literal $($variant_name:literal $variant_number:literal)+)) => {
@@ -94,8 +114,12 @@ macro_rules! field {
                     number: $variant_number,
                     coding: None, // Ignored.
                     subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
                 },
             )*],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
         }
     };
 }
@@ -215,6 +239,153 @@ test_success!(
     ]
 );
 
+// Streaming responses encode every item with the same encoder,
+// so each item must be encoded independently of the ones before it,
+// even after a failure leaves some pre-computed lengths unused.
+#[test]
+fn test_streaming_items() {
+    let mut encoder = ResponseEncoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                field!("a" (message 1
+                    "s" (scalar (ScalarCoding::StringUtf8Implicit) 1)
+                )),
+                field!("b" (scalar (ScalarCoding::Int32Packed) 2)),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    let mut encode = |value| {
+        let mut buffer = BytesMut::new();
+        let mut encode_buffer = unsafe { transmute(EncodeBufClone { buf: &mut buffer }) };
+        encoder.encode(value, &mut encode_buffer).map(|()| buffer)
+    };
+
+    let first = encode(bare_record!(
+        "a" record!("s" Val::String("hi".into()));
+        "b" Val::List(vec![Val::S32(1), Val::S32(2)])
+    ));
+    assert_eq!(
+        first.unwrap().as_ref(),
+        &[
+            10, // 'a' tag: (1 << 3) + 2
+            4,  // length of submessage
+            10, //   's' tag: (1 << 3) + 2
+            2,  //   length of "hi"
+            104, 105, //     "hi"
+            18,  // 'b' tag: (2 << 3) + 2
+            2,   // byte length of packed varint
+            1,   //   1
+            2,   //   2
+        ],
+    );
+
+    // The packed field's length is computed before the bad submessage is reached.
+    let failed = encode(bare_record!(
+        "a" Val::S32(1);
+        "b" Val::List(vec![Val::S32(3)])
+    ));
+    assert!(failed.is_err());
+
+    let second = encode(bare_record!(
+        "a" Val::Option(None);
+        "b" Val::List(vec![Val::S32(3)])
+    ));
+    assert_eq!(
+        second.unwrap().as_ref(),
+        &[
+            18, // 'b' tag: (2 << 3) + 2
+            1,  // byte length of packed varint
+            3,  //   3
+        ],
+    );
+
+    let third = encode(bare_record!(
+        "a" record!("s" Val::String("".into()));
+        "b" Val::List(vec![])
+    ));
+    assert_eq!(
+        third.unwrap().as_ref(),
+        &[
+            10, // 'a' tag: (1 << 3) + 2
+            0,  // length of submessage
+        ],
+    );
+}
+
+// The length pre-pass for `test_messages_deep_nested_lengths` pushes 7 lengths
+// (one for each submessage and packed field in the expected encoding).
+// Pre-allocating at least that many means it never has to reallocate.
+#[test]
+fn test_lengths_hint() {
+    let encoder = ResponseEncoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![
+                field!("x" (message 1
+                    "a" (scalar (ScalarCoding::Sint32Implicit) 1)
+                )),
+                field!("y" (message 2
+                    "aa" (message 30
+                        "strings" (scalar (ScalarCoding::StringUtf8Expanded) 1)
+                        "variants" (oneof
+                            "another" (message 5
+                                "aaa" (scalar (ScalarCoding::FloatPacked) 1)
+                            )
+                            "unused" (scalar (ScalarCoding::BoolExplicit) 6)
+                        )
+                        "youre-either" (enumeration (CompoundCoding::EnumPacked) 128
+                            "in" 1
+                            "out" 0
+                            "above" 10_000
+                        )
+                    )
+                    "bb" (scalar (ScalarCoding::Int64Packed) 3)
+                )),
+            ],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    assert_eq!(encoder.lengths_hint(), 7);
+
+    // Absurdly deep schemas are capped.
+    let mut deep = field!("leaf" (scalar (ScalarCoding::Int32Packed) 1));
+    for _ in 0..300 {
+        deep = Field {
+            name: String::from("nested"),
+            number: 1,
+            coding: Some(Coding::CompoundCoding(CompoundCoding::Message as i32)),
+            subfields: vec![deep],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        };
+    }
+    let encoder = ResponseEncoder::new(
+        &Field {
+            number: 0,       // Ignored.
+            name: "".into(), // Ignored.
+            coding: None,    // Ignored.
+            subfields: vec![deep],
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        },
+        Arc::new(Name::parse(COMPONENT_NAME).component().unwrap()),
+    )
+    .unwrap();
+    assert_eq!(encoder.lengths_hint(), 256);
+}
+
 test_success!(
     test_bytes_implicit,
     "bytes-implicit": (scalar (ScalarCoding::BytesImplicit) 12)
@@ -317,3 +488,35 @@ test_success!(
         0,                                    // length of bytes
     ]
 );
+
+test_success!(
+    test_duration_positive,
+    "timeout": (duration 1)
+        Val::Option(Some(Box::new(Val::S64(3_500_000_000))));
+    "unset": (duration 2)
+        Val::Option(None);
+    expect = &[
+        10,                           // 'timeout' tag: (1 << 3) + 2
+        8,                            // length of submessage
+          8,                          //   'seconds' tag: (1 << 3) + 0
+          3,                          //   3
+          16,                         //   'nanos' tag: (2 << 3) + 0
+          128, 202, 181, 238, 1,      //   500000000
+    ]
+);
+
+test_success!(
+    test_duration_negative,
+    "offset": (duration 1)
+        Val::Option(Some(Box::new(Val::S64(-1_500_000_000))));
+    expect = &[
+        10,                           // 'offset' tag: (1 << 3) + 2
+        22,                           // length of submessage
+          8,                          //   'seconds' tag: (1 << 3) + 0
+          255, 255, 255, 255, 255,    //   -1 [sign-extended]
+          255, 255, 255, 255, 1,
+          16,                         //   'nanos' tag: (2 << 3) + 0
+          128, 182, 202, 145, 254,    //   -500000000 [sign-extended]
+          255, 255, 255, 255, 1,
+    ]
+);
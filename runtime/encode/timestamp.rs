@@ -0,0 +1,104 @@
+//! Encoding logic for `google.protobuf.Timestamp`,
+//! which is represented as a record of `seconds` and `nanos`.
+
+use std::result::Result as StdResult;
+
+use prost::encoding::{encode_varint, encoded_len_varint, WireType};
+use tonic::codec::EncodeBuf;
+use wasmtime::component::Val;
+
+use crate::{
+    tag, CompoundEncoder, EncodeError, Encoder, EXPLICIT_NON_OPTION, TIMESTAMP_NON_RECORD,
+};
+use metadata_proto::work::runtime::Field;
+
+/// Tag of the `seconds` field: `(1 << 3) + 0`.
+const SECONDS_TAG: u64 = 8;
+
+/// Tag of the `nanos` field: `(2 << 3) + 0`.
+const NANOS_TAG: u64 = 16;
+
+impl Encoder {
+    pub(crate) fn timestamp(timestamp: &Field) -> Self {
+        Self {
+            encode: timestamp_encode,
+            length: timestamp_length,
+            tag: tag(timestamp.number, WireType::LengthDelimited),
+            compound: CompoundEncoder { scalar: () },
+        }
+    }
+}
+
+/// Extract `seconds` and `nanos` from `record { seconds: s64, nanos: u32 }`.
+#[inline(always)]
+fn fields(value: &Val) -> StdResult<(i64, u32), EncodeError> {
+    match value {
+        Val::Record(fields) => match fields.as_slice() {
+            [(_, Val::S64(seconds)), (_, Val::U32(nanos))] => Ok((*seconds, *nanos)),
+            _ => Err(EncodeError::new(TIMESTAMP_NON_RECORD)),
+        },
+        _ => Err(EncodeError::new(TIMESTAMP_NON_RECORD)),
+    }
+}
+
+/// Length of the contents of a timestamp message (excluding its own tag and length).
+/// Zero-valued fields are omitted.
+#[inline(always)]
+fn content_length(seconds: i64, nanos: u32) -> u32 {
+    let mut length = 0;
+    if seconds != 0 {
+        length += 1 + encoded_len_varint(seconds as u64);
+    }
+    if nanos != 0 {
+        length += 1 + encoded_len_varint(nanos as u64);
+    }
+    length as u32
+}
+
+/// Encode a timestamp message. Always explicitly presence-tracked.
+///
+/// The content length is cheap to recompute, so nothing is queued in `lengths`.
+fn timestamp_encode(
+    encoder: &Encoder,
+    value: &Val,
+    _lengths: &mut Vec<u32>,
+    buf: &mut EncodeBuf<'_>,
+) -> StdResult<(), EncodeError> {
+    if let Val::Option(option) = value {
+        if let Some(value) = option {
+            let (seconds, nanos) = fields(value)?;
+            encode_varint(encoder.tag, buf);
+            encode_varint(content_length(seconds, nanos) as u64, buf);
+            if seconds != 0 {
+                encode_varint(SECONDS_TAG, buf);
+                encode_varint(seconds as u64, buf);
+            }
+            if nanos != 0 {
+                encode_varint(NANOS_TAG, buf);
+                encode_varint(nanos as u64, buf);
+            }
+        }
+        // Absent timestamps are ignored.
+        Ok(())
+    } else {
+        Err(EncodeError::new(EXPLICIT_NON_OPTION))
+    }
+}
+
+fn timestamp_length(
+    encoder: &Encoder,
+    value: &Val,
+    _lengths: &mut Vec<u32>,
+) -> StdResult<u32, EncodeError> {
+    if let Val::Option(option) = value {
+        Ok(if let Some(value) = option {
+            let (seconds, nanos) = fields(value)?;
+            let length = content_length(seconds, nanos);
+            length + (encoded_len_varint(encoder.tag) + encoded_len_varint(length as u64)) as u32
+        } else {
+            0 // Absent timestamps are ignored.
+        })
+    } else {
+        Err(EncodeError::new(EXPLICIT_NON_OPTION))
+    }
+}
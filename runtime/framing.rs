@@ -0,0 +1,213 @@
+//! Cheap checks on the framing of gRPC requests, before Tonic buffers and decodes them.
+//!
+//! Each message in a gRPC request body is framed by a 5-byte header:
+//! a compression flag followed by the big-endian length of the message.
+//! Tonic validates the header too, but only after buffering the whole message,
+//! so a request that could never decode still costs a full read.
+//! Checking the header up front rejects those requests as soon as it arrives,
+//! with a status describing what was wrong.
+
+use std::result::Result as StdResult;
+
+use axum::body::Body as AxumBody;
+use bytes::{Bytes, BytesMut};
+use futures::stream::{once, StreamExt};
+use http::header::CONTENT_TYPE;
+use http::Request as HttpRequest;
+use http_body_util::BodyExt;
+use tonic::Status;
+
+/// Length of the header framing each message in a gRPC request body.
+const FRAME_HEADER_SIZE: usize = 5;
+
+/// Content type of all gRPC requests, optionally followed by `+` or `;` and more details.
+const GRPC_CONTENT_TYPE: &str = "application/grpc";
+
+/// Check a request's content type and the header of its first message.
+/// Return the request intact if both look valid, and an error status otherwise.
+///
+/// Compression is not supported, so only uncompressed messages pass.
+/// Messages longer than `max_message_size` (if set) are rejected without reading them.
+/// A request with an empty body is passed through for Tonic to reject.
+pub(crate) async fn check_framing(
+    request: HttpRequest<AxumBody>,
+    max_message_size: Option<usize>,
+) -> StdResult<HttpRequest<AxumBody>, Box<Status>> {
+    check_content_type(&request)?;
+
+    // The header usually arrives in the first data frame, but it may be split across several.
+    let (parts, mut body) = request.into_parts();
+    let mut head = BytesMut::new();
+    while head.len() < FRAME_HEADER_SIZE {
+        match body.frame().await {
+            Some(Ok(frame)) => match frame.into_data() {
+                Ok(data) => head.extend_from_slice(&data),
+                // Trailers mark the end of the body.
+                Err(_) => break,
+            },
+            Some(Err(error)) => {
+                return Err(Box::new(Status::cancelled(format!(
+                    "Failed reading request body: {error}"
+                ))))
+            }
+            None => break,
+        }
+    }
+    match head.len() {
+        0 => {}
+        length if length < FRAME_HEADER_SIZE => {
+            return Err(Box::new(Status::invalid_argument(
+                "Truncated message header",
+            )));
+        }
+        _ => check_frame_header(&head, max_message_size)?,
+    }
+
+    // Put back what was read ahead of the rest of the body.
+    let head: Bytes = head.freeze();
+    let body = AxumBody::from_stream(once(async move { Ok(head) }).chain(body.into_data_stream()));
+    Ok(HttpRequest::from_parts(parts, body))
+}
+
+/// Reject requests that are not gRPC at all.
+fn check_content_type<B>(request: &HttpRequest<B>) -> StdResult<(), Box<Status>> {
+    let content_type = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|content_type| content_type.to_str().ok())
+        .unwrap_or_default();
+    match content_type.strip_prefix(GRPC_CONTENT_TYPE) {
+        Some(rest) if rest.is_empty() || rest.starts_with(['+', ';']) => Ok(()),
+        _ => Err(Box::new(Status::invalid_argument(format!(
+            "Unsupported content type: {content_type:?}"
+        )))),
+    }
+}
+
+/// Validate a message header: the first [`FRAME_HEADER_SIZE`] bytes of `head`.
+fn check_frame_header(head: &[u8], max_message_size: Option<usize>) -> StdResult<(), Box<Status>> {
+    match head[0] {
+        0 => {}
+        1 => {
+            return Err(Box::new(Status::unimplemented(
+                "Compressed requests are not supported",
+            )))
+        }
+        flag => {
+            return Err(Box::new(Status::invalid_argument(format!(
+                "Invalid compression flag: {flag}"
+            ))))
+        }
+    }
+    let length = u32::from_be_bytes([head[1], head[2], head[3], head[4]]) as usize;
+    match max_message_size {
+        Some(max) if length > max => Err(Box::new(Status::resource_exhausted(format!(
+            "Message length {length} exceeds the limit of {max} bytes"
+        )))),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use futures::stream;
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+    use tonic::Code;
+
+    use super::*;
+
+    const MAX_MESSAGE_SIZE: Option<usize> = Some(1024);
+
+    /// A gRPC request whose body arrives in the given chunks.
+    fn request(content_type: &str, chunks: &[&'static [u8]]) -> HttpRequest<AxumBody> {
+        let frames = chunks
+            .iter()
+            .map(|chunk| Ok::<_, Infallible>(Frame::data(Bytes::from_static(chunk))))
+            .collect::<Vec<_>>();
+        HttpRequest::post("/foo.Bar/Baz")
+            .header(CONTENT_TYPE, content_type)
+            .body(AxumBody::new(StreamBody::new(stream::iter(frames))))
+            .unwrap()
+    }
+
+    async fn rejection(request: HttpRequest<AxumBody>) -> (Code, String) {
+        let status = check_framing(request, MAX_MESSAGE_SIZE).await.unwrap_err();
+        (status.code(), String::from(status.message()))
+    }
+
+    #[tokio::test]
+    async fn test_bad_compression_flag() {
+        assert_eq!(
+            rejection(request("application/grpc", &[&[2, 0, 0, 0, 1, 8]])).await,
+            (
+                Code::InvalidArgument,
+                String::from("Invalid compression flag: 2")
+            ),
+        );
+        assert_eq!(
+            rejection(request("application/grpc", &[&[1, 0, 0, 0, 1, 8]])).await,
+            (
+                Code::Unimplemented,
+                String::from("Compressed requests are not supported"),
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_impossible_length() {
+        // 1 MiB, with only one byte of it present.
+        assert_eq!(
+            rejection(request("application/grpc", &[&[0, 0, 16, 0, 0, 8]])).await,
+            (
+                Code::ResourceExhausted,
+                String::from("Message length 1048576 exceeds the limit of 1024 bytes"),
+            ),
+        );
+        assert_eq!(
+            rejection(request("application/grpc", &[&[0, 0, 0]])).await,
+            (
+                Code::InvalidArgument,
+                String::from("Truncated message header")
+            ),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_wrong_content_type() {
+        assert_eq!(
+            rejection(request("application/json", &[b"{}"])).await,
+            (
+                Code::InvalidArgument,
+                String::from("Unsupported content type: \"application/json\""),
+            ),
+        );
+        assert_eq!(
+            rejection(request("application/grpcx", &[&[0, 0, 0, 0, 0]]))
+                .await
+                .0,
+            Code::InvalidArgument,
+        );
+    }
+
+    #[tokio::test]
+    async fn test_valid_request_intact() {
+        // The header is split across chunks, and the message follows it.
+        let valid = request("application/grpc+proto", &[&[0, 0], &[0, 0, 2, 8], &[42]]);
+        let body = check_framing(valid, MAX_MESSAGE_SIZE)
+            .await
+            .unwrap()
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .to_bytes();
+        assert_eq!(body.as_ref(), &[0, 0, 0, 0, 2, 8, 42]);
+
+        // Empty requests are left for Tonic to reject.
+        let empty = request("application/grpc", &[]);
+        assert!(check_framing(empty, MAX_MESSAGE_SIZE).await.is_ok());
+    }
+}
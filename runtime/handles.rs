@@ -0,0 +1,438 @@
+//! Opaque handles to resources that the runtime manages on behalf of a component.
+//!
+//! A message marked with the `(vimana.resource)` option (see `compiler/options.proto`)
+//! becomes a WIT `resource` in one of the `types` interfaces imported by the component.
+//! The runtime implements every such resource as a numbered handle
+//! shared by all instances of the pod's component,
+//! so a handle returned while handling one request can be passed back into another,
+//! even though each request gets a fresh instance.
+//! A handle lives until the component drops it.
+//!
+//! Clients only ever see the number of a handle,
+//! as field #1 of the message that the resource represents.
+//! The codec decodes and encodes that message like any other,
+//! and the runtime swaps numbers for handles around each call.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, bail, Result};
+use papaya::HashSet as LockFreeConcurrentHashSet;
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{Component, Linker, Resource, ResourceAny, ResourceType, Val};
+use wasmtime::{AsContextMut, Engine as WasmEngine, StoreContextMut};
+
+use crate::host::InstanceState;
+use metadata_proto::work::runtime::field::{Coding, CompoundCoding};
+use metadata_proto::work::runtime::{Field, GrpcMethod};
+
+/// Suffix of the interfaces in which the compiler defines message types (including resources).
+const TYPES_INTERFACE_SUFFIX: &str = "/types";
+
+/// Host representation of every runtime-managed resource.
+pub(crate) struct Handle;
+
+/// The live handles of a single pod.
+///
+/// Cloned along with the host state, but all clones share the same handles.
+#[derive(Clone, Default)]
+pub(crate) struct HandleTable(Arc<HandleTableInner>);
+
+/// The request and response of a method that passes resources in either direction.
+pub(crate) struct ResourceFields {
+    request: Field,
+    response: Field,
+}
+
+#[derive(Default)]
+struct HandleTableInner {
+    /// Most recently issued handle number. Zero is never issued.
+    last: AtomicU32,
+
+    /// Numbers of the handles issued but not yet dropped.
+    live: LockFreeConcurrentHashSet<u32>,
+}
+
+impl HandleTable {
+    /// Issue a new handle, as the constructor of a resource.
+    pub(crate) fn create(&self) -> Resource<Handle> {
+        let number = self.0.last.fetch_add(1, Ordering::Relaxed) + 1;
+        self.0.live.pin().insert(number);
+        Resource::new_own(number)
+    }
+
+    /// Take a handle returned by the component,
+    /// returning the number by which clients refer to it.
+    pub(crate) fn export(&self, store: impl AsContextMut, handle: ResourceAny) -> Result<u32> {
+        let number = handle.try_into_resource::<Handle>(store)?.rep();
+        self.check_live(number)?;
+        Ok(number)
+    }
+
+    /// Give the component the handle that a client refers to by number.
+    pub(crate) fn import(&self, store: impl AsContextMut, number: u32) -> Result<ResourceAny> {
+        self.check_live(number)?;
+        Resource::<Handle>::new_own(number).try_into_resource_any(store)
+    }
+
+    /// Swap the handle numbers in a decoded request for the handles they refer to.
+    pub(crate) fn import_request(
+        &self,
+        mut store: impl AsContextMut,
+        fields: &ResourceFields,
+        request: &mut Val,
+    ) -> Result<()> {
+        visit_message(
+            &fields.request,
+            request,
+            &mut |resource, value, _optional| {
+                let number = handle_number(resource, value)?;
+                *value = Val::Resource(self.import(store.as_context_mut(), number)?);
+                Ok(())
+            },
+        )
+    }
+
+    /// Swap the handles in a response for the numbers by which clients refer to them.
+    /// Errors returned instead of a response never carry handles.
+    pub(crate) fn export_response(
+        &self,
+        mut store: impl AsContextMut,
+        fields: &ResourceFields,
+        response: &mut Val,
+    ) -> Result<()> {
+        let response = match response {
+            Val::Result(Ok(Some(response))) => response.as_mut(),
+            Val::Result(_) => return Ok(()),
+            response => response,
+        };
+        visit_message(
+            &fields.response,
+            response,
+            &mut |resource, value, optional| {
+                let Val::Resource(handle) = value else {
+                    bail!("Expected a resource for '{}'", resource.name);
+                };
+                let number = self.export(store.as_context_mut(), *handle)?;
+                let handle_name = resource
+                    .subfields
+                    .first()
+                    .map(|handle| handle.name.clone())
+                    .unwrap_or_default();
+                let record = Val::Record(vec![(handle_name, Val::U32(number))]);
+                *value = if optional {
+                    Val::Option(Some(Box::new(record)))
+                } else {
+                    record
+                };
+                Ok(())
+            },
+        )
+    }
+
+    /// Forget a handle once the component drops it, as the destructor of a resource.
+    fn release(&self, number: u32) {
+        self.0.live.pin().remove(&number);
+    }
+
+    fn check_live(&self, number: u32) -> Result<()> {
+        if !self.0.live.pin().contains(&number) {
+            bail!("Unknown or dropped handle {number}");
+        }
+        Ok(())
+    }
+}
+
+impl ResourceFields {
+    /// Return the request and response of a method,
+    /// unless neither refers to any resources (so there are no handles to swap).
+    pub(crate) fn new(method: &GrpcMethod) -> Option<Self> {
+        let request = method.request.clone().unwrap_or_default();
+        let response = method.response.clone().unwrap_or_default();
+        (has_resources(&request) || has_resources(&response)).then_some(Self { request, response })
+    }
+}
+
+fn has_resources(message: &Field) -> bool {
+    message
+        .subfields
+        .iter()
+        .any(|subfield| subfield.resource || has_resources(subfield))
+}
+
+/// Call `swap` on every resource within a message value, laid out the way the codec decodes it.
+/// `swap` is told whether the resource is wrapped in an option
+/// (singular fields and map values) or bare (list elements and oneof variants).
+fn visit_message(
+    message: &Field,
+    record: &mut Val,
+    swap: &mut impl FnMut(&Field, &mut Val, bool) -> Result<()>,
+) -> Result<()> {
+    let Val::Record(values) = record else {
+        bail!("Expected a record for '{}'", message.name);
+    };
+    for (subfield, (_name, value)) in message.subfields.iter().zip(values) {
+        visit_field(subfield, value, swap)?;
+    }
+    Ok(())
+}
+
+fn visit_field(
+    field: &Field,
+    value: &mut Val,
+    swap: &mut impl FnMut(&Field, &mut Val, bool) -> Result<()>,
+) -> Result<()> {
+    let Some(Coding::CompoundCoding(coding)) = field.coding else {
+        return Ok(());
+    };
+    match CompoundCoding::try_from(coding) {
+        Ok(CompoundCoding::Message) => visit_nested(field, value, true, swap),
+        Ok(CompoundCoding::MessageExpanded) => {
+            if let Val::List(elements) = value {
+                for element in elements {
+                    visit_nested(field, element, false, swap)?;
+                }
+            }
+            Ok(())
+        }
+        Ok(CompoundCoding::Oneof) => {
+            let Val::Option(Some(variant)) = value else {
+                return Ok(());
+            };
+            let Val::Variant(name, Some(payload)) = variant.as_mut() else {
+                return Ok(());
+            };
+            match field.subfields.iter().find(|variant| variant.name == *name) {
+                Some(variant)
+                    if variant.coding
+                        == Some(Coding::CompoundCoding(CompoundCoding::Message as i32)) =>
+                {
+                    visit_nested(variant, payload, false, swap)
+                }
+                _ => Ok(()),
+            }
+        }
+        Ok(CompoundCoding::Map) => {
+            let (Some(value_field), Val::List(entries)) = (field.subfields.get(1), value) else {
+                return Ok(());
+            };
+            for entry in entries {
+                if let Val::Tuple(pair) = entry {
+                    if let Some(entry_value) = pair.get_mut(1) {
+                        visit_field(value_field, entry_value, swap)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Ok(()),
+    }
+}
+
+/// A nested message, which is either a resource itself or may contain some.
+fn visit_nested(
+    field: &Field,
+    value: &mut Val,
+    optional: bool,
+    swap: &mut impl FnMut(&Field, &mut Val, bool) -> Result<()>,
+) -> Result<()> {
+    if field.resource {
+        return swap(field, value, optional);
+    }
+    match value {
+        Val::Option(Some(record)) => visit_message(field, record, swap),
+        Val::Option(None) => Ok(()),
+        record => visit_message(field, record, swap),
+    }
+}
+
+/// The handle number that a client sent in place of a resource.
+fn handle_number(resource: &Field, value: &Val) -> Result<u32> {
+    let record = match value {
+        Val::Option(Some(record)) => record.as_ref(),
+        Val::Option(None) => bail!("Missing handle for '{}'", resource.name),
+        record => record,
+    };
+    match record {
+        Val::Record(values) => match values.first() {
+            Some((_name, Val::U32(number))) => Ok(*number),
+            _ => Err(anyhow!("Malformed handle for '{}'", resource.name)),
+        },
+        _ => Err(anyhow!("Expected a record for '{}'", resource.name)),
+    }
+}
+
+/// Define every resource that the component imports from a `types` interface
+/// as a runtime-managed handle.
+pub(crate) fn link_handles(
+    linker: &mut Linker<InstanceState>,
+    component: &Component,
+    wasmtime: &WasmEngine,
+) -> Result<()> {
+    for (interface, item) in component.component_type().imports(wasmtime) {
+        let ComponentItem::ComponentInstance(instance) = item else {
+            continue;
+        };
+        if !interface.ends_with(TYPES_INTERFACE_SUFFIX) {
+            continue;
+        }
+        let mut types = linker.instance(interface)?;
+        for (name, item) in instance.exports(wasmtime) {
+            if let ComponentItem::Resource(_) = item {
+                types.resource(name, ResourceType::host::<Handle>(), |context, number| {
+                    context.data().host.handles.release(number);
+                    Ok(())
+                })?;
+                types.func_wrap(
+                    &format!("[constructor]{name}"),
+                    |context: StoreContextMut<'_, InstanceState>, (): ()| {
+                        Ok((context.data().host.handles.create(),))
+                    },
+                )?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use metadata_proto::work::runtime::field::ScalarCoding;
+    use wasmtime::Store;
+
+    use super::*;
+
+    /// Fields of a method that takes a page of cursors and returns another:
+    /// a singular `cursor` resource, and a list of `previous` ones.
+    fn cursor_fields() -> ResourceFields {
+        let handle = Field {
+            number: 1,
+            name: String::from("handle"),
+            coding: Some(Coding::ScalarCoding(ScalarCoding::Uint32Implicit as i32)),
+            ..Default::default()
+        };
+        let cursor = |number: u32, name: &str, coding: CompoundCoding| Field {
+            number,
+            name: String::from(name),
+            subfields: vec![handle.clone()],
+            coding: Some(Coding::CompoundCoding(coding as i32)),
+            resource: true,
+            ..Default::default()
+        };
+        let page = Field {
+            subfields: vec![
+                cursor(1, "cursor", CompoundCoding::Message),
+                cursor(2, "previous", CompoundCoding::MessageExpanded),
+            ],
+            ..Default::default()
+        };
+        ResourceFields::new(&GrpcMethod {
+            request: Some(page.clone()),
+            response: Some(page),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    fn page(cursor: Val, previous: Vec<Val>) -> Val {
+        Val::Record(vec![
+            (String::from("cursor"), cursor),
+            (String::from("previous"), Val::List(previous)),
+        ])
+    }
+
+    /// A cursor as the codec represents it: a message holding the handle number.
+    fn number(number: u32) -> Val {
+        Val::Record(vec![(String::from("handle"), Val::U32(number))])
+    }
+
+    /// A singular cursor field as the codec represents it.
+    fn optional(value: Val) -> Val {
+        Val::Option(Some(Box::new(value)))
+    }
+
+    #[test]
+    fn test_handle_across_calls() {
+        let wasmtime = WasmEngine::default();
+        let handles = HandleTable::default();
+        let fields = cursor_fields();
+
+        // The component creates two handles while handling the first request
+        // and returns them in the response.
+        let mut first = Store::new(&wasmtime, ());
+        let current = handles.create();
+        let previous = handles.create();
+        let (current_number, previous_number) = (current.rep(), previous.rep());
+        let mut response = Val::Result(Ok(Some(Box::new(page(
+            Val::Resource(current.try_into_resource_any(&mut first).unwrap()),
+            vec![Val::Resource(
+                previous.try_into_resource_any(&mut first).unwrap(),
+            )],
+        )))));
+        handles
+            .export_response(&mut first, &fields, &mut response)
+            .unwrap();
+        let Val::Result(Ok(Some(exported))) = response else {
+            panic!("Response is no longer a result");
+        };
+        assert_eq!(
+            *exported,
+            page(
+                optional(number(current_number)),
+                vec![number(previous_number)],
+            ),
+        );
+
+        // The client passes the current cursor back in a later request,
+        // handled by a fresh instance in a fresh store.
+        let mut second = Store::new(&wasmtime, ());
+        let mut request = page(optional(number(current_number)), Vec::new());
+        handles
+            .import_request(&mut second, &fields, &mut request)
+            .unwrap();
+        let Val::Record(values) = request else {
+            panic!("Request is no longer a record");
+        };
+        let Val::Resource(imported) = values[0].1 else {
+            panic!("Cursor is not a resource");
+        };
+        let imported = imported.try_into_resource::<Handle>(&mut second).unwrap();
+        assert_eq!(imported.rep(), current_number);
+
+        // Clients cannot make up numbers, omit handles, nor reuse dropped handles.
+        let mut made_up = page(optional(number(previous_number + 1)), Vec::new());
+        assert!(handles
+            .import_request(&mut second, &fields, &mut made_up)
+            .is_err());
+        let mut missing = page(Val::Option(None), Vec::new());
+        assert!(handles
+            .import_request(&mut second, &fields, &mut missing)
+            .is_err());
+        handles.release(current_number);
+        let mut dropped = page(optional(number(current_number)), Vec::new());
+        assert!(handles
+            .import_request(&mut second, &fields, &mut dropped)
+            .is_err());
+        assert_ne!(handles.create().rep(), current_number);
+    }
+
+    #[test]
+    fn test_methods_without_resources() {
+        let method = GrpcMethod {
+            request: Some(Field {
+                subfields: vec![Field {
+                    number: 1,
+                    name: String::from("query"),
+                    coding: Some(Coding::ScalarCoding(
+                        ScalarCoding::StringUtf8Implicit as i32,
+                    )),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            response: None,
+            ..Default::default()
+        };
+        assert!(ResourceFields::new(&method).is_none());
+    }
+}
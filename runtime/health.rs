@@ -0,0 +1,265 @@
+//! Liveness and readiness checks for gRPC pods.
+//!
+//! A component opts in by implementing the standard [gRPC health service][1]
+//! and answering for two sub-services:
+//!
+//! - `liveness`: `NOT_SERVING` means the component is broken
+//!   and its container should be restarted.
+//! - `readiness`: `NOT_SERVING` means the component is alive but should not receive traffic yet
+//!   (e.g. still initializing, or draining).
+//!
+//! Kubelet reaches these checks through exec probes,
+//! whose commands are interpreted by `ExecSync` rather than executed:
+//!
+//!     exec:
+//!       command: ["vimana-probe", "readiness"]
+//!
+//! Components without a health service are considered both alive and ready
+//! for as long as they run.
+//!
+//! [1]: https://github.com/grpc/grpc/blob/master/doc/health-checking.md
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use axum::body::Body as AxumBody;
+use bytes::Buf;
+use http::{Request as HttpRequest, StatusCode};
+use http_body_util::BodyExt;
+use prost::Message;
+use tonic::service::Routes;
+use tonic::Code;
+use tower::ServiceExt;
+
+/// Fully-qualified name of the standard gRPC health service.
+const HEALTH_SERVICE: &str = "grpc.health.v1.Health";
+
+/// The only unary method of the [health service](HEALTH_SERVICE).
+const HEALTH_CHECK_METHOD: &str = "Check";
+
+/// First element of an exec probe command handled by Vimana.
+pub(crate) const PROBE_COMMAND: &str = "vimana-probe";
+
+/// `grpc.health.v1.HealthCheckResponse.ServingStatus.SERVING`.
+const SERVING: i32 = 1;
+
+/// `grpc.health.v1.HealthCheckRequest`.
+#[derive(Clone, PartialEq, Message)]
+struct HealthCheckRequest {
+    #[prost(string, tag = "1")]
+    service: String,
+}
+
+/// `grpc.health.v1.HealthCheckResponse`.
+#[derive(Clone, PartialEq, Message)]
+struct HealthCheckResponse {
+    #[prost(int32, tag = "1")]
+    status: i32,
+}
+
+/// A kind of health check.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Probe {
+    /// Should the container keep running?
+    Liveness,
+    /// Should the pod receive traffic?
+    Readiness,
+}
+
+/// Most recent health check results for a pod.
+///
+/// Cloned along with the pod, but all clones share the same results.
+#[derive(Clone, Default)]
+pub(crate) struct Health(Arc<[AtomicU8; 2]>);
+
+/// Result of the latest check of some kind.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Checked {
+    /// Never checked.
+    Unknown = 0,
+    Passing = 1,
+    Failing = 2,
+}
+
+impl Probe {
+    /// Parse an exec probe command, e.g. `["vimana-probe", "liveness"]`.
+    /// Return `None` for any other command.
+    pub(crate) fn from_command(command: &[String]) -> Option<Self> {
+        match command {
+            [program, probe] if program == PROBE_COMMAND => match probe.as_str() {
+                "liveness" => Some(Self::Liveness),
+                "readiness" => Some(Self::Readiness),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Name of the health sub-service answering this probe.
+    fn service(self) -> &'static str {
+        match self {
+            Self::Liveness => "liveness",
+            Self::Readiness => "readiness",
+        }
+    }
+}
+
+impl Health {
+    /// Record the result of a check.
+    pub(crate) fn record(&self, probe: Probe, passing: bool) {
+        let checked = if passing {
+            Checked::Passing
+        } else {
+            Checked::Failing
+        };
+        self.0[probe as usize].store(checked as u8, Ordering::Relaxed);
+    }
+
+    /// Return the result of the latest check of the given kind.
+    pub(crate) fn get(&self, probe: Probe) -> Checked {
+        match self.0[probe as usize].load(Ordering::Relaxed) {
+            1 => Checked::Passing,
+            2 => Checked::Failing,
+            _ => Checked::Unknown,
+        }
+    }
+}
+
+/// Ask a pod's component whether it passes the given check.
+pub(crate) async fn check(routes: &Routes, probe: Probe) -> Result<bool> {
+    let message = HealthCheckRequest {
+        service: String::from(probe.service()),
+    }
+    .encode_to_vec();
+    // Uncompressed gRPC frame: flag byte, big-endian length, message.
+    let mut frame = Vec::with_capacity(5 + message.len());
+    frame.push(0);
+    frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&message);
+
+    let request = HttpRequest::post(format!("/{HEALTH_SERVICE}/{HEALTH_CHECK_METHOD}"))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(AxumBody::from(frame))
+        .context("Failed building health check request")?;
+    let response = routes
+        .clone()
+        .into_axum_router()
+        .oneshot(request)
+        .await
+        .context("Failed sending health check request")?;
+    if response.status() == StatusCode::NOT_FOUND {
+        // No health service: running is as healthy as it gets.
+        return Ok(true);
+    }
+
+    let (parts, body) = response.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map_err(|error| anyhow!("Failed reading health check response: {error}"))?;
+    // Responses without a body carry the status in their headers ("trailers-only").
+    let grpc_status = body
+        .trailers()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .or_else(|| parts.headers.get("grpc-status"))
+        .and_then(|status| status.to_str().ok()?.parse::<i32>().ok())
+        .map(Code::from)
+        .unwrap_or(Code::Unknown);
+    match grpc_status {
+        Code::Ok => {}
+        Code::Unimplemented => return Ok(true),
+        code => return Err(anyhow!("Health check failed with status {code:?}")),
+    }
+
+    let mut frame = body.to_bytes();
+    if frame.remaining() < 5 {
+        return Err(anyhow!("Truncated health check response"));
+    }
+    frame.advance(5);
+    let response = HealthCheckResponse::decode(frame).context("Malformed health check response")?;
+    Ok(response.status == SERVING)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::method_routing::post;
+    use axum::Router;
+    use bytes::Bytes;
+    use http::Response as HttpResponse;
+
+    use super::*;
+
+    /// A component that is alive, but not (yet) ready.
+    fn initializing_component() -> Routes {
+        let check = post(|request: HttpRequest<AxumBody>| async move {
+            let mut frame = request.into_body().collect().await.unwrap().to_bytes();
+            frame.advance(5);
+            let request = HealthCheckRequest::decode(frame).unwrap();
+            let status = if request.service == "liveness" {
+                SERVING
+            } else {
+                2
+            };
+            let message = HealthCheckResponse { status }.encode_to_vec();
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(&message);
+            HttpResponse::builder()
+                .header("content-type", "application/grpc")
+                .header("grpc-status", "0")
+                .body(AxumBody::from(Bytes::from(frame)))
+                .unwrap()
+        });
+        Routes::from(
+            Router::new().route(&format!("/{HEALTH_SERVICE}/{HEALTH_CHECK_METHOD}"), check),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_alive_but_not_ready() {
+        let routes = initializing_component();
+        assert!(check(&routes, Probe::Liveness).await.unwrap());
+        assert!(!check(&routes, Probe::Readiness).await.unwrap());
+
+        let health = Health::default();
+        assert_eq!(health.get(Probe::Readiness), Checked::Unknown);
+        health.record(Probe::Liveness, true);
+        health.record(Probe::Readiness, false);
+        assert_eq!(health.clone().get(Probe::Liveness), Checked::Passing);
+        assert_eq!(health.clone().get(Probe::Readiness), Checked::Failing);
+    }
+
+    #[tokio::test]
+    async fn test_no_health_service() {
+        let routes = Routes::from(Router::new());
+        assert!(check(&routes, Probe::Liveness).await.unwrap());
+        assert!(check(&routes, Probe::Readiness).await.unwrap());
+    }
+
+    #[test]
+    fn test_probe_command() {
+        let command = |args: &[&str]| {
+            args.iter()
+                .map(|arg| String::from(*arg))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            Probe::from_command(&command(&["vimana-probe", "liveness"])),
+            Some(Probe::Liveness),
+        );
+        assert_eq!(
+            Probe::from_command(&command(&["vimana-probe", "readiness"])),
+            Some(Probe::Readiness),
+        );
+        assert_eq!(
+            Probe::from_command(&command(&["vimana-probe", "startup"])),
+            None
+        );
+        assert_eq!(
+            Probe::from_command(&command(&["cat", "/tmp/healthy"])),
+            None
+        );
+    }
+}
@@ -1,20 +1,128 @@
 //! Host functions provided by Vimana.
 
-use std::sync::Arc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
 
 use anyhow::Result;
 use wasmtime::component::Linker;
 use wasmtime::Engine as WasmEngine;
 
+use crate::capability::Capabilities;
+use crate::handles::HandleTable;
+use crate::scratch::Scratch;
+use crate::usage::MemoryTracker;
+
 /// State available to host-defined functions.
-pub(crate) struct HostState {}
+#[derive(Clone)]
+pub(crate) struct HostState {
+    /// The pod's scratch storage, if it requested any.
+    scratch: Option<Arc<Scratch>>,
+
+    /// Environment variables visible through `wasi:cli/environment`.
+    environment: Environment,
+
+    /// What the request being handled may do through host functions.
+    capabilities: Capabilities,
+
+    /// Resources managed by the runtime on behalf of the pod's component.
+    pub(crate) handles: HandleTable,
+}
 
 impl HostState {
-    pub(crate) fn new() -> Self {
-        Self {}
+    pub(crate) fn new(scratch: Option<Arc<Scratch>>, environment: Environment) -> Self {
+        Self {
+            scratch,
+            environment,
+            capabilities: Capabilities::ALL,
+            handles: HandleTable::default(),
+        }
+    }
+
+    /// Return a copy of this state for a single request with the given capabilities.
+    pub(crate) fn with_capabilities(&self, capabilities: Capabilities) -> Self {
+        Self {
+            capabilities,
+            ..self.clone()
+        }
     }
 }
 
+/// Data owned by the store of a single instance, handling a single request.
+pub(crate) struct InstanceState {
+    /// State available to host-defined functions.
+    pub(crate) host: Arc<HostState>,
+
+    /// Counts the instance's memory towards its pod's resource usage.
+    pub(crate) memory: MemoryTracker,
+}
+
+/// A pod's environment variables, shared between the pod controller and the running component.
+///
+/// The variables can be [replaced](Self::replace) while the pod is running.
+/// Each new instance sees the latest values;
+/// instances already handling a request are unaffected.
+#[derive(Clone, Default)]
+pub(crate) struct Environment(Arc<RwLock<Variables>>);
+
+/// A snapshot of environment variables as name-value pairs, ordered by name.
+type Variables = Arc<[(String, String)]>;
+
+impl Environment {
+    pub(crate) fn new(variables: &HashMap<String, String>) -> Self {
+        Self(Arc::new(RwLock::new(sorted(variables))))
+    }
+
+    /// Swap in a new set of variables.
+    pub(crate) fn replace(&self, variables: &HashMap<String, String>) {
+        let variables = sorted(variables);
+        match self.0.write() {
+            Ok(mut guard) => *guard = variables,
+            // Readers only clone an `Arc`, so a poisoned lock still holds valid variables.
+            Err(poisoned) => *poisoned.into_inner() = variables,
+        }
+    }
+
+    /// Return the current variables as name-value pairs, ordered by name.
+    pub(crate) fn get(&self) -> Vec<(String, String)> {
+        let variables = match self.0.read() {
+            Ok(guard) => guard.clone(),
+            Err(poisoned) => poisoned.into_inner().clone(),
+        };
+        variables.to_vec()
+    }
+}
+
+/// Environment variable holding the pod's own IP address,
+/// *e.g.* for a component to build absolute URLs or register with a discovery service.
+/// This is the address other pods route to, never a loopback address.
+pub(crate) const POD_IP_VARIABLE: &str = "VIMANA_POD_IP";
+
+/// Environment variable holding the port on which the pod serves gRPC.
+pub(crate) const POD_PORT_VARIABLE: &str = "VIMANA_POD_PORT";
+
+/// Add the [pod's address](POD_IP_VARIABLE) to a container's environment variables.
+/// The pod's address takes precedence over any variables of the same name.
+pub(crate) fn with_pod_address(
+    variables: &HashMap<String, String>,
+    address: SocketAddr,
+) -> HashMap<String, String> {
+    let mut variables = variables.clone();
+    variables.insert(String::from(POD_IP_VARIABLE), address.ip().to_string());
+    variables.insert(String::from(POD_PORT_VARIABLE), address.port().to_string());
+    variables
+}
+
+/// Order variables by name so components observe a stable environment.
+fn sorted(variables: &HashMap<String, String>) -> Variables {
+    let mut variables: Vec<(String, String)> = variables
+        .iter()
+        .map(|(name, value)| (name.clone(), value.clone()))
+        .collect();
+    variables.sort();
+    Arc::from(variables)
+}
+
 pub(crate) mod wasi {
     pub(crate) mod cli {
         pub(crate) mod environment {
@@ -27,17 +135,17 @@ pub(crate) mod wasi {
             /// in the component model, this import function should return the same
             /// values each time it is called.
             pub(crate) async fn get_environment(
-                context: wasmtime::StoreContextMut<'_, std::sync::Arc<crate::host::HostState>>,
+                context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
                 parameters: (),
             ) -> anyhow::Result<(Vec<(String, String)>,)> {
-                Ok((Vec::new(),))
+                Ok((context.data().host.environment.get(),))
             }
         }
 
         pub(crate) mod exit {
             /// Exit the current instance and any linked instances.
             pub(crate) async fn exit(
-                context: wasmtime::StoreContextMut<'_, std::sync::Arc<crate::host::HostState>>,
+                _context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
                 parameters: (Result<(), ()>,),
             ) -> anyhow::Result<()> {
                 Ok(())
@@ -46,13 +154,125 @@ pub(crate) mod wasi {
     }
 }
 
+pub(crate) mod vimana {
+    pub(crate) mod host {
+        pub(crate) mod scratch {
+            use crate::capability::Capability;
+            use crate::scratch::Scratch;
+
+            /// Return the contents of the named scratch file.
+            pub(crate) async fn read(
+                context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
+                parameters: (String,),
+            ) -> anyhow::Result<(Result<Vec<u8>, String>,)> {
+                let (file,) = parameters;
+                Ok((match scratch(&context) {
+                    Ok(scratch) => scratch.read(&file).await.map_err(|e| format!("{e:#}")),
+                    Err(error) => Err(error),
+                },))
+            }
+
+            /// Replace the contents of the named scratch file, creating it if necessary.
+            pub(crate) async fn write(
+                context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
+                parameters: (String, Vec<u8>),
+            ) -> anyhow::Result<(Result<(), String>,)> {
+                let (file, contents) = parameters;
+                Ok((match scratch(&context) {
+                    Ok(scratch) => scratch
+                        .write(&file, &contents)
+                        .await
+                        .map_err(|e| format!("{e:#}")),
+                    Err(error) => Err(error),
+                },))
+            }
+
+            /// Delete the named scratch file if it exists.
+            pub(crate) async fn delete(
+                context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
+                parameters: (String,),
+            ) -> anyhow::Result<(Result<(), String>,)> {
+                let (file,) = parameters;
+                Ok((match scratch(&context) {
+                    Ok(scratch) => scratch.delete(&file).await.map_err(|e| format!("{e:#}")),
+                    Err(error) => Err(error),
+                },))
+            }
+
+            /// Return the pod's scratch area, which outlives the borrow of the store context,
+            /// if the request may use it.
+            fn scratch(
+                context: &wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
+            ) -> Result<std::sync::Arc<Scratch>, String> {
+                let state = &context.data().host;
+                state.capabilities.require(Capability::Storage)?;
+                state
+                    .scratch
+                    .clone()
+                    .ok_or_else(|| String::from("Pod has no scratch storage"))
+            }
+        }
+
+        pub(crate) mod network {
+            use anyhow::{bail, Context};
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+            use tokio::net::TcpStream;
+
+            use crate::capability::Capability;
+
+            /// Maximum size of a response to [`exchange`].
+            const MAX_RESPONSE_BYTES: u64 = 16 * 1024 * 1024;
+
+            /// Send a request to a TCP address,
+            /// and return everything the peer sends back until it closes the connection.
+            pub(crate) async fn exchange(
+                context: wasmtime::StoreContextMut<'_, crate::host::InstanceState>,
+                parameters: (String, Vec<u8>),
+            ) -> anyhow::Result<(Result<Vec<u8>, String>,)> {
+                let (address, request) = parameters;
+                if let Err(error) = context
+                    .data()
+                    .host
+                    .capabilities
+                    .require(Capability::Network)
+                {
+                    return Ok((Err(error),));
+                }
+                Ok((send(&address, &request).await.map_err(|e| format!("{e:#}")),))
+            }
+
+            /// Exchange a single request and response over a new connection to `address`.
+            async fn send(address: &str, request: &[u8]) -> anyhow::Result<Vec<u8>> {
+                let mut stream = TcpStream::connect(address)
+                    .await
+                    .with_context(|| format!("Failed to connect to {address:?}"))?;
+                stream
+                    .write_all(request)
+                    .await
+                    .context("Failed to send request")?;
+                stream.shutdown().await.context("Failed to send request")?;
+                let mut response = Vec::new();
+                stream
+                    .take(MAX_RESPONSE_BYTES + 1)
+                    .read_to_end(&mut response)
+                    .await
+                    .context("Failed to receive response")?;
+                if response.len() as u64 > MAX_RESPONSE_BYTES {
+                    bail!("Response exceeds {MAX_RESPONSE_BYTES} bytes");
+                }
+                Ok(response)
+            }
+        }
+    }
+}
+
 macro_rules! boxed {
     ($function:expr) => {
         |context, parameters| Box::new($function(context, parameters))
     };
 }
 
-pub(crate) fn grpc_linker(wasmtime: &WasmEngine) -> Result<Linker<Arc<HostState>>> {
+pub(crate) fn grpc_linker(wasmtime: &WasmEngine) -> Result<Linker<InstanceState>> {
     let mut linker = Linker::new(wasmtime);
 
     let mut environment = linker.instance("wasi:cli/environment@0.2.1")?;
@@ -64,5 +284,146 @@ pub(crate) fn grpc_linker(wasmtime: &WasmEngine) -> Result<Linker<Arc<HostState>
     let mut exit = linker.instance("wasi:cli/exit@0.2.1")?;
     exit.func_wrap_async("exit", boxed!(wasi::cli::exit::exit))?;
 
+    let mut scratch = linker.instance("vimana:host/scratch")?;
+    scratch.func_wrap_async("read", boxed!(vimana::host::scratch::read))?;
+    scratch.func_wrap_async("write", boxed!(vimana::host::scratch::write))?;
+    scratch.func_wrap_async("delete", boxed!(vimana::host::scratch::delete))?;
+
+    let mut network = linker.instance("vimana:host/network")?;
+    network.func_wrap_async("exchange", boxed!(vimana::host::network::exchange))?;
+
     Ok(linker)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use wasmtime::component::{Component, TypedFunc};
+    use wasmtime::{Config as WasmConfig, Store};
+
+    use super::*;
+    use crate::capability::{capability_policy, GRANT_ANNOTATION_PREFIX};
+    use crate::usage::ResourceUsage;
+
+    /// A component exporting `call: func(address: string) -> result<list<u8>, string>`,
+    /// which sends `ping` to the address through the network host function.
+    const CALLER: &str = r#"
+        (component
+          (import "vimana:host/network" (instance $network
+            (export "exchange" (func
+              (param "address" string)
+              (param "request" (list u8))
+              (result (result (list u8) (error string)))
+            ))
+          ))
+          (core module $libc
+            (memory (export "memory") 1)
+            (global $next (mut i32) (i32.const 1024))
+            (func (export "realloc") (param i32 i32 i32 i32) (result i32)
+              (local $pointer i32)
+              (local.set $pointer (global.get $next))
+              (global.set $next (i32.add (global.get $next) (local.get 3)))
+              (local.get $pointer)
+            )
+          )
+          (core instance $libc (instantiate $libc))
+          (core func $exchange
+            (canon lower
+              (func $network "exchange")
+              (memory $libc "memory")
+              (realloc (func $libc "realloc"))
+            )
+          )
+          (core module $m
+            (import "libc" "memory" (memory 1))
+            (import "network" "exchange" (func $exchange (param i32 i32 i32 i32 i32)))
+            (data (i32.const 16) "ping")
+            (func (export "call") (param i32 i32) (result i32)
+              (call $exchange (local.get 0) (local.get 1) (i32.const 16) (i32.const 4) (i32.const 32))
+              (i32.const 32)
+            )
+          )
+          (core instance $i
+            (instantiate $m
+              (with "libc" (instance $libc))
+              (with "network" (instance (export "exchange" (func $exchange))))
+            )
+          )
+          (func (export "call")
+            (param "address" string)
+            (result (result (list u8) (error string)))
+            (canon lift
+              (core func $i "call")
+              (memory $libc "memory")
+              (realloc (func $libc "realloc"))
+            )
+          )
+        )
+    "#;
+
+    #[tokio::test]
+    async fn test_network_capability() {
+        let wasmtime = WasmEngine::new(WasmConfig::new().async_support(true)).unwrap();
+        let linker = grpc_linker(&wasmtime).unwrap();
+        let instantiator = linker
+            .instantiate_pre(&Component::new(&wasmtime, CALLER).unwrap())
+            .unwrap();
+
+        // A peer that answers a single request with `pong`.
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let peer = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            stream.read_to_end(&mut request).await.unwrap();
+            stream.write_all(b"pong").await.unwrap();
+            request
+        });
+
+        let policy = capability_policy(&HashMap::from([
+            (
+                format!("{GRANT_ANNOTATION_PREFIX}storage"),
+                String::from("0.0.0.0/0"),
+            ),
+            (
+                format!("{GRANT_ANNOTATION_PREFIX}network"),
+                String::from("10.0.0.0/8"),
+            ),
+        ]))
+        .unwrap()
+        .unwrap();
+        let state = HostState::new(None, Environment::new(&HashMap::new()));
+        let call = |caller: IpAddr| {
+            let mut store = Store::new(
+                &wasmtime,
+                InstanceState {
+                    host: Arc::new(state.with_capabilities(policy.capabilities(Some(caller)))),
+                    memory: ResourceUsage::default().memory_tracker(),
+                },
+            );
+            let instantiator = instantiator.clone();
+            let address = address.clone();
+            async move {
+                let instance = instantiator.instantiate_async(&mut store).await.unwrap();
+                let call: TypedFunc<(String,), (Result<Vec<u8>, String>,)> =
+                    instance.get_typed_func(&mut store, "call").unwrap();
+                call.call_async(&mut store, (address,)).await.unwrap().0
+            }
+        };
+
+        // A component denied network capability fails an outbound call,
+        // without ever reaching the peer.
+        assert_eq!(
+            call(IpAddr::from([192, 168, 0, 1])).await,
+            Err(String::from("Request lacks the network capability")),
+        );
+        assert_eq!(
+            call(IpAddr::from([10, 0, 0, 1])).await,
+            Ok(b"pong".to_vec())
+        );
+        assert_eq!(peer.await.unwrap(), b"ping");
+    }
+}
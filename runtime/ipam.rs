@@ -1,14 +1,18 @@
 //! IP address management.
 
+use std::collections::HashSet;
 use std::fmt::{Display, Result as FmtResult};
-use std::io::{pipe, PipeReader, Write};
+use std::fs::metadata;
+use std::io::{pipe, PipeReader, Result as IoResult, Write};
 use std::mem::drop;
-use std::net::IpAddr;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, UdpSocket};
+use std::os::unix::fs::PermissionsExt;
 use std::simd::u8x16;
 use std::sync::Arc;
 
 use anyhow::{anyhow, Context, Result};
 use futures::stream::TryStreamExt;
+use papaya::HashMap as LockFreeConcurrentHashMap;
 use rtnetlink::{new_connection, Handle as NetlinkHandle};
 use serde::Deserialize;
 use serde_json::{from_slice, json, to_vec};
@@ -16,7 +20,7 @@ use sha2::{Digest, Sha256};
 use tokio::process::Command;
 use tokio::task::spawn;
 
-use logging::log_info;
+use logging::{log_info, log_warn};
 use names::{hexify, PodName};
 
 /// CNI plugin API version.
@@ -49,6 +53,40 @@ struct IpamInner {
 
     /// Name of the network interface to use (e.g. `eth0`).
     interface: String,
+
+    /// Number of addresses in the pod subnet.
+    pool_size: u64,
+
+    /// Every address allocated by this client and not yet de-allocated,
+    /// with its prefix length and the pod it was allocated for.
+    /// The plugin's own records are shared with the downstream runtime,
+    /// so this is the only way to tell which allocations are ours.
+    allocations: LockFreeConcurrentHashMap<IpAddr, (u8, PodName)>,
+}
+
+/// Compares IPAM allocations against the addresses held by live pods.
+///
+/// Pod creation and removal briefly disagree with IPAM by design
+/// (an address is allocated before its pod exists, and freed after its pod is killed),
+/// so a discrepancy is only reported once it persists across two consecutive audits.
+#[derive(Default)]
+pub(crate) struct IpamAudit {
+    /// Orphaned allocations found by the previous audit.
+    orphaned: HashSet<IpAddr>,
+
+    /// Unallocated pod addresses found by the previous audit.
+    unallocated: HashSet<IpAddr>,
+}
+
+/// Persistent discrepancies found by an [audit](IpamAudit).
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Divergence {
+    /// Allocated addresses held by no live pod, which would otherwise leak.
+    pub(crate) orphaned: Vec<IpAddr>,
+
+    /// Addresses held by live pods but no longer allocated,
+    /// which could be handed out again to another pod.
+    pub(crate) unallocated: Vec<IpAddr>,
 }
 
 /// An allocated and activated IP address.
@@ -84,6 +122,7 @@ impl Ipam {
             new_connection().context("Failed to establish netlink connection")?;
         spawn(connection);
 
+        let pool_size = subnet_size(pod_cidr)?;
         let config = to_vec(&json!({
             "cniVersion": CNI_VERSION,
             "name": CNI_NETWORK_NAME,
@@ -105,9 +144,54 @@ impl Ipam {
             config,
             netlink_handle,
             interface,
+            pool_size,
+            allocations: LockFreeConcurrentHashMap::new(),
         })))
     }
 
+    /// Number of addresses in the pod subnet, allocated or not.
+    /// Saturates at [`u64::MAX`] for huge IPv6 subnets.
+    pub(crate) fn pool_size(&self) -> u64 {
+        self.0.pool_size
+    }
+
+    /// Check that the IPAM plugin is an executable file,
+    /// so addresses can be allocated for new pods.
+    pub(crate) fn check_plugin(&self) -> Result<()> {
+        let metadata = metadata(&self.0.path)
+            .with_context(|| format!("IPAM plugin not found: {:?}", self.0.path))?;
+        if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 {
+            Ok(())
+        } else {
+            Err(anyhow!("IPAM plugin is not executable: {:?}", self.0.path))
+        }
+    }
+
+    /// Return every address currently allocated by this client.
+    pub(crate) fn allocated(&self) -> HashSet<IpAddr> {
+        self.0.allocations.pin().keys().copied().collect()
+    }
+
+    /// Deactivate (if still active) and de-allocate an address
+    /// that an [audit](IpamAudit) found orphaned.
+    pub(crate) async fn reclaim(&self, address: IpAddr) -> Result<()> {
+        let (prefix_length, pod_name) = self
+            .0
+            .allocations
+            .pin()
+            .get(&address)
+            .cloned()
+            .ok_or_else(|| anyhow!("Address {address} is not allocated"))?;
+        let address = IpAddress {
+            ipam: self.clone(),
+            address,
+            prefix_length,
+            pod_name,
+        };
+        // The leak may have happened because deactivation already failed, or after it succeeded.
+        address.release().await
+    }
+
     /// Allocate and return a fresh IP address.
     pub(crate) async fn address(&self, pod_name: &PodName) -> Result<IpAddress> {
         let output = self
@@ -165,6 +249,10 @@ impl Ipam {
             });
         }
 
+        self.0
+            .allocations
+            .pin()
+            .insert(address, (prefix_length, pod_name.clone()));
         Ok(IpAddress {
             ipam: self.clone(),
             address,
@@ -245,6 +333,17 @@ impl IpAddress {
         Ok(())
     }
 
+    /// [Deactivate](Self::deactivate) the address if it's still active,
+    /// then [de-allocate](Self::deallocate) it,
+    /// for when it's unclear how far the address got.
+    /// A failure to deactivate is only logged.
+    pub(crate) async fn release(&self) -> Result<()> {
+        if let Err(error) = self.deactivate().await {
+            log_warn!(pod: &self.pod_name, "{:?}", error);
+        }
+        self.deallocate().await
+    }
+
     /// De-allocate the address for re-use by other pods.
     /// It must be [deactivated](Self::deactivate) before being de-allocated.
     pub(crate) async fn deallocate(&self) -> Result<()> {
@@ -252,6 +351,7 @@ impl IpAddress {
             .run_plugin_command("DEL", &self.pod_name)
             .await
             .context("Failed to run IPAM DEL")?;
+        self.ipam.0.allocations.pin().remove(&self.address);
         log_info!(
             pod: &self.pod_name,
             "Successful IPAM deallocation: {}",
@@ -261,6 +361,63 @@ impl IpAddress {
     }
 }
 
+#[cfg(test)]
+impl IpAddress {
+    /// Return an address for `pod_name` without running the plugin or activating it,
+    /// for tests of the pod lifecycle (which need neither a plugin nor network privileges).
+    pub(crate) fn unallocated(ipam: &Ipam, address: IpAddr, pod_name: &PodName) -> Self {
+        Self {
+            ipam: ipam.clone(),
+            address,
+            prefix_length: 32,
+            pod_name: pod_name.clone(),
+        }
+    }
+}
+
+impl IpamAudit {
+    /// Compare the `allocated` addresses to those held by `live` pods,
+    /// returning the discrepancies that were also found by the previous audit.
+    pub(crate) fn compare(
+        &mut self,
+        allocated: &HashSet<IpAddr>,
+        live: &HashSet<IpAddr>,
+    ) -> Divergence {
+        let orphaned: HashSet<IpAddr> = allocated.difference(live).copied().collect();
+        let unallocated: HashSet<IpAddr> = live.difference(allocated).copied().collect();
+        let persistent = |current: &HashSet<IpAddr>, previous: &HashSet<IpAddr>| {
+            let mut persistent: Vec<IpAddr> = current.intersection(previous).copied().collect();
+            persistent.sort_unstable();
+            persistent
+        };
+        let divergence = Divergence {
+            orphaned: persistent(&orphaned, &self.orphaned),
+            unallocated: persistent(&unallocated, &self.unallocated),
+        };
+        self.orphaned = orphaned;
+        self.unallocated = unallocated;
+        divergence
+    }
+}
+
+/// Return the number of addresses in a subnet given in CIDR notation (e.g. `10.1.0.0/16`).
+fn subnet_size(cidr: &str) -> Result<u64> {
+    let (address, prefix_length) = cidr
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Missing prefix length in pod subnet: {cidr:?}"))?;
+    let address: IpAddr = address
+        .parse()
+        .with_context(|| format!("Invalid pod subnet: {cidr:?}"))?;
+    let prefix_length: u32 = prefix_length
+        .parse()
+        .with_context(|| format!("Invalid prefix length in pod subnet: {cidr:?}"))?;
+    let address_bits: u32 = if address.is_ipv4() { 32 } else { 128 };
+    let host_bits = address_bits
+        .checked_sub(prefix_length)
+        .ok_or_else(|| anyhow!("Prefix length too long in pod subnet: {cidr:?}"))?;
+    Ok(1u64.checked_shl(host_bits).unwrap_or(u64::MAX))
+}
+
 impl Display for IpAddress {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> FmtResult {
         self.address.fmt(f)
@@ -360,6 +517,20 @@ async fn lookup_interface(netlink_handle: &NetlinkHandle, interface: &str) -> Re
     }
 }
 
+/// Return `Ok` iff the kernel can route traffic to `address`.
+///
+/// Binding a socket to a pod address can succeed before the CNI plugin
+/// has finished wiring up the interface, so this asks for a route explicitly.
+/// Connecting a UDP socket performs the route lookup without sending any packets.
+pub(crate) fn routable(address: IpAddr) -> IoResult<()> {
+    let unspecified = match address {
+        IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+        IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+    };
+    // Port 9 is the discard service, though nothing is ever sent.
+    UdpSocket::bind((unspecified, 0))?.connect((address, 9))
+}
+
 /// The `host-local` IPAM plugin cannot handle characters like `:` and `@` found in pod names.
 /// Compute a legal container ID by hashing the pod name and encoding it in hexadecimal.
 fn ipam_container_id(pod: &PodName) -> String {
@@ -393,3 +564,89 @@ struct IpamAddResultIp {
     address: String,
     gateway: String,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::fs::{remove_file, set_permissions, write, Permissions};
+    use std::process;
+
+    use names::Name;
+
+    use super::*;
+
+    const POD_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.2.3#a";
+
+    #[tokio::test]
+    async fn test_release_returns_address_to_pool() {
+        // A stand-in for the `host-local` plugin that accepts any command.
+        let plugin = temp_dir().join(format!("fake-ipam-{}", process::id()));
+        write(&plugin, "#!/bin/sh\nexit 0\n").unwrap();
+        set_permissions(&plugin, Permissions::from_mode(0o755)).unwrap();
+        let ipam = Ipam::host_local(
+            plugin.to_string_lossy().into_owned(),
+            "10.1.0.0/24",
+            String::from("lo"),
+        )
+        .await
+        .unwrap();
+
+        // An address allocated for a pod that never made it into the pod map.
+        let pod_name = Name::parse(POD_NAME).pod().unwrap();
+        let address = "10.1.0.7".parse().unwrap();
+        ipam.0
+            .allocations
+            .pin()
+            .insert(address, (24, pod_name.clone()));
+        let ip_address = IpAddress {
+            ipam: ipam.clone(),
+            address,
+            prefix_length: 24,
+            pod_name,
+        };
+        assert_eq!(ipam.allocated(), HashSet::from([address]));
+
+        // The address was never activated, but it's still returned to the pool.
+        ip_address.release().await.unwrap();
+        assert!(ipam.allocated().is_empty());
+        remove_file(plugin).unwrap();
+    }
+
+    #[test]
+    fn test_audit_detects_orphan() {
+        let address = |address: &str| address.parse::<IpAddr>().unwrap();
+        let live = HashSet::from([address("10.1.0.2"), address("10.1.0.3")]);
+        // The pod holding `10.1.0.4` is gone, but its address was never de-allocated.
+        let allocated = HashSet::from([
+            address("10.1.0.2"),
+            address("10.1.0.3"),
+            address("10.1.0.4"),
+        ]);
+        let mut audit = IpamAudit::default();
+
+        // Could be a pod in the middle of removal, so give it another round.
+        assert_eq!(audit.compare(&allocated, &live), Divergence::default());
+        assert_eq!(
+            audit.compare(&allocated, &live),
+            Divergence {
+                orphaned: vec![address("10.1.0.4")],
+                unallocated: Vec::new(),
+            },
+        );
+
+        // Once reclaimed, the orphan is no longer reported.
+        let reclaimed = HashSet::from([address("10.1.0.2"), address("10.1.0.3")]);
+        assert_eq!(audit.compare(&reclaimed, &live), Divergence::default());
+
+        // A pod whose address was freed out from under it is reported the other way around.
+        let freed = HashSet::from([address("10.1.0.2")]);
+        audit.compare(&freed, &live);
+        assert_eq!(
+            audit.compare(&freed, &live),
+            Divergence {
+                orphaned: Vec::new(),
+                unallocated: vec![address("10.1.0.3")],
+            },
+        );
+    }
+}
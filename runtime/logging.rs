@@ -7,6 +7,10 @@
 #[doc(hidden)]
 pub use tracing::{event, Level};
 
+/// Target of [audit](log_audit) events,
+/// which are exempt from the log level and can be routed to a dedicated sink.
+pub const AUDIT_TARGET: &str = "audit";
+
 /// The most basic requirements for emitting a log:
 /// - Log level.
 /// - Component or pod: all log messages occur in the context of a component (or pod) name
@@ -70,6 +74,15 @@ macro_rules! log_warn {
     };
 }
 
+/// Log a warning when there really is no relevant component or pod name to use as context.
+/// Always use [`log_warn`] instead if possible.
+#[macro_export]
+macro_rules! log_warn_globally {
+    ($($arg:tt)+) => {
+        $crate::event!($crate::Level::WARN, $($arg)+);
+    };
+}
+
 #[macro_export]
 macro_rules! log_info {
     (component: $component:expr, $($arg:tt)+) => {
@@ -90,3 +103,27 @@ macro_rules! log_info_globally {
         $crate::event!($crate::Level::INFO, $($arg)+);
     };
 }
+
+/// Record a pod lifecycle operation in the audit trail,
+/// along with the CRI RPC that triggered it.
+/// Audit events are kept apart from operational logs by their [target](AUDIT_TARGET),
+/// but are tagged with the pod name like any other pod log.
+#[macro_export]
+macro_rules! log_audit {
+    (pod: $pod:expr, operation: $operation:literal, rpc: $rpc:literal) => {{
+        // Check the type of `$pod` by moving the reference.
+        let pod: &PodName = $pod;
+        $crate::event!(
+            target: $crate::AUDIT_TARGET,
+            $crate::Level::INFO,
+            domain = pod.component.server.domain.to_string(),
+            server = pod.component.server.server,
+            version = pod.component.version,
+            pod = pod.pod.to_string(),
+            operation = $operation,
+            rpc = $rpc,
+            "Pod {}",
+            $operation,
+        );
+    }};
+}
@@ -0,0 +1,347 @@
+//! Per-tenant routing of log records.
+//!
+//! Every log record emitted in the context of a component or pod
+//! carries a `domain` attribute (see the [`logging`] crate).
+//! The node-wide exporter receives every record,
+//! and a domain with a configured route additionally has its own records,
+//! and only those, exported to a dedicated sink that the tenant can read.
+//!
+//! [Audit](logging::log_audit) events are the exception:
+//! if an audit sink is configured, they are exported there and nowhere else,
+//! so the audit trail stays separate from operational logs.
+
+use std::collections::HashMap;
+use std::fmt::{Debug, Formatter, Result as FmtResult};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+
+use anyhow::{anyhow, Context, Result};
+use opentelemetry::logs::AnyValue;
+use opentelemetry::InstrumentationScope;
+use opentelemetry_sdk::error::{OTelSdkError, OTelSdkResult};
+use opentelemetry_sdk::logs::{LogBatch, LogExporter, LogProcessor, SdkLogRecord};
+use opentelemetry_sdk::Resource;
+use serde_json::{Map, Number, Value};
+
+use logging::AUDIT_TARGET;
+use names::DomainUuid;
+
+/// Name of the log record attribute identifying the tenant.
+const DOMAIN_ATTRIBUTE: &str = "domain";
+
+/// Separates the domain from the destination in a log route specification.
+const ROUTE_SEPARATOR: char = '=';
+
+/// Parse a log route of the form `<domain-id>=<path>`.
+pub(crate) fn parse_log_route(route: &str) -> Result<(DomainUuid, String)> {
+    let (domain, path) = route
+        .split_once(ROUTE_SEPARATOR)
+        .ok_or_else(|| anyhow!("Log route must look like <domain-id>=<path>: {route:?}"))?;
+    let domain = DomainUuid::parse(domain)
+        .with_context(|| format!("Invalid domain in log route: {route:?}"))?;
+    if path.is_empty() {
+        return Err(anyhow!("Empty path in log route: {route:?}"));
+    }
+    Ok((domain, String::from(path)))
+}
+
+/// Log processor that hands every record to the node-wide processor,
+/// then to the processor routed for the record's domain, if any.
+/// Audit records go only to the audit processor instead, if there is one.
+pub(crate) struct DomainLogRouter<N, T> {
+    /// Receives every record (except audit records, if there is an audit processor).
+    node: N,
+    /// Receives only the records of the domain, keyed by its canonical string form.
+    routes: HashMap<String, T>,
+    /// Receives only the audit records.
+    audit: Option<T>,
+}
+
+impl<N, T> DomainLogRouter<N, T> {
+    pub(crate) fn new(node: N, routes: HashMap<DomainUuid, T>, audit: Option<T>) -> Self {
+        Self {
+            node,
+            routes: routes
+                .into_iter()
+                .map(|(domain, processor)| (domain.to_string(), processor))
+                .collect(),
+            audit,
+        }
+    }
+
+    /// Return the audit processor if the record is an audit record.
+    fn audit(&self, record: &SdkLogRecord) -> Option<&T> {
+        self.audit
+            .as_ref()
+            .filter(|_| record.target().is_some_and(|target| target == AUDIT_TARGET))
+    }
+
+    /// Look up the processor routed for the domain that the record is tagged with.
+    fn route(&self, record: &SdkLogRecord) -> Option<&T> {
+        record
+            .attributes_iter()
+            .find(|(key, _)| key.as_str() == DOMAIN_ATTRIBUTE)
+            .and_then(|(_, value)| match value {
+                AnyValue::String(domain) => self.routes.get(domain.as_str()),
+                _ => None,
+            })
+    }
+}
+
+impl<N: Debug, T> Debug for DomainLogRouter<N, T> {
+    fn fmt(&self, formatter: &mut Formatter<'_>) -> FmtResult {
+        formatter
+            .debug_struct("DomainLogRouter")
+            .field("node", &self.node)
+            .field("routes", &self.routes.keys().collect::<Vec<_>>())
+            .field("audit", &self.audit.is_some())
+            .finish()
+    }
+}
+
+impl<N: LogProcessor, T: LogProcessor> LogProcessor for DomainLogRouter<N, T> {
+    fn emit(&self, record: &mut SdkLogRecord, scope: &InstrumentationScope) {
+        if let Some(audit) = self.audit(record) {
+            return audit.emit(record, scope);
+        }
+        self.node.emit(record, scope);
+        if let Some(processor) = self.route(record) {
+            processor.emit(record, scope);
+        }
+    }
+
+    fn force_flush(&self) -> OTelSdkResult {
+        // Flush everything even if something fails, then report the first failure.
+        let mut result = self.node.force_flush();
+        for processor in self.routes.values().chain(&self.audit) {
+            result = result.and(processor.force_flush());
+        }
+        result
+    }
+
+    fn shutdown(&self) -> OTelSdkResult {
+        let mut result = self.node.shutdown();
+        for processor in self.routes.values().chain(&self.audit) {
+            result = result.and(processor.shutdown());
+        }
+        result
+    }
+
+    fn set_resource(&mut self, resource: &Resource) {
+        self.node.set_resource(resource);
+        for processor in self.routes.values_mut().chain(&mut self.audit) {
+            processor.set_resource(resource);
+        }
+    }
+}
+
+/// Exports log records as JSON lines appended to a file,
+/// where a tenant's own collector can pick them up.
+#[derive(Debug)]
+pub(crate) struct FileLogExporter {
+    file: Mutex<File>,
+}
+
+impl FileLogExporter {
+    pub(crate) fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Unable to open log file: {path:?}"))?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+}
+
+impl LogExporter for FileLogExporter {
+    async fn export(&self, batch: LogBatch<'_>) -> OTelSdkResult {
+        let mut lines = Vec::new();
+        for (record, _) in batch.iter() {
+            serde_json::to_writer(&mut lines, &record_json(record))
+                .map_err(|error| OTelSdkError::InternalFailure(error.to_string()))?;
+            lines.push(b'\n');
+        }
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|error| OTelSdkError::InternalFailure(error.to_string()))?;
+        file.write_all(&lines)
+            .map_err(|error| OTelSdkError::InternalFailure(error.to_string()))
+    }
+}
+
+/// Flatten a log record into a JSON object with its attributes at the top level.
+fn record_json(record: &SdkLogRecord) -> Value {
+    let mut json = Map::new();
+    if let Some(timestamp) = record.timestamp().or(record.observed_timestamp()) {
+        let nanos = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        json.insert(String::from("time"), Value::from(nanos as u64));
+    }
+    if let Some(severity) = record.severity_text() {
+        json.insert(String::from("severity"), Value::from(severity));
+    }
+    if let Some(body) = record.body() {
+        json.insert(String::from("body"), any_value_json(body));
+    }
+    for (key, value) in record.attributes_iter() {
+        json.insert(key.to_string(), any_value_json(value));
+    }
+    Value::Object(json)
+}
+
+fn any_value_json(value: &AnyValue) -> Value {
+    match value {
+        AnyValue::Int(int) => Value::from(*int),
+        AnyValue::Double(double) => Number::from_f64(*double).map_or(Value::Null, Value::Number),
+        AnyValue::String(string) => Value::from(string.as_str()),
+        AnyValue::Boolean(boolean) => Value::from(*boolean),
+        AnyValue::ListAny(list) => Value::Array(list.iter().map(any_value_json).collect()),
+        AnyValue::Map(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.to_string(), any_value_json(value)))
+                .collect(),
+        ),
+        other => Value::from(format!("{other:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
+    use opentelemetry_sdk::logs::SdkLoggerProvider;
+    use tracing::subscriber::with_default;
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::registry::Registry;
+
+    use logging::{log_audit, log_info, log_info_globally};
+    use names::{ComponentName, PodName};
+
+    use super::*;
+
+    /// Collects every record it receives as JSON.
+    #[derive(Debug, Default, Clone)]
+    struct Recorder(Arc<Mutex<Vec<Value>>>);
+
+    impl Recorder {
+        fn bodies(&self) -> Vec<String> {
+            self.field("body")
+        }
+
+        fn domains(&self) -> Vec<String> {
+            self.field(DOMAIN_ATTRIBUTE)
+        }
+
+        fn field(&self, name: &str) -> Vec<String> {
+            self.0
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|record| record[name].as_str().map(String::from))
+                .collect()
+        }
+    }
+
+    impl LogProcessor for Recorder {
+        fn emit(&self, record: &mut SdkLogRecord, _: &InstrumentationScope) {
+            self.0.lock().unwrap().push(record_json(record));
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+    }
+
+    const FOO: &str = "0123456789abcdef0123456789abcdef";
+    const BAR: &str = "fedcba9876543210fedcba9876543210";
+    const BAZ: &str = "00000000000000000000000000000000";
+
+    #[test]
+    fn test_routes_by_domain() {
+        let node = Recorder::default();
+        let foo = Recorder::default();
+        let bar = Recorder::default();
+        let router = DomainLogRouter::new(
+            node.clone(),
+            HashMap::from([
+                (DomainUuid::parse(FOO).unwrap(), foo.clone()),
+                (DomainUuid::parse(BAR).unwrap(), bar.clone()),
+            ]),
+            None,
+        );
+        let provider = SdkLoggerProvider::builder()
+            .with_log_processor(router)
+            .build();
+        let subscriber = Registry::default().with(OpenTelemetryTracingBridge::new(&provider));
+
+        let component = |domain| {
+            ComponentName::new(DomainUuid::parse(domain).unwrap(), "server", "1.0.0").unwrap()
+        };
+        with_default(subscriber, || {
+            log_info!(component: &component(FOO), "foo");
+            log_info!(component: &component(BAR), "bar");
+            log_info!(component: &component(BAZ), "baz");
+            log_info_globally!("global");
+        });
+
+        assert_eq!(node.bodies(), vec!["foo", "bar", "baz", "global"]);
+        assert_eq!(foo.bodies(), vec!["foo"]);
+        assert_eq!(bar.bodies(), vec!["bar"]);
+        assert_eq!(foo.domains(), vec![FOO]);
+        assert_eq!(bar.domains(), vec![BAR]);
+    }
+
+    #[test]
+    fn test_routes_audit_records() {
+        let node = Recorder::default();
+        let foo = Recorder::default();
+        let audit = Recorder::default();
+        let router = DomainLogRouter::new(
+            node.clone(),
+            HashMap::from([(DomainUuid::parse(FOO).unwrap(), foo.clone())]),
+            Some(audit.clone()),
+        );
+        let provider = SdkLoggerProvider::builder()
+            .with_log_processor(router)
+            .build();
+        let subscriber = Registry::default().with(OpenTelemetryTracingBridge::new(&provider));
+
+        let component =
+            ComponentName::new(DomainUuid::parse(FOO).unwrap(), "server", "1.0.0").unwrap();
+        let pod = PodName::new(component.clone(), 7);
+        with_default(subscriber, || {
+            log_info!(pod: &pod, "Successful pod kill");
+            log_audit!(pod: &pod, operation: "killed", rpc: "StopPodSandbox");
+        });
+
+        // Audit records go to the audit sink only.
+        assert_eq!(node.bodies(), vec!["Successful pod kill"]);
+        assert_eq!(foo.bodies(), vec!["Successful pod kill"]);
+        assert_eq!(audit.bodies(), vec!["Pod killed"]);
+        assert_eq!(audit.domains(), vec![FOO]);
+        assert_eq!(audit.field("operation"), vec!["killed"]);
+        assert_eq!(audit.field("rpc"), vec!["StopPodSandbox"]);
+        assert_eq!(audit.field("pod"), vec![pod.pod.to_string()]);
+    }
+
+    #[test]
+    fn test_parse_log_route() {
+        let (domain, path) = parse_log_route(&format!("{FOO}=/var/log/foo.jsonl")).unwrap();
+        assert_eq!(domain.to_string(), FOO);
+        assert_eq!(path, "/var/log/foo.jsonl");
+
+        assert!(parse_log_route("/var/log/foo.jsonl").is_err());
+        assert!(parse_log_route("not-a-domain=/var/log/foo.jsonl").is_err());
+        assert!(parse_log_route(&format!("{FOO}=")).is_err());
+    }
+}
@@ -9,41 +9,71 @@
 //!   handles orchestration requests from Kubelet.
 #![feature(portable_simd)]
 
+mod admission;
+mod affinity;
+mod cache;
+mod capability;
+mod connections;
 mod containers;
 mod cri;
+mod descriptor;
+mod framing;
+mod handles;
+mod health;
 mod host;
 mod ipam;
+mod logs;
 mod pods;
+mod policy;
+mod reload;
+mod scratch;
+mod shutdown;
+mod startup;
 mod state;
+mod status;
+mod throttle;
+mod usage;
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
 use std::error::Error as StdError;
-use std::fs::{create_dir_all, remove_file, File};
-use std::io::BufReader;
+use std::fs::{create_dir_all, read_to_string, remove_file, File};
+use std::io::{stdout, BufReader, Write};
 use std::path::Path;
 use std::result::Result as StdResult;
+use std::str::FromStr;
+use std::time::Duration;
 
-use anyhow::Context;
-use clap::Parser;
+use anyhow::{Context, Result};
+use clap::{CommandFactory, Parser, Subcommand};
+use clap_complete::{generate, Shell};
 use futures::FutureExt;
 use hyper_util::rt::TokioIo;
+use opentelemetry::global;
 use opentelemetry_appender_tracing::layer::OpenTelemetryTracingBridge;
-use opentelemetry_sdk::logs::LoggerProviderBuilder;
-use opentelemetry_stdout::LogExporter as StdoutLogExporter;
+use opentelemetry_sdk::logs::{LoggerProviderBuilder, SimpleLogProcessor};
+use opentelemetry_sdk::metrics::SdkMeterProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use opentelemetry_stdout::{
+    LogExporter as StdoutLogExporter, MetricExporter as StdoutMetricExporter,
+    SpanExporter as StdoutSpanExporter,
+};
 use serde::Deserialize;
 use serde_json::from_reader;
 use tokio::net::{UnixListener, UnixStream};
 use tokio::select;
 use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::oneshot;
+use tokio::task::spawn;
 use tokio_stream::wrappers::UnixListenerStream;
 use tonic::transport::{Endpoint, Server};
 use tower::service_fn;
-use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::filter::{filter_fn, FilterExt, LevelFilter};
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::registry::Registry;
+use tracing_subscriber::reload::Layer as ReloadLayer;
 use wasmtime::{Config as WasmConfig, Engine as WasmEngine};
 
+use admission::tick_epochs;
 use api_proto::runtime::v1::image_service_client::ImageServiceClient;
 use api_proto::runtime::v1::image_service_server::ImageServiceServer;
 use api_proto::runtime::v1::runtime_service_client::RuntimeServiceClient;
@@ -51,8 +81,15 @@ use api_proto::runtime::v1::runtime_service_server::RuntimeServiceServer;
 use containers::ContainerStore;
 use cri::image::ProxyingImageService;
 use cri::runtime::{ProxyingRuntimeService, CONTAINER_RUNTIME_NAME, CONTAINER_RUNTIME_VERSION};
+use cri::{RuntimeHandler, UnknownHandlerPolicy};
+use decode::{DecoderOptions, ErrorVerbosity};
 use ipam::Ipam;
-use state::WorkRuntime;
+use logging::AUDIT_TARGET;
+use logs::{parse_log_route, DomainLogRouter, FileLogExporter};
+use pods::PodInitializer;
+use reload::{reload_on_hangup, Reloadable};
+use scratch::ScratchStore;
+use state::{node_id_from_hostname, PodLimits, WorkRuntime};
 
 /// Default value for [`VimanadConfig::incoming`].
 const DEFAULT_INCOMING: &str = "/run/vimana/vimanad.sock";
@@ -66,11 +103,34 @@ const DEFAULT_IPAM_PLUGIN: &str = "/opt/cni/bin/host-local";
 const DEFAULT_NETWORK_INTERFACE: &str = "eth0";
 /// Default value for [`VimanadConfig::pod_ips`].
 const DEFAULT_POD_IPS: &str = "10.1.0.0/16";
+/// Default value for [`VimanadConfig::scratch_store`].
+const DEFAULT_SCRATCH_STORE: &str = "/var/lib/vimana/scratch";
+/// Default value for [`VimanadConfig::ipam_audit_period`].
+const DEFAULT_IPAM_AUDIT_PERIOD: u64 = 60;
+/// Default value for [`VimanadConfig::pod_drain_timeout`].
+const DEFAULT_POD_DRAIN_TIMEOUT: u64 = 1;
+/// Where the kernel exposes the node's hostname,
+/// from which [`VimanadConfig::node_id`] is derived by default.
+const HOSTNAME_PATH: &str = "/proc/sys/kernel/hostname";
+/// Default value for [`VimanadConfig::log_level`].
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::INFO;
+/// Default value for [`VimanadConfig::runtime_handler`].
+const DEFAULT_RUNTIME_HANDLER: &str = "vimana-handler";
+
+/// Name of the runtime's executable, for which shell completions are generated.
+const EXECUTABLE_NAME: &str = "vimanad";
+
+/// Wasm proposals enabled in the engine (see `main`), as reported in the node status.
+const WASM_FEATURES: [&str; 4] = ["component-model", "gc", "tail-call", "function-references"];
 
 /// Vimana work node runtime.
 ///
 /// Every option is configurable as a command-line argument or in the configuration file located at `config`.
 /// Command-line options take precedence.
+///
+/// On SIGHUP, the configuration file is read again and `log_level` and `insecure_registries`
+/// take effect immediately, without disturbing running pods.
+/// Changes to any other option require a restart.
 #[derive(Parser, Deserialize, Default)]
 #[serde(rename_all = "camelCase")]
 #[command(name = CONTAINER_RUNTIME_NAME, version = CONTAINER_RUNTIME_VERSION, verbatim_doc_comment)]
@@ -93,10 +153,50 @@ struct VimanadConfig {
     #[arg(long, value_name = "PATH")]
     image_store: Option<String>,
 
-    /// Container registries that should be pulled from using HTTP rather than HTTPS
+    /// Root filesystem path under which to provision ephemeral per-pod scratch storage
+    #[arg(long, value_name = "PATH")]
+    scratch_store: Option<String>,
+
+    /// Compress pulled images on disk with zstd at this level (1-22),
+    /// trading slower pod initialization for less disk usage.
+    /// Images are stored uncompressed if unset
+    #[arg(long, value_name = "LEVEL")]
+    image_compression_level: Option<i32>,
+
+    /// Maximum number of distinct components whose codecs are kept in memory.
+    /// Least recently used components without running pods are evicted first.
+    /// Unlimited if unset
+    #[arg(long, value_name = "COUNT")]
+    max_cached_components: Option<usize>,
+
+    /// Minimum level of log records to emit:
+    /// `off`, `error`, `warn`, `info`, `debug`, or `trace` (default: info).
+    /// Reloaded on SIGHUP
+    #[arg(long, value_name = "LEVEL")]
+    log_level: Option<String>,
+
+    /// Additionally export the logs of a tenant's components to a file of its own
+    /// (`<domain-id>=<path>`), as JSON lines, without the logs of any other tenant
+    #[arg(long, value_name = "ROUTE")]
+    log_routes: Vec<String>,
+
+    /// Export an audit trail of pod lifecycle operations to this file, as JSON lines,
+    /// instead of mixing it into the operational logs.
+    /// Audit events are emitted regardless of the log level
+    #[arg(long, value_name = "PATH")]
+    audit_log: Option<String>,
+
+    /// Container registries that should be pulled from using HTTP rather than HTTPS.
+    /// Registries may only redirect to plain HTTP if the target host is listed too.
+    /// Reloaded on SIGHUP
     #[arg(long, value_name = "HOST")]
     insecure_registries: Vec<String>,
 
+    /// Images (`<registry>/<domain-id>/<server-id>:<version>`) likely to be hosted on this node,
+    /// to pull and prepare at startup so their first pods start faster
+    #[arg(long, value_name = "IMAGE")]
+    warm_images: Vec<String>,
+
     /// Path to a CNI plugin to handle IPAM
     #[arg(long, value_name = "PATH")]
     ipam_plugin: Option<String>,
@@ -110,6 +210,141 @@ struct VimanadConfig {
     /// Exclusive subnet for all IP addresses that can be allocated to pods on this node
     #[arg(long, value_name = "CIDR")]
     pod_ips: Option<String>,
+
+    /// Name of the K8s runtime handler for which pods and images are served by Vimana
+    /// (all other handlers are forwarded downstream)
+    #[arg(long, value_name = "NAME")]
+    runtime_handler: Option<String>,
+
+    /// Runtime handlers served by the downstream OCI runtime
+    /// (the default, empty handler is always included)
+    #[arg(long, value_name = "NAME")]
+    downstream_handlers: Vec<String>,
+
+    /// What to do with pods requesting a runtime handler
+    /// that is neither `runtime_handler` nor one of `downstream_handlers`
+    #[arg(long, value_enum, value_name = "POLICY")]
+    unknown_handler_policy: Option<UnknownHandlerPolicy>,
+
+    /// How often to compare IPAM allocations against the pods on this node, in seconds,
+    /// to detect leaked or prematurely freed addresses (default: 60)
+    #[arg(long, value_name = "SECONDS")]
+    ipam_audit_period: Option<u64>,
+
+    /// De-allocate addresses that the IPAM audit finds allocated to no live pod
+    #[arg(long)]
+    #[serde(default)]
+    reclaim_orphaned_ips: bool,
+
+    /// Count how often each request field is present on the wire,
+    /// exported as a metric per component and field (off by default)
+    #[arg(long)]
+    #[serde(default)]
+    field_presence: bool,
+
+    /// Export a span for decoding each request and encoding each response,
+    /// tagged with the component and version, to attribute latency per component (off by default)
+    #[arg(long)]
+    #[serde(default)]
+    trace_requests: bool,
+
+    /// Reject requests with field numbers larger than any declared in the component's schema,
+    /// rather than skipping them like other unknown fields
+    #[arg(long)]
+    #[serde(default)]
+    limit_field_numbers: bool,
+
+    /// Reject requests with enum numbers outside the 32-bit signed range,
+    /// rather than truncating them to 32 bits like other Protobuf parsers
+    #[arg(long)]
+    #[serde(default)]
+    strict_enum_numbers: bool,
+
+    /// Reject requests with maps in which the same key appears more than once,
+    /// rather than keeping the last value like other Protobuf parsers
+    #[arg(long)]
+    #[serde(default)]
+    unique_map_keys: bool,
+
+    /// Reject requests with messages nested more than this many levels deep (default: 100)
+    #[arg(long, value_name = "DEPTH")]
+    max_decode_depth: Option<u32>,
+
+    /// Reject requests with more than this many elements in any one repeated field
+    /// (default: 1000000)
+    #[arg(long, value_name = "COUNT")]
+    max_repeated_elements: Option<u32>,
+
+    /// Return only a generic message to clients that send malformed requests,
+    /// rather than the field path and offset of the error (which are still logged)
+    #[arg(long)]
+    #[serde(default)]
+    redact_decode_errors: bool,
+
+    /// Serve each component's gRPC service descriptors from its pods
+    /// as a binary `FileDescriptorSet`, for gateways that route without the original `.proto`
+    #[arg(long)]
+    #[serde(default)]
+    serve_descriptors: bool,
+
+    /// Maximum number of connections each pod may hold open at once,
+    /// unless its component sets its own limit (default: unlimited)
+    #[arg(long, value_name = "COUNT")]
+    max_connections_per_pod: Option<usize>,
+
+    /// Maximum number of requests each pod may handle at once,
+    /// unless its component sets its own limit (default: unlimited).
+    /// Waiting requests are admitted in order of their method's priority
+    /// (see the `vimana.host/request-priorities` pod annotation).
+    #[arg(long, value_name = "COUNT")]
+    max_concurrent_requests_per_pod: Option<usize>,
+
+    /// How long a pod stopped with its sandbox (`StopPodSandbox`) may spend draining
+    /// in-flight requests, such as long-lived streams, before it is killed forcefully,
+    /// in seconds (default: 1).
+    /// Stopping just the container honors the grace period given by the Kubelet instead
+    #[arg(long, value_name = "SECONDS")]
+    pod_drain_timeout: Option<u64>,
+
+    /// Identifier of this node, embedded in the upper bits of every pod ID
+    /// so pod IDs are unique across the cluster
+    /// (default: derived from the node's hostname)
+    #[arg(long, value_name = "ID")]
+    node_id: Option<u32>,
+
+    /// Maximum number of pods each domain may create on this node per second,
+    /// also allowing bursts of up to that many pods at once (default: unlimited).
+    /// Pod sandboxes requested beyond the limit fail with `RESOURCE_EXHAUSTED`
+    #[arg(long, value_name = "PODS")]
+    max_pod_creations_per_domain: Option<u32>,
+
+    /// Run a command instead of serving,
+    /// e.g. to debug an already-running runtime
+    #[command(subcommand)]
+    #[serde(skip)]
+    command: Option<Command>,
+}
+
+/// Commands for interactive use on a node.
+#[derive(Subcommand)]
+enum Command {
+    /// Stream container lifecycle events from the CRI socket at `incoming`
+    Events {
+        /// Only show events for pods of this component (`<domain>:<server>@<version>`)
+        #[arg(long, value_name = "NAME")]
+        component: Option<String>,
+    },
+
+    /// Print a completion script for the given shell to stdout
+    Completions {
+        #[arg(value_name = "SHELL")]
+        shell: Shell,
+    },
+}
+
+/// Write a completion script for every option and command to `out`.
+fn completions(shell: Shell, out: &mut impl Write) {
+    generate(shell, &mut VimanadConfig::command(), EXECUTABLE_NAME, out);
 }
 
 #[tokio::main]
@@ -117,13 +352,23 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
     // Read configuration from the command-line first,
     // falling back on the JSON configuration file for unset fields.
     let args = VimanadConfig::parse();
-    let config = args.config.map_or(VimanadConfig::default(), |config_path| {
-        from_reader(BufReader::new(
-            File::open(&config_path)
-                .expect(&format!("Error opening config file '{}'", config_path)),
-        ))
-        .expect(&format!("Error parsing config file '{}'", config_path))
-    });
+    if let Some(Command::Completions { shell }) = args.command {
+        completions(shell, &mut stdout());
+        return Ok(());
+    }
+    let config = read_config(args.config.as_deref())?;
+
+    // Keep the command-line values of hot-reloadable options to apply again on SIGHUP.
+    let config_path = args.config.clone();
+    let reload_args = VimanadConfig {
+        log_level: args.log_level.clone(),
+        insecure_registries: args.insecure_registries.clone(),
+        ..Default::default()
+    };
+    let Reloadable {
+        log_level,
+        insecure_registries,
+    } = reloadable(&args, &config)?;
 
     // Select all options from command-line first, config file second, default value third.
     let incoming = args
@@ -138,11 +383,40 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
         .image_store
         .or(config.image_store)
         .unwrap_or(String::from(DEFAULT_IMAGE_STORE));
-    let insecure_registries = args
-        .insecure_registries
+    let image_compression_level = args
+        .image_compression_level
+        .or(config.image_compression_level);
+    let max_cached_components = args.max_cached_components.or(config.max_cached_components);
+    let max_connections_per_pod = args
+        .max_connections_per_pod
+        .or(config.max_connections_per_pod);
+    let max_concurrent_requests_per_pod = args
+        .max_concurrent_requests_per_pod
+        .or(config.max_concurrent_requests_per_pod);
+    let pod_drain_timeout = args
+        .pod_drain_timeout
+        .or(config.pod_drain_timeout)
+        .unwrap_or(DEFAULT_POD_DRAIN_TIMEOUT);
+    let max_pod_creations_per_domain = args
+        .max_pod_creations_per_domain
+        .or(config.max_pod_creations_per_domain);
+    let node_id = match args.node_id.or(config.node_id) {
+        Some(node_id) => node_id,
+        None => node_id_from_hostname(
+            read_to_string(HOSTNAME_PATH)
+                .context("Error reading hostname")?
+                .trim(),
+        ),
+    };
+    let scratch_store = args
+        .scratch_store
+        .or(config.scratch_store)
+        .unwrap_or(String::from(DEFAULT_SCRATCH_STORE));
+    let warm_images = args
+        .warm_images
         .into_iter()
-        .chain(config.insecure_registries.into_iter())
-        .collect::<HashSet<_>>();
+        .chain(config.warm_images.into_iter())
+        .collect::<Vec<_>>();
     let ipam_plugin = args
         .ipam_plugin
         .or(config.ipam_plugin)
@@ -155,14 +429,91 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
         .pod_ips
         .or(config.pod_ips)
         .unwrap_or(String::from(DEFAULT_POD_IPS));
+    let ipam_audit_period = args
+        .ipam_audit_period
+        .or(config.ipam_audit_period)
+        .unwrap_or(DEFAULT_IPAM_AUDIT_PERIOD);
+    let reclaim_orphaned_ips = args.reclaim_orphaned_ips || config.reclaim_orphaned_ips;
+    let runtime_handler = RuntimeHandler::new(
+        &args
+            .runtime_handler
+            .or(config.runtime_handler)
+            .unwrap_or(String::from(DEFAULT_RUNTIME_HANDLER)),
+        &args
+            .downstream_handlers
+            .into_iter()
+            .chain(config.downstream_handlers.into_iter())
+            .collect::<Vec<_>>(),
+        args.unknown_handler_policy
+            .or(config.unknown_handler_policy)
+            .unwrap_or_default(),
+    );
+
+    let decoder_options = DecoderOptions {
+        field_presence: args.field_presence || config.field_presence,
+        limit_field_numbers: args.limit_field_numbers || config.limit_field_numbers,
+        strict_enum_numbers: args.strict_enum_numbers || config.strict_enum_numbers,
+        unique_map_keys: args.unique_map_keys || config.unique_map_keys,
+        max_depth: args.max_decode_depth.or(config.max_decode_depth),
+        max_repeated_elements: args.max_repeated_elements.or(config.max_repeated_elements),
+        error_verbosity: if args.redact_decode_errors || config.redact_decode_errors {
+            ErrorVerbosity::Redacted
+        } else {
+            ErrorVerbosity::Full
+        },
+    };
+    let serve_descriptors = args.serve_descriptors || config.serve_descriptors;
+    let trace_requests = args.trace_requests || config.trace_requests;
+
+    if let Some(Command::Events { component }) = args.command {
+        return Ok(cri::events::tail(&incoming, component.as_deref()).await?);
+    }
 
+    let log_routes = args
+        .log_routes
+        .iter()
+        .chain(config.log_routes.iter())
+        .map(|route| {
+            let (domain, path) = parse_log_route(route)?;
+            let exporter = FileLogExporter::open(path)?;
+            Ok((domain, SimpleLogProcessor::new(exporter)))
+        })
+        .collect::<Result<HashMap<_, _>>>()?;
+    let audit_log = args
+        .audit_log
+        .or(config.audit_log)
+        .map(FileLogExporter::open)
+        .transpose()?
+        .map(SimpleLogProcessor::new);
     let logger_provider = LoggerProviderBuilder::default()
-        .with_simple_exporter(StdoutLogExporter::default())
+        .with_log_processor(DomainLogRouter::new(
+            SimpleLogProcessor::new(StdoutLogExporter::default()),
+            log_routes,
+            audit_log,
+        ))
         .build();
+    let (log_level, log_level_handle) = ReloadLayer::new(log_level);
     Registry::default()
-        .with(LevelFilter::INFO)
-        .with(OpenTelemetryTracingBridge::new(&logger_provider))
+        .with(
+            OpenTelemetryTracingBridge::new(&logger_provider).with_filter(
+                // The audit trail must be complete, whatever the log level.
+                log_level.or(filter_fn(|metadata| metadata.target() == AUDIT_TARGET)),
+            ),
+        )
         .init();
+    global::set_meter_provider(
+        SdkMeterProvider::builder()
+            .with_periodic_exporter(StdoutMetricExporter::default())
+            .build(),
+    );
+    // Without a tracer provider, spans are no-ops.
+    if trace_requests {
+        global::set_tracer_provider(
+            SdkTracerProvider::builder()
+                .with_batch_exporter(StdoutSpanExporter::default())
+                .build(),
+        );
+    }
 
     // This seems to be the most idiomatic way to create a client with a UDS transport:
     // https://github.com/hyperium/tonic/blob/v0.12.3/examples/src/uds/client.rs.
@@ -209,16 +560,57 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
             .async_support(true)
             // Epoch interruption for preemptive multithreading.
             // https://docs.rs/wasmtime/latest/wasmtime/struct.Config.html#method.epoch_interruption
-            //.epoch_interruption(true)
-            // Enable support for various Wasm proposals...
+            .epoch_interruption(true)
+            // Enable support for various Wasm proposals (keep `WASM_FEATURES` in sync)...
             .wasm_component_model(true)
             .wasm_gc(true)
             .wasm_tail_call(true)
             .wasm_function_references(true),
     )?;
 
-    let containers = ContainerStore::new(&image_store, insecure_registries, &wasmtime)?;
-    let runtime = WorkRuntime::new(wasmtime, containers.clone(), ipam, shutdown_rx.shared());
+    // Running requests reach a preemption point at every tick.
+    spawn(tick_epochs(wasmtime.clone()));
+
+    let containers = ContainerStore::new(
+        &image_store,
+        insecure_registries,
+        &wasmtime,
+        image_compression_level,
+    )?;
+    let pod_store = PodInitializer::new(
+        containers.clone(),
+        decoder_options,
+        max_cached_components,
+        serve_descriptors,
+        max_concurrent_requests_per_pod,
+    );
+    let runtime = WorkRuntime::new(
+        wasmtime,
+        pod_store,
+        ipam,
+        ScratchStore::new(&scratch_store),
+        shutdown_rx.shared(),
+        node_id,
+        PodLimits {
+            max_connections: max_connections_per_pod,
+            pod_drain_timeout: Duration::from_secs(pod_drain_timeout),
+            max_pod_creations_per_domain,
+        },
+    );
+
+    spawn(reload_on_hangup(
+        signal(SignalKind::hangup())
+            .unwrap_or_else(|err| panic!("Cannot listen for SIGHUP: {err}")),
+        move || reloadable(&reload_args, &read_config(config_path.as_deref())?),
+        log_level_handle,
+        containers.clone(),
+    ));
+
+    runtime.audit_addresses(Duration::from_secs(ipam_audit_period), reclaim_orphaned_ips);
+
+    // Warm up in the background so it doesn't delay serving CRI requests.
+    let warmer = runtime.pod_store.clone();
+    spawn(async move { warmer.warm(&warm_images).await });
 
     // Bind to our CRI API socket.
     // This is last fallible thing before starting the CRI API server
@@ -228,12 +620,18 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
     let cri_listener =
         UnixListener::bind(&incoming).expect(&format!("Cannot bind Unix socket '{}'", &incoming));
 
+    // The runtime serves until the process exits, and the OS reclaims its memory faster
+    // than dropping thousands of pods and codecs one at a time on the way out.
+    runtime.leak_on_exit();
+
     let result = Server::builder()
         .add_service(RuntimeServiceServer::new(
-            ProxyingRuntimeService::new(runtime, oci_runtime_client).await?,
+            ProxyingRuntimeService::new(runtime, runtime_handler.clone(), oci_runtime_client)
+                .await?,
         ))
         .add_service(ImageServiceServer::new(ProxyingImageService::new(
             containers,
+            runtime_handler,
             oci_image_client,
         )))
         .serve_with_incoming_shutdown(UnixListenerStream::new(cri_listener), shutdown_signal)
@@ -246,3 +644,55 @@ async fn main() -> StdResult<(), Box<dyn StdError>> {
     result?;
     Ok(unlink_socket_result?)
 }
+
+/// Read the JSON configuration file at `path`, if any.
+fn read_config(path: Option<&str>) -> Result<VimanadConfig> {
+    let Some(path) = path else {
+        return Ok(VimanadConfig::default());
+    };
+    let file = File::open(path).with_context(|| format!("Error opening config file {path:?}"))?;
+    from_reader(BufReader::new(file)).with_context(|| format!("Error parsing config file {path:?}"))
+}
+
+/// Select the [hot-reloadable](Reloadable) options
+/// from the command-line first, config file second, default value third.
+fn reloadable(args: &VimanadConfig, config: &VimanadConfig) -> Result<Reloadable> {
+    let log_level = match args.log_level.as_ref().or(config.log_level.as_ref()) {
+        Some(level) => {
+            LevelFilter::from_str(level).with_context(|| format!("Invalid log level {level:?}"))?
+        }
+        None => DEFAULT_LOG_LEVEL,
+    };
+    let insecure_registries = args
+        .insecure_registries
+        .iter()
+        .chain(config.insecure_registries.iter())
+        .cloned()
+        .collect::<HashSet<_>>();
+    Ok(Reloadable {
+        log_level,
+        insecure_registries,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_completions() {
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish, Shell::PowerShell] {
+            let mut script = Vec::new();
+            completions(shell, &mut script);
+            let script = String::from_utf8(script).unwrap();
+            assert!(
+                script.contains("events"),
+                "{shell} completions lack subcommands"
+            );
+            assert!(
+                script.contains("max-decode-depth"),
+                "{shell} completions lack options",
+            );
+        }
+    }
+}
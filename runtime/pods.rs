@@ -1,47 +1,162 @@
 //! General server boilerplate for all data-plane services.
 
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::future::Future;
+use std::mem::forget;
+use std::net::IpAddr;
 use std::pin::Pin;
 use std::result::Result as StdResult;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 
-use anyhow::{anyhow, Context, Error, Result};
+use anyhow::{anyhow, bail, Context, Error, Result};
 use axum::body::Body as AxumBody;
-use axum::routing::method_routing::post;
+use axum::routing::method_routing::{get, post};
+use bytes::{Buf, Bytes};
 use futures::future::Shared;
 use futures::FutureExt;
-use http::{Request as HttpRequest, Response as HttpResponse};
+use http::header::CONTENT_TYPE;
+use http::{HeaderValue, Request as HttpRequest, Response as HttpResponse};
+use http_body_util::BodyExt;
+use opentelemetry::metrics::{Counter, ObservableCounter};
+use opentelemetry::trace::{Span, Status as SpanStatus, Tracer};
+use opentelemetry::{global, KeyValue};
+use papaya::HashMap as LockFreeConcurrentHashMap;
+use prost::Message;
 use tokio::task::spawn;
 use tonic::body::BoxBody;
-use tonic::codec::{Codec as TonicCodec, EnabledCompressionEncodings};
-use tonic::metadata::KeyAndValueRef;
+use tonic::codec::{
+    Codec as TonicCodec, DecodeBuf, Decoder as TonicDecoder, EnabledCompressionEncodings,
+    EncodeBuf, Encoder as TonicEncoder,
+};
+use tonic::metadata::{KeyAndValueRef, MetadataMap};
 use tonic::server::{Grpc, UnaryService};
 use tonic::service::Routes;
+use tonic::transport::server::TcpConnectInfo;
 use tonic::{Request as TonicRequest, Response as TonicResponse, Status};
-use wasmtime::component::{ComponentExportIndex, InstancePre, Val};
-use wasmtime::{Engine as WasmEngine, Store};
+use wasmtime::component::types::ComponentItem;
+use wasmtime::component::{Component, ComponentExportIndex, InstancePre, Type, Val};
+use wasmtime::{Engine as WasmEngine, Store, UpdateDeadline};
 
+use crate::admission::{self, MethodPriorities, RequestQueue, Slot};
+use crate::cache::ResponseCache;
+use crate::capability::CapabilityPolicy;
 use crate::containers::ContainerStore;
-use crate::host::{grpc_linker, HostState};
+use crate::cri::image::registry_and_component_from_image_spec;
+use crate::descriptor::{descriptor_set, DESCRIPTOR_PATH};
+use crate::framing::check_framing;
+use crate::handles::{link_handles, ResourceFields};
+use crate::host::{grpc_linker, Environment, HostState, InstanceState};
+use crate::scratch::Scratch;
 use crate::state::SingleUse;
-use decode::RequestDecoder;
+use crate::status::ErrorMapping;
+use crate::usage::ResourceUsage;
+use api_proto::runtime::v1::{ImageSpec, PodSandboxMetadata};
+use decode::{DecoderOptions, RequestDecoder};
 use encode::ResponseEncoder;
-use logging::log_warn;
-use metadata_proto::work::runtime::Field;
+use logging::{log_error, log_info, log_warn, log_warn_globally};
+use metadata_proto::work::runtime::field::{Coding, CompoundCoding};
+use metadata_proto::work::runtime::{Field, GrpcMethod, Metadata, ResponseCaching};
 use names::ComponentName;
 
 /// gRPC pods always use this arbitrarily chosen port for networking.
 pub(crate) const GRPC_PORT: u16 = 80;
 
+/// Name of the meter for all runtime metrics.
+const METER_NAME: &str = "vimanad";
+
+/// Name of the tracer for all runtime spans.
+const TRACER_NAME: &str = "vimanad";
+
+/// Opt-in metric counting how often each request field is present,
+/// e.g. to find fields that can be safely deprecated.
+const FIELD_PRESENCE_METRIC: &str = "vimana.decode.field_presence";
+
+/// Metric counting requests rejected as malformed, by domain,
+/// so that a misbehaving client of any one service stands out.
+const MALFORMED_REQUESTS_METRIC: &str = "vimana.decode.malformed_requests";
+
+/// Response trailer identifying the component (including version) that served a request,
+/// e.g. for canary analysis by clients and the gateway.
+pub(crate) const COMPONENT_TRAILER: &str = "vimana-component";
+
 /// Initializes pods in the background.
 ///
 /// Unlike regular asynchronous functions,
 /// returned futures are [`Shared`], so they can be polled by multiple threads,
 /// and work begins immediately without having to poll them.
+#[derive(Clone)]
 pub(crate) struct PodInitializer {
     /// Means to fetch containers from an external registry.
     containers: ContainerStore,
+
+    /// Codecs shared by every pod of the same component.
+    codecs: CodecCache,
+
+    /// Whether gRPC pods serve their component's Protobuf descriptors at [`DESCRIPTOR_PATH`].
+    serve_descriptors: bool,
+
+    /// Default [limit](crate::admission) on the number of requests each pod handles at once.
+    /// Components may override it in their metadata. Unlimited if unset.
+    max_concurrent_requests: Option<usize>,
+}
+
+/// How a pod handles each request, as configured by the pod's annotations.
+#[derive(Clone, Default)]
+pub(crate) struct RequestPolicy {
+    /// Restricts the host state of each request by caller, if set.
+    pub(crate) capabilities: Option<Arc<CapabilityPolicy>>,
+
+    /// Admission priorities of the pod's methods.
+    pub(crate) priorities: Arc<MethodPriorities>,
+}
+
+/// Request decoders and response encoders for every method of a component,
+/// keyed by component name.
+///
+/// Building codecs from metadata is the bulk of pod initialization
+/// after the component itself is loaded,
+/// so they are built once per component (or ahead of time by [warming](PodInitializer::warm))
+/// and reused by later pods.
+///
+/// If the number of cached components is limited,
+/// the least recently used components are evicted first,
+/// preferring those whose codecs are not in use by any pod.
+#[derive(Clone, Default)]
+struct CodecCache {
+    codecs: Arc<LockFreeConcurrentHashMap<ComponentName, Arc<CachedCodecs>>>,
+
+    /// Logical clock for least-recently-used eviction, ticked on every lookup.
+    clock: Arc<AtomicU64>,
+
+    /// Maximum number of components whose codecs are kept. Unlimited if unset.
+    max_components: Option<usize>,
+
+    /// Optional behavior for every request decoder built by this cache.
+    /// Field presence is exported as a [metric](FIELD_PRESENCE_METRIC).
+    decoder_options: DecoderOptions,
+}
+
+/// Codecs for a single component, keyed by `<service>/<method>`.
+type ComponentCodecs = HashMap<String, Codec>;
+
+/// A [`CodecCache`] entry.
+struct CachedCodecs {
+    codecs: Arc<ComponentCodecs>,
+
+    /// [Clock](CodecCache::clock) reading as of the latest lookup.
+    last_used: AtomicU64,
+}
+
+/// An initialized gRPC pod, ready to bind to a port and serve.
+pub(crate) struct GrpcPod {
+    /// Router implementing every service of the component.
+    pub(crate) routes: Routes,
+
+    /// The component's own [connection limit](crate::connections) from its metadata.
+    /// Zero defers to the node's default.
+    pub(crate) max_connections: u32,
 }
 
 /// Pod initialization starts asynchronously during `RunPodSandbox`,
@@ -56,21 +171,103 @@ pub(crate) type SharedResultFuture<T> =
     Shared<Pin<Box<dyn Future<Output = StdResult<Arc<T>, SingleUse<Error>>> + Send>>>;
 
 impl PodInitializer {
-    pub(crate) fn new(containers: ContainerStore) -> Self {
-        PodInitializer { containers }
+    pub(crate) fn new(
+        containers: ContainerStore,
+        decoder_options: DecoderOptions,
+        max_cached_components: Option<usize>,
+        serve_descriptors: bool,
+        max_concurrent_requests: Option<usize>,
+    ) -> Self {
+        PodInitializer {
+            containers,
+            codecs: CodecCache {
+                codecs: Arc::default(),
+                clock: Arc::default(),
+                max_components: max_cached_components,
+                decoder_options,
+            },
+            serve_descriptors,
+            max_concurrent_requests,
+        }
+    }
+
+    /// Prepare the given images (`<registry>/<domain-id>/<server-id>:<version>`)
+    /// before any of their pods arrive, pulling them if necessary and building their codecs,
+    /// so the first pod of each component starts sooner.
+    ///
+    /// Failures are logged and otherwise ignored.
+    /// The affected component is simply initialized from scratch by its first pod.
+    pub(crate) async fn warm(&self, images: &[String]) {
+        for image in images {
+            let (registry, name) = match registry_and_component_from_image_spec(image) {
+                Ok(parsed) => parsed,
+                Err(error) => {
+                    log_warn_globally!("Cannot warm malformed image {image:?}: {error:?}");
+                    continue;
+                }
+            };
+            match self.warm_component(&registry, name.clone(), image).await {
+                Ok(()) => log_info!(component: &name, "Warmed component"),
+                Err(error) => log_warn!(component: &name, "Failed warming component: {error:?}"),
+            }
+        }
+    }
+
+    /// Never tear down the cached codecs, even once the initializer is dropped.
+    /// See [`WorkRuntime::leak_on_exit`](crate::state::WorkRuntime::leak_on_exit).
+    pub(crate) fn leak_on_exit(&self) {
+        self.codecs.leak();
+    }
+
+    async fn warm_component(&self, registry: &str, name: ComponentName, image: &str) -> Result<()> {
+        let container = match self.containers.get(&name).await {
+            Ok(container) => container,
+            Err(_) => {
+                let image_spec = ImageSpec {
+                    image: String::from(image),
+                    ..ImageSpec::default()
+                };
+                self.containers
+                    .pull(registry, &name, &image_spec, None)
+                    .await?;
+                self.containers.get(&name).await?
+            }
+        };
+        self.codecs
+            .get_or_build(&Arc::new(name), &container.metadata)?;
+        Ok(())
+    }
+
+    /// Abort any image pulls for the given pod sandbox, now or later.
+    pub(crate) fn cancel_pulls(&self, pod: &PodSandboxMetadata) {
+        self.containers.cancel_pulls(pod);
+    }
+
+    /// Forget about cancelled pulls for a pod sandbox that has been removed.
+    pub(crate) fn forget_pulls(&self, pod: &PodSandboxMetadata) {
+        self.containers.forget_pulls(pod);
     }
 
     /// Initialize a new gRPC pod for the named component using a background task.
-    /// A gRPC pod is represented by a Tonic [`Routes`] object that implements it.
+    /// A gRPC pod is represented by a Tonic [`Routes`] object that implements it,
+    /// along with limits from the component's metadata.
     pub(crate) fn grpc(
         &self,
         wasmtime: &WasmEngine,
         name: Arc<ComponentName>,
-    ) -> SharedResultFuture<Routes> {
+        scratch: Option<Arc<Scratch>>,
+        environment: Environment,
+        policy: RequestPolicy,
+        usage: ResourceUsage,
+    ) -> SharedResultFuture<GrpcPod> {
         spawn(initialize_grpc(
+            self.clone(),
             wasmtime.clone(),
-            self.containers.clone(),
             name.clone(),
+            scratch,
+            environment,
+            policy,
+            usage,
         ))
         .map(|result| {
             result
@@ -86,34 +283,55 @@ impl PodInitializer {
 
 /// Initialize a new gRPC pod for the named component.
 async fn initialize_grpc(
+    initializer: PodInitializer,
     wasmtime: WasmEngine,
-    containers: ContainerStore,
     name: Arc<ComponentName>,
-) -> StdResult<Arc<Routes>, Error> {
-    let container = containers.get(name.as_ref()).await?;
+    scratch: Option<Arc<Scratch>>,
+    environment: Environment,
+    policy: RequestPolicy,
+    usage: ResourceUsage,
+) -> StdResult<Arc<GrpcPod>, Error> {
+    let container = initializer.containers.get(name.as_ref()).await?;
+    let codecs = initializer
+        .codecs
+        .get_or_build(&name, &container.metadata)?;
+    let state = Arc::new(HostState::new(scratch, environment));
 
-    let linker = grpc_linker(&wasmtime)?;
+    let mut linker = grpc_linker(&wasmtime)?;
+    link_handles(&mut linker, &container.component, &wasmtime)?;
     let instantiator = linker
         .instantiate_pre(&container.component)
         .context("Linking error")?;
 
+    let component_trailer = HeaderValue::from_str(&name.to_string())
+        .context("Component name is not a valid header value")?;
+
+    // Every method of the pod shares the same request slots.
+    let queue = admission::max_concurrent_requests(
+        container.metadata.max_concurrent_requests,
+        initializer.max_concurrent_requests,
+    )
+    .map(RequestQueue::new);
+
     let mut service_router = Routes::default().into_axum_router();
     for service in container.metadata.service.iter() {
         let mut method_router = Routes::default().into_axum_router();
 
         for (method_name, method) in service.methods.iter() {
-            let codec = Codec::new(
-                method
-                    .request
-                    .as_ref()
-                    .ok_or(anyhow!("Metadata missing request"))?,
-                method
-                    .response
-                    .as_ref()
-                    .ok_or(anyhow!("Metadata missing response"))?,
-                name.clone(),
+            check_function_signature(&container.component, &wasmtime, method).with_context(
+                || {
+                    format!(
+                        "Component disagrees with metadata for {:?}",
+                        method.function
+                    )
+                },
             )?;
 
+            let codec = codecs
+                .get(&format!("{}/{}", service.name, method_name))
+                .cloned()
+                .ok_or_else(|| anyhow!("Missing codec for method {method_name:?}"))?;
+
             let export_index = container
                 .component
                 .get_export_index(None, &method.function)
@@ -123,9 +341,24 @@ async fn initialize_grpc(
                 function: export_index,
                 instantiator: instantiator.clone(),
                 wasmtime: wasmtime.clone(),
-                state: Arc::new(HostState::new()),
+                state: state.clone(),
+                capabilities: policy.capabilities.clone(),
+                queue: queue.clone(),
+                priority: policy.priorities.get(&service.name, method_name),
+                usage: usage.clone(),
                 component: name.clone(),
+                // Cached responses are shared by every caller, so only cache them
+                // if every request has the same capabilities.
+                cache: codec
+                    .0
+                    .cache
+                    .clone()
+                    .filter(|_| policy.capabilities.is_none()),
+                resources: ResourceFields::new(method),
+                errors: ErrorMapping::new(&method.error_codes)
+                    .with_context(|| format!("Invalid error codes for {:?}", method.function))?,
             }));
+            let component_trailer = component_trailer.clone();
 
             method_router = method_router.route(
                 &format!("/{}", method_name),
@@ -134,6 +367,19 @@ async fn initialize_grpc(
                         // Codec and method objects are cloned here.
                         let codec = codec;
                         let method = method;
+                        let component_trailer = component_trailer;
+
+                        // Fail fast on requests that could never decode.
+                        let request = match check_framing(request, MAX_DECODING_MESSAGE_SIZE).await
+                        {
+                            Ok(request) => request,
+                            Err(status) => {
+                                return Ok(with_component_trailer(
+                                    status.into_http(),
+                                    component_trailer,
+                                ))
+                            }
+                        };
 
                         let mut grpc = Grpc::new(codec)
                             .apply_compression_config(
@@ -145,7 +391,10 @@ async fn initialize_grpc(
                                 MAX_ENCODING_MESSAGE_SIZE,
                             );
                         // TODO: Handle streaming RPC's (currently assumes all are unary).
-                        Ok::<HttpResponse<BoxBody>, Infallible>(grpc.unary(method, request).await)
+                        Ok::<HttpResponse<BoxBody>, Infallible>(with_component_trailer(
+                            grpc.unary(method, request).await,
+                            component_trailer,
+                        ))
                     })
                 }),
             );
@@ -154,7 +403,230 @@ async fn initialize_grpc(
         service_router = service_router.nest(&format!("/{}", service.name), method_router);
     }
 
-    Ok(Arc::new(Routes::from(service_router)))
+    if initializer.serve_descriptors {
+        let descriptors = Bytes::from(
+            descriptor_set(&container.metadata)
+                .context("Failed to build service descriptors")?
+                .encode_to_vec(),
+        );
+        service_router = service_router.route(
+            DESCRIPTOR_PATH,
+            get(|| async move { ([(CONTENT_TYPE, "application/x-protobuf")], descriptors) }),
+        );
+    }
+
+    Ok(Arc::new(GrpcPod {
+        routes: Routes::from(service_router),
+        max_connections: container.metadata.max_connections,
+    }))
+}
+
+impl CodecCache {
+    /// Return the codecs for every method of the named component,
+    /// building them from the metadata unless they're already cached.
+    fn get_or_build(
+        &self,
+        name: &Arc<ComponentName>,
+        metadata: &Metadata,
+    ) -> Result<Arc<ComponentCodecs>> {
+        let now = self.clock.fetch_add(1, Ordering::Relaxed);
+        let codecs = self.codecs.pin();
+        if let Some(cached) = codecs.get(name.as_ref()) {
+            cached.last_used.store(now, Ordering::Relaxed);
+            return Ok(cached.codecs.clone());
+        }
+        let decoder_options = self
+            .decoder_options
+            .with_component_depth(metadata.max_depth);
+        let mut built = ComponentCodecs::new();
+        for service in metadata.service.iter() {
+            for (method_name, method) in service.methods.iter() {
+                let codec = Codec::new(
+                    method
+                        .request
+                        .as_ref()
+                        .ok_or(anyhow!("Metadata missing request"))?,
+                    method
+                        .response
+                        .as_ref()
+                        .ok_or(anyhow!("Metadata missing response"))?,
+                    name.clone(),
+                    method.caching.as_ref(),
+                    decoder_options,
+                )?;
+                built.insert(format!("{}/{}", service.name, method_name), codec);
+            }
+        }
+        // Another pod may have raced to build the same codecs. Either copy is fine.
+        let built = codecs
+            .get_or_insert(
+                name.as_ref().clone(),
+                Arc::new(CachedCodecs {
+                    codecs: Arc::new(built),
+                    last_used: AtomicU64::new(now),
+                }),
+            )
+            .codecs
+            .clone();
+        self.evict(name);
+        Ok(built)
+    }
+
+    /// Keep the cache (and every codec in it) alive until the process exits.
+    ///
+    /// Dropping a codec means dropping each of its decoders and encoders field by field,
+    /// which adds up to a slow, serial teardown for a node with many cached components,
+    /// only for the OS to reclaim the memory anyway.
+    /// Codecs evicted later are still dropped as usual.
+    fn leak(&self) {
+        forget(self.codecs.clone());
+    }
+
+    /// Evict components in excess of the limit (if any), except the one named `keep`.
+    fn evict(&self, keep: &ComponentName) {
+        let Some(max_components) = self.max_components else {
+            return;
+        };
+        let codecs = self.codecs.pin();
+        let excess = codecs.len().saturating_sub(max_components);
+        if excess == 0 {
+            return;
+        }
+        let mut candidates = codecs
+            .iter()
+            .filter(|(name, _)| *name != keep)
+            .map(|(name, cached)| {
+                let last_used = cached.last_used.load(Ordering::Relaxed);
+                (cached.in_use(), last_used, name.clone())
+            })
+            .collect::<Vec<_>>();
+        // Unused before used, then least recently used first.
+        candidates.sort_by_key(|(in_use, last_used, _)| (*in_use, *last_used));
+        for (_, _, name) in candidates.into_iter().take(excess) {
+            codecs.remove(&name);
+            log_info!(component: &name, "Evicted codecs from cache");
+        }
+    }
+}
+
+impl CachedCodecs {
+    /// Whether any pod still holds on to these codecs.
+    fn in_use(&self) -> bool {
+        self.codecs
+            .values()
+            .any(|codec| Arc::strong_count(&codec.0) > 1)
+    }
+}
+
+/// Set the [component trailer](COMPONENT_TRAILER) on a gRPC response.
+///
+/// Any existing value is overwritten, so it always reflects the component that actually ran.
+/// Responses that fail before producing a body are "trailers-only",
+/// meaning the trailers are sent as headers instead.
+fn with_component_trailer(
+    response: HttpResponse<BoxBody>,
+    component: HeaderValue,
+) -> HttpResponse<BoxBody> {
+    let (mut parts, body) = response.into_parts();
+    if parts.headers.contains_key("grpc-status") {
+        parts.headers.insert(COMPONENT_TRAILER, component);
+        return HttpResponse::from_parts(parts, body);
+    }
+    let body = body
+        .map_frame(move |mut frame| {
+            if let Some(trailers) = frame.trailers_mut() {
+                trailers.insert(COMPONENT_TRAILER, component.clone());
+            }
+            frame
+        })
+        .boxed_unsync();
+    HttpResponse::from_parts(parts, body)
+}
+
+/// Make sure the records taken and returned by the method's function
+/// have the same number of fields as the request and response described in the metadata.
+///
+/// Decoding and encoding is driven entirely by the metadata,
+/// so version skew between the component and its metadata
+/// would otherwise only surface as an error on every request.
+fn check_function_signature(
+    component: &Component,
+    wasmtime: &WasmEngine,
+    method: &GrpcMethod,
+) -> Result<()> {
+    let function = match component
+        .component_type()
+        .get_export(wasmtime, &method.function)
+    {
+        Some(ComponentItem::ComponentFunc(function)) => function,
+        _ => bail!("Function not found: {:?}", method.function),
+    };
+
+    // The first parameter is the request context. The second is the request itself.
+    let request = function
+        .params()
+        .nth(1)
+        .map(|(_name, request)| request)
+        .ok_or_else(|| anyhow!("Function lacks a request parameter"))?;
+    check_record_arity(
+        method
+            .request
+            .as_ref()
+            .ok_or(anyhow!("Metadata missing request"))?,
+        &request,
+        "request",
+    )?;
+
+    // Functions may return the response directly or as the success case of a result.
+    let response = match function.results().next() {
+        Some(Type::Result(result)) => result.ok(),
+        response => response,
+    }
+    .ok_or_else(|| anyhow!("Function lacks a response"))?;
+    check_record_arity(
+        method
+            .response
+            .as_ref()
+            .ok_or(anyhow!("Metadata missing response"))?,
+        &response,
+        "response",
+    )
+}
+
+/// Recursively compare the field count of a record type against a message in the metadata.
+/// Nested messages are checked as well. `path` locates the record for error messages.
+fn check_record_arity(message: &Field, actual: &Type, path: &str) -> Result<()> {
+    let Type::Record(record) = actual else {
+        bail!("Expected a record for {path}");
+    };
+    if record.fields().len() != message.subfields.len() {
+        bail!(
+            "Record for {path} has {} fields but the metadata expects {}",
+            record.fields().len(),
+            message.subfields.len(),
+        );
+    }
+    for (subfield, field) in message.subfields.iter().zip(record.fields()) {
+        if subfield.resource {
+            // Resources are opaque handles rather than records.
+            continue;
+        }
+        let nested = match (subfield.coding, &field.ty) {
+            (Some(Coding::CompoundCoding(coding)), Type::Option(option))
+                if coding == CompoundCoding::Message as i32 =>
+            {
+                option.ty()
+            }
+            (Some(Coding::CompoundCoding(coding)), Type::List(list))
+                if coding == CompoundCoding::MessageExpanded as i32 =>
+            {
+                list.ty()
+            }
+            _ => continue,
+        };
+        check_record_arity(subfield, &nested, &format!("{path}.{}", subfield.name))?;
+    }
+    Ok(())
 }
 
 // TODO: Revisit these limits. They were chosen arbitrarily.
@@ -173,8 +645,53 @@ pub(crate) struct Codec(Arc<CodecInner>);
 
 /// A message decoder (for requests) and an encoder (for responses).
 struct CodecInner {
-    decoder: RequestDecoder,
-    encoder: ResponseEncoder,
+    decoder: KeyedRequestDecoder,
+    encoder: TracedResponseEncoder,
+
+    /// Response cache shared by every pod of the component,
+    /// for idempotent read-only methods only.
+    cache: Option<Arc<ResponseCache>>,
+
+    /// Reports the decoder's field presence counts, if tracked.
+    _presence: Option<ObservableCounter<u64>>,
+}
+
+/// Wraps a [`RequestDecoder`],
+/// optionally retaining the raw request bytes to use as a [cache](ResponseCache) key.
+#[derive(Clone)]
+pub(crate) struct KeyedRequestDecoder {
+    inner: RequestDecoder,
+
+    /// Whether to retain the raw request bytes.
+    keyed: bool,
+
+    spans: ComponentSpans,
+
+    /// Counts every rejected request as the [malformed requests metric](MALFORMED_REQUESTS_METRIC).
+    malformed: Counter<u64>,
+
+    /// Domain of the component, the only attribute of the malformed requests metric.
+    domain: [KeyValue; 1],
+}
+
+/// Wraps a [`ResponseEncoder`] to trace each response.
+#[derive(Clone)]
+pub(crate) struct TracedResponseEncoder {
+    inner: ResponseEncoder,
+    spans: ComponentSpans,
+}
+
+/// Identifies a component on every span started on its behalf,
+/// so that latency can be attributed per component and version.
+///
+/// Reference-counted so the attributes are not copied for every request.
+#[derive(Clone)]
+struct ComponentSpans(Arc<[KeyValue; 3]>);
+
+/// A decoded request, along with the raw request bytes for cached methods only.
+pub(crate) struct KeyedRequest {
+    key: Option<Bytes>,
+    value: Val,
 }
 
 /// Pairs with a [`Codec`] to implement a service (*e.g.* [`UnaryService`])
@@ -190,7 +707,7 @@ struct MethodInner {
     function: ComponentExportIndex,
 
     /// An efficient means of instantiating new instances.
-    instantiator: InstancePre<Arc<HostState>>,
+    instantiator: InstancePre<InstanceState>,
 
     /// Global Wasm engine to run hosted services.
     wasmtime: WasmEngine,
@@ -198,8 +715,30 @@ struct MethodInner {
     /// Shared host state used by every method in this pod.
     state: Arc<HostState>,
 
+    /// Restricts the host state of each request by caller, if the pod has a policy.
+    capabilities: Option<Arc<CapabilityPolicy>>,
+
+    /// Admits requests in order of priority, if the pod has a concurrency limit.
+    queue: Option<RequestQueue>,
+
+    /// Admission priority of every request to this method.
+    priority: u8,
+
+    /// Resource usage of every instance of the pod, as reported by `ContainerStats`.
+    usage: ResourceUsage,
+
     /// Name of the component this method is a part of, for error logging.
     component: Arc<ComponentName>,
+
+    /// The component's [response cache](CodecInner::cache),
+    /// for pods without a [capability policy](CapabilityPolicy) only.
+    cache: Option<Arc<ResponseCache>>,
+
+    /// Where the request and response carry resource handles, if either does.
+    resources: Option<ResourceFields>,
+
+    /// Maps errors returned by the function to gRPC statuses.
+    errors: ErrorMapping,
 }
 
 impl Codec {
@@ -207,19 +746,107 @@ impl Codec {
         decoder: &Field,
         encoder: &Field,
         component: Arc<ComponentName>,
+        caching: Option<&ResponseCaching>,
+        decoder_options: DecoderOptions,
     ) -> Result<Self> {
+        let decoder = RequestDecoder::with_options(decoder, component.clone(), decoder_options)?;
+        let presence = decoder_options
+            .field_presence
+            .then(|| field_presence_counter(decoder.clone(), &component));
+        let spans = ComponentSpans::new(&component);
         Ok(Codec(Arc::new(CodecInner {
-            decoder: RequestDecoder::new(decoder, component.clone())?,
-            encoder: ResponseEncoder::new(encoder, component)?,
+            decoder: KeyedRequestDecoder {
+                inner: decoder,
+                keyed: caching.is_some(),
+                spans: spans.clone(),
+                malformed: global::meter(METER_NAME)
+                    .u64_counter(MALFORMED_REQUESTS_METRIC)
+                    .with_description("Number of requests rejected as malformed")
+                    .build(),
+                domain: [KeyValue::new("domain", component.server.domain.to_string())],
+            },
+            encoder: TracedResponseEncoder {
+                inner: ResponseEncoder::new(encoder, component)?,
+                spans,
+            },
+            cache: caching.map(|caching| Arc::new(ResponseCache::new(caching))),
+            _presence: presence,
         })))
     }
 }
 
+impl ComponentSpans {
+    fn new(component: &ComponentName) -> Self {
+        Self(Arc::new([
+            KeyValue::new(
+                "domain",
+                Arc::<str>::from(component.server.domain.to_string()),
+            ),
+            KeyValue::new("server", Arc::<str>::from(component.server.server.as_str())),
+            KeyValue::new("version", Arc::<str>::from(component.version.as_str())),
+        ]))
+    }
+
+    /// Start a span called `name`, with any extra `attributes`.
+    fn start<S: Tracer>(
+        &self,
+        tracer: &S,
+        name: &'static str,
+        attributes: impl IntoIterator<Item = KeyValue>,
+    ) -> S::Span {
+        tracer
+            .span_builder(name)
+            .with_attributes(self.0.iter().cloned().chain(attributes))
+            .start(tracer)
+    }
+
+    /// End a span with the result of the operation it covered.
+    /// A failure is recorded as an event on the span.
+    fn end<T>(mut span: impl Span, result: &StdResult<T, Status>) {
+        if let Err(status) = result {
+            let message = String::from(status.message());
+            span.add_event(
+                "error",
+                vec![
+                    KeyValue::new("code", format!("{:?}", status.code())),
+                    KeyValue::new("message", message.clone()),
+                ],
+            );
+            span.set_status(SpanStatus::error(message));
+        }
+    }
+}
+
+/// Export the field presence counts of a request decoder
+/// as the [field presence metric](FIELD_PRESENCE_METRIC),
+/// attributed to the component and field.
+fn field_presence_counter(
+    decoder: RequestDecoder,
+    component: &ComponentName,
+) -> ObservableCounter<u64> {
+    let component = component.to_string();
+    global::meter(METER_NAME)
+        .u64_observable_counter(FIELD_PRESENCE_METRIC)
+        .with_description("Number of times each request field appeared on the wire")
+        .with_callback(move |observer| {
+            for (field, count) in decoder.field_presence() {
+                observer.observe(
+                    count,
+                    &[
+                        KeyValue::new("component", component.clone()),
+                        KeyValue::new("field", field),
+                    ],
+                );
+            }
+        })
+        .build()
+}
+
 impl TonicCodec for Codec {
     type Encode = Val;
-    type Decode = Val;
-    type Encoder = ResponseEncoder;
-    type Decoder = RequestDecoder;
+    type Decode = KeyedRequest;
+    type Encoder = TracedResponseEncoder;
+    type Decoder = KeyedRequestDecoder;
 
     fn encoder(&mut self) -> Self::Encoder {
         self.0.encoder.clone()
@@ -230,88 +857,718 @@ impl TonicCodec for Codec {
     }
 }
 
+impl TonicDecoder for KeyedRequestDecoder {
+    type Item = KeyedRequest;
+    type Error = Status;
+
+    fn decode(&mut self, src: &mut DecodeBuf<'_>) -> StdResult<Option<Self::Item>, Self::Error> {
+        // Tonic buffers each message contiguously,
+        // so the first chunk is the entire serialized request.
+        let key = self.keyed.then(|| Bytes::copy_from_slice(src.chunk()));
+        let size = KeyValue::new("request.size", src.remaining() as i64);
+        let span = self
+            .spans
+            .start(&global::tracer(TRACER_NAME), "decode", [size]);
+        let result = self.inner.decode(src);
+        ComponentSpans::end(span, &result);
+        let value = result.inspect_err(|_| self.malformed.add(1, &self.domain))?;
+        Ok(value.map(|value| KeyedRequest { key, value }))
+    }
+}
+
+impl TonicEncoder for TracedResponseEncoder {
+    type Item = Val;
+    type Error = Status;
+
+    fn encode(&mut self, item: Self::Item, dst: &mut EncodeBuf<'_>) -> StdResult<(), Self::Error> {
+        let span = self.spans.start(&global::tracer(TRACER_NAME), "encode", []);
+        let result = self.inner.encode(item, dst);
+        ComponentSpans::end(span, &result);
+        result
+    }
+}
+
 type BoxedStatusResultFuture<T> =
     Pin<Box<dyn Future<Output = StdResult<T, Status>> + Send + 'static>>;
 
-impl UnaryService<Val> for Method {
+impl UnaryService<KeyedRequest> for Method {
     type Response = Val;
     type Future = BoxedStatusResultFuture<TonicResponse<Self::Response>>;
 
-    fn call(&mut self, request: TonicRequest<Val>) -> Self::Future {
+    fn call(&mut self, request: TonicRequest<KeyedRequest>) -> Self::Future {
         let method = self.clone();
         Box::pin(async move {
-            // TODO: See if we can pool instances somehow.
-            let mut store = Store::new(&method.0.wasmtime, method.0.state.clone());
-            let instance = method
-                .0
-                .instantiator
-                .instantiate_async(&mut store)
-                .await
-                .map_err(|error| {
-                    // TODO: Log these errors.
-                    let _component = method.0.component.as_ref();
-                    Status::internal("Module instantiation error")
-                })?;
+            let (metadata, extensions, request) = request.into_parts();
+            let caller = extensions
+                .get::<TcpConnectInfo>()
+                .and_then(TcpConnectInfo::remote_addr)
+                .map(|address| address.ip());
+            let response = match (&method.0.cache, request.key) {
+                (Some(cache), Some(key)) => {
+                    cache
+                        .get_or_dispatch(key, || method.dispatch(caller, metadata, request.value))
+                        .await?
+                }
+                _ => method.dispatch(caller, metadata, request.value).await?,
+            };
+            Ok(TonicResponse::new(response))
+        })
+    }
+}
 
-            let function = instance
-                .get_func(&mut store, &method.0.function)
-                .ok_or_else(|| {
-                    // TODO: Log these errors.
-                    let _function_index = &method.0.function;
-                    Status::internal("Function selection error")
-                })?;
+impl Method {
+    /// Invoke the component function to handle a single request.
+    async fn dispatch(
+        &self,
+        caller: Option<IpAddr>,
+        metadata: MetadataMap,
+        mut request: Val,
+    ) -> StdResult<Val, Status> {
+        // Hold a slot until the response is ready, unless preempted in the meantime.
+        let slot = match &self.0.queue {
+            Some(queue) => Some(Slot::new(queue.admit(self.0.priority).await)),
+            None => None,
+        };
+        let state = match &self.0.capabilities {
+            Some(policy) => Arc::new(self.0.state.with_capabilities(policy.capabilities(caller))),
+            None => self.0.state.clone(),
+        };
+        // TODO: See if we can pool instances somehow.
+        let mut store = Store::new(
+            &self.0.wasmtime,
+            InstanceState {
+                host: state,
+                memory: self.0.usage.memory_tracker(),
+            },
+        );
+        store.limiter(|state| &mut state.memory);
+        // Check for more urgent requests at every epoch tick,
+        // and otherwise just yield so long-running requests don't hog the executor.
+        match slot {
+            Some(slot) => {
+                store.epoch_deadline_callback(move |_| {
+                    Ok(match slot.preempt() {
+                        Some(resumed) => UpdateDeadline::YieldCustom(1, Box::pin(resumed)),
+                        None => UpdateDeadline::Yield(1),
+                    })
+                });
+                store.set_epoch_deadline(1);
+            }
+            None => store.epoch_deadline_async_yield_and_update(1),
+        }
+        let instance = self
+            .0
+            .usage
+            .on_cpu(self.0.instantiator.instantiate_async(&mut store))
+            .await
+            .map_err(|error| {
+                log_error!(
+                    component: self.0.component.as_ref(),
+                    "Module instantiation error: {error:?}",
+                );
+                Status::internal("Module instantiation error")
+            })?;
 
-            let (metadata, extensions, request) = request.into_parts();
+        let function = instance
+            .get_func(&mut store, &self.0.function)
+            .ok_or_else(|| {
+                log_error!(
+                    component: self.0.component.as_ref(),
+                    "Function selection error: {:?}",
+                    self.0.function,
+                );
+                Status::internal("Function selection error")
+            })?;
 
-            let mut headers = Vec::with_capacity(metadata.len());
-            for header in metadata.iter() {
-                match header {
-                    KeyAndValueRef::Ascii(key, value) => {
-                        if let Ok(value) = value.to_str() {
-                            let key = String::from(key.as_str());
-                            let value = String::from(value);
-                            headers.push(Val::Tuple(vec![Val::String(key), Val::String(value)]));
-                        } else {
-                            // Silently ignore non-ASCII header value, but log a warning.
-                            log_warn!(
-                                component: method.0.component.as_ref(),
-                                "Non-ASCII request header value: {:?} = {:?}",
-                                key, value,
-                            );
-                        }
-                    }
-                    KeyAndValueRef::Binary(key, value) => {
-                        // Silently ignore non-ASCII header key, but log a warning.
+        let mut headers = Vec::with_capacity(metadata.len());
+        for header in metadata.iter() {
+            match header {
+                KeyAndValueRef::Ascii(key, value) => {
+                    if let Ok(value) = value.to_str() {
+                        let key = String::from(key.as_str());
+                        let value = String::from(value);
+                        headers.push(Val::Tuple(vec![Val::String(key), Val::String(value)]));
+                    } else {
+                        // Silently ignore non-ASCII header value, but log a warning.
                         log_warn!(
-                            component: method.0.component.as_ref(),
-                            "Non-ASCII request header: {:?} = {:?}",
+                            component: self.0.component.as_ref(),
+                            "Non-ASCII request header value: {:?} = {:?}",
                             key, value,
                         );
                     }
                 }
+                KeyAndValueRef::Binary(key, value) => {
+                    // Silently ignore non-ASCII header key, but log a warning.
+                    log_warn!(
+                        component: self.0.component.as_ref(),
+                        "Non-ASCII request header: {:?} = {:?}",
+                        key, value,
+                    );
+                }
             }
+        }
 
-            let context = Val::Record(vec![("headers".into(), Val::List(headers))]);
-            let parameters = vec![context, request];
+        // Clients refer to resources by number, but the component takes the handles themselves.
+        if let Some(resources) = &self.0.resources {
+            self.0
+                .state
+                .handles
+                .import_request(&mut store, resources, &mut request)
+                .map_err(|error| Status::invalid_argument(error.to_string()))?;
+        }
 
-            // The results slice just has to have the right size.
-            // Contents are ignored and overridden during invocation.
-            let mut results = vec![Val::Option(None)];
+        let context = Val::Record(vec![("headers".into(), Val::List(headers))]);
+        let parameters = vec![context, request];
 
-            function
-                .call_async(&mut store, &parameters, &mut results)
-                .await
+        // The results slice just has to have the right size.
+        // Contents are ignored and overridden during invocation.
+        let mut results = vec![Val::Option(None)];
+
+        self.0
+            .usage
+            .on_cpu(function.call_async(&mut store, &parameters, &mut results))
+            .await
+            .map_err(|error| {
+                log_warn!(
+                    component: self.0.component.as_ref(),
+                    "Function invocation error: {error:?}",
+                );
+                Status::internal("Function invocation error")
+            })?;
+
+        // The request is no longer needed. Let the next request on this thread reuse its storage.
+        parameters.into_iter().for_each(decode::recycle);
+
+        // Should be safe to pop since we initialized it with an item.
+        let mut response = results.pop().unwrap();
+        if let Some(resources) = &self.0.resources {
+            self.0
+                .state
+                .handles
+                .export_response(&mut store, resources, &mut response)
                 .map_err(|error| {
-                    // TODO: Log these errors.
-                    let _component = method.0.component.as_ref();
-                    Status::internal("Function invocation error")
+                    log_warn!(
+                        component: self.0.component.as_ref(),
+                        "Handle export error: {error:?}",
+                    );
+                    Status::internal("Handle export error")
                 })?;
+        }
+        self.0.errors.resolve(response).map_err(|status| *status)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Result as IoResult, Write};
+    use std::sync::Mutex;
+
+    use futures::stream;
+    use http::HeaderMap;
+    use http_body::Frame;
+    use http_body_util::StreamBody;
+    use opentelemetry::trace::TracerProvider;
+    use std::time::Duration;
+
+    use opentelemetry_sdk::error::OTelSdkResult;
+    use opentelemetry_sdk::metrics::data::{AggregatedMetrics, MetricData, ResourceMetrics};
+    use opentelemetry_sdk::metrics::exporter::PushMetricExporter;
+    use opentelemetry_sdk::metrics::{PeriodicReader, SdkMeterProvider, Temporality};
+    use opentelemetry_sdk::trace::{SdkTracerProvider, SpanData, SpanExporter};
+    use tonic::codec::Streaming;
+    use tracing::subscriber::with_default;
+
+    use metadata_proto::work::runtime::field::{Charset, ScalarCoding};
+    use metadata_proto::work::runtime::GrpcService;
+    use names::Name;
+
+    use super::*;
+
+    /// A component exporting `handle: func(context, request: {a: string, b: string}) -> {c: u32}`.
+    const COMPONENT: &str = r#"
+        (component
+          (core module $m
+            (memory (export "memory") 1)
+            (func (export "realloc") (param i32 i32 i32 i32) (result i32) unreachable)
+            (func (export "handle") (param i32 i32 i32 i32 i32 i32) (result i32) unreachable)
+          )
+          (core instance $i (instantiate $m))
+          (type $context (record (field "headers" (list (tuple string string)))))
+          (export $context-export "context" (type $context))
+          (type $request (record (field "a" string) (field "b" string)))
+          (export $request-export "request" (type $request))
+          (type $response (record (field "c" u32)))
+          (export $response-export "response" (type $response))
+          (func $handle
+            (param "context" $context-export)
+            (param "request" $request-export)
+            (result $response-export)
+            (canon lift
+              (core func $i "handle")
+              (memory $i "memory")
+              (realloc (func $i "realloc"))
+            )
+          )
+          (export "handle" (func $handle))
+        )
+    "#;
+
+    fn message(fields: &[(&str, ScalarCoding)]) -> Field {
+        Field {
+            number: 0,
+            name: String::default(),
+            coding: None,
+            subfields: fields
+                .iter()
+                .enumerate()
+                .map(|(index, (name, coding))| Field {
+                    number: index as u32 + 1,
+                    name: String::from(*name),
+                    coding: Some(Coding::ScalarCoding(*coding as i32)),
+                    subfields: Vec::new(),
+                    charset: Charset::Unrestricted as i32,
+                    ..Default::default()
+                })
+                .collect(),
+            charset: Charset::Unrestricted as i32,
+            ..Default::default()
+        }
+    }
+
+    fn method(request: Field) -> GrpcMethod {
+        GrpcMethod {
+            function: String::from("handle"),
+            request: Some(request),
+            response: Some(message(&[("c", ScalarCoding::Uint32Implicit)])),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_record_arity_mismatch() {
+        let wasmtime = WasmEngine::default();
+        let component = Component::new(&wasmtime, COMPONENT).unwrap();
+
+        let matching = method(message(&[
+            ("a", ScalarCoding::StringUtf8Implicit),
+            ("b", ScalarCoding::StringUtf8Implicit),
+        ]));
+        check_function_signature(&component, &wasmtime, &matching).unwrap();
+
+        // The metadata is newer than the component, with an extra request field.
+        let skewed = method(message(&[
+            ("a", ScalarCoding::StringUtf8Implicit),
+            ("b", ScalarCoding::StringUtf8Implicit),
+            ("d", ScalarCoding::StringUtf8Implicit),
+        ]));
+        let error = check_function_signature(&component, &wasmtime, &skewed).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Record for request has 2 fields but the metadata expects 3",
+        );
+    }
+
+    const COMPONENT_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.2.3";
+
+    #[test]
+    fn test_warmed_codecs_reused() {
+        let name = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+        let metadata = Metadata {
+            service: vec![GrpcService {
+                name: String::from("package.Service"),
+                methods: HashMap::from([(
+                    String::from("Method"),
+                    method(message(&[("a", ScalarCoding::StringUtf8Implicit)])),
+                )]),
+            }],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let cache = CodecCache::default();
+        let warmed = cache.get_or_build(&name, &metadata).unwrap();
+        assert!(warmed.contains_key("package.Service/Method"));
+
+        // The first pod finds the warmed codecs and does not build its own,
+        // even if the metadata it passes could not be built.
+        let unbuildable = Metadata {
+            service: vec![GrpcService {
+                name: String::from("package.Service"),
+                methods: HashMap::from([(String::from("Method"), GrpcMethod::default())]),
+            }],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let initialized = cache.get_or_build(&name, &unbuildable).unwrap();
+        assert!(Arc::ptr_eq(&warmed, &initialized));
+    }
+
+    #[tokio::test]
+    async fn test_response_cache_shared_across_pods() {
+        let name = Arc::new(Name::parse(COMPONENT_NAME).component().unwrap());
+        let metadata = Metadata {
+            service: vec![GrpcService {
+                name: String::from("package.Service"),
+                methods: HashMap::from([(
+                    String::from("Method"),
+                    GrpcMethod {
+                        caching: Some(ResponseCaching {
+                            ttl_millis: 60_000,
+                            max_entries: 8,
+                        }),
+                        ..method(message(&[("a", ScalarCoding::StringUtf8Implicit)]))
+                    },
+                )]),
+            }],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let codecs = CodecCache::default();
+        let cache =
+            |codecs: &ComponentCodecs| codecs["package.Service/Method"].0.cache.clone().unwrap();
+
+        // Two pods of the same component share one response cache.
+        let first = cache(&codecs.get_or_build(&name, &metadata).unwrap());
+        let second = cache(&codecs.get_or_build(&name, &metadata).unwrap());
+        assert!(Arc::ptr_eq(&first, &second));
+
+        // So a response computed by the first pod is served by the second.
+        let request = Bytes::from_static(&[10, 2, 104, 105]);
+        let response = first
+            .get_or_dispatch(request.clone(), || async { Ok(Val::U32(7)) })
+            .await
+            .unwrap();
+        assert_eq!(response, Val::U32(7));
+        let cached = second
+            .get_or_dispatch(request, || async { panic!("Response should be cached") })
+            .await
+            .unwrap();
+        assert_eq!(cached, Val::U32(7));
+    }
+
+    #[test]
+    fn test_cache_limit_evicts_unused() {
+        let metadata = Metadata {
+            service: vec![GrpcService {
+                name: String::from("package.Service"),
+                methods: HashMap::from([(
+                    String::from("Method"),
+                    method(message(&[("a", ScalarCoding::StringUtf8Implicit)])),
+                )]),
+            }],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let component = |version: &str| {
+            let name = COMPONENT_NAME.replace("1.2.3", version);
+            Arc::new(Name::parse(&name).component().unwrap())
+        };
+        let (active, unused, latest) = (component("1.0.0"), component("2.0.0"), component("3.0.0"));
+        let cache = CodecCache {
+            max_components: Some(2),
+            ..CodecCache::default()
+        };
+
+        // A pod holds on to the codecs of the least recently used component.
+        let in_use =
+            cache.get_or_build(&active, &metadata).unwrap()["package.Service/Method"].clone();
+        let unused_codecs = cache.get_or_build(&unused, &metadata).unwrap();
+        cache.get_or_build(&latest, &metadata).unwrap();
+
+        let cached = cache.codecs.pin();
+        assert_eq!(cached.len(), 2);
+        assert!(cached.contains_key(active.as_ref()));
+        assert!(!cached.contains_key(unused.as_ref()));
+        assert!(cached.contains_key(latest.as_ref()));
+
+        // Evicted codecs are rebuilt on demand.
+        let rebuilt = cache.get_or_build(&unused, &metadata).unwrap();
+        assert!(!Arc::ptr_eq(&unused_codecs, &rebuilt));
+        drop(in_use);
+    }
+
+    #[test]
+    fn test_leaked_codecs_outlive_cache() {
+        let metadata = Metadata {
+            service: vec![GrpcService {
+                name: String::from("package.Service"),
+                methods: HashMap::from([(
+                    String::from("Method"),
+                    method(message(&[("a", ScalarCoding::StringUtf8Implicit)])),
+                )]),
+            }],
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let codecs = |cache: &CodecCache| {
+            (0..1000)
+                .map(|patch| {
+                    let name = COMPONENT_NAME.replace("1.2.3", &format!("1.2.{patch}"));
+                    let name = Arc::new(Name::parse(&name).component().unwrap());
+                    let codecs = cache.get_or_build(&name, &metadata).unwrap();
+                    Arc::downgrade(&codecs["package.Service/Method"].0)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Ordinarily, dropping the cache drops every codec in it.
+        let cache = CodecCache::default();
+        let dropped = codecs(&cache);
+        drop(cache);
+        assert!(dropped.iter().all(|codec| codec.upgrade().is_none()));
+
+        // Once leaked, dropping the cache skips them all.
+        let cache = CodecCache::default();
+        cache.leak();
+        let leaked = codecs(&cache);
+        drop(cache);
+        assert!(leaked.iter().all(|codec| codec.upgrade().is_some()));
+    }
+
+    /// Collects every span it exports.
+    #[derive(Debug, Default, Clone)]
+    struct SpanRecorder(Arc<Mutex<Vec<SpanData>>>);
 
-            let response = TonicResponse::new(
-                // Should be safe to pop since we initialized it with an item.
-                results.pop().unwrap(),
+    impl SpanExporter for SpanRecorder {
+        async fn export(&self, batch: Vec<SpanData>) -> OTelSdkResult {
+            self.0.lock().unwrap().extend(batch);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_component_spans() {
+        let recorder = SpanRecorder::default();
+        let provider = SdkTracerProvider::builder()
+            .with_simple_exporter(recorder.clone())
+            .build();
+        let tracer = provider.tracer("test");
+        let spans = ComponentSpans::new(&Name::parse(COMPONENT_NAME).component().unwrap());
+
+        let size = KeyValue::new("request.size", 5);
+        ComponentSpans::end(spans.start(&tracer, "decode", [size]), &Ok(()));
+        ComponentSpans::end(
+            spans.start(&tracer, "encode", []),
+            &Err::<(), _>(Status::internal("Message is not a record")),
+        );
+
+        let exported = recorder.0.lock().unwrap();
+        assert_eq!(exported.len(), 2);
+        let attribute = |span: &SpanData, key: &str| {
+            span.attributes
+                .iter()
+                .find(|attribute| attribute.key.as_str() == key)
+                .map(|attribute| attribute.value.to_string())
+        };
+        for span in exported.iter() {
+            assert_eq!(
+                attribute(span, "domain").as_deref(),
+                Some("1234567890abcdef1234567890abcdef"),
             );
-            Ok(response)
-        })
+            assert_eq!(attribute(span, "server").as_deref(), Some("some-server"));
+            assert_eq!(attribute(span, "version").as_deref(), Some("1.2.3"));
+        }
+
+        let (decode, encode) = (&exported[0], &exported[1]);
+        assert_eq!(decode.name, "decode");
+        assert_eq!(attribute(decode, "request.size").as_deref(), Some("5"));
+        assert_eq!(decode.status, SpanStatus::Unset);
+        assert!(decode.events.is_empty());
+
+        assert_eq!(encode.name, "encode");
+        assert_eq!(encode.status, SpanStatus::error("Message is not a record"),);
+        let events = encode.events.iter().collect::<Vec<_>>();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].name, "error");
+        assert!(events[0]
+            .attributes
+            .contains(&KeyValue::new("message", "Message is not a record")));
+    }
+
+    /// Records the latest count of malformed requests per domain.
+    #[derive(Debug, Default, Clone)]
+    struct MalformedRecorder(Arc<Mutex<HashMap<String, u64>>>);
+
+    impl PushMetricExporter for MalformedRecorder {
+        async fn export(&self, metrics: &ResourceMetrics) -> OTelSdkResult {
+            let mut counts = self.0.lock().unwrap();
+            for metric in metrics.scope_metrics().flat_map(|scope| scope.metrics()) {
+                if metric.name() != MALFORMED_REQUESTS_METRIC {
+                    continue;
+                }
+                let AggregatedMetrics::U64(MetricData::Sum(sum)) = metric.data() else {
+                    panic!("Malformed requests metric is not a counter");
+                };
+                for point in sum.data_points() {
+                    for attribute in point.attributes() {
+                        counts.insert(attribute.value.to_string(), point.value());
+                    }
+                }
+            }
+            Ok(())
+        }
+
+        fn force_flush(&self) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn shutdown_with_timeout(&self, _timeout: Duration) -> OTelSdkResult {
+            Ok(())
+        }
+
+        fn temporality(&self) -> Temporality {
+            Temporality::Cumulative
+        }
+    }
+
+    #[tokio::test]
+    async fn test_malformed_requests_metric() {
+        let recorder = MalformedRecorder::default();
+        let provider = SdkMeterProvider::builder()
+            .with_reader(PeriodicReader::builder(recorder.clone()).build())
+            .build();
+        global::set_meter_provider(provider.clone());
+
+        let codec = |domain: &str| {
+            let name = COMPONENT_NAME.replace("1234567890abcdef1234567890abcdef", domain);
+            Codec::new(
+                &message(&[("a", ScalarCoding::StringUtf8Implicit)]),
+                &message(&[("c", ScalarCoding::Uint32Implicit)]),
+                Arc::new(Name::parse(&name).component().unwrap()),
+                None,
+                DecoderOptions::default(),
+            )
+            .unwrap()
+        };
+        // Field `a` claims 5 bytes, but only 2 follow.
+        let decode = |mut codec: Codec, message: &'static [u8]| async move {
+            let mut frame = vec![0];
+            frame.extend_from_slice(&(message.len() as u32).to_be_bytes());
+            frame.extend_from_slice(message);
+            let body = StreamBody::new(stream::iter([Ok::<_, Status>(Frame::data(Bytes::from(
+                frame,
+            )))]));
+            Streaming::new_request(codec.decoder(), body, None, None)
+                .message()
+                .await
+                .map(|request| request.is_some())
+        };
+        let (first, second) = (
+            codec("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"),
+            codec("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"),
+        );
+
+        assert!(decode(first.clone(), &[10, 5, 104, 105]).await.is_err());
+        assert!(decode(first.clone(), &[10, 5, 104, 105]).await.is_err());
+        assert!(decode(second.clone(), &[10, 5, 104, 105]).await.is_err());
+        // Well-formed requests are not counted.
+        assert!(decode(second, &[10, 2, 104, 105]).await.unwrap());
+
+        provider.force_flush().unwrap();
+        let counts = recorder.0.lock().unwrap();
+        assert_eq!(counts.get("aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"), Some(&2));
+        assert_eq!(counts.get("bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb"), Some(&1));
+    }
+
+    /// Collects formatted log output in memory.
+    #[derive(Debug, Default, Clone)]
+    struct LogRecorder(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for LogRecorder {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IoResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_component_logs() {
+        let recorder = LogRecorder::default();
+        let writer = recorder.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .json()
+            .with_writer(move || writer.clone())
+            .finish();
+        let metadata = Metadata {
+            service: Vec::new(),
+            max_connections: 0,
+            max_depth: 0,
+            max_concurrent_requests: 0,
+        };
+        let component = |version: &str| {
+            let name = COMPONENT_NAME.replace("1.2.3", version);
+            Arc::new(Name::parse(&name).component().unwrap())
+        };
+        let cache = CodecCache {
+            max_components: Some(1),
+            ..CodecCache::default()
+        };
+
+        // Building codecs for a second component evicts the first.
+        with_default(subscriber, || {
+            cache.get_or_build(&component("1.0.0"), &metadata).unwrap();
+            cache.get_or_build(&component("2.0.0"), &metadata).unwrap();
+        });
+
+        let output = String::from_utf8(recorder.0.lock().unwrap().clone()).unwrap();
+        let logs = output
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(logs.len(), 1);
+        let fields = &logs[0]["fields"];
+        assert_eq!(fields["message"], "Evicted codecs from cache");
+        assert_eq!(fields["domain"], "1234567890abcdef1234567890abcdef");
+        assert_eq!(fields["server"], "some-server");
+        // Logs and spans share the same key for the version.
+        assert_eq!(fields["version"], "1.0.0");
+    }
+
+    #[tokio::test]
+    async fn test_component_trailer() {
+        // The component (or anything else) cannot spoof the trailer.
+        let mut trailers = HeaderMap::new();
+        trailers.insert("grpc-status", HeaderValue::from_static("0"));
+        trailers.insert(COMPONENT_TRAILER, HeaderValue::from_static("spoofed"));
+        let body = StreamBody::new(stream::iter(vec![
+            Ok::<_, Status>(Frame::data(Bytes::from_static(&[0, 0, 0, 0, 0]))),
+            Ok(Frame::trailers(trailers)),
+        ]))
+        .boxed_unsync();
+
+        let response = with_component_trailer(
+            HttpResponse::new(body),
+            HeaderValue::from_static(COMPONENT_NAME),
+        );
+        let trailers = response
+            .into_body()
+            .collect()
+            .await
+            .unwrap()
+            .trailers()
+            .cloned()
+            .unwrap();
+        assert_eq!(trailers.get(COMPONENT_TRAILER).unwrap(), COMPONENT_NAME);
+        assert_eq!(trailers.get("grpc-status").unwrap(), "0");
+    }
+
+    #[test]
+    fn test_component_trailer_trailers_only() {
+        let response = with_component_trailer(
+            Status::not_found("nope").into_http(),
+            HeaderValue::from_static(COMPONENT_NAME),
+        );
+        assert_eq!(
+            response.headers().get(COMPONENT_TRAILER).unwrap(),
+            COMPONENT_NAME,
+        );
     }
 }
@@ -0,0 +1,220 @@
+//! Node-level network policy for pod servers.
+//!
+//! A pod may restrict which clients can reach it
+//! by annotating the pod sandbox with comma-separated lists of source CIDRs:
+//!
+//!     vimana.host/allow-sources: 10.0.0.0/8,fd00::/8
+//!     vimana.host/deny-sources: 10.6.0.0/16
+//!
+//! Connections are checked against the policy as they are accepted.
+//! A denied source always loses;
+//! otherwise, if any allowed sources are listed, the client must match one of them.
+//! Rejected connections are closed before any request is read.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::result::Result as StdResult;
+use std::sync::Arc;
+
+use anyhow::{anyhow, Context, Result};
+use tokio::net::TcpStream;
+use tokio_stream::{Stream, StreamExt};
+
+use logging::log_info;
+use names::PodName;
+
+/// Pod annotation listing the only source CIDRs allowed to connect.
+pub(crate) const ALLOW_SOURCES_ANNOTATION: &str = "vimana.host/allow-sources";
+
+/// Pod annotation listing source CIDRs that may never connect.
+pub(crate) const DENY_SOURCES_ANNOTATION: &str = "vimana.host/deny-sources";
+
+/// Which client addresses may connect to a pod.
+#[derive(Debug, PartialEq)]
+pub(crate) struct NetworkPolicy {
+    /// If non-empty, clients must belong to one of these subnets.
+    allow: Vec<Cidr>,
+
+    /// Clients in any of these subnets are rejected.
+    deny: Vec<Cidr>,
+}
+
+/// An IP subnet, e.g. `10.0.0.0/8`.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Cidr {
+    network: IpAddr,
+    prefix_length: u8,
+}
+
+impl NetworkPolicy {
+    /// Return `true` iff a client at `source` may connect.
+    pub(crate) fn permits(&self, source: IpAddr) -> bool {
+        let source = source.to_canonical();
+        !self.deny.iter().any(|cidr| cidr.contains(source))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(source)))
+    }
+}
+
+impl Cidr {
+    pub(crate) fn parse(cidr: &str) -> Result<Self> {
+        let (network, prefix_length) = cidr
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| anyhow!("Missing prefix length in CIDR: {cidr:?}"))?;
+        let network: IpAddr = network
+            .parse()
+            .with_context(|| format!("Invalid address in CIDR: {cidr:?}"))?;
+        let prefix_length: u8 = prefix_length
+            .parse()
+            .with_context(|| format!("Invalid prefix length in CIDR: {cidr:?}"))?;
+        let max_prefix_length = if network.is_ipv4() { 32 } else { 128 };
+        if prefix_length > max_prefix_length {
+            return Err(anyhow!("Prefix length too long in CIDR: {cidr:?}"));
+        }
+        Ok(Self {
+            network,
+            prefix_length,
+        })
+    }
+
+    pub(crate) fn contains(&self, address: IpAddr) -> bool {
+        match (self.network, address) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(address)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - self.prefix_length as u32)
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Parse the pod's [network policy](NetworkPolicy) from its annotations.
+/// Return `None` if neither annotation is present.
+pub(crate) fn network_policy(
+    annotations: &HashMap<String, String>,
+) -> Result<Option<Arc<NetworkPolicy>>> {
+    let parse = |key: &str| -> Result<Vec<Cidr>> {
+        annotations.get(key).map_or(Ok(Vec::new()), |cidrs| {
+            cidrs
+                .split(',')
+                .filter(|cidr| !cidr.trim().is_empty())
+                .map(Cidr::parse)
+                .collect::<Result<Vec<_>>>()
+                .with_context(|| format!("Invalid {key:?} annotation"))
+        })
+    };
+    let allow = parse(ALLOW_SOURCES_ANNOTATION)?;
+    let deny = parse(DENY_SOURCES_ANNOTATION)?;
+    if allow.is_empty() && deny.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Arc::new(NetworkPolicy { allow, deny })))
+}
+
+/// Drop incoming connections from clients the policy does not permit.
+/// Every connection is let through if there is no policy.
+pub(crate) fn enforce<E>(
+    incoming: impl Stream<Item = StdResult<TcpStream, E>>,
+    policy: Option<Arc<NetworkPolicy>>,
+    name: PodName,
+) -> impl Stream<Item = StdResult<TcpStream, E>> {
+    incoming.filter(move |connection| {
+        let (Some(policy), Ok(stream)) = (&policy, connection) else {
+            return true;
+        };
+        match stream.peer_addr() {
+            Ok(peer) if policy.permits(peer.ip()) => true,
+            Ok(peer) => {
+                log_info!(pod: &name, "Rejected connection from {}", peer.ip());
+                false
+            }
+            // The client is already gone.
+            Err(_) => false,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use names::Name;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpSocket};
+    use tokio_stream::wrappers::TcpListenerStream;
+
+    use super::*;
+
+    const POD_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0#7";
+
+    fn annotations(allow: &str, deny: &str) -> HashMap<String, String> {
+        HashMap::from([
+            (String::from(ALLOW_SOURCES_ANNOTATION), String::from(allow)),
+            (String::from(DENY_SOURCES_ANNOTATION), String::from(deny)),
+        ])
+    }
+
+    #[test]
+    fn test_network_policy() {
+        assert_eq!(network_policy(&HashMap::new()).unwrap(), None);
+        assert!(network_policy(&annotations("10.0.0.0/33", "")).is_err());
+        assert!(network_policy(&annotations("10.0.0.0", "")).is_err());
+
+        let policy = network_policy(&annotations("10.0.0.0/8, fd00::/8", "10.6.0.0/16"))
+            .unwrap()
+            .unwrap();
+        assert!(policy.permits("10.1.2.3".parse().unwrap()));
+        assert!(policy.permits("fd12::1".parse().unwrap()));
+        // IPv4-mapped IPv6 addresses are treated as IPv4.
+        assert!(policy.permits("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!policy.permits("10.6.0.1".parse().unwrap()));
+        assert!(!policy.permits("192.168.0.1".parse().unwrap()));
+
+        // Deny-only policies allow everything else.
+        let policy = network_policy(&annotations("", "0.0.0.0/0"))
+            .unwrap()
+            .unwrap();
+        assert!(!policy.permits("192.168.0.1".parse().unwrap()));
+        assert!(policy.permits("::1".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_enforce_at_accept() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let policy = network_policy(&annotations("127.0.0.0/8", "127.0.0.2/32")).unwrap();
+        let mut incoming = Box::pin(enforce(
+            TcpListenerStream::new(listener),
+            policy,
+            Name::parse(POD_NAME).pod().unwrap(),
+        ));
+
+        // Any address in 127.0.0.0/8 is loopback on Linux.
+        let connect_from = |source: &str| {
+            let socket = TcpSocket::new_v4().unwrap();
+            socket
+                .bind(SocketAddr::new(source.parse().unwrap(), 0))
+                .unwrap();
+            socket.connect(address)
+        };
+        let mut denied = connect_from("127.0.0.2").await.unwrap();
+        let _allowed = connect_from("127.0.0.1").await.unwrap();
+
+        // Only the allowed client comes out the other side.
+        let accepted = incoming.next().await.unwrap().unwrap();
+        assert_eq!(
+            accepted.peer_addr().unwrap().ip(),
+            "127.0.0.1".parse::<IpAddr>().unwrap(),
+        );
+        // The denied client sees its connection closed.
+        assert_eq!(denied.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,118 @@
+//! Re-reading configuration on SIGHUP.
+//!
+//! Only the [hot-reloadable](Reloadable) settings take effect without a restart:
+//! the log level and the set of insecure registries.
+//! Every other setting is read once at startup,
+//! and changes to it are ignored until the runtime is restarted.
+//! Running pods are never disturbed by a reload.
+
+use std::collections::HashSet;
+
+use anyhow::Result;
+use tokio::signal::unix::Signal;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::registry::Registry;
+use tracing_subscriber::reload::Handle;
+
+use crate::containers::ContainerStore;
+use logging::{log_info_globally, log_warn_globally};
+
+/// Handle to swap the global log level filter in place.
+pub(crate) type LogLevelHandle = Handle<LevelFilter, Registry>;
+
+/// The subset of configuration that can change while the runtime is running.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Reloadable {
+    /// Minimum level of log records to emit.
+    pub(crate) log_level: LevelFilter,
+
+    /// Container registries that should be pulled from using HTTP rather than HTTPS.
+    pub(crate) insecure_registries: HashSet<String>,
+}
+
+/// Each time `hangup` fires, read the configuration again with `read`
+/// and apply the [hot-reloadable](Reloadable) settings.
+/// If the configuration cannot be read, the current settings remain in effect.
+pub(crate) async fn reload_on_hangup<F>(
+    mut hangup: Signal,
+    read: F,
+    log_level: LogLevelHandle,
+    containers: ContainerStore,
+) where
+    F: Fn() -> Result<Reloadable>,
+{
+    while hangup.recv().await.is_some() {
+        let reloadable = match read() {
+            Ok(reloadable) => reloadable,
+            Err(error) => {
+                log_warn_globally!("Ignoring SIGHUP; failed to reload configuration: {error:?}");
+                continue;
+            }
+        };
+        if let Err(error) = log_level.reload(reloadable.log_level) {
+            log_warn_globally!("Failed to reload log level: {error}");
+        }
+        containers.set_insecure_registries(reloadable.insecure_registries);
+        log_info_globally!(
+            "Reloaded configuration with log level {}; other changes require a restart",
+            reloadable.log_level,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::time::Duration;
+
+    use tokio::signal::unix::{signal, SignalKind};
+    use tokio::task::spawn;
+    use tokio::time::{sleep, timeout};
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::reload::Layer;
+    use wasmtime::Engine as WasmEngine;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_hangup_reloads_log_level() {
+        let (layer, handle) = Layer::new(LevelFilter::INFO);
+        // The handle only works as long as the layer is alive.
+        let _subscriber = Registry::default().with(layer);
+        let containers = ContainerStore::new(
+            temp_dir()
+                .join(format!("vimana-reload-test-{}", std::process::id()))
+                .to_str()
+                .unwrap(),
+            HashSet::new(),
+            &WasmEngine::default(),
+            None,
+        )
+        .unwrap();
+
+        // Register the listener before raising the signal,
+        // otherwise the default disposition would terminate the test process.
+        let hangup = signal(SignalKind::hangup()).unwrap();
+        spawn(reload_on_hangup(
+            hangup,
+            || {
+                Ok(Reloadable {
+                    log_level: LevelFilter::DEBUG,
+                    insecure_registries: HashSet::from([String::from("localhost:5000")]),
+                })
+            },
+            handle.clone(),
+            containers,
+        ));
+        assert_eq!(handle.clone_current(), Some(LevelFilter::INFO));
+
+        assert_eq!(unsafe { libc::raise(libc::SIGHUP) }, 0);
+        timeout(Duration::from_secs(5), async {
+            while handle.clone_current() != Some(LevelFilter::DEBUG) {
+                sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Log level should change after SIGHUP");
+    }
+}
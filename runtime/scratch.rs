@@ -0,0 +1,220 @@
+//! Ephemeral per-pod scratch storage.
+//!
+//! A pod may request a private scratch directory by annotating the pod sandbox
+//! with the maximum number of bytes it needs:
+//!
+//!     vimana.host/scratch-bytes: 16777216
+//!
+//! The directory is provisioned when the container is created
+//! and wiped when the pod is killed.
+//! Components access it through the `vimana:host/scratch` host functions,
+//! which address flat files by name.
+
+use std::collections::HashMap;
+use std::fs::{create_dir_all as sync_create_dir_all, remove_dir_all as sync_remove_dir_all};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tokio::fs::{metadata, read, remove_file, write};
+use tokio::sync::Mutex;
+
+use names::PodName;
+
+/// Pod annotation requesting scratch storage, with a size limit in bytes.
+pub(crate) const SCRATCH_BYTES_ANNOTATION: &str = "vimana.host/scratch-bytes";
+
+/// Largest scratch area any single pod may request (256 MiB).
+pub(crate) const MAX_SCRATCH_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Root directory under which every pod's scratch area is provisioned.
+#[derive(Clone)]
+pub(crate) struct ScratchStore {
+    root: PathBuf,
+}
+
+/// A single pod's scratch area.
+pub(crate) struct Scratch {
+    /// Directory containing the scratch files.
+    path: PathBuf,
+
+    /// Maximum total size of all scratch files, in bytes.
+    limit: u64,
+
+    /// Total size of all scratch files, in bytes.
+    /// Held for the duration of each mutation so concurrent writes can't exceed the limit.
+    used: Mutex<u64>,
+}
+
+impl ScratchStore {
+    pub(crate) fn new(root: &str) -> Self {
+        Self {
+            root: PathBuf::from(root),
+        }
+    }
+
+    /// Create an empty scratch directory for the pod.
+    /// Any leftover contents from a previous container in the same pod are wiped.
+    pub(crate) fn provision(&self, name: &PodName, limit: u64) -> Result<Scratch> {
+        let path = self.path(name);
+        remove_if_exists(&path)?;
+        sync_create_dir_all(&path)
+            .with_context(|| format!("Failed to create scratch directory: {:?}", path))?;
+        Ok(Scratch {
+            path,
+            limit,
+            used: Mutex::new(0),
+        })
+    }
+
+    /// Delete the pod's scratch directory and everything in it, if it exists.
+    pub(crate) fn release(&self, name: &PodName) -> Result<()> {
+        remove_if_exists(&self.path(name))
+    }
+
+    /// Scratch for e.g. pod 42 would be stored under `<root>/42/`.
+    /// Pod IDs are unique within a node.
+    fn path(&self, name: &PodName) -> PathBuf {
+        self.root.join(name.pod.to_string())
+    }
+}
+
+impl Scratch {
+    /// Return the entire contents of the named scratch file.
+    pub(crate) async fn read(&self, file: &str) -> Result<Vec<u8>> {
+        let path = self.file_path(file)?;
+        read(&path)
+            .await
+            .with_context(|| format!("Failed reading scratch file: {file:?}"))
+    }
+
+    /// Replace the contents of the named scratch file, creating it if necessary.
+    /// Fails without modifying anything if the write would exceed the size limit.
+    pub(crate) async fn write(&self, file: &str, contents: &[u8]) -> Result<()> {
+        let path = self.file_path(file)?;
+        let mut used = self.used.lock().await;
+        let existing = existing_size(&path).await?;
+        let total = *used - existing + contents.len() as u64;
+        if total > self.limit {
+            bail!(
+                "Scratch limit exceeded: {total} bytes requested of {} available",
+                self.limit,
+            );
+        }
+        write(&path, contents)
+            .await
+            .with_context(|| format!("Failed writing scratch file: {file:?}"))?;
+        *used = total;
+        Ok(())
+    }
+
+    /// Delete the named scratch file if it exists.
+    pub(crate) async fn delete(&self, file: &str) -> Result<()> {
+        let path = self.file_path(file)?;
+        let mut used = self.used.lock().await;
+        let existing = existing_size(&path).await?;
+        match remove_file(&path).await {
+            Ok(()) => *used -= existing,
+            Err(error) if error.kind() == ErrorKind::NotFound => {}
+            Err(error) => {
+                return Err(error)
+                    .with_context(|| format!("Failed deleting scratch file: {file:?}"))
+            }
+        }
+        Ok(())
+    }
+
+    /// Scratch files are flat: names must not navigate the filesystem.
+    fn file_path(&self, file: &str) -> Result<PathBuf> {
+        if file.is_empty() || file == "." || file == ".." || file.contains(['/', '\0']) {
+            return Err(anyhow!("Invalid scratch file name: {file:?}"));
+        }
+        Ok(self.path.join(file))
+    }
+}
+
+/// Parse the requested [scratch size](SCRATCH_BYTES_ANNOTATION) from pod annotations.
+/// Return `None` if the annotation is absent.
+pub(crate) fn scratch_bytes(annotations: &HashMap<String, String>) -> Result<Option<u64>> {
+    annotations
+        .get(SCRATCH_BYTES_ANNOTATION)
+        .map(|bytes| {
+            let bytes: u64 = bytes
+                .trim()
+                .parse()
+                .with_context(|| format!("Invalid scratch size: {bytes:?}"))?;
+            if bytes > MAX_SCRATCH_BYTES {
+                bail!("Scratch size {bytes} exceeds the maximum of {MAX_SCRATCH_BYTES} bytes");
+            }
+            Ok(bytes)
+        })
+        .transpose()
+}
+
+/// Size of the file at `path`, or zero if it doesn't exist.
+async fn existing_size(path: &Path) -> Result<u64> {
+    match metadata(path).await {
+        Ok(metadata) => Ok(metadata.len()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(0),
+        Err(error) => Err(error).with_context(|| format!("Failed to stat {:?}", path)),
+    }
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match sync_remove_dir_all(path) {
+        Ok(()) => Ok(()),
+        Err(error) if error.kind() == ErrorKind::NotFound => Ok(()),
+        Err(error) => {
+            Err(error).with_context(|| format!("Failed to remove scratch directory: {:?}", path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+
+    use names::Name;
+
+    use super::*;
+
+    const POD_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0#7";
+
+    #[tokio::test]
+    async fn test_scratch_lifecycle() {
+        let root = temp_dir().join(format!("vimana-scratch-test-{}", std::process::id()));
+        let store = ScratchStore::new(root.to_str().unwrap());
+        let name = Name::parse(POD_NAME).pod().unwrap();
+
+        // Provisioned on container creation.
+        let scratch = store.provision(&name, 8).unwrap();
+        assert!(root.join("7").is_dir());
+
+        scratch.write("upload", b"hello").await.unwrap();
+        assert_eq!(scratch.read("upload").await.unwrap(), b"hello");
+        // Overwriting frees the old contents first.
+        scratch.write("upload", b"goodbye").await.unwrap();
+        // The total across files is bounded.
+        assert!(scratch.write("other", b"hi").await.is_err());
+        scratch.delete("upload").await.unwrap();
+        scratch.write("other", b"hi").await.unwrap();
+        assert!(scratch.write("../escape", b"hi").await.is_err());
+
+        // Removed when the pod is killed.
+        store.release(&name).unwrap();
+        assert!(!root.join("7").exists());
+        // Releasing is idempotent.
+        store.release(&name).unwrap();
+    }
+
+    #[test]
+    fn test_scratch_bytes() {
+        let annotations = |value: &str| {
+            HashMap::from([(String::from(SCRATCH_BYTES_ANNOTATION), String::from(value))])
+        };
+        assert_eq!(scratch_bytes(&HashMap::new()).unwrap(), None);
+        assert_eq!(scratch_bytes(&annotations("1024")).unwrap(), Some(1024));
+        assert!(scratch_bytes(&annotations("lots")).is_err());
+        assert!(scratch_bytes(&annotations(&(MAX_SCRATCH_BYTES + 1).to_string())).is_err());
+    }
+}
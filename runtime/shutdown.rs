@@ -0,0 +1,138 @@
+//! Shutdown hooks, giving components a chance to clean up before their pod stops.
+//!
+//! A pod may designate one of its component's unary gRPC methods as a shutdown hook
+//! by annotating the pod sandbox with the method's fully-qualified path:
+//!
+//!     vimana.host/shutdown-method: <package>.<Service>/<Method>
+//!
+//! When the container is stopped gracefully,
+//! the method is called with an empty request message (e.g. `google.protobuf.Empty`)
+//! before the server stops accepting connections.
+//! The call counts against the stop's grace period,
+//! and is skipped entirely if the container is aborted forcefully.
+
+use std::collections::HashMap;
+
+use anyhow::{anyhow, Context, Result};
+use axum::body::Body as AxumBody;
+use http::Request as HttpRequest;
+use http_body_util::BodyExt;
+use tonic::service::Routes;
+use tonic::Code;
+use tower::ServiceExt;
+
+/// Pod annotation naming the gRPC method to call before the container stops.
+pub(crate) const SHUTDOWN_METHOD_ANNOTATION: &str = "vimana.host/shutdown-method";
+
+/// Uncompressed gRPC frame containing an empty message:
+/// flag byte followed by a big-endian length of zero.
+const EMPTY_FRAME: [u8; 5] = [0; 5];
+
+/// Parse the [shutdown method](SHUTDOWN_METHOD_ANNOTATION) from pod annotations, if any.
+/// The result is the method's path, without a leading slash.
+pub(crate) fn shutdown_method(annotations: &HashMap<String, String>) -> Result<Option<String>> {
+    annotations
+        .get(SHUTDOWN_METHOD_ANNOTATION)
+        .map(|method| {
+            let method = method.trim().trim_start_matches('/');
+            match method.split_once('/') {
+                Some((service, name))
+                    if !service.is_empty() && !name.is_empty() && !name.contains('/') =>
+                {
+                    Ok(String::from(method))
+                }
+                _ => Err(anyhow!(
+                    "Invalid {SHUTDOWN_METHOD_ANNOTATION:?} annotation: {method:?}",
+                )),
+            }
+        })
+        .transpose()
+}
+
+/// Call a pod's shutdown hook, failing unless the component responds successfully.
+pub(crate) async fn call_shutdown_hook(routes: &Routes, method: &str) -> Result<()> {
+    let request = HttpRequest::post(format!("/{method}"))
+        .header("content-type", "application/grpc")
+        .header("te", "trailers")
+        .body(AxumBody::from(EMPTY_FRAME.to_vec()))
+        .context("Failed building shutdown hook request")?;
+    let response = routes
+        .clone()
+        .into_axum_router()
+        .oneshot(request)
+        .await
+        .context("Failed sending shutdown hook request")?;
+
+    let (parts, body) = response.into_parts();
+    let body = body
+        .collect()
+        .await
+        .map_err(|error| anyhow!("Failed reading shutdown hook response: {error}"))?;
+    // Responses without a body carry the status in their headers ("trailers-only").
+    let grpc_status = body
+        .trailers()
+        .and_then(|trailers| trailers.get("grpc-status"))
+        .or_else(|| parts.headers.get("grpc-status"))
+        .and_then(|status| status.to_str().ok()?.parse::<i32>().ok())
+        .map(Code::from)
+        .unwrap_or(Code::Unknown);
+    match grpc_status {
+        Code::Ok => Ok(()),
+        code => Err(anyhow!("Shutdown hook failed with status {code:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::routing::method_routing::post;
+    use axum::Router;
+    use http::Response as HttpResponse;
+
+    use super::*;
+
+    fn annotations(method: &str) -> HashMap<String, String> {
+        HashMap::from([(
+            String::from(SHUTDOWN_METHOD_ANNOTATION),
+            String::from(method),
+        )])
+    }
+
+    #[test]
+    fn test_shutdown_method() {
+        assert_eq!(shutdown_method(&HashMap::new()).unwrap(), None);
+        assert_eq!(
+            shutdown_method(&annotations("foo.Bar/Drain")).unwrap(),
+            Some(String::from("foo.Bar/Drain")),
+        );
+        assert_eq!(
+            shutdown_method(&annotations(" /foo.Bar/Drain ")).unwrap(),
+            Some(String::from("foo.Bar/Drain")),
+        );
+        assert!(shutdown_method(&annotations("Drain")).is_err());
+        assert!(shutdown_method(&annotations("foo.Bar/")).is_err());
+        assert!(shutdown_method(&annotations("foo/Bar/Drain")).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_call_shutdown_hook() {
+        let respond = |status: &'static str| {
+            post(move || async move {
+                HttpResponse::builder()
+                    .header("content-type", "application/grpc")
+                    .header("grpc-status", status)
+                    .body(AxumBody::empty())
+                    .unwrap()
+            })
+        };
+        let routes = Routes::from(
+            Router::new()
+                .route("/foo.Bar/Drain", respond("0"))
+                .route("/foo.Bar/Fail", respond("13")),
+        );
+        assert!(call_shutdown_hook(&routes, "foo.Bar/Drain").await.is_ok());
+        assert!(call_shutdown_hook(&routes, "foo.Bar/Fail").await.is_err());
+        assert!(call_shutdown_hook(&routes, "foo.Bar/Missing")
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,191 @@
+//! Startup ordering among components running on the same node.
+//!
+//! A pod may declare that its component depends on other components
+//! by annotating the pod sandbox with a comma-separated list of component names:
+//!
+//!     vimana.host/startup-dependencies: <domain>:<server>@<version>,...
+//!
+//! Such a pod will not bind its gRPC port until every dependency
+//! has at least one [running](crate::state::PodState::Running) pod on the node.
+
+use std::collections::HashMap;
+use std::pin::pin;
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use papaya::{Compute, HashMap as LockFreeConcurrentHashMap, Operation};
+use tokio::sync::Notify;
+use tokio::time::timeout;
+
+use names::{ComponentName, Name};
+
+/// Pod annotation listing the components that must be running before the pod starts.
+pub(crate) const STARTUP_DEPENDENCIES_ANNOTATION: &str = "vimana.host/startup-dependencies";
+
+/// Maximum time a pod will wait for its startup dependencies before failing to start.
+pub(crate) const STARTUP_DEPENDENCY_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Tracks which components currently have running pods on this node.
+pub(crate) struct RunningComponents {
+    /// Number of running pods for each component.
+    /// Components with no running pods are absent.
+    counts: LockFreeConcurrentHashMap<ComponentName, usize>,
+
+    /// Wakes up any pods waiting on startup dependencies whenever a component starts running.
+    started: Notify,
+}
+
+impl RunningComponents {
+    pub(crate) fn new() -> Self {
+        Self {
+            counts: LockFreeConcurrentHashMap::new(),
+            started: Notify::new(),
+        }
+    }
+
+    /// Record that a pod for the given component is now running.
+    pub(crate) fn started(&self, component: &ComponentName) {
+        self.counts
+            .pin()
+            .update_or_insert(component.clone(), |count| count + 1, 1);
+        self.started.notify_waiters();
+    }
+
+    /// Record that a previously-running pod for the given component is no longer running.
+    pub(crate) fn stopped(&self, component: &ComponentName) {
+        let counts = self.counts.pin();
+        let _: Compute<'_, _, _, ()> = counts.compute(component.clone(), |entry| match entry {
+            Some((_, count)) if *count > 1 => Operation::Insert(count - 1),
+            Some(_) => Operation::Remove,
+            None => Operation::Abort(()),
+        });
+    }
+
+    /// Return `true` iff the component has at least one running pod.
+    pub(crate) fn is_running(&self, component: &ComponentName) -> bool {
+        self.counts.pin().contains_key(component)
+    }
+
+    /// Wait until every component in `dependencies` has a running pod,
+    /// or return an error if that takes longer than `duration`.
+    pub(crate) async fn wait_for(
+        &self,
+        dependencies: &[ComponentName],
+        duration: Duration,
+    ) -> Result<()> {
+        timeout(duration, async {
+            loop {
+                // Register interest in the notification *before* checking,
+                // so a component that starts in between is not missed.
+                let mut notified = pin!(self.started.notified());
+                notified.as_mut().enable();
+                if dependencies
+                    .iter()
+                    .all(|component| self.is_running(component))
+                {
+                    return;
+                }
+                notified.await;
+            }
+        })
+        .await
+        .map_err(|_| {
+            let missing: Vec<String> = dependencies
+                .iter()
+                .filter(|component| !self.is_running(component))
+                .map(ComponentName::to_string)
+                .collect();
+            anyhow!(
+                "Timed out after {} seconds waiting for startup dependencies: {}",
+                duration.as_secs(),
+                missing.join(", "),
+            )
+        })
+    }
+}
+
+/// Parse the [startup dependencies](STARTUP_DEPENDENCIES_ANNOTATION) from pod annotations.
+/// Return an empty list if the annotation is absent.
+pub(crate) fn startup_dependencies(
+    annotations: &HashMap<String, String>,
+) -> Result<Vec<ComponentName>> {
+    annotations
+        .get(STARTUP_DEPENDENCIES_ANNOTATION)
+        .map_or(Ok(Vec::new()), |dependencies| {
+            dependencies
+                .split(',')
+                .map(str::trim)
+                .filter(|dependency| !dependency.is_empty())
+                .map(|dependency| {
+                    Name::parse(dependency)
+                        .component()
+                        .with_context(|| format!("Invalid startup dependency: {dependency:?}"))
+                })
+                .collect()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::slice::from_ref;
+    use std::sync::Arc;
+
+    use tokio::task::spawn;
+
+    use super::*;
+
+    const SERVER: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0";
+    const DATABASE: &str = "1234567890abcdef1234567890abcdef:some-database@2.0.0";
+
+    #[tokio::test]
+    async fn test_wait_for_dependency() {
+        let database = Name::parse(DATABASE).component().unwrap();
+        let annotations = HashMap::from([(
+            String::from(STARTUP_DEPENDENCIES_ANNOTATION),
+            String::from(DATABASE),
+        )]);
+        let dependencies = startup_dependencies(&annotations).unwrap();
+        assert_eq!(dependencies, vec![database.clone()]);
+
+        let running = Arc::new(RunningComponents::new());
+        let waiter = {
+            let running = running.clone();
+            spawn(async move {
+                running
+                    .wait_for(&dependencies, Duration::from_secs(10))
+                    .await
+            })
+        };
+
+        // The server is still waiting because the database is not running yet.
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        running.started(&database);
+        waiter.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_wait_for_dependency_timeout() {
+        let server = Name::parse(SERVER).component().unwrap();
+        let database = Name::parse(DATABASE).component().unwrap();
+        let running = RunningComponents::new();
+
+        // A different component running does not satisfy the dependency.
+        running.started(&server);
+        let error = running
+            .wait_for(from_ref(&database), Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert!(error.to_string().contains(DATABASE));
+
+        // Neither does a dependency that started and then stopped.
+        running.started(&database);
+        running.stopped(&database);
+        assert!(!running.is_running(&database));
+        assert!(running
+            .wait_for(&[database], Duration::from_millis(10))
+            .await
+            .is_err());
+    }
+}
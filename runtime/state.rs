@@ -1,36 +1,67 @@
 //! State machine used by the CRI service to manage pods.
 
-use std::collections::HashMap;
-use std::net::SocketAddr;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Display;
+use std::future::Future;
+use std::io::Error as IoError;
+use std::mem::forget;
+use std::net::{IpAddr, SocketAddr};
+use std::panic::AssertUnwindSafe;
 use std::result::Result as StdResult;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use std::sync::Mutex as SyncMutex;
-use std::time::{Duration, SystemTime};
+use std::sync::{Mutex as SyncMutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
 use anyhow::{anyhow, Error, Result};
 use futures::future::Shared;
+use futures::FutureExt;
 use papaya::{Compute, HashMap as LockFreeConcurrentHashMap, Operation};
 use tokio::select;
-use tokio::sync::oneshot;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{broadcast, oneshot};
 use tokio::task::{spawn, JoinHandle};
-use tokio::time::timeout;
+use tokio::time::{interval, sleep, timeout};
 use tonic::service::Routes;
 use tonic::transport::server::TcpIncoming;
-use tonic::transport::{Error as ServerError, Server};
+use tonic::transport::Server;
+use tonic::Status;
 use wasmtime::Engine as WasmEngine;
 
-use crate::containers::ContainerStore;
-use crate::ipam::{IpAddress, Ipam};
-use crate::pods::{PodInitializer, SharedResultFuture, GRPC_PORT};
-use api_proto::runtime::v1::{ContainerMetadata, ImageSpec, PodSandboxMetadata};
-use logging::{log_info, log_warn};
+use crate::admission::method_priorities;
+use crate::affinity::{cpuset, CpuSet, PinnedRuntimes};
+use crate::capability::capability_policy;
+use crate::connections::{limit_connections, max_connections};
+use crate::health::{check, Health, Probe};
+use crate::host::{with_pod_address, Environment};
+use crate::ipam::{routable, IpAddress, Ipam, IpamAudit};
+use crate::pods::{GrpcPod, PodInitializer, RequestPolicy, SharedResultFuture, GRPC_PORT};
+use crate::policy::{enforce, network_policy, NetworkPolicy};
+use crate::scratch::{scratch_bytes, Scratch, ScratchStore};
+use crate::shutdown::{call_shutdown_hook, shutdown_method};
+use crate::startup::{startup_dependencies, RunningComponents, STARTUP_DEPENDENCY_TIMEOUT};
+use crate::throttle::PodCreationLimiter;
+use crate::usage::ResourceUsage;
+use api_proto::runtime::v1::{
+    ContainerEventType, ContainerMetadata, ImageSpec, PodSandboxMetadata,
+};
+use logging::{log_audit, log_info, log_warn, log_warn_globally};
 use names::{ComponentName, PodId, PodName};
 
 const VIMANA_LABEL_PREFIX: &str = "vimana.host/";
 
 const K8S_CONTAINER_RESTART_COUNT_ANNOTATION: &str = "io.kubernetes.container.restartCount";
 
+/// How many times to bind a pod's port before giving up on its address becoming routable.
+const ROUTABLE_ATTEMPTS: u32 = 10;
+
+/// How long to wait between attempts for a pod's address to become routable.
+const ROUTABLE_BACKOFF: Duration = Duration::from_millis(100);
+
+/// How many container events each subscriber may fall behind before it starts missing some.
+const CONTAINER_EVENT_BUFFER: usize = 256;
+
 /// Global runtime state for a work node.
 pub(crate) struct WorkRuntime {
     /// Global Wasm engine to run hosted services.
@@ -40,9 +71,11 @@ pub(crate) struct WorkRuntime {
     // TODO: Report the size of this data structure in some sort of runtime stats.
     /// Map of locally running pod IDs to pod controllers.
     /// Lock-freedom is important to help isolate tenants from one another.
-    pods: LockFreeConcurrentHashMap<PodId, Pod>,
+    /// Shared with the [address audit](Self::audit_addresses), if any.
+    pods: Arc<LockFreeConcurrentHashMap<PodId, Pod>>,
 
     /// To generate unique pod IDs.
+    /// Starts from the [first ID](first_pod_id) of the node.
     next_pod_id: AtomicUsize,
 
     /// Remote store from which to retrieve container images by ID,
@@ -52,12 +85,52 @@ pub(crate) struct WorkRuntime {
     /// IP address management system.
     ipam: Ipam,
 
+    /// Components with running pods, used to enforce startup dependencies.
+    running: RunningComponents,
+
+    /// How long a pod waits for its startup dependencies before failing to start.
+    startup_dependency_timeout: Duration,
+
+    /// Runtimes for pod servers pinned to specific CPU cores.
+    pinned: PinnedRuntimes,
+
+    /// Where pods' ephemeral scratch areas are provisioned.
+    scratch: ScratchStore,
+
+    /// Default limit on the number of connections each pod holds open at once.
+    /// Components may override it in their metadata. Unlimited if unset.
+    max_connections: Option<usize>,
+
+    /// How long to let a pod drain its in-flight requests when its sandbox is stopped
+    /// before forcefully aborting it.
+    pod_drain_timeout: Duration,
+
+    /// Limits how quickly each domain may create pods. Unlimited if unset.
+    pod_creations: Option<PodCreationLimiter>,
+
+    /// Publishes container lifecycle transitions to [subscribers](Self::subscribe_events).
+    events: broadcast::Sender<ContainerEvent>,
+
     /// All data-place servers should start gracefully shutting down
     /// upon completion of this shareable future.
     /// Individual pods can be shut down with their [killer](Pod::killer).
     shutdown: Shared<oneshot::Receiver<()>>,
 }
 
+/// Node-wide limits on every pod, set by the operator.
+pub(crate) struct PodLimits {
+    /// Default limit on the number of connections each pod holds open at once.
+    /// Components may override it in their metadata. Unlimited if unset.
+    pub(crate) max_connections: Option<usize>,
+
+    /// How long to let a pod drain its in-flight requests when its sandbox is stopped
+    /// before forcefully aborting it.
+    pub(crate) pod_drain_timeout: Duration,
+
+    /// Maximum number of pods each domain may create per second. Unlimited if unset.
+    pub(crate) max_pod_creations_per_domain: Option<u32>,
+}
+
 /// Pod lifecycle state.
 ///
 /// Pods generally follow a simple linear lifecycle:
@@ -93,6 +166,27 @@ pub(crate) enum PodState {
     Killed,
 }
 
+impl PodState {
+    /// Whether the pod is ready to receive traffic,
+    /// which is only once its server is bound and spawned.
+    ///
+    /// Readiness [probes](WorkRuntime::probe) fail until then,
+    /// so Kubelet does not route traffic to the pod.
+    /// The pod sandbox itself is ready all along, until it's killed;
+    /// Kubelet would tear down and recreate a sandbox reported as not ready.
+    pub(crate) fn is_ready(self) -> bool {
+        match self {
+            Self::Running => true,
+            Self::Initiated
+            | Self::Created
+            | Self::Starting
+            | Self::Stopped
+            | Self::Removed
+            | Self::Killed => false,
+        }
+    }
+}
+
 /// All information known about a pod / container pair
 /// throughout its [lifecycle](PodState).
 #[derive(Clone)]
@@ -121,12 +215,34 @@ pub(crate) struct Pod {
     /// Creation timestamp of the pod sandbox in nanoseconds. Must be > 0.
     pub(crate) pod_created_at: i64,
 
+    /// Components that must be running on this node before the container can start.
+    startup_dependencies: Vec<ComponentName>,
+
+    /// Size limit of the pod's scratch storage, if it requested any.
+    scratch_bytes: Option<u64>,
+
+    /// Restricts which clients may connect to the pod's server, if set.
+    network_policy: Option<Arc<NetworkPolicy>>,
+
+    /// Restricts which callers may use each host function capability
+    /// and sets the admission priority of each method.
+    request_policy: RequestPolicy,
+
+    /// Component method to call before the container stops gracefully, if any.
+    shutdown_method: Option<String>,
+
+    /// Latest liveness and readiness check results reported by the component.
+    pub(crate) health: Health,
+
+    /// CPU and memory used by the pod's instances, across restarts of the container.
+    pub(crate) usage: ResourceUsage,
+
     // --------------------------------
     // The following are populated after `CreateContainer`:
     // --------------------------------
-    /// Axum router implementing the pod.
+    /// Axum router implementing the pod, with limits from the component's metadata.
     /// Kubelet ensures that the image has been pulled right before calling `CreateContainer`.
-    routes: Option<SharedResultFuture<Routes>>,
+    routes: Option<SharedResultFuture<GrpcPod>>,
 
     /// Creation timestamp of the container in nanoseconds. Must be > 0.
     pub(crate) container_created_at: i64,
@@ -143,9 +259,21 @@ pub(crate) struct Pod {
     /// Environment variable keys and values.
     environment: HashMap<String, String>,
 
+    /// The environment as seen by the running component.
+    /// Updated in place when only the environment changes between `CreateContainer` calls.
+    live_environment: Environment,
+
     /// Image specified when creating the container.
     pub(crate) image_spec: Option<ImageSpec>,
 
+    /// Scratch storage provisioned for the container, if the pod requested any.
+    scratch: Option<Arc<Scratch>>,
+
+    /// CPU cores to which the pod server is pinned, if any.
+    /// Requested by pod annotation or set by `UpdateContainerResources`,
+    /// and applied when the server starts.
+    pub(crate) cpuset: Option<CpuSet>,
+
     // --------------------------------
     // The following are populated after `StartContainer`:
     // --------------------------------
@@ -155,6 +283,9 @@ pub(crate) struct Pod {
     /// Shuts down the running container, either the easy way or the hard way.
     killer: SingleUse<ContainerKiller>,
 
+    /// How the container's server exited, once it has (whether or not it was stopped).
+    pub(crate) exit: ContainerExit,
+
     // --------------------------------
     // The following are populated after `StopContainer`:
     // --------------------------------
@@ -162,24 +293,59 @@ pub(crate) struct Pod {
     pub(crate) container_finished_at: i64,
 }
 
+impl Pod {
+    /// Environment variables visible to the pod's component:
+    /// the container's own variables, along with the pod's address.
+    fn component_environment(
+        &self,
+        environment: &HashMap<String, String>,
+    ) -> HashMap<String, String> {
+        with_pod_address(
+            environment,
+            SocketAddr::new(self.ip_address.address, GRPC_PORT),
+        )
+    }
+}
+
 impl WorkRuntime {
     /// Return a new runtime with no running pods.
     pub(crate) fn new(
         wasmtime: WasmEngine,
-        containers: ContainerStore,
+        pod_store: PodInitializer,
         ipam: Ipam,
+        scratch: ScratchStore,
         shutdown: Shared<oneshot::Receiver<()>>,
+        node_id: u32,
+        limits: PodLimits,
     ) -> Self {
         Self {
             wasmtime,
-            pods: LockFreeConcurrentHashMap::new(),
-            next_pod_id: AtomicUsize::new(0),
-            pod_store: PodInitializer::new(containers),
+            pods: Arc::new(LockFreeConcurrentHashMap::new()),
+            next_pod_id: AtomicUsize::new(first_pod_id(node_id)),
+            pod_store,
             ipam,
+            running: RunningComponents::new(),
+            startup_dependency_timeout: STARTUP_DEPENDENCY_TIMEOUT,
+            pinned: PinnedRuntimes::new(shutdown.clone()),
+            scratch,
+            max_connections: limits.max_connections,
+            pod_drain_timeout: limits.pod_drain_timeout,
+            pod_creations: limits
+                .max_pod_creations_per_domain
+                .map(PodCreationLimiter::new),
+            events: broadcast::Sender::new(CONTAINER_EVENT_BUFFER),
             shutdown,
         }
     }
 
+    /// Never tear down the pod map or the codec cache,
+    /// so that exiting the process skips dropping every pod and codec one by one.
+    /// Only call this for a runtime that lives until the process exits.
+    pub(crate) fn leak_on_exit(&self) {
+        forget(self.pods.clone());
+        self.pod_store.leak_on_exit();
+    }
+
     /// Create a new [pod controller](PodController)
     /// in the [initiated](PodController::Initiated) state.
     /// Return a newly generated ID.
@@ -193,11 +359,29 @@ impl WorkRuntime {
         labels: HashMap<String, String>,
         annotations: HashMap<String, String>,
     ) -> Result<PodName> {
-        // TODO: Does the pod sandbox / container ID have to be unique within a node,
-        //   or across all nodes?
-        //   if the latter, figure out how to get a unique node ID involved somehow.
-        let pod_id = self.next_pod_id.fetch_add(1, Ordering::Relaxed);
-        let pod_name = PodName::new(component_name.as_ref().clone(), pod_id);
+        let pod_name = self.next_pod_name(&component_name);
+        let pod_id = pod_name.pod;
+        let startup_dependencies = startup_dependencies(&annotations)?;
+        let scratch_bytes = scratch_bytes(&annotations)?;
+        let network_policy = network_policy(&annotations)?;
+        let request_policy = RequestPolicy {
+            capabilities: capability_policy(&annotations)?,
+            priorities: method_priorities(&annotations)?,
+        };
+        let cpuset = cpuset(&annotations)?;
+        let shutdown_method = shutdown_method(&annotations)?;
+
+        // Only pods that could actually be created count against the domain's rate,
+        // but they count before taking an address, which is what a storm would exhaust.
+        if let Some(pod_creations) = &self.pod_creations {
+            let domain = &component_name.server.domain;
+            if !pod_creations.acquire(domain, Instant::now()) {
+                return Err(Status::resource_exhausted(format!(
+                    "Domain {domain} is creating pods too quickly"
+                ))
+                .into());
+            }
+        }
 
         let ip_address = self.ipam.address(&pod_name).await?;
 
@@ -209,6 +393,14 @@ impl WorkRuntime {
             pod_labels: labels,
             pod_annotations: annotations,
             pod_created_at: now(),
+            startup_dependencies,
+            scratch_bytes,
+            network_policy,
+            request_policy,
+            shutdown_method,
+            cpuset,
+            health: Health::default(),
+            usage: ResourceUsage::default(),
             // These are set at later states:
             routes: None,
             container_created_at: 0,
@@ -216,25 +408,47 @@ impl WorkRuntime {
             container_labels: HashMap::default(),
             container_annotations: HashMap::default(),
             environment: HashMap::default(),
+            live_environment: Environment::default(),
             image_spec: None,
+            scratch: None,
             container_started_at: 0,
             killer: SingleUse::default(),
+            exit: ContainerExit::default(),
             container_finished_at: 0,
         };
 
-        let pods = self.pods.pin();
-        match pods.try_insert(pod_id, pod) {
-            Ok(_) => {
+        // Don't hold the pin across the await below.
+        let inserted = self
+            .pods
+            .pin()
+            .try_insert(pod_id, pod)
+            .map(|_| ())
+            .map_err(|error| error.not_inserted);
+        match inserted {
+            Ok(()) => {
                 log_info!(pod: &pod_name, "Successful pod initialization");
+                log_audit!(pod: &pod_name, operation: "initialized", rpc: "RunPodSandbox");
                 Ok(pod_name)
             }
-            Err(_) => {
+            Err(pod) => {
                 // Impossible unless the number of pods overflows `usize`.
+                // Even so, the address allocated above must not leak.
+                if let Err(error) = pod.ip_address.release().await {
+                    log_warn!(pod: &pod_name, "Failed releasing address: {error:?}");
+                }
                 Err(anyhow!("Pod id collision: {:?}", pod_id))
             }
         }
     }
 
+    /// Generate a fresh name for a new pod of the given component.
+    fn next_pod_name(&self, component_name: &ComponentName) -> PodName {
+        // The node ID in the upper bits keeps pod IDs unique across nodes,
+        // so the control plane can reason about pods cluster-wide.
+        let pod_id = self.next_pod_id.fetch_add(1, Ordering::Relaxed);
+        PodName::new(component_name.clone(), pod_id)
+    }
+
     /// Set the environment variables in an [initiated](PodController::Initiated) pod controller,
     /// converting it to a [created](PodController::Created) controller.
     pub(crate) fn create_container(
@@ -247,7 +461,20 @@ impl WorkRuntime {
         image_spec: &Option<ImageSpec>,
     ) -> Result<()> {
         let mut circumstance = CreateContainerCircumstance::Initial;
+        // Set if the pod was killed (or deleted outright) concurrently.
+        let mut killed = false;
         let pods = self.pods.pin();
+
+        // Provision scratch storage up front if this will be the initial creation,
+        // since the compute closure below must not perform I/O.
+        let scratch = match pods.get(&name.pod) {
+            Some(pod) if matches!(pod.state, PodState::Initiated | PodState::Removed) => pod
+                .scratch_bytes
+                .map(|limit| self.scratch.provision(name, limit).map(Arc::new))
+                .transpose()?,
+            _ => None,
+        };
+
         match pods.compute(name.pod, |entry| match entry {
             Some((_, pod)) => {
                 match pod.state {
@@ -266,10 +493,17 @@ impl WorkRuntime {
                         // The Vimana labels match. Transition to `Created`.
                         circumstance = CreateContainerCircumstance::Initial;
                         let mut pod = pod.clone();
-                        pod.routes = Some(
-                            self.pod_store
-                                .grpc(&self.wasmtime, pod.component_name.clone()),
-                        );
+                        pod.live_environment =
+                            Environment::new(&pod.component_environment(environment));
+                        pod.routes = Some(self.pod_store.grpc(
+                            &self.wasmtime,
+                            pod.component_name.clone(),
+                            scratch.clone(),
+                            pod.live_environment.clone(),
+                            pod.request_policy.clone(),
+                            pod.usage.clone(),
+                        ));
+                        pod.scratch = scratch.clone();
                         pod.state = PodState::Created;
                         pod.container_metadata = container_metadata.clone();
                         pod.container_labels = labels.clone();
@@ -283,12 +517,23 @@ impl WorkRuntime {
                     PodState::Created | PodState::Starting | PodState::Running => {
                         // Support idempotency if the parameters are equal
                         // (modulo 'attempt' and 'restart-count').
-                        if container_metadata_equal(&pod.container_metadata, container_metadata)
-                            && &pod.container_labels == labels
-                            && container_annotations_equal(&pod.container_annotations, annotations)
-                            && &pod.environment == environment
-                            && &pod.image_spec == image_spec
-                        {
+                        // The environment may differ; it's reloaded in place.
+                        let existing = ContainerParameters {
+                            metadata: &pod.container_metadata,
+                            labels: &pod.container_labels,
+                            annotations: &pod.container_annotations,
+                            environment: &pod.environment,
+                            image_spec: &pod.image_spec,
+                        };
+                        let requested = ContainerParameters {
+                            metadata: container_metadata,
+                            labels,
+                            annotations,
+                            environment,
+                            image_spec,
+                        };
+                        let change = existing.change(&requested);
+                        if change != ContainerChange::Structural {
                             let mut pod = pod.clone();
                             pod.state = PodState::Created;
                             let pod_initialization_failed =
@@ -307,13 +552,20 @@ impl WorkRuntime {
                                 // `StartContainer` failed because initializing the gRPC pod failed.
                                 // Retry initializing the pod on subsequent attempts.
                                 circumstance = CreateContainerCircumstance::Reattempt;
-                                pod.routes = Some(
-                                    self.pod_store
-                                        .grpc(&self.wasmtime, pod.component_name.clone()),
-                                );
+                                pod.routes = Some(self.pod_store.grpc(
+                                    &self.wasmtime,
+                                    pod.component_name.clone(),
+                                    pod.scratch.clone(),
+                                    pod.live_environment.clone(),
+                                    pod.request_policy.clone(),
+                                    pod.usage.clone(),
+                                ));
+                            } else if change == ContainerChange::Reloadable {
+                                circumstance = CreateContainerCircumstance::Reload;
                             } else {
                                 circumstance = CreateContainerCircumstance::Idempotent;
                             }
+                            pod.environment = environment.clone();
                             pod.container_metadata = container_metadata.clone();
                             pod.container_annotations = annotations.clone();
                             pod.container_created_at = now();
@@ -324,21 +576,45 @@ impl WorkRuntime {
                             )))
                         }
                     }
-                    PodState::Stopped | PodState::Killed => {
+                    PodState::Stopped => {
                         // Unexpected Kubelet behavior.
                         Operation::Abort(Some(anyhow!("Bad prior state: {:?}", pod.state)))
                     }
+                    PodState::Killed => {
+                        // `StopPodSandbox` may race with `CreateContainer`.
+                        // The kill always wins, so the pod never comes back to life.
+                        killed = true;
+                        Operation::Abort(Some(anyhow!("Pod was killed")))
+                    }
                 }
             }
-            None => Operation::Abort(Some(anyhow!("Pod not found"))),
+            None => {
+                killed = true;
+                Operation::Abort(Some(anyhow!("Pod not found")))
+            }
         }) {
-            Compute::Updated { old: _, new: _ } => {
+            Compute::Updated {
+                old: _,
+                new: (_, pod),
+            } => {
                 match circumstance {
                     CreateContainerCircumstance::Initial => {
-                        log_info!(pod: name, "Successful container creation")
+                        log_info!(pod: name, "Successful container creation");
+                        log_audit!(pod: name, operation: "created", rpc: "CreateContainer");
+                        self.publish(name, ContainerEventType::ContainerCreatedEvent);
                     }
                     CreateContainerCircumstance::Reattempt => {
-                        log_info!(pod: name, "Reattempted container creation")
+                        pod.live_environment
+                            .replace(&pod.component_environment(&pod.environment));
+                        log_info!(pod: name, "Reattempted container creation");
+                        log_audit!(pod: name, operation: "created", rpc: "CreateContainer");
+                        self.publish(name, ContainerEventType::ContainerCreatedEvent);
+                    }
+                    CreateContainerCircumstance::Reload => {
+                        pod.live_environment
+                            .replace(&pod.component_environment(&pod.environment));
+                        log_info!(pod: name, "Reloaded container environment");
+                        log_audit!(pod: name, operation: "created", rpc: "CreateContainer");
                     }
                     CreateContainerCircumstance::Idempotent => {
                         log_info!(pod: name, "Idempotent container creation")
@@ -347,7 +623,13 @@ impl WorkRuntime {
                 Ok(())
             }
             Compute::Aborted(None) => Ok(()),
-            Compute::Aborted(Some(error)) => Err(error),
+            Compute::Aborted(Some(error)) => {
+                // The kill may have released the scratch area before it was provisioned above.
+                if killed && scratch.is_some() {
+                    self.scratch.release(name)?;
+                }
+                Err(error)
+            }
             _ => {
                 // All possible compute outcomes should have been handled.
                 Err(anyhow!("State machine logical impossibility"))
@@ -357,22 +639,32 @@ impl WorkRuntime {
 
     /// Start up a server for a [created](PodState::Created) pod controller
     /// on its configured gRPC port.
+    /// A [stopped](PodState::Stopped) controller is restarted the same way,
+    /// reusing its initialized component unless initialization failed.
     ///
     /// First, convert it to a [starting](PodState::Starting) controller
     /// (to establish exclusivity),
+    /// then bind the port and confirm the pod's address is routable
+    /// (releasing the port and retrying shortly if it isn't yet),
     /// then spawn the background task to run the server,
     /// then convert it to a [running](PodState::Running) controller
     /// (to mark it as complete).
     pub(crate) async fn start_container(&self, name: &PodName) -> Result<()> {
-        if let Some(future) = self.start_container_without_wait(name)? {
-            // Indicates the server was not yet ready. Await it before trying again.
-            let _ = future.await;
-            if self.start_container_without_wait(name)?.is_some() {
-                // This should never happen because we already know the server was ready.
-                return Err(anyhow!("Logical impossibility (juggling routes future)"));
-            }
+        // Don't bind the port until all startup dependencies are running.
+        let dependencies = self
+            .pods
+            .pin()
+            .get(&name.pod)
+            .map(|pod| pod.startup_dependencies.clone())
+            .unwrap_or_default();
+        if !dependencies.is_empty() {
+            log_info!(pod: name, "Waiting for startup dependencies");
+            self.running
+                .wait_for(&dependencies, self.startup_dependency_timeout)
+                .await?;
         }
-        Ok(())
+
+        start_with_retries(name, || self.start_container_without_wait(name)).await
     }
 
     /// This function exists to sidestep a [known issue][1] with Rust's `Send`-safety detection.
@@ -380,11 +672,11 @@ impl WorkRuntime {
     /// Otherwise, [`start_container`](Self::start_container) could have simply recursed.
     ///
     /// [1]: https://users.rust-lang.org/t/future-is-not-send-as-this-value-is-used-across-an-await-but-i-drop-the-value-before-the-await/57574
-    fn start_container_without_wait(
-        &self,
-        name: &PodName,
-    ) -> Result<Option<SharedResultFuture<Routes>>> {
-        let mut ready_routes: Option<Arc<Routes>> = None;
+    fn start_container_without_wait(&self, name: &PodName) -> Result<StartAttempt> {
+        let mut ready_routes: Option<Arc<GrpcPod>> = None;
+        // The state to fall back to if this attempt gives up after claiming the pod.
+        let mut prior_state = PodState::Created;
+        let mut reinitialized_routes: Option<SharedResultFuture<GrpcPod>> = None;
         let pods = self.pods.pin();
         match pods.compute(name.pod, |entry| match entry {
             Some((_, pod)) => match pod.state {
@@ -426,11 +718,41 @@ impl WorkRuntime {
                     },
                 ),
                 PodState::Stopped => {
-                    // If we're coming from `Stopped`, the container has been killed.
-                    // TODO: I think we just have to re-bind the TCP port then?
-                    Operation::Abort(StartContainerAbort::Error(anyhow!(
-                        "Restarting a stopped container is not yet implemented",
-                    )))
+                    // If we're coming from `Stopped`, the server has been killed
+                    // but the initialized component usually outlives it.
+                    // Reuse it and just re-bind the port, if possible.
+                    match restart_routes(&pod.routes) {
+                        RestartRoutes::Reuse(routes) => {
+                            log_info!(pod: name, "Reusing initialized component");
+                            ready_routes = Some(routes);
+                            prior_state = PodState::Stopped;
+                            let mut pod = pod.clone();
+                            pod.state = PodState::Starting;
+                            Operation::Insert(pod)
+                        }
+                        RestartRoutes::Waiting(routes) => {
+                            log_info!(pod: name, "Waiting to restart container");
+                            Operation::Abort(StartContainerAbort::Waiting(routes))
+                        }
+                        RestartRoutes::Reinitialize => {
+                            // Initialization failed the last time. Try again from scratch,
+                            // and start the container like a freshly created one once it's done.
+                            log_info!(pod: name, "Reinitializing component to restart container");
+                            let routes = self.pod_store.grpc(
+                                &self.wasmtime,
+                                pod.component_name.clone(),
+                                pod.scratch.clone(),
+                                pod.live_environment.clone(),
+                                pod.request_policy.clone(),
+                                pod.usage.clone(),
+                            );
+                            reinitialized_routes = Some(routes.clone());
+                            let mut pod = pod.clone();
+                            pod.routes = Some(routes);
+                            pod.state = PodState::Created;
+                            Operation::Insert(pod)
+                        }
+                    }
                 }
                 PodState::Starting | PodState::Running => {
                     log_info!(pod: name, "Idempotent container start");
@@ -450,6 +772,10 @@ impl WorkRuntime {
                 old: _,
                 new: (_, pod),
             } => {
+                if let Some(future) = reinitialized_routes {
+                    // Back to `Created`. Await the new component before trying again.
+                    return Ok(StartAttempt::Waiting(future));
+                }
                 log_info!(pod: name, "Container starting");
 
                 // The only code paths that result in `Compute::Updated`
@@ -463,46 +789,56 @@ impl WorkRuntime {
                 // TODO: Revisit implications of keepalive.
                 let keepalive = None;
 
-                TcpIncoming::new(address, nodelay, keepalive).map_or_else(
-                    |bind_error| {
-                        // If the pod is still `Starting`,
-                        // "unlock" its state by setting it back to `Created`
-                        // before propagating the bind error.
-                        pods.compute(name.pod, |entry| match entry {
-                            Some((_, existing_pod)) => match &existing_pod.state {
-                                PodState::Starting => {
-                                    let mut pod = existing_pod.clone();
-                                    pod.state = PodState::Created;
-                                    Operation::Insert(pod)
-                                }
-                                // The pod may have been stopped or killed by another task.
-                                // Leave it that way.
-                                PodState::Stopped | PodState::Killed => Operation::Abort(()),
-                                // These transitions would be unexpected logic errors.
-                                // Leave it that way anyway.
-                                PodState::Initiated
-                                | PodState::Created
-                                | PodState::Running
-                                | PodState::Removed => {
-                                    log_warn!(
-                                        pod: name,
-                                        "State changed while handling bind error: {:?}",
-                                        existing_pod.state,
-                                    );
-                                    Operation::Abort(())
-                                }
-                            },
-                            None => {
+                // If the pod is still `Starting`,
+                // "unlock" its state by setting it back to `Created` (or `Stopped`, if restarting)
+                // before giving up on this attempt.
+                let unlock = |reason: &str| {
+                    pods.compute(name.pod, |entry| match entry {
+                        Some((_, existing_pod)) => match &existing_pod.state {
+                            PodState::Starting => {
+                                let mut pod = existing_pod.clone();
+                                pod.state = prior_state;
+                                Operation::Insert(pod)
+                            }
+                            // The pod may have been stopped or killed by another task.
+                            // Leave it that way.
+                            PodState::Stopped | PodState::Killed => Operation::Abort(()),
+                            // These transitions would be unexpected logic errors.
+                            // Leave it that way anyway.
+                            PodState::Initiated
+                            | PodState::Created
+                            | PodState::Running
+                            | PodState::Removed => {
                                 log_warn!(
                                     pod: name,
-                                    "Container disappeared while handling bind error",
+                                    "State changed while handling {reason}: {:?}",
+                                    existing_pod.state,
                                 );
                                 Operation::Abort(())
                             }
-                        });
+                        },
+                        None => {
+                            log_warn!(pod: name, "Container disappeared while handling {reason}");
+                            Operation::Abort(())
+                        }
+                    });
+                };
+
+                TcpIncoming::new(address, nodelay, keepalive).map_or_else(
+                    |bind_error| {
+                        unlock("bind error");
                         Err(anyhow!(bind_error).context("Failed binding to port"))
                     },
                     |incoming| {
+                        // The bind can succeed before the CNI plugin has finished wiring up
+                        // the pod's address. Release the port and retry shortly,
+                        // so the pod is not reported as running while unreachable.
+                        if let Err(error) = routable(pod.ip_address.address) {
+                            drop(incoming);
+                            unlock("unroutable address");
+                            return Ok(StartAttempt::Unroutable(error));
+                        }
+
                         // Shut down the server gracefully when either:
                         // - The pod is specifically targetted for shut down by the CRI controller.
                         // - All pods are shut down globally.
@@ -515,23 +851,43 @@ impl WorkRuntime {
                             }
                         };
 
-                        let task = spawn(
-                            // [This suggestion](https://github.com/hyperium/tonic/pull/1893),
-                            // (using Axum directly instead of Tonic)
-                            // obviates the need to implement Tonic's `NamedService`,
-                            // which is not dyn-compatible.
-                            Server::builder()
-                                .add_routes(routes.as_ref().clone())
-                                .serve_with_incoming_shutdown(incoming, shutdown),
-                        );
+                        // [This suggestion](https://github.com/hyperium/tonic/pull/1893),
+                        // (using Axum directly instead of Tonic)
+                        // obviates the need to implement Tonic's `NamedService`,
+                        // which is not dyn-compatible.
+                        let server = Server::builder()
+                            .add_routes(routes.routes.clone())
+                            .serve_with_incoming_shutdown(
+                                limit_connections(
+                                    enforce(incoming, pod.network_policy.clone(), name.clone()),
+                                    max_connections(routes.max_connections, self.max_connections),
+                                    name.clone(),
+                                ),
+                                shutdown,
+                            );
+                        let exit = ContainerExit::default();
+                        let server = exit.clone().watch(server);
+                        let task = match &pod.cpuset {
+                            Some(cpuset) => self.pinned.spawn(cpuset, server),
+                            None => spawn(server),
+                        };
 
                         let mut pod = pod.clone();
                         pod.state = PodState::Running;
                         pod.killer = SingleUse::of(ContainerKiller {
+                            hook: pod.shutdown_method.clone().map(|method| ShutdownHook {
+                                pod: name.clone(),
+                                routes: routes.routes.clone(),
+                                method,
+                            }),
                             shutdown: shutdown_target_tx,
                             join: task,
+                            exit: exit.clone(),
                         });
+                        pod.exit = exit;
                         pod.container_started_at = now();
+                        // A restarted container has not finished (yet).
+                        pod.container_finished_at = 0;
 
                         // Now update the pod map again,
                         // making sure this pod's state has not changed since we set it to `Starting`.
@@ -555,7 +911,10 @@ impl WorkRuntime {
                         }) {
                             Compute::Updated { old: _, new: _ } => {
                                 log_info!(pod: name, "Successful container start");
-                                Ok(None)
+                                log_audit!(pod: name, operation: "started", rpc: "StartContainer");
+                                self.publish(name, ContainerEventType::ContainerStartedEvent);
+                                self.running.started(&name.component);
+                                Ok(StartAttempt::Started)
                             }
                             Compute::Aborted(error) => {
                                 // If there was some sort of synchronization error,
@@ -575,8 +934,10 @@ impl WorkRuntime {
                     },
                 )
             }
-            Compute::Aborted(StartContainerAbort::Done) => Ok(None),
-            Compute::Aborted(StartContainerAbort::Waiting(future)) => Ok(Some(future)),
+            Compute::Aborted(StartContainerAbort::Done) => Ok(StartAttempt::Started),
+            Compute::Aborted(StartContainerAbort::Waiting(future)) => {
+                Ok(StartAttempt::Waiting(future))
+            }
             Compute::Aborted(StartContainerAbort::Error(error)) => Err(error),
             _ => Err(anyhow!(
                 "State machine logical impossibility (initiating start)",
@@ -619,6 +980,7 @@ impl WorkRuntime {
                     let mut pod = pod.clone();
                     prior_state = pod.state;
                     pod.state = PodState::Stopped;
+                    pod.container_finished_at = now();
                     Operation::Insert(pod)
                 }
                 PodState::Stopped => {
@@ -637,7 +999,10 @@ impl WorkRuntime {
                 new: (_, pod),
             } => {
                 log_info!(pod: name, "Successful container stop");
+                log_audit!(pod: name, operation: "stopped", rpc: "StopContainer");
+                self.publish(name, ContainerEventType::ContainerStoppedEvent);
                 if prior_state == PodState::Running {
+                    self.running.stopped(&name.component);
                     // If the pod was previously `Running`, then we have to kill it.
                     if let Some(killer) = pod.killer.take() {
                         Ok(Some(killer))
@@ -662,6 +1027,46 @@ impl WorkRuntime {
         }
     }
 
+    /// Pin the container's server to the given CPU cores the next time it starts.
+    /// A server that is already running cannot move,
+    /// so the cores of a running container can only be "updated" to the same cores.
+    pub(crate) fn update_container_resources(&self, name: &PodName, cpuset: CpuSet) -> Result<()> {
+        let pods = self.pods.pin();
+        match pods.compute(name.pod, |entry| match entry {
+            Some((_, pod)) => match pod.state {
+                PodState::Created | PodState::Stopped => {
+                    let mut pod = pod.clone();
+                    pod.cpuset = Some(cpuset.clone());
+                    Operation::Insert(pod)
+                }
+                PodState::Starting | PodState::Running => {
+                    if pod.cpuset.as_ref() == Some(&cpuset) {
+                        Operation::Insert(pod.clone())
+                    } else {
+                        Operation::Abort(anyhow!(
+                            "Cannot change the CPU set of a running container"
+                        ))
+                    }
+                }
+                PodState::Initiated | PodState::Removed | PodState::Killed => {
+                    // Unexpected Kubelet behavior.
+                    Operation::Abort(anyhow!("Bad prior state: {:?}", pod.state))
+                }
+            },
+            None => Operation::Abort(anyhow!("Container not found")),
+        }) {
+            Compute::Updated { old: _, new: _ } => {
+                log_info!(pod: name, "Successful container resources update");
+                Ok(())
+            }
+            Compute::Aborted(error) => Err(error),
+            _ => {
+                // All possible compute outcomes should have been handled.
+                Err(anyhow!("State machine logical impossibility"))
+            }
+        }
+    }
+
     pub(crate) fn remove_container(&self, name: &PodName) -> Result<()> {
         let pods = self.pods.pin();
         match pods.compute(name.pod, |entry| match entry {
@@ -694,6 +1099,8 @@ impl WorkRuntime {
                 new: (_, _),
             } => {
                 log_info!(pod: name, "Successful container removal");
+                log_audit!(pod: name, operation: "removed", rpc: "RemoveContainer");
+                self.publish(name, ContainerEventType::ContainerDeletedEvent);
                 Ok(())
             }
             Compute::Aborted(None) => Ok(()),
@@ -708,19 +1115,21 @@ impl WorkRuntime {
     /// Stop a running container / pod by killing the running server (if necessary)
     /// and transitioning the pod to [`Stopped`](PodState::Stopped).
     /// Attempts graceful server shutdown at first,
-    /// waiting at most `timeout` before forcefully aborting.
-    /// If `free_address` is `true`, also frees the pod's IP address.
+    /// waiting at most the [drain timeout](Self::pod_drain_timeout) before forcefully aborting.
+    /// Also frees the pod's scratch area and IP address.
     pub(crate) async fn kill_pod(&self, name: &PodName) -> Result<()> {
         if let Some((killer, ip_address)) = self.kill_pod_without_wait(name)? {
             // If the pod must be killed, do that before freeing the IP address.
             if let Some(killer) = killer.take() {
-                // Give it a courtesy second to shut down gracefully.
+                // Give it a chance to drain in-flight requests.
                 // The kubelet should have first attempted to kill the container
                 // with an explicit grace period.
-                if !killer.kill_with_timeout(Duration::from_secs(1)).await {
+                if !killer.kill_with_timeout(self.pod_drain_timeout).await {
                     log_warn!(pod: name, "Pod killed forcefully");
                 }
             }
+            // Nothing can use the scratch storage once the server has shut down.
+            self.scratch.release(name)?;
             ip_address.deactivate().await?;
             ip_address.deallocate().await?;
         }
@@ -765,6 +1174,16 @@ impl WorkRuntime {
                 new: (_, pod),
             } => {
                 log_info!(pod: name, "Successful pod kill");
+                log_audit!(pod: name, operation: "killed", rpc: "StopPodSandbox");
+                if prior_state == PodState::Running {
+                    self.running.stopped(&name.component);
+                }
+                // Killing the pod stops its container too, if it hadn't been stopped already.
+                if prior_state == PodState::Starting || prior_state == PodState::Running {
+                    self.publish(name, ContainerEventType::ContainerStoppedEvent);
+                }
+                // The pod will never need its image now.
+                self.pod_store.cancel_pulls(&pod.pod_sandbox_metadata);
                 Ok(Some((pod.killer.clone(), pod.ip_address.clone())))
             }
             Compute::Aborted(None) => Ok(None),
@@ -795,8 +1214,12 @@ impl WorkRuntime {
             },
             None => Operation::Abort(anyhow!("Pod not found")),
         }) {
-            Compute::Removed(_, _) => {
+            Compute::Removed(_, pod) => {
+                self.pod_store.forget_pulls(&pod.pod_sandbox_metadata);
+                // Normally already released when the pod was killed.
+                self.scratch.release(name)?;
                 log_info!(pod: name, "Successful pod deletion");
+                log_audit!(pod: name, operation: "deleted", rpc: "RemovePodSandbox");
                 Ok(())
             }
             Compute::Aborted(error) => Err(error),
@@ -831,6 +1254,99 @@ impl WorkRuntime {
         }
     }
 
+    /// Run a [health check](crate::health) against a running container's component,
+    /// recording and returning whether it passed.
+    ///
+    /// A container that is not running fails every check.
+    pub(crate) async fn probe(&self, name: &PodName, probe: Probe) -> Result<bool> {
+        let (routes, health) = match self.pods.pin().get(&name.pod) {
+            Some(pod) if pod.state.is_ready() => (
+                pod.routes
+                    .as_ref()
+                    .and_then(|routes| routes.peek()?.as_ref().ok().cloned()),
+                pod.health.clone(),
+            ),
+            Some(_) => return Ok(false),
+            None => return Err(anyhow!("Container not found")),
+        };
+        let routes = routes.ok_or(anyhow!(
+            "Logical impossibility (running container without routes)"
+        ))?;
+        let passing = check(&routes.routes, probe).await?;
+        health.record(probe, passing);
+        Ok(passing)
+    }
+
+    /// Subscribe to container lifecycle transitions from now on.
+    pub(crate) fn subscribe_events(&self) -> ContainerEvents {
+        ContainerEvents {
+            events: self.events.subscribe(),
+            pods: self.pods.clone(),
+        }
+    }
+
+    /// Notify [subscribers](Self::subscribe_events), if any, of a container transition.
+    fn publish(&self, name: &PodName, event_type: ContainerEventType) {
+        // Sending only fails if there are no subscribers.
+        let _ = self.events.send(ContainerEvent {
+            name: name.clone(),
+            event_type,
+            created_at: now(),
+        });
+    }
+
+    /// Count the pods on this node in each lifecycle state.
+    pub(crate) fn pod_counts(&self) -> BTreeMap<PodState, usize> {
+        let mut counts = BTreeMap::new();
+        for pod in self.pods.pin().values() {
+            *counts.entry(pod.state).or_default() += 1;
+        }
+        counts
+    }
+
+    /// Number of addresses in the pod subnet, allocated or not.
+    pub(crate) fn ip_pool_size(&self) -> u64 {
+        self.ipam.pool_size()
+    }
+
+    /// Check whether the runtime can run pods,
+    /// which it can until it starts shutting down.
+    pub(crate) fn check_runtime(&self) -> Result<()> {
+        match self.shutdown.clone().now_or_never() {
+            Some(_) => Err(anyhow!("Shutting down")),
+            None => Ok(()),
+        }
+    }
+
+    /// Check whether new pods can be networked,
+    /// which requires a working [IPAM plugin](Ipam::check_plugin).
+    pub(crate) fn check_network(&self) -> Result<()> {
+        self.ipam.check_plugin()
+    }
+
+    /// In the background, periodically compare IPAM allocations against the pod map
+    /// until shutdown, logging any persistent divergence.
+    /// If `reclaim` is `true`, also de-allocate orphaned addresses so they don't leak.
+    pub(crate) fn audit_addresses(&self, period: Duration, reclaim: bool) {
+        let pods = self.pods.clone();
+        let ipam = self.ipam.clone();
+        let shutdown = self.shutdown.clone();
+        spawn(async move {
+            let mut audit = IpamAudit::default();
+            let mut ticks = interval(period);
+            let audits = async {
+                loop {
+                    ticks.tick().await;
+                    audit_addresses(&pods, &ipam, &mut audit, reclaim).await;
+                }
+            };
+            select! {
+                _ = audits => {}
+                _ = shutdown => {}
+            }
+        });
+    }
+
     /// Like [`Self::list_pods`],
     /// but with the added `name` condition for exact match by ID.
     /// Skips the exhaustive search and adds at most 1 pod to results.
@@ -863,7 +1379,7 @@ impl WorkRuntime {
         F: Fn(&PodName, &Pod) -> T,
     {
         // If readiness is unspecified, all states match.
-        if readiness.map_or(true, |ready| {
+        if readiness.is_none_or(|ready| {
             // Either readiness must be desired, or the pod must be killed (but not both).
             ready ^ (pod.state == PodState::Killed)
         }) && Self::match_labels(&pod.pod_labels, labels)
@@ -978,19 +1494,129 @@ enum CreateContainerCircumstance {
     /// A fluke situation where Kubelet might call `CreateContainer` twice
     /// with exactly the same parameters (including 'attempt` and 'restart-count').
     Idempotent,
+    /// `CreateContainer` was called again for an existing container
+    /// with only [reloadable](ContainerChange::Reloadable) changes,
+    /// which are applied to the running component without recreating the pod.
+    Reload,
+}
+
+/// The parameters of a `CreateContainer` request that identify a container.
+struct ContainerParameters<'a> {
+    metadata: &'a Option<ContainerMetadata>,
+    labels: &'a HashMap<String, String>,
+    annotations: &'a HashMap<String, String>,
+    environment: &'a HashMap<String, String>,
+    image_spec: &'a Option<ImageSpec>,
+}
+
+/// How a repeated `CreateContainer` request differs from the existing container.
+#[derive(PartialEq, Debug)]
+enum ContainerChange {
+    /// Equal, modulo 'attempt' and 'restart-count'.
+    None,
+    /// Only the environment differs,
+    /// which a running component can pick up without being recreated.
+    Reloadable,
+    /// Anything else differs. The container would have to be recreated.
+    Structural,
+}
+
+impl ContainerParameters<'_> {
+    fn change(&self, requested: &ContainerParameters) -> ContainerChange {
+        if !container_metadata_equal(self.metadata, requested.metadata)
+            || self.labels != requested.labels
+            || !container_annotations_equal(self.annotations, requested.annotations)
+            || self.image_spec != requested.image_spec
+        {
+            ContainerChange::Structural
+        } else if self.environment != requested.environment {
+            ContainerChange::Reloadable
+        } else {
+            ContainerChange::None
+        }
+    }
 }
 
 /// Possible reasons why starting a container might be aborted.
 /// See [`start_container`](WorkRuntime::start_container).
 enum StartContainerAbort {
     /// Pod is still initializing asynchronously.
-    Waiting(SharedResultFuture<Routes>),
+    Waiting(SharedResultFuture<GrpcPod>),
     /// There was a problem.
     Error(Error),
     /// Support idempotency if the pod is already started.
     Done,
 }
 
+/// Outcome of a single attempt to start a container.
+/// See [`start_container`](WorkRuntime::start_container).
+enum StartAttempt {
+    /// The container is running.
+    Started,
+    /// Pod is still initializing asynchronously. Await the future before trying again.
+    Waiting(SharedResultFuture<GrpcPod>),
+    /// The port was bound, but the pod's address is not routable yet.
+    /// The pod is back to [`Created`](PodState::Created) and the start can be retried.
+    Unroutable(IoError),
+}
+
+/// How to get the routes for a [stopped](PodState::Stopped) container when restarting it.
+enum RestartRoutes {
+    /// The component initialized successfully and can serve again as-is.
+    Reuse(Arc<GrpcPod>),
+    /// The component is still initializing.
+    Waiting(SharedResultFuture<GrpcPod>),
+    /// The component failed to initialize (or never started to),
+    /// so it must be initialized anew.
+    Reinitialize,
+}
+
+/// Decide whether the routes future of a stopped container is still usable for a restart.
+/// Only failed initialization is retried; stopping the server does not invalidate the routes.
+fn restart_routes(routes: &Option<SharedResultFuture<GrpcPod>>) -> RestartRoutes {
+    match routes {
+        Some(future) => match future.peek() {
+            Some(Ok(routes)) => RestartRoutes::Reuse(routes.clone()),
+            Some(Err(_)) => RestartRoutes::Reinitialize,
+            None => RestartRoutes::Waiting(future.clone()),
+        },
+        None => RestartRoutes::Reinitialize,
+    }
+}
+
+/// Repeatedly `attempt` to start the named container until it is running.
+/// Awaits initialization whenever the pod is not ready yet,
+/// and backs off while its address is not yet routable.
+async fn start_with_retries<F>(name: &PodName, mut attempt: F) -> Result<()>
+where
+    F: FnMut() -> Result<StartAttempt>,
+{
+    let mut waited = false;
+    let mut unroutable = 0;
+    loop {
+        match attempt()? {
+            StartAttempt::Started => return Ok(()),
+            StartAttempt::Waiting(future) => {
+                if waited {
+                    // This should never happen because we already know the server was ready.
+                    return Err(anyhow!("Logical impossibility (juggling routes future)"));
+                }
+                // Indicates the server was not yet ready. Await it before trying again.
+                let _ = future.await;
+                waited = true;
+            }
+            StartAttempt::Unroutable(error) => {
+                unroutable += 1;
+                if unroutable >= ROUTABLE_ATTEMPTS {
+                    return Err(anyhow!(error).context("Pod address never became routable"));
+                }
+                log_info!(pod: name, "Waiting for pod address to become routable: {error}");
+                sleep(ROUTABLE_BACKOFF).await;
+            }
+        }
+    }
+}
+
 /// Return true iff `left` equals `right`, ignoring [`attempt`](ContainerMetadata::attempt).
 fn container_metadata_equal(
     left: &Option<ContainerMetadata>,
@@ -1024,19 +1650,69 @@ fn container_annotations_equal(
     left == right
 }
 
+/// A container lifecycle transition, as published to [subscribers](WorkRuntime::subscribe_events).
+#[derive(Clone, Debug)]
+pub(crate) struct ContainerEvent {
+    /// The pod whose container transitioned.
+    pub(crate) name: PodName,
+
+    /// Which transition occurred.
+    pub(crate) event_type: ContainerEventType,
+
+    /// Timestamp of the transition in nanoseconds.
+    pub(crate) created_at: i64,
+}
+
+/// A subscription to [container events](ContainerEvent).
+pub(crate) struct ContainerEvents {
+    events: broadcast::Receiver<ContainerEvent>,
+
+    /// Shared with the runtime, to look up each pod as its event is received.
+    pods: Arc<LockFreeConcurrentHashMap<PodId, Pod>>,
+}
+
+impl ContainerEvents {
+    /// Wait for the next event,
+    /// then transform it along with the pod's current state (if the pod still exists).
+    /// Return `None` once the runtime is gone.
+    ///
+    /// A subscriber that falls too far behind skips the events it missed.
+    pub(crate) async fn next<T, F>(&mut self, transform: &F) -> Option<T>
+    where
+        F: Fn(&ContainerEvent, Option<&Pod>) -> T,
+    {
+        loop {
+            match self.events.recv().await {
+                Ok(event) => return Some(transform(&event, self.pods.pin().get(&event.name.pod))),
+                Err(RecvError::Lagged(missed)) => {
+                    log_warn_globally!("Container event subscriber missed {missed} events");
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+}
+
 /// Used to shut down a running container. Can only be used once.
 struct ContainerKiller {
+    /// Called before shutting down the server gracefully, if the pod has a shutdown hook.
+    hook: Option<ShutdownHook>,
+
     /// Send to this channel to shut down the server gracefully.
     shutdown: oneshot::Sender<()>,
 
     /// Useful for two things:
     /// - Awaiting graceful shutdown after sending the signal to [`shutdown`](Self::shutdown).
     /// - Forcibly shutting down.
-    join: JoinHandle<StdResult<(), ServerError>>,
+    join: JoinHandle<()>,
+
+    /// Shared with the pod, to record a forceful shutdown.
+    exit: ContainerExit,
 }
 
 impl ContainerKiller {
-    /// Attempt to kill the container gracefully at first.
+    /// Attempt to kill the container gracefully at first,
+    /// calling the [shutdown hook](ShutdownHook), if any, before shutting down the server.
     /// If that fails, or the timeout expires while waiting for graceful shut down to complete,
     /// forcefully abort the task instead.
     ///
@@ -1044,17 +1720,157 @@ impl ContainerKiller {
     /// and `false` if it was forcefully aborted.
     async fn kill_with_timeout(self, duration: Duration) -> bool {
         let aborter = self.join.abort_handle();
-        if self.shutdown.send(()).is_ok() && timeout(duration, self.join).await.is_ok() {
+        let graceful = async {
+            if let Some(hook) = self.hook {
+                hook.call().await;
+            }
+            if self.shutdown.send(()).is_err() {
+                return false;
+            }
+            let _ = self.join.await;
+            true
+        };
+        if timeout(duration, graceful).await.unwrap_or(false) {
             true
         } else {
             aborter.abort();
+            self.exit.record(
+                KILLED_EXIT_CODE,
+                "Killed",
+                format!(
+                    "Not shut down gracefully within {} seconds",
+                    duration.as_secs()
+                ),
+            );
             false
         }
     }
 
-    /// Kill a container immediately. In-flight requests are simply dropped.
+    /// Kill a container immediately. In-flight requests are simply dropped,
+    /// and the shutdown hook is not called.
     fn forcefully_abort(self) {
         self.join.abort();
+        self.exit.record(
+            KILLED_EXIT_CODE,
+            "Killed",
+            String::from("Aborted while starting"),
+        );
+    }
+}
+
+/// A component method to call before its container is stopped gracefully.
+struct ShutdownHook {
+    /// Pod whose container is stopping, for logging.
+    pod: PodName,
+
+    /// The running server's routes, through which the method is called.
+    routes: Routes,
+
+    /// Fully-qualified path of the [shutdown method](crate::shutdown::SHUTDOWN_METHOD_ANNOTATION).
+    method: String,
+}
+
+impl ShutdownHook {
+    /// Call the hook. A failing hook does not prevent the container from stopping.
+    async fn call(self) {
+        match call_shutdown_hook(&self.routes, &self.method).await {
+            Ok(()) => log_info!(pod: &self.pod, "Shutdown hook succeeded"),
+            Err(error) => log_warn!(pod: &self.pod, "Shutdown hook failed: {error:#}"),
+        }
+    }
+}
+
+/// The first pod ID generated on the node with the given ID.
+///
+/// The node ID occupies the upper 32 bits of each pod ID,
+/// leaving the lower 32 bits for a per-node counter.
+fn first_pod_id(node_id: u32) -> PodId {
+    (node_id as PodId) << (PodId::BITS - u32::BITS)
+}
+
+/// Derive a node ID from the node's hostname,
+/// for when none is configured explicitly.
+///
+/// Uses 32-bit [FNV-1a](http://www.isthe.com/chongo/tech/comp/fnv/),
+/// which (unlike the standard library's hasher) is stable across Rust releases,
+/// so a node keeps its ID when `vimanad` is rebuilt.
+pub(crate) fn node_id_from_hostname(hostname: &str) -> u32 {
+    hostname.bytes().fold(FNV_OFFSET_BASIS, |hash, byte| {
+        (hash ^ byte as u32).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// Initial state of a 32-bit [FNV-1a](node_id_from_hostname) hash.
+const FNV_OFFSET_BASIS: u32 = 0x811c9dc5;
+
+/// Multiplier of a 32-bit [FNV-1a](node_id_from_hostname) hash.
+const FNV_PRIME: u32 = 0x01000193;
+
+/// Exit code reported for a forcefully killed container,
+/// by analogy to a process killed by `SIGKILL`.
+const KILLED_EXIT_CODE: i32 = 128 + 9;
+
+/// How a container's server exited, if it has.
+///
+/// Cloned along with the pod, but all clones share the same exit.
+/// Only the first exit is recorded.
+#[derive(Clone, Default)]
+pub(crate) struct ContainerExit(Arc<OnceLock<Exit>>);
+
+/// A recorded [container exit](ContainerExit), as reported in the container status.
+#[derive(Debug)]
+pub(crate) struct Exit {
+    /// Zero for a graceful shutdown, non-zero for a failure.
+    pub(crate) code: i32,
+
+    /// Brief CamelCase reason (e.g. `Error`).
+    pub(crate) reason: &'static str,
+
+    /// Human-readable explanation.
+    pub(crate) message: String,
+
+    /// Timestamp of the exit in nanoseconds.
+    pub(crate) finished_at: i64,
+}
+
+impl ContainerExit {
+    pub(crate) fn get(&self) -> Option<&Exit> {
+        self.0.get()
+    }
+
+    /// Record the exit, unless one was recorded already.
+    fn record(&self, code: i32, reason: &'static str, message: String) {
+        let _ = self.0.set(Exit {
+            code,
+            reason,
+            message,
+            finished_at: now(),
+        });
+    }
+
+    /// Run a container's server to completion, recording how it exits.
+    /// A server that is aborted never completes, so it's up to the [killer](ContainerKiller).
+    async fn watch<E: Display>(self, server: impl Future<Output = StdResult<(), E>>) {
+        match AssertUnwindSafe(server).catch_unwind().await {
+            Ok(Ok(())) => self.record(0, "Completed", String::from("Shut down gracefully")),
+            Ok(Err(error)) => self.record(1, "Error", format!("Server failed: {error}")),
+            Err(panic) => self.record(
+                2,
+                "Panicked",
+                format!("Server panicked: {}", panic_message(panic.as_ref())),
+            ),
+        }
+    }
+}
+
+/// Best-effort extraction of the message from a panic payload.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "(no message)"
     }
 }
 
@@ -1097,6 +1913,36 @@ impl<T> Clone for SingleUse<T> {
     }
 }
 
+/// A single round of [`WorkRuntime::audit_addresses`].
+async fn audit_addresses(
+    pods: &LockFreeConcurrentHashMap<PodId, Pod>,
+    ipam: &Ipam,
+    audit: &mut IpamAudit,
+    reclaim: bool,
+) {
+    // Look at the pods before the allocations,
+    // so an address allocated in between looks orphaned for one round rather than missing.
+    // Killed pods have already given up their addresses.
+    let live: HashSet<IpAddr> = pods
+        .pin()
+        .values()
+        .filter(|pod| pod.state != PodState::Killed)
+        .map(|pod| pod.ip_address.address)
+        .collect();
+    let divergence = audit.compare(&ipam.allocated(), &live);
+    for address in divergence.unallocated {
+        log_warn_globally!("Pod address {address} is no longer allocated by IPAM");
+    }
+    for address in divergence.orphaned {
+        log_warn_globally!("IPAM address {address} is allocated to no live pod");
+        if reclaim {
+            if let Err(error) = ipam.reclaim(address).await {
+                log_warn_globally!("Failed reclaiming orphaned address {address}: {error:?}");
+            }
+        }
+    }
+}
+
 // Return non-leap nanoseconds since 1970-01-01 00:00:00 UTC+0 as `i64`.
 // Return zero if executed before 1970. Wraps around in 2262.
 pub(crate) fn now() -> i64 {
@@ -1106,3 +1952,497 @@ pub(crate) fn now() -> i64 {
         .as_nanos() as u64
         % (i64::MAX as u64)) as i64
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env::temp_dir;
+    use std::future::pending;
+    use std::io::ErrorKind;
+    use std::process;
+    use std::sync::atomic::AtomicBool;
+
+    use axum::body::Body as AxumBody;
+    use axum::routing::method_routing::post;
+    use axum::Router;
+    use http::Response as HttpResponse;
+
+    use crate::containers::ContainerStore;
+    use crate::host::{POD_IP_VARIABLE, POD_PORT_VARIABLE};
+    use crate::startup::STARTUP_DEPENDENCIES_ANNOTATION;
+    use decode::DecoderOptions;
+
+    use super::*;
+
+    const POD_NAME: &str = "1234567890abcdef1234567890abcdef:some-server@1.2.3#a";
+    const SERVER: &str = "1234567890abcdef1234567890abcdef:some-server@1.0.0";
+    const DATABASE: &str = "1234567890abcdef1234567890abcdef:some-database@2.0.0";
+
+    /// Return a runtime with no pods, along with the sender to shut it down.
+    async fn runtime(node_id: u32) -> (WorkRuntime, oneshot::Sender<()>) {
+        let wasmtime = WasmEngine::default();
+        let root = temp_dir().join(format!("vimana-state-test-{}-{node_id}", process::id()));
+        let containers = ContainerStore::new(
+            root.join("images").to_str().unwrap(),
+            HashSet::new(),
+            &wasmtime,
+            None,
+        )
+        .unwrap();
+        // Pods in these tests are given loopback addresses directly, so the plugin never runs.
+        let ipam = Ipam::host_local(
+            String::from("/nonexistent"),
+            "127.0.0.0/8",
+            String::from("lo"),
+        )
+        .await
+        .unwrap();
+        let (shutdown, signal) = oneshot::channel();
+        let runtime = WorkRuntime::new(
+            wasmtime,
+            PodInitializer::new(containers, DecoderOptions::default(), None, false, None),
+            ipam,
+            ScratchStore::new(root.join("scratch").to_str().unwrap()),
+            signal.shared(),
+            node_id,
+            PodLimits {
+                max_connections: None,
+                pod_drain_timeout: Duration::from_secs(1),
+                max_pod_creations_per_domain: None,
+            },
+        );
+        (runtime, shutdown)
+    }
+
+    /// Add a [created](PodState::Created) pod for `component` at a loopback `address`,
+    /// whose component is already initialized (and serves nothing).
+    fn created_pod(
+        runtime: &WorkRuntime,
+        component: &str,
+        address: [u8; 4],
+        annotations: HashMap<String, String>,
+    ) -> PodName {
+        let component_name = Arc::new(names::Name::parse(component).component().unwrap());
+        let name = runtime.next_pod_name(&component_name);
+        let routes: SharedResultFuture<GrpcPod> = async {
+            Ok(Arc::new(GrpcPod {
+                routes: Routes::default(),
+                max_connections: 0,
+            }))
+        }
+        .boxed()
+        .shared();
+        let pod = Pod {
+            state: PodState::Created,
+            ip_address: IpAddress::unallocated(&runtime.ipam, IpAddr::from(address), &name),
+            component_name,
+            pod_sandbox_metadata: PodSandboxMetadata::default(),
+            pod_labels: HashMap::default(),
+            startup_dependencies: startup_dependencies(&annotations).unwrap(),
+            pod_annotations: annotations,
+            pod_created_at: now(),
+            scratch_bytes: None,
+            network_policy: None,
+            request_policy: RequestPolicy::default(),
+            shutdown_method: None,
+            cpuset: None,
+            health: Health::default(),
+            usage: ResourceUsage::default(),
+            routes: Some(routes),
+            container_created_at: now(),
+            container_metadata: None,
+            container_labels: HashMap::default(),
+            container_annotations: HashMap::default(),
+            environment: HashMap::default(),
+            live_environment: Environment::default(),
+            image_spec: None,
+            scratch: None,
+            container_started_at: 0,
+            killer: SingleUse::default(),
+            exit: ContainerExit::default(),
+            container_finished_at: 0,
+        };
+        runtime.pods.pin().insert(name.pod, pod);
+        name
+    }
+
+    #[tokio::test]
+    async fn test_pod_ids_unique_across_nodes() {
+        let (first, _first_shutdown) = runtime(3).await;
+        let (second, _second_shutdown) = runtime(4).await;
+
+        // The first pod of the same component on each node.
+        let on_first = created_pod(&first, SERVER, [127, 0, 0, 6], HashMap::default());
+        let on_second = created_pod(&second, SERVER, [127, 0, 0, 7], HashMap::default());
+        assert_ne!(on_first.pod, on_second.pod);
+        assert_ne!(on_first, on_second);
+        assert_ne!(on_first.to_string(), on_second.to_string());
+
+        // Pods on the same node only differ in the counter.
+        let next = created_pod(&first, SERVER, [127, 0, 0, 8], HashMap::default());
+        assert_eq!(on_first.pod >> 32, next.pod >> 32);
+        assert_eq!(next.pod, on_first.pod + 1);
+        assert_eq!(first_pod_id(0), 0);
+    }
+
+    #[test]
+    fn test_node_id_from_hostname() {
+        assert_eq!(
+            node_id_from_hostname("node-a"),
+            node_id_from_hostname("node-a"),
+        );
+        assert_ne!(
+            node_id_from_hostname("node-a"),
+            node_id_from_hostname("node-b"),
+        );
+        // Known FNV-1a values, which must never change.
+        assert_eq!(node_id_from_hostname(""), 0x811c9dc5);
+        assert_eq!(node_id_from_hostname("a"), 0xe40c292c);
+    }
+
+    #[test]
+    fn test_reload_environment() {
+        let metadata = Some(ContainerMetadata {
+            name: String::from("server"),
+            attempt: 0,
+        });
+        let labels = HashMap::from([(
+            String::from("vimana.host/server"),
+            String::from("some-server"),
+        )]);
+        let annotations = HashMap::default();
+        let image_spec = Some(ImageSpec::default());
+        let old_environment = HashMap::from([(String::from("LOG_LEVEL"), String::from("info"))]);
+        let new_environment = HashMap::from([(String::from("LOG_LEVEL"), String::from("debug"))]);
+
+        let existing = ContainerParameters {
+            metadata: &metadata,
+            labels: &labels,
+            annotations: &annotations,
+            environment: &old_environment,
+            image_spec: &image_spec,
+        };
+        let requested = ContainerParameters {
+            environment: &new_environment,
+            ..existing
+        };
+        assert_eq!(existing.change(&existing), ContainerChange::None);
+        assert_eq!(existing.change(&requested), ContainerChange::Reloadable);
+
+        // Changing anything else still requires recreation.
+        let other_image = Some(ImageSpec {
+            image: String::from("other"),
+            ..ImageSpec::default()
+        });
+        let recreated = ContainerParameters {
+            image_spec: &other_image,
+            ..requested
+        };
+        assert_eq!(existing.change(&recreated), ContainerChange::Structural);
+
+        // The running component observes the new value once it's applied.
+        let live = Environment::new(&old_environment);
+        let component_view = live.clone();
+        live.replace(&new_environment);
+        assert_eq!(
+            component_view.get(),
+            vec![(String::from("LOG_LEVEL"), String::from("debug"))],
+        );
+    }
+
+    #[test]
+    fn test_pod_address_environment() {
+        let environment = HashMap::from([
+            (String::from("LOG_LEVEL"), String::from("info")),
+            (String::from(POD_IP_VARIABLE), String::from("127.0.0.1")),
+        ]);
+
+        // The component sees its pod's routable address, even if the container tried to set it.
+        let address = SocketAddr::new(IpAddr::from([10, 0, 0, 7]), GRPC_PORT);
+        let component_view = Environment::new(&with_pod_address(&environment, address));
+        assert_eq!(
+            component_view.get(),
+            vec![
+                (String::from("LOG_LEVEL"), String::from("info")),
+                (String::from(POD_IP_VARIABLE), String::from("10.0.0.7")),
+                (String::from(POD_PORT_VARIABLE), String::from("80")),
+            ],
+        );
+
+        // IPv6 addresses are not bracketed.
+        let address = SocketAddr::new("fd00::7".parse().unwrap(), GRPC_PORT);
+        let variables = with_pod_address(&environment, address);
+        assert_eq!(variables[POD_IP_VARIABLE], "fd00::7");
+    }
+
+    fn state(runtime: &WorkRuntime, name: &PodName) -> PodState {
+        runtime.pods.pin().get(&name.pod).unwrap().state
+    }
+
+    #[tokio::test]
+    async fn test_start_waits_for_dependency() {
+        let (runtime, _shutdown) = runtime(1).await;
+        let database = created_pod(&runtime, DATABASE, [127, 0, 0, 2], HashMap::default());
+        let annotations = HashMap::from([(
+            String::from(STARTUP_DEPENDENCIES_ANNOTATION),
+            String::from(DATABASE),
+        )]);
+        let server = created_pod(&runtime, SERVER, [127, 0, 0, 3], annotations.clone());
+        let runtime = Arc::new(runtime);
+
+        // The server does not start while the database is merely created.
+        let starting = {
+            let (runtime, server) = (runtime.clone(), server.clone());
+            spawn(async move { runtime.start_container(&server).await })
+        };
+        sleep(Duration::from_millis(50)).await;
+        assert!(!starting.is_finished());
+        assert_eq!(state(&runtime, &server), PodState::Created);
+
+        // Once the database is running, so is the server.
+        runtime.start_container(&database).await.unwrap();
+        starting.await.unwrap().unwrap();
+        assert_eq!(state(&runtime, &server), PodState::Running);
+        runtime
+            .stop_container(&server, Duration::ZERO)
+            .await
+            .unwrap();
+        runtime
+            .stop_container(&database, Duration::ZERO)
+            .await
+            .unwrap();
+
+        // Another server gives up on a database that never starts again.
+        let mut runtime = Arc::into_inner(runtime).unwrap();
+        runtime.startup_dependency_timeout = Duration::from_millis(10);
+        let stranded = created_pod(&runtime, SERVER, [127, 0, 0, 4], annotations);
+        let error = runtime.start_container(&stranded).await.unwrap_err();
+        assert!(error.to_string().contains(DATABASE));
+        assert_eq!(state(&runtime, &stranded), PodState::Created);
+    }
+
+    #[tokio::test]
+    async fn test_ready_once_running() {
+        let (runtime, _shutdown) = runtime(2).await;
+        let name = created_pod(&runtime, SERVER, [127, 0, 0, 5], HashMap::default());
+
+        // The pod fails readiness checks until its server is listening.
+        assert!(!runtime.probe(&name, Probe::Readiness).await.unwrap());
+        runtime.start_container(&name).await.unwrap();
+        assert!(runtime.probe(&name, Probe::Readiness).await.unwrap());
+
+        runtime.stop_container(&name, Duration::ZERO).await.unwrap();
+        assert!(!runtime.probe(&name, Probe::Readiness).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_pods_not_throttled() {
+        let (mut runtime, _shutdown) = runtime(5).await;
+        runtime.pod_creations = Some(PodCreationLimiter::new(1));
+        let component = Arc::new(names::Name::parse(SERVER).component().unwrap());
+        let annotations = HashMap::from([(
+            String::from(STARTUP_DEPENDENCIES_ANNOTATION),
+            String::from("not a component"),
+        )]);
+
+        // Rejected annotations never use up the domain's rate.
+        for _ in 0..3 {
+            let error = runtime
+                .init_pod(
+                    component.clone(),
+                    PodSandboxMetadata::default(),
+                    HashMap::default(),
+                    annotations.clone(),
+                )
+                .await
+                .unwrap_err();
+            assert!(error.downcast_ref::<Status>().is_none());
+        }
+        let pod_creations = runtime.pod_creations.as_ref().unwrap();
+        assert!(pod_creations.acquire(&component.server.domain, Instant::now()));
+    }
+
+    #[tokio::test]
+    async fn test_restart_reuses_routes() {
+        let initialized: SharedResultFuture<GrpcPod> = async {
+            Ok(Arc::new(GrpcPod {
+                routes: Routes::default(),
+                max_connections: 0,
+            }))
+        }
+        .boxed()
+        .shared();
+        let routes = Some(initialized.clone());
+
+        // A container stopped before its component finished initializing waits for it.
+        assert!(matches!(restart_routes(&routes), RestartRoutes::Waiting(_)));
+
+        // Once initialized, every stop / restart cycle reuses the same component.
+        let Ok(component) = initialized.await else {
+            panic!("Expected the component to initialize");
+        };
+        for _ in 0..2 {
+            match restart_routes(&routes) {
+                RestartRoutes::Reuse(reused) => assert!(Arc::ptr_eq(&reused, &component)),
+                _ => panic!("Expected the initialized component to be reused"),
+            }
+        }
+
+        // Failed initialization is retried.
+        let failed: SharedResultFuture<GrpcPod> =
+            async { Err(SingleUse::of(anyhow!("Linking error"))) }
+                .boxed()
+                .shared();
+        let _ = failed.clone().await;
+        assert!(matches!(
+            restart_routes(&Some(failed)),
+            RestartRoutes::Reinitialize
+        ));
+        assert!(matches!(restart_routes(&None), RestartRoutes::Reinitialize));
+    }
+
+    async fn panicking() -> StdResult<(), &'static str> {
+        panic!("Unexpected state")
+    }
+
+    #[tokio::test]
+    async fn test_container_exit() {
+        let graceful = ContainerExit::default();
+        graceful.clone().watch(async { Ok::<(), &str>(()) }).await;
+        let exit = graceful.get().unwrap();
+        assert_eq!((exit.code, exit.reason), (0, "Completed"));
+
+        let failed = ContainerExit::default();
+        failed
+            .clone()
+            .watch(async { Err("Connection reset by peer") })
+            .await;
+        let exit = failed.get().unwrap();
+        assert_eq!((exit.code, exit.reason), (1, "Error"));
+        assert_eq!(exit.message, "Server failed: Connection reset by peer");
+        assert!(exit.finished_at > 0);
+
+        let panicked = ContainerExit::default();
+        panicked.clone().watch(panicking()).await;
+        let exit = panicked.get().unwrap();
+        assert_eq!((exit.code, exit.reason), (2, "Panicked"));
+        assert_eq!(exit.message, "Server panicked: Unexpected state");
+
+        // A server killed forcefully never finishes on its own.
+        let (shutdown, _ignored) = oneshot::channel();
+        let killed = ContainerExit::default();
+        let killer = ContainerKiller {
+            hook: None,
+            shutdown,
+            join: spawn(killed.clone().watch(pending::<StdResult<(), &str>>())),
+            exit: killed.clone(),
+        };
+        assert!(!killer.kill_with_timeout(Duration::ZERO).await);
+        let exit = killed.get().unwrap();
+        assert_eq!((exit.code, exit.reason), (KILLED_EXIT_CODE, "Killed"));
+
+        // Only the first exit counts.
+        failed.record(KILLED_EXIT_CODE, "Killed", String::default());
+        assert_eq!(failed.get().unwrap().reason, "Error");
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout() {
+        // A server that takes a while to finish its in-flight requests once told to shut down.
+        let drain = |timeout| async move {
+            let (shutdown, signal) = oneshot::channel();
+            let exit = ContainerExit::default();
+            let killer = ContainerKiller {
+                hook: None,
+                shutdown,
+                join: spawn(exit.clone().watch(async move {
+                    let _ = signal.await;
+                    sleep(Duration::from_millis(50)).await;
+                    Ok::<(), &str>(())
+                })),
+                exit: exit.clone(),
+            };
+            let graceful = killer.kill_with_timeout(timeout).await;
+            (graceful, exit.get().unwrap().reason)
+        };
+
+        // It finishes draining within a long enough window,
+        // but is aborted once a shorter window runs out.
+        assert_eq!(drain(Duration::from_secs(5)).await, (true, "Completed"));
+        assert_eq!(drain(Duration::from_millis(10)).await, (false, "Killed"));
+    }
+
+    #[tokio::test]
+    async fn test_shutdown_hook() {
+        // A component that records whether its shutdown hook was called.
+        let killer = || {
+            let called = Arc::new(AtomicBool::new(false));
+            let drain = post({
+                let called = called.clone();
+                move || async move {
+                    called.store(true, Ordering::Relaxed);
+                    HttpResponse::builder()
+                        .header("content-type", "application/grpc")
+                        .header("grpc-status", "0")
+                        .body(AxumBody::empty())
+                        .unwrap()
+                }
+            });
+            let (shutdown, signal) = oneshot::channel();
+            let exit = ContainerExit::default();
+            let killer = ContainerKiller {
+                hook: Some(ShutdownHook {
+                    pod: names::Name::parse(POD_NAME).pod().unwrap(),
+                    routes: Routes::from(Router::new().route("/foo.Bar/Drain", drain)),
+                    method: String::from("foo.Bar/Drain"),
+                }),
+                shutdown,
+                join: spawn(exit.clone().watch(async move {
+                    let _ = signal.await;
+                    Ok::<(), &str>(())
+                })),
+                exit,
+            };
+            (killer, called)
+        };
+
+        // The hook runs before a graceful stop.
+        let (graceful, called) = killer();
+        assert!(graceful.kill_with_timeout(Duration::from_secs(5)).await);
+        assert!(called.load(Ordering::Relaxed));
+
+        // But not when the container is aborted.
+        let (forced, called) = killer();
+        forced.forcefully_abort();
+        assert!(!called.load(Ordering::Relaxed));
+    }
+
+    #[tokio::test]
+    async fn test_start_retries_unroutable_address() {
+        let name = names::Name::parse(POD_NAME).pod().unwrap();
+        let unreachable = || IoError::from(ErrorKind::NetworkUnreachable);
+
+        // The address becomes routable on the third bind.
+        let mut attempts = 0;
+        let started = start_with_retries(&name, || {
+            attempts += 1;
+            Ok(if attempts < 3 {
+                StartAttempt::Unroutable(unreachable())
+            } else {
+                StartAttempt::Started
+            })
+        })
+        .await;
+        assert!(started.is_ok());
+        assert_eq!(attempts, 3);
+
+        // Eventually give up on an address that never becomes routable.
+        let mut attempts = 0;
+        let started = start_with_retries(&name, || {
+            attempts += 1;
+            Ok(StartAttempt::Unroutable(unreachable()))
+        })
+        .await;
+        assert!(started.is_err());
+        assert_eq!(attempts, ROUTABLE_ATTEMPTS);
+    }
+}
@@ -0,0 +1,149 @@
+//! Mapping from component errors to gRPC statuses.
+//!
+//! A component function may return `result<response, error>`
+//! where `error` is a WIT variant or enum.
+//! Each error case maps to a gRPC status code according to the method's
+//! [error codes](metadata_proto::work::runtime::GrpcMethod::error_codes).
+
+use std::collections::HashMap;
+use std::result::Result as StdResult;
+
+use anyhow::{bail, Result};
+use tonic::{Code, Status};
+use wasmtime::component::Val;
+
+use metadata_proto::work::runtime::ErrorCode;
+
+/// Largest canonical gRPC status code (`UNAUTHENTICATED`).
+const MAX_STATUS_CODE: u32 = 16;
+
+/// Maps error cases returned by a single method's function to gRPC status codes.
+pub(crate) struct ErrorMapping {
+    codes: HashMap<String, Code>,
+}
+
+impl ErrorMapping {
+    pub(crate) fn new(error_codes: &[ErrorCode]) -> Result<Self> {
+        let mut codes = HashMap::with_capacity(error_codes.len());
+        for error_code in error_codes {
+            if error_code.code > MAX_STATUS_CODE {
+                bail!(
+                    "Invalid status code for error case {:?}: {}",
+                    error_code.case,
+                    error_code.code,
+                );
+            }
+            if codes
+                .insert(error_code.case.clone(), Code::from(error_code.code as i32))
+                .is_some()
+            {
+                bail!("Duplicate error case: {:?}", error_code.case);
+            }
+        }
+        Ok(Self { codes })
+    }
+
+    /// Convert the value returned by a component function into either a response or a status.
+    ///
+    /// A `result` is unwrapped, and its error case (if any) mapped to a status.
+    /// Any other value is the response itself.
+    pub(crate) fn resolve(&self, returned: Val) -> StdResult<Val, Box<Status>> {
+        let status = match returned {
+            Val::Result(Ok(Some(response))) => return Ok(*response),
+            Val::Result(Ok(None)) => Status::internal("Function returned no response"),
+            Val::Result(Err(Some(error))) => self.status(*error),
+            Val::Result(Err(None)) => Status::unknown("Function returned an error"),
+            response => return Ok(response),
+        };
+        Err(Box::new(status))
+    }
+
+    /// Return the status for an error value.
+    /// The status message is the payload of a variant case if it's a string,
+    /// or otherwise the case name.
+    fn status(&self, error: Val) -> Status {
+        let (case, payload) = match error {
+            Val::Variant(case, payload) => (case, payload.map(|payload| *payload)),
+            Val::Enum(case) => (case, None),
+            _ => return Status::unknown("Function returned an unrecognized error"),
+        };
+        let code = self.codes.get(&case).copied().unwrap_or(Code::Unknown);
+        match payload {
+            Some(Val::String(message)) => Status::new(code, message),
+            _ => Status::new(code, case),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mapping() -> ErrorMapping {
+        ErrorMapping::new(&[
+            ErrorCode {
+                case: String::from("not-found"),
+                code: Code::NotFound as u32,
+            },
+            ErrorCode {
+                case: String::from("denied"),
+                code: Code::PermissionDenied as u32,
+            },
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_variant_maps_to_not_found() {
+        let status = mapping()
+            .resolve(Val::Result(Err(Some(Box::new(Val::Variant(
+                String::from("not-found"),
+                Some(Box::new(Val::String(String::from("No such user")))),
+            ))))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+        assert_eq!(status.message(), "No such user");
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mapping = mapping();
+
+        // Successful results are unwrapped, and bare responses pass through.
+        let response = Val::Record(Vec::new());
+        assert_eq!(
+            mapping
+                .resolve(Val::Result(Ok(Some(Box::new(response.clone())))))
+                .unwrap(),
+            response,
+        );
+        assert_eq!(mapping.resolve(response.clone()).unwrap(), response);
+
+        // Enum cases work too, using the case name as the message.
+        let status = mapping
+            .resolve(Val::Result(Err(Some(Box::new(Val::Enum(String::from(
+                "denied",
+            )))))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::PermissionDenied);
+        assert_eq!(status.message(), "denied");
+
+        // Unmapped cases are unknown.
+        let status = mapping
+            .resolve(Val::Result(Err(Some(Box::new(Val::Enum(String::from(
+                "oops",
+            )))))))
+            .unwrap_err();
+        assert_eq!(status.code(), Code::Unknown);
+    }
+
+    #[test]
+    fn test_invalid_mapping() {
+        let error_code = |case: &str, code| ErrorCode {
+            case: String::from(case),
+            code,
+        };
+        assert!(ErrorMapping::new(&[error_code("a", 17)]).is_err());
+        assert!(ErrorMapping::new(&[error_code("a", 5), error_code("a", 7)]).is_err());
+    }
+}
@@ -0,0 +1,34 @@
+//! Fixtures shared by the request decoder and response encoder tests.
+
+use std::mem::transmute;
+
+use bytes::BytesMut;
+use tonic::codec::{DecodeBuf, EncodeBuf};
+
+/// This has to be an exact clone of [`tonic::codec::DecodeBuf`],
+/// which has a private constructor that prevents instantiation here.
+/// We get around that by unsafely transmuting a structurally-equivalent clone.
+/// This is technically undefined behavior, but it works well enough for tests.
+///
+/// https://github.com/hyperium/tonic/blob/v0.12.3/tonic/src/codec/buffer.rs#L13
+#[allow(dead_code)] // Only ever transmuted.
+struct DecodeBufClone<'a> {
+    buf: &'a mut BytesMut,
+    len: usize,
+}
+
+/// Like [`DecodeBufClone`], for [`tonic::codec::EncodeBuf`].
+#[allow(dead_code)] // Only ever transmuted.
+struct EncodeBufClone<'a> {
+    buf: &'a mut BytesMut,
+}
+
+/// Return a Tonic buffer to decode the first `len` bytes of `buf`.
+pub fn decode_buf(buf: &mut BytesMut, len: usize) -> DecodeBuf<'_> {
+    unsafe { transmute::<DecodeBufClone<'_>, DecodeBuf<'_>>(DecodeBufClone { buf, len }) }
+}
+
+/// Return a Tonic buffer to encode into `buf`.
+pub fn encode_buf(buf: &mut BytesMut) -> EncodeBuf<'_> {
+    unsafe { transmute::<EncodeBufClone<'_>, EncodeBuf<'_>>(EncodeBufClone { buf }) }
+}
@@ -1,23 +1,34 @@
 """'Happy path' unit tests."""
 
+from concurrent.futures import ThreadPoolExecutor
 from ipaddress import ip_address
 from unittest import main
 
 from grpc import RpcError, StatusCode, insecure_channel
 from runtime.tests.api_pb2 import (
     ContainerConfig,
+    ContainerEventType,
     ContainerMetadata,
+    ContainerAttributes,
     ContainerResources,
     ContainerState,
+    ContainerStats,
+    ContainerStatsFilter,
+    ContainerStatsRequest,
     ContainerStatusRequest,
     ContainerUser,
     CreateContainerRequest,
+    ExecSyncRequest,
+    GetEventsRequest,
     ImageFsInfoResponse,
     ImageSpec,
     ImageStatusRequest,
     KeyValue,
+    ListContainerStatsRequest,
+    ListContainerStatsResponse,
     PodSandboxConfig,
     PodSandboxMetadata,
+    PodSandboxState,
     PodSandboxStatusRequest,
     RemoveContainerRequest,
     RemoveImageRequest,
@@ -35,6 +46,7 @@ from runtime.tests.components.adder_pb2_grpc import AdderServiceStub
 from runtime.tests.util import (
     RUNTIME_HANDLER,
     RUNTIME_NAME,
+    TIMEOUT,
     VimanadTestCase,
     ipHostName,
 )
@@ -189,10 +201,23 @@ class SuccessTest(VimanadTestCase):
         self.assertTrue(containerId.startswith('c-'))
         self.assertEqual(containerId[len('c-') :], podSandboxId[len('p-') :])
 
+        # The sandbox is ready right away (otherwise Kubelet would recreate it),
+        # but the pod fails readiness checks until the server is listening.
+        response = self.runtimeService.PodSandboxStatus(
+            PodSandboxStatusRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.assertEqual(response.status.state, PodSandboxState.SANDBOX_READY)
+        readinessRequest = ExecSyncRequest(
+            container_id=containerId, cmd=['vimana-probe', 'readiness']
+        )
+        self.assertEqual(self.runtimeService.ExecSync(readinessRequest).exit_code, 1)
+
         self.runtimeService.StartContainer(
             StartContainerRequest(container_id=containerId),
         )
 
+        self.assertEqual(self.runtimeService.ExecSync(readinessRequest).exit_code, 0)
+
         # Finally, try exercising the data plane.
         client = AdderServiceStub(insecure_channel(f'{ipHostName(ipAddress)}:80'))
         response = client.AddFloats(AddFloatsRequest(x=3.5, y=-1.2))
@@ -229,6 +254,554 @@ class SuccessTest(VimanadTestCase):
             RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
         )
 
+    def test_AuditLog(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='audited',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='audited-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+        podSandboxId = response.pod_sandbox_id
+        self.assertEqual(
+            self.auditEvents(domain),
+            [('initialized', 'RunPodSandbox')],
+        )
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+        containerId = response.container_id
+        self.runtimeService.StartContainer(
+            StartContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
+        # Every lifecycle operation is in the audit trail, in order.
+        self.assertEqual(
+            self.auditEvents(domain),
+            [
+                ('initialized', 'RunPodSandbox'),
+                ('created', 'CreateContainer'),
+                ('started', 'StartContainer'),
+                ('stopped', 'StopContainer'),
+                ('removed', 'RemoveContainer'),
+                ('killed', 'StopPodSandbox'),
+                ('deleted', 'RemovePodSandbox'),
+            ],
+        )
+
+    def test_RestartContainer(self):
+        domain, server, version, componentName, labels, imageSpec = self.setupImage(
+            server='restartable',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='restartable-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+
+        podSandboxId = response.pod_sandbox_id
+
+        response = self.runtimeService.PodSandboxStatus(
+            PodSandboxStatusRequest(pod_sandbox_id=podSandboxId),
+        )
+
+        ipAddress = ip_address(response.status.network.ip)
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+
+        containerId = response.container_id
+        client = AdderServiceStub(insecure_channel(f'{ipHostName(ipAddress)}:80'))
+
+        self.runtimeService.StartContainer(
+            StartContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+
+        response = self.runtimeService.ContainerStatus(
+            ContainerStatusRequest(container_id=containerId),
+        )
+        self.assertEqual(response.status.state, ContainerState.CONTAINER_EXITED)
+        firstStartedAt = response.status.started_at
+        self.assertGreater(response.status.finished_at, firstStartedAt)
+
+        # Kubelet restarts exited containers in place.
+        # Starting twice is idempotent.
+        for _ in range(2):
+            self.runtimeService.StartContainer(
+                StartContainerRequest(container_id=containerId),
+            )
+
+        response = self.runtimeService.ContainerStatus(
+            ContainerStatusRequest(container_id=containerId),
+        )
+        self.assertEqual(response.status.state, ContainerState.CONTAINER_RUNNING)
+        self.assertGreater(response.status.started_at, firstStartedAt)
+        self.assertEqual(response.status.finished_at, 0)
+        self.assertEqual(response.status.exit_code, 0)
+
+        # The restarted server serves on the same address.
+        response = client.AddFloats(AddFloatsRequest(x=3.5, y=-1.2))
+        self.assertEqual(response, AddFloatsResponse(result=2.3))
+
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
+    def test_CreateContainer_RacesStopPodSandbox(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='racy',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        for attempt in range(10):
+            response = self.runtimeService.RunPodSandbox(
+                RunPodSandboxRequest(
+                    runtime_handler=RUNTIME_HANDLER,
+                    config=PodSandboxConfig(
+                        metadata=PodSandboxMetadata(
+                            name=f'{domain}-name-{attempt}',
+                            uid=f'{domain}-uid-{attempt}',
+                            namespace=f'{domain}-namespace',
+                        ),
+                        hostname='racy-pod-hostname',
+                        labels=labels,
+                    ),
+                ),
+            )
+
+            podSandboxId = response.pod_sandbox_id
+            createRequest = CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            )
+
+            with ThreadPoolExecutor(max_workers=2) as executor:
+                created = executor.submit(
+                    self.runtimeService.CreateContainer, createRequest
+                )
+                killed = executor.submit(
+                    self.runtimeService.StopPodSandbox,
+                    StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+                )
+                killed.result()
+                # Either the container was created before the kill, or creation failed.
+                try:
+                    created.result()
+                except RpcError:
+                    pass
+
+            # Either way, the kill wins.
+            response = self.runtimeService.PodSandboxStatus(
+                PodSandboxStatusRequest(pod_sandbox_id=podSandboxId),
+            )
+            self.assertEqual(response.status.state, PodSandboxState.SANDBOX_NOTREADY)
+            with self.assertRaises(RpcError):
+                self.runtimeService.CreateContainer(createRequest)
+
+            self.runtimeService.RemovePodSandbox(
+                RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+            )
+
+    def test_ExecSync_Probe(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='probed',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='probed-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+
+        podSandboxId = response.pod_sandbox_id
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+
+        containerId = response.container_id
+
+        self.runtimeService.StartContainer(
+            StartContainerRequest(container_id=containerId),
+        )
+
+        # The adder has no health service, so it passes every check while running.
+        for probe in ['liveness', 'readiness']:
+            response = self.runtimeService.ExecSync(
+                ExecSyncRequest(container_id=containerId, cmd=['vimana-probe', probe]),
+            )
+            self.assertEqual(response.exit_code, 0)
+
+        # There is no shell to run anything else.
+        try:
+            self.runtimeService.ExecSync(
+                ExecSyncRequest(container_id=containerId, cmd=['cat', '/tmp/healthy']),
+            )
+        except RpcError as error:
+            self.assertEqual(error.code(), StatusCode.UNIMPLEMENTED)
+        else:
+            self.fail('Expected arbitrary commands to be unimplemented')
+
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+
+        # A stopped container fails every check.
+        response = self.runtimeService.ExecSync(
+            ExecSyncRequest(container_id=containerId, cmd=['vimana-probe', 'liveness']),
+        )
+        self.assertNotEqual(response.exit_code, 0)
+        self.assertEqual(response.stderr, b'Liveness check failed')
+
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
+    def test_ContainerStats(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='measured',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='measured-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+
+        podSandboxId = response.pod_sandbox_id
+
+        response = self.runtimeService.PodSandboxStatus(
+            PodSandboxStatusRequest(pod_sandbox_id=podSandboxId),
+        )
+
+        ipAddress = ip_address(response.status.network.ip)
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+
+        containerId = response.container_id
+
+        self.runtimeService.StartContainer(
+            StartContainerRequest(container_id=containerId),
+        )
+
+        client = AdderServiceStub(insecure_channel(f'{ipHostName(ipAddress)}:80'))
+        response = client.AddFloats(AddFloatsRequest(x=3.5, y=-1.2))
+        self.assertEqual(response, AddFloatsResponse(result=2.3))
+
+        # Handling the request took some CPU time.
+        # Each request's instance (and its memory) is gone once the response is sent.
+        response = self.runtimeService.ContainerStats(
+            ContainerStatsRequest(container_id=containerId),
+        )
+        self.assertEqual(response.stats.attributes.id, containerId)
+        self.assertEqual(response.stats.attributes.labels, labels)
+        self.assertGreater(response.stats.cpu.usage_core_nano_seconds.value, 0)
+        self.assertEqual(response.stats.memory.working_set_bytes.value, 0)
+
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+
+        # A stopped container uses nothing.
+        response = self.runtimeService.ContainerStats(
+            ContainerStatsRequest(container_id=containerId),
+        )
+        self.assertEqual(response.stats.cpu.usage_core_nano_seconds.value, 0)
+        self.assertEqual(response.stats.memory.working_set_bytes.value, 0)
+
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
+    def test_ListContainerStats(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='listed',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='listed-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+
+        podSandboxId = response.pod_sandbox_id
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+
+        containerId = response.container_id
+
+        # The downstream runtime has one OCI container of its own.
+        self.downstreamRuntimeService.returnNext(
+            'ListContainerStats',
+            ListContainerStatsResponse(
+                stats=[
+                    ContainerStats(
+                        attributes=ContainerAttributes(id='oci-container-id'),
+                    ),
+                ],
+            ),
+        )
+
+        response = self.runtimeService.ListContainerStats(
+            ListContainerStatsRequest(filter=ContainerStatsFilter(label_selector=labels)),
+        )
+        self.assertEqual(
+            sorted(stats.attributes.id for stats in response.stats),
+            sorted([containerId, 'oci-container-id']),
+        )
+
+        # Filtering by the pod sandbox finds the same Wasm container.
+        self.downstreamRuntimeService.returnNext(
+            'ListContainerStats', ListContainerStatsResponse()
+        )
+        response = self.runtimeService.ListContainerStats(
+            ListContainerStatsRequest(
+                filter=ContainerStatsFilter(pod_sandbox_id=podSandboxId),
+            ),
+        )
+        self.assertEqual(len(response.stats), 1)
+        self.assertEqual(response.stats[0].attributes.id, containerId)
+
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
+    def test_GetContainerEvents(self):
+        domain, _, _, _, labels, imageSpec = self.setupImage(
+            server='evented',
+            version='1.2.3',
+            module='runtime/tests/components/adder-c.component.wasm',
+            metadata='runtime/tests/components/adder.binpb',
+        )
+
+        # The downstream runtime has no events of its own.
+        self.downstreamRuntimeService.returnNext('GetContainerEvents', iter([]))
+        events = self.runtimeService.GetContainerEvents(
+            GetEventsRequest(), timeout=TIMEOUT.total_seconds()
+        )
+        # Headers arrive once the subscription is established.
+        events.initial_metadata()
+
+        response = self.runtimeService.RunPodSandbox(
+            RunPodSandboxRequest(
+                runtime_handler=RUNTIME_HANDLER,
+                config=PodSandboxConfig(
+                    metadata=PodSandboxMetadata(
+                        name=f'{domain}-name',
+                        uid=f'{domain}-uid',
+                        namespace=f'{domain}-namespace',
+                    ),
+                    hostname='evented-pod-hostname',
+                    labels=labels,
+                ),
+            ),
+        )
+
+        podSandboxId = response.pod_sandbox_id
+
+        response = self.runtimeService.CreateContainer(
+            CreateContainerRequest(
+                pod_sandbox_id=podSandboxId,
+                config=ContainerConfig(
+                    metadata=ContainerMetadata(name=f'{domain}-container-name'),
+                    image=imageSpec,
+                    labels=labels,
+                ),
+            ),
+        )
+
+        containerId = response.container_id
+
+        self.runtimeService.StartContainer(
+            StartContainerRequest(container_id=containerId),
+        )
+        self.runtimeService.StopContainer(
+            StopContainerRequest(container_id=containerId, timeout=1),
+        )
+        self.runtimeService.RemoveContainer(
+            RemoveContainerRequest(container_id=containerId),
+        )
+
+        # Each transition arrives in order, along with the pod's status.
+        eventTypes = []
+        for event in events:
+            if event.container_id != containerId:
+                continue
+            self.assertEqual(event.pod_sandbox_status.id, podSandboxId)
+            self.assertGreater(event.created_at, 0)
+            eventTypes.append(event.container_event_type)
+            if len(eventTypes) == 4:
+                break
+        events.cancel()
+        self.assertEqual(
+            eventTypes,
+            [
+                ContainerEventType.CONTAINER_CREATED_EVENT,
+                ContainerEventType.CONTAINER_STARTED_EVENT,
+                ContainerEventType.CONTAINER_STOPPED_EVENT,
+                ContainerEventType.CONTAINER_DELETED_EVENT,
+            ],
+        )
+
+        self.runtimeService.StopPodSandbox(
+            StopPodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+        self.runtimeService.RemovePodSandbox(
+            RemovePodSandboxRequest(pod_sandbox_id=podSandboxId),
+        )
+
     def test_ContainerStatus(self):
         domain, server, version, componentName, labels, imageSpec = self.setupImage(
             server='some-server',
@@ -92,6 +92,7 @@ class VimanadTestCase(TestCase):
         cls.imageService = cls.tester.imageService
         cls.setupImage = cls.tester.setupImage
         cls.imageId = cls.tester.imageId
+        cls.auditEvents = cls.tester.auditEvents
         cls.downstreamRuntimeService = cls.tester.downstreamRuntimeService
         cls.downstreamImageService = cls.tester.downstreamImageService
 
@@ -148,11 +149,13 @@ class VimanadTester:
                     and not _isPortAvailable(self._imageRegistryPort),
                 )
                 self._imageStore = TemporaryDirectory()
+                self._auditLog = _tmpName()
                 self._vimanad, self._vimanadSocket = startVimanad(
                     downstreamSocket,
                     self._imageRegistryPort,
                     self._imageStore.name,
                     IPAM_WRAPPER.name,
+                    self._auditLog,
                 )
                 try:
                     # We need a separate thread just to collect the logs:
@@ -316,6 +319,17 @@ class VimanadTester:
             except (Empty, ShutDown):
                 return logs
 
+    def auditEvents(self, domain: str) -> list[tuple[str, str]]:
+        """
+        Return the `(operation, rpc)` pairs recorded in the audit log
+        for pods in the given domain, in the order they happened.
+        """
+        if not exists(self._auditLog):
+            return []
+        with open(self._auditLog) as f:
+            events = [parseJson(line) for line in f]
+        return [(event['operation'], event['rpc']) for event in events if event['domain'] == domain]
+
     def printVimanadLogs(self, testCase: TestCase):
         """Print collected `vimanad` logs to standard error, if there are any."""
         logs = self.vimanadLogs()
@@ -331,6 +345,7 @@ def startVimanad(
     imageRegistryPort: int,
     imageStorePath: str,
     ipamPath: str,
+    auditLogPath: str,
 ) -> tuple[Popen, str]:
     """Start a background process running the work node daemon.
 
@@ -349,6 +364,7 @@ def startVimanad(
         f'--ipam-plugin={ipamPath}',
         f'--network-interface={networkInterface}',
         f'--pod-ips={podIps}',
+        f'--audit-log={auditLogPath}',
     ]
     # Open a line-buffered text-mode pipe for stdout
     # and convert all CR/LF sequences to plain LF.
@@ -0,0 +1,101 @@
+//! Limits on how quickly each domain may create pods on a node.
+//!
+//! A misbehaving controller could otherwise flood the node with `RunPodSandbox` requests
+//! for a single domain, starving every other tenant of IP addresses and CPU time.
+//! Each domain gets its own token bucket that refills at a steady rate
+//! and holds at most one second's worth of pods,
+//! so short bursts are tolerated but sustained storms are rejected.
+
+use std::time::Instant;
+
+use papaya::{Compute, HashMap as LockFreeConcurrentHashMap, Operation};
+
+use names::DomainUuid;
+
+/// Rate-limits pod creation independently for each domain.
+pub(crate) struct PodCreationLimiter {
+    /// Number of pods each domain may create per second,
+    /// which is also the largest burst a domain may create at once.
+    rate: f64,
+
+    /// Remaining pod creations for each domain that has created any pods.
+    /// Lock-freedom is important to help isolate tenants from one another.
+    buckets: LockFreeConcurrentHashMap<DomainUuid, Bucket>,
+}
+
+/// Pod creations available to a domain as of the last time it created a pod.
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl PodCreationLimiter {
+    pub(crate) fn new(pods_per_second: u32) -> Self {
+        Self {
+            rate: pods_per_second as f64,
+            buckets: LockFreeConcurrentHashMap::new(),
+        }
+    }
+
+    /// Take one pod creation from the domain's bucket as of the given time.
+    /// Return false if the domain has exhausted its rate, in which case nothing is taken.
+    pub(crate) fn acquire(&self, domain: &DomainUuid, now: Instant) -> bool {
+        let buckets = self.buckets.pin();
+        let result: Compute<'_, _, _, ()> = buckets.compute(domain.clone(), |entry| {
+            let tokens = match entry {
+                Some((_, bucket)) => {
+                    let elapsed = now.saturating_duration_since(bucket.updated);
+                    (bucket.tokens + elapsed.as_secs_f64() * self.rate).min(self.rate)
+                }
+                None => self.rate,
+            };
+            if tokens >= 1.0 {
+                Operation::Insert(Bucket {
+                    tokens: tokens - 1.0,
+                    updated: now,
+                })
+            } else {
+                Operation::Abort(())
+            }
+        });
+        !matches!(result, Compute::Aborted(_))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn test_burst_throttled_per_domain() {
+        let limiter = PodCreationLimiter::new(3);
+        let noisy = DomainUuid::parse("1234567890abcdef1234567890abcdef").unwrap();
+        let quiet = DomainUuid::parse("fedcba0987654321fedcba0987654321").unwrap();
+        let start = Instant::now();
+
+        // The noisy domain spends its whole burst, then gets throttled.
+        for _ in 0..3 {
+            assert!(limiter.acquire(&noisy, start));
+        }
+        assert!(!limiter.acquire(&noisy, start));
+        assert!(!limiter.acquire(&noisy, start));
+
+        // Another domain is unaffected.
+        for _ in 0..3 {
+            assert!(limiter.acquire(&quiet, start));
+        }
+
+        // Tokens refill over time, up to one second's worth.
+        let later = start + Duration::from_millis(400);
+        assert!(limiter.acquire(&noisy, later));
+        assert!(!limiter.acquire(&noisy, later));
+        let much_later = start + Duration::from_secs(60);
+        for _ in 0..3 {
+            assert!(limiter.acquire(&noisy, much_later));
+        }
+        assert!(!limiter.acquire(&noisy, much_later));
+    }
+}
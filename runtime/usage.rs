@@ -0,0 +1,215 @@
+//! Resource usage of each pod, as reported by `ContainerStats`.
+//!
+//! Every request to a pod is handled by a fresh instance in its own store,
+//! so usage is accumulated across all of the pod's instances.
+//! CPU time is the time spent polling instantiation and function calls,
+//! which is when guest code runs on the polling thread
+//! (time spent waiting on asynchronous host functions is excluded).
+//! Memory is the linear memory held by every live instance,
+//! as reported by each store's [resource limiter](ResourceLimiter).
+
+use std::future::{poll_fn, Future};
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use anyhow::{Error, Result};
+use wasmtime::ResourceLimiter;
+
+/// Resource usage of a single pod, shared by all of its instances.
+#[derive(Clone, Default)]
+pub(crate) struct ResourceUsage(Arc<UsageInner>);
+
+/// See [`ResourceUsage`].
+#[derive(Default)]
+struct UsageInner {
+    /// Total CPU time spent running the pod's instances.
+    cpu_nanos: AtomicU64,
+
+    /// Linear memory currently held by the pod's live instances.
+    memory_bytes: AtomicU64,
+
+    /// When the previous [sample](ResourceUsage::sample) was taken, and the CPU time at that point,
+    /// to compute the CPU usage rate between samples.
+    last_sample: Mutex<Option<(Instant, u64)>>,
+}
+
+/// A snapshot of a pod's [resource usage](ResourceUsage).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct UsageSample {
+    /// Cumulative CPU time, in nanoseconds.
+    pub(crate) cpu_nanos: u64,
+
+    /// Average CPU usage since the previous sample, in nanocores
+    /// (*i.e.* billionths of one core). Zero for the first sample.
+    pub(crate) nano_cores: u64,
+
+    /// Linear memory currently in use, in bytes.
+    pub(crate) memory_bytes: u64,
+}
+
+/// Counts the linear memory of a single store towards its pod's [usage](ResourceUsage),
+/// and gives it back when the store is dropped.
+pub(crate) struct MemoryTracker {
+    usage: ResourceUsage,
+
+    /// Memory counted for this store so far.
+    bytes: u64,
+
+    /// Size of the latest growth, taken back if it fails.
+    growing: u64,
+}
+
+impl ResourceUsage {
+    /// Run the future to completion, counting the time spent polling it as CPU time.
+    pub(crate) async fn on_cpu<F: Future>(&self, future: F) -> F::Output {
+        let mut future = pin!(future);
+        poll_fn(|context| {
+            let start = Instant::now();
+            let poll = future.as_mut().poll(context);
+            self.0
+                .cpu_nanos
+                .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+            poll
+        })
+        .await
+    }
+
+    /// Return a tracker for the memory of a new store.
+    pub(crate) fn memory_tracker(&self) -> MemoryTracker {
+        MemoryTracker {
+            usage: self.clone(),
+            bytes: 0,
+            growing: 0,
+        }
+    }
+
+    /// Take a snapshot of the usage so far.
+    pub(crate) fn sample(&self) -> UsageSample {
+        let now = Instant::now();
+        let cpu_nanos = self.0.cpu_nanos.load(Ordering::Relaxed);
+        let previous = match self.0.last_sample.lock() {
+            Ok(mut last) => last.replace((now, cpu_nanos)),
+            Err(poisoned) => poisoned.into_inner().replace((now, cpu_nanos)),
+        };
+        let nano_cores = previous.map_or(0, |(then, previous_cpu_nanos)| {
+            let elapsed = now.duration_since(then).as_nanos();
+            let used = cpu_nanos.saturating_sub(previous_cpu_nanos) as u128;
+            // Nanoseconds of CPU time per second of wall time.
+            (used * 1_000_000_000).checked_div(elapsed).unwrap_or(0) as u64
+        });
+        UsageSample {
+            cpu_nanos,
+            nano_cores,
+            memory_bytes: self.0.memory_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl ResourceLimiter for MemoryTracker {
+    fn memory_growing(
+        &mut self,
+        current: usize,
+        desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        // Only count the growth, which is the whole size when a memory is first created.
+        self.growing = desired.saturating_sub(current) as u64;
+        self.bytes += self.growing;
+        self.usage
+            .0
+            .memory_bytes
+            .fetch_add(self.growing, Ordering::Relaxed);
+        Ok(true)
+    }
+
+    fn memory_grow_failed(&mut self, _error: Error) -> Result<()> {
+        self.bytes -= self.growing;
+        self.usage
+            .0
+            .memory_bytes
+            .fetch_sub(self.growing, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn table_growing(
+        &mut self,
+        _current: usize,
+        _desired: usize,
+        _maximum: Option<usize>,
+    ) -> Result<bool> {
+        Ok(true)
+    }
+}
+
+impl Drop for MemoryTracker {
+    fn drop(&mut self) {
+        self.usage
+            .0
+            .memory_bytes
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread::sleep;
+    use std::time::Duration;
+
+    use wasmtime::{Engine as WasmEngine, Instance, Module, Store};
+
+    use super::*;
+
+    /// Size of a single page of linear memory.
+    const PAGE_SIZE: u64 = 65536;
+
+    #[test]
+    fn test_memory_of_live_instances() {
+        let wasmtime = WasmEngine::default();
+        let module = Module::new(&wasmtime, r#"(module (memory 2))"#).unwrap();
+        let usage = ResourceUsage::default();
+        let instantiate = || {
+            let mut store = Store::new(&wasmtime, usage.memory_tracker());
+            store.limiter(|tracker| tracker);
+            Instance::new(&mut store, &module, &[]).unwrap();
+            store
+        };
+
+        let first = instantiate();
+        assert_eq!(usage.sample().memory_bytes, 2 * PAGE_SIZE);
+        let second = instantiate();
+        assert_eq!(usage.sample().memory_bytes, 4 * PAGE_SIZE);
+
+        // Memory is given back as soon as each store is dropped.
+        drop(first);
+        assert_eq!(usage.sample().memory_bytes, 2 * PAGE_SIZE);
+        drop(second);
+        assert_eq!(usage.sample().memory_bytes, 0);
+    }
+
+    #[tokio::test]
+    async fn test_cpu_time() {
+        let usage = ResourceUsage::default();
+        assert_eq!(usage.sample(), UsageSample::default());
+
+        // Blocking inside a poll counts, but waiting for the next poll does not.
+        usage
+            .on_cpu(async {
+                sleep(Duration::from_millis(10));
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            })
+            .await;
+        let sample = usage.sample();
+        assert!(sample.cpu_nanos >= 10_000_000);
+        assert!(sample.cpu_nanos < 50_000_000);
+
+        // The rate is measured between consecutive samples.
+        usage
+            .on_cpu(async { sleep(Duration::from_millis(10)) })
+            .await;
+        let sample = usage.sample();
+        assert!(sample.nano_cores > 0);
+        assert!(sample.nano_cores <= 1_000_000_000);
+    }
+}